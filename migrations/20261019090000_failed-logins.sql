@@ -0,0 +1,13 @@
+-- audit trail of failed /auth attempts
+CREATE TABLE failed_logins
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    username VARCHAR NOT NULL,
+    user_id uuid NULL REFERENCES users (id) ON DELETE SET NULL,
+    ip_address VARCHAR NOT NULL,
+    reason VARCHAR NOT NULL, -- invalid_password | unknown_user | locked
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX failed_logins_username_idx ON failed_logins (username, created_at);
+CREATE INDEX failed_logins_ip_address_idx ON failed_logins (ip_address, created_at);
@@ -0,0 +1,25 @@
+-- external accounts (discord, google, oidc) linked to users
+CREATE TABLE identities
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL,
+    subject VARCHAR NOT NULL,
+    email VARCHAR NULL,
+    username VARCHAR NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (provider, subject)
+);
+
+CREATE INDEX identities_user_id_idx ON identities (user_id);
+
+-- in-flight authorization code + PKCE flows
+CREATE TABLE oauth_states
+(
+    state VARCHAR PRIMARY KEY,
+    provider VARCHAR NOT NULL,
+    code_verifier VARCHAR NOT NULL,
+    user_id uuid NULL REFERENCES users (id) ON DELETE CASCADE, -- set when linking to a logged in user
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
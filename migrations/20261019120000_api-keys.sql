@@ -0,0 +1,16 @@
+-- personal api keys for bots and scripts, only the sha256 of the key is stored
+CREATE TABLE api_keys
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name VARCHAR NOT NULL,
+    prefix VARCHAR NOT NULL UNIQUE, -- public part of the key, used for lookup
+    key_hash VARCHAR NOT NULL,
+    scopes VARCHAR NOT NULL, -- space separated
+    last_used_at TIMESTAMP NULL,
+    expires_at TIMESTAMP NULL,
+    revoked_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX api_keys_user_id_idx ON api_keys (user_id);
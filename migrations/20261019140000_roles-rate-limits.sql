@@ -0,0 +1,10 @@
+-- user | admin
+ALTER TABLE users ADD COLUMN role VARCHAR NOT NULL DEFAULT 'user';
+
+-- optional persistence of the in memory rate limit token buckets
+CREATE TABLE rate_limit_buckets
+(
+    bucket_key VARCHAR PRIMARY KEY, -- user:<id> | key:<id>
+    tokens DOUBLE PRECISION NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
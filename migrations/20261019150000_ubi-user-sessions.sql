@@ -0,0 +1,27 @@
+-- typed session expiry, existing rows hold rfc3339 strings from Ubisoft
+ALTER TABLE ubi_users
+    ALTER COLUMN expiration TYPE TIMESTAMPTZ USING expiration::timestamptz;
+
+-- keeps updated_at current on every update, whoever issues it
+CREATE OR REPLACE FUNCTION set_updated_at() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at = CURRENT_TIMESTAMP;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER ubi_users_set_updated_at
+    BEFORE UPDATE ON ubi_users
+    FOR EACH ROW EXECUTE PROCEDURE set_updated_at();
+
+-- every session issued or renewed for a service account, for troubleshooting
+CREATE TABLE ubi_session_history
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    ubi_user_id uuid NOT NULL REFERENCES ubi_users (id) ON DELETE CASCADE,
+    kind VARCHAR NOT NULL, -- login | renew
+    expiration TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX ubi_session_history_ubi_user_id_idx ON ubi_session_history (ubi_user_id, created_at);
@@ -0,0 +1,27 @@
+-- stats of a player as seen at some point, one row per profile and region.
+-- A new row is only written when something changed, checked_at tracks the
+-- last time the unchanged row was confirmed
+CREATE TABLE player_snapshots
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    profile_id VARCHAR NOT NULL,
+    platform VARCHAR NOT NULL,
+    name_on_platform VARCHAR NOT NULL,
+    region VARCHAR NOT NULL,
+    season INTEGER NOT NULL,
+    rank INTEGER NOT NULL,
+    max_rank INTEGER NOT NULL,
+    mmr REAL NOT NULL,
+    max_mmr REAL NOT NULL,
+    kills INTEGER NOT NULL,
+    deaths INTEGER NOT NULL,
+    wins INTEGER NOT NULL,
+    losses INTEGER NOT NULL,
+    abandons INTEGER NOT NULL,
+    level INTEGER NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    checked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX player_snapshots_profile_idx ON player_snapshots (profile_id, region, created_at);
+CREATE INDEX player_snapshots_name_idx ON player_snapshots (platform, lower(name_on_platform), checked_at);
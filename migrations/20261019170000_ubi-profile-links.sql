@@ -0,0 +1,17 @@
+-- Ubisoft profiles linked to a zbot user, a Discord user, or both
+CREATE TABLE ubi_profile_links
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NULL REFERENCES users (id) ON DELETE CASCADE,
+    discord_user_id VARCHAR NULL,
+    profile_id VARCHAR NOT NULL,
+    platform VARCHAR NOT NULL,
+    name_on_platform VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK (user_id IS NOT NULL OR discord_user_id IS NOT NULL)
+);
+
+-- a user links a profile once, a Discord user has one profile for slash commands
+CREATE UNIQUE INDEX ubi_profile_links_user_profile_idx ON ubi_profile_links (user_id, profile_id) WHERE user_id IS NOT NULL;
+CREATE UNIQUE INDEX ubi_profile_links_discord_idx ON ubi_profile_links (discord_user_id) WHERE discord_user_id IS NOT NULL;
+CREATE INDEX ubi_profile_links_profile_idx ON ubi_profile_links (profile_id);
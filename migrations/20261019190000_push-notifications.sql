@@ -0,0 +1,27 @@
+-- mobile devices registered for push notifications
+CREATE TABLE device_tokens
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR NOT NULL, -- fcm | apns
+    token VARCHAR NOT NULL UNIQUE,
+    name VARCHAR NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX device_tokens_user_id_idx ON device_tokens (user_id);
+
+-- users without a row get every notification and no quiet hours
+CREATE TABLE notification_preferences
+(
+    user_id uuid PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    rank_up BOOLEAN NOT NULL DEFAULT TRUE,
+    rank_down BOOLEAN NOT NULL DEFAULT TRUE,
+    level_up BOOLEAN NOT NULL DEFAULT TRUE,
+    quiet_hours_start TIME NULL, -- local time, with quiet_hours_end
+    quiet_hours_end TIME NULL,
+    utc_offset_minutes INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((quiet_hours_start IS NULL) = (quiet_hours_end IS NULL))
+);
@@ -0,0 +1,21 @@
+-- labeled, structured addresses, several per user. country holds the
+-- ISO 3166-1 alpha-2 code, older rows keep their free text until updated
+ALTER TABLE user_locations
+    ALTER COLUMN street TYPE VARCHAR,
+    ALTER COLUMN city TYPE VARCHAR,
+    ALTER COLUMN state TYPE VARCHAR,
+    ALTER COLUMN country TYPE VARCHAR,
+    ADD COLUMN label VARCHAR NOT NULL DEFAULT 'home',
+    ADD COLUMN postal_code VARCHAR NULL,
+    ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+UPDATE user_locations SET country = upper(country) WHERE country ~ '^[A-Za-z]{2}$';
+
+-- only one location per user was written so far, number any duplicates
+WITH numbered AS (
+    SELECT id, row_number() OVER (PARTITION BY user_id ORDER BY id) AS position FROM user_locations
+)
+UPDATE user_locations l SET label = 'home ' || numbered.position
+FROM numbered WHERE numbered.id = l.id AND numbered.position > 1;
+
+CREATE UNIQUE INDEX user_locations_user_label_idx ON user_locations (user_id, label);
@@ -0,0 +1,4 @@
+-- the profile ETag is derived from updated_at, so every write has to bump it
+CREATE TRIGGER users_set_updated_at
+    BEFORE UPDATE ON users
+    FOR EACH ROW EXECUTE PROCEDURE set_updated_at();
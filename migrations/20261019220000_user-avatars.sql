@@ -0,0 +1,2 @@
+-- storage prefix of the uploaded avatar thumbnails, NULL for external images
+ALTER TABLE users ADD COLUMN avatar_key VARCHAR NULL;
@@ -0,0 +1,16 @@
+-- what /users/{username} shows to other users, email is never shown
+ALTER TABLE users
+    ADD COLUMN show_full_name BOOLEAN NOT NULL DEFAULT true,
+    ADD COLUMN show_bio BOOLEAN NOT NULL DEFAULT true,
+    ADD COLUMN show_image BOOLEAN NOT NULL DEFAULT true,
+    ADD COLUMN show_locations BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN show_ubi_profiles BOOLEAN NOT NULL DEFAULT true,
+    ADD COLUMN show_badges BOOLEAN NOT NULL DEFAULT true;
+
+CREATE TABLE user_badges
+(
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    badge VARCHAR NOT NULL,
+    awarded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, badge)
+);
@@ -0,0 +1,12 @@
+-- self-service deletion, the account is purged once the grace period ends
+ALTER TABLE users ADD COLUMN deletion_scheduled_for TIMESTAMP NULL;
+
+CREATE INDEX users_deletion_scheduled_for_idx ON users (deletion_scheduled_for)
+    WHERE deletion_scheduled_for IS NOT NULL;
+
+-- locations go with their user
+DELETE FROM user_locations WHERE user_id IS NULL;
+ALTER TABLE user_locations
+    ALTER COLUMN user_id SET NOT NULL,
+    DROP CONSTRAINT user_locations_user_id_fkey,
+    ADD CONSTRAINT user_locations_user_id_fkey FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
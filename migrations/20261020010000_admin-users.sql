@@ -0,0 +1,32 @@
+-- operator tooling: suspensions, forced password resets and impersonation
+ALTER TABLE users
+    ADD COLUMN suspended_at TIMESTAMP NULL,
+    ADD COLUMN suspended_reason VARCHAR NULL,
+    ADD COLUMN password_reset_required BOOLEAN NOT NULL DEFAULT false;
+
+-- set on sessions an admin opened as the user
+ALTER TABLE sessions ADD COLUMN impersonated_by uuid NULL REFERENCES users (id) ON DELETE CASCADE;
+
+-- every admin action on a user, with who did it and why
+CREATE TABLE admin_actions
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    admin_id uuid NULL REFERENCES users (id) ON DELETE SET NULL,
+    target_user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    action VARCHAR NOT NULL, -- suspend | unsuspend | force_password_reset | impersonate
+    reason VARCHAR NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX admin_actions_target_user_id_idx ON admin_actions (target_user_id, created_at);
+
+-- single use links to set a new password
+CREATE TABLE password_resets
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,48 @@
+-- append-only log of security relevant events, replaces failed_logins and admin_actions
+CREATE TABLE audit_events
+(
+    id uuid DEFAULT uuid_generate_v4() PRIMARY KEY,
+    event_type VARCHAR NOT NULL, -- signup | login | login_failed | password_change | admin_suspend | ...
+    actor_id uuid NULL, -- who did it, NULL when nobody is logged in. No FK, the log outlives users
+    target_user_id uuid NULL, -- the account it happened to
+    ip_address VARCHAR NULL,
+    user_agent VARCHAR NULL,
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX audit_events_target_user_id_idx ON audit_events (target_user_id, created_at);
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id, created_at);
+CREATE INDEX audit_events_event_type_idx ON audit_events (event_type, created_at);
+
+-- rows are never changed. Deleting is reserved to the account purge,
+-- which sets zbot.purging for its transaction
+CREATE OR REPLACE FUNCTION audit_events_append_only()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF TG_OP = 'DELETE' AND current_setting('zbot.purging', true) = 'on' THEN
+        RETURN OLD;
+    END IF;
+    RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE
+    ON audit_events
+    FOR EACH ROW
+EXECUTE PROCEDURE audit_events_append_only();
+
+INSERT INTO audit_events (event_type, target_user_id, ip_address, details, created_at)
+SELECT 'login_failed', user_id, ip_address, jsonb_build_object('username', username, 'reason', reason), created_at
+FROM failed_logins;
+
+INSERT INTO audit_events (event_type, actor_id, target_user_id, details, created_at)
+SELECT 'admin_' || action, admin_id, target_user_id,
+       CASE WHEN reason IS NULL THEN '{}'::jsonb ELSE jsonb_build_object('reason', reason) END, created_at
+FROM admin_actions;
+
+DROP TABLE failed_logins;
+DROP TABLE admin_actions;
@@ -0,0 +1,11 @@
+-- one way follows between users, two follows make friends
+CREATE TABLE follows
+(
+    follower_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    followee_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (follower_id, followee_id),
+    CHECK (follower_id <> followee_id)
+);
+
+CREATE INDEX follows_followee_id_idx ON follows (followee_id, created_at);
@@ -0,0 +1,9 @@
+-- '@' marks an email login, so usernames can not contain it. Existing
+-- names are renamed, their owners can still log in with the email address
+UPDATE users
+SET username   = replace(username, '@', '_') || '_' || left(id::text, 4),
+    updated_at = CURRENT_TIMESTAMP
+WHERE position('@' in username) > 0;
+
+ALTER TABLE users
+    ADD CONSTRAINT users_username_no_at CHECK (position('@' in username) = 0);
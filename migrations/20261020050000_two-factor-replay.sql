@@ -0,0 +1,8 @@
+-- TOTP codes and 2fa login challenges are accepted once
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT NULL; -- newest accepted TOTP time step
+
+CREATE TABLE used_challenges
+(
+    jti VARCHAR PRIMARY KEY,
+    expires_at TIMESTAMP NOT NULL -- the row is kept until the challenge expired anyway
+);
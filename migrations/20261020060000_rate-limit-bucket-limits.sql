@@ -0,0 +1,5 @@
+-- buckets keep the limit of the role they were charged with, admin buckets
+-- share the user: prefix. Old rows do not know theirs, they start full again
+DELETE FROM rate_limit_buckets;
+ALTER TABLE rate_limit_buckets ADD COLUMN capacity INT NOT NULL;
+ALTER TABLE rate_limit_buckets ADD COLUMN refill_per_minute INT NOT NULL;
@@ -0,0 +1,3 @@
+-- only accounts added to the pool (POST /ubi/accounts) join it at startup,
+-- rows from before can not be told apart and have to be added again
+ALTER TABLE ubi_users ADD COLUMN pool_member BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- badges belong to the Ubisoft profile that earned them, linking a profile
+-- proves no ownership of it. Users show the badges of the profiles they link.
+-- Badges awarded so far are awarded again with the next snapshot of the profile
+DROP TABLE user_badges;
+
+CREATE TABLE profile_badges
+(
+    profile_id VARCHAR NOT NULL,
+    badge VARCHAR NOT NULL,
+    awarded_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (profile_id, badge)
+);
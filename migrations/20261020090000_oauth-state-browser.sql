@@ -0,0 +1,4 @@
+-- a flow can only be finished by the browser that started it, which holds
+-- the nonce behind browser_hash in a cookie. Flows in flight are dropped
+DELETE FROM oauth_states;
+ALTER TABLE oauth_states ADD COLUMN browser_hash VARCHAR NOT NULL;
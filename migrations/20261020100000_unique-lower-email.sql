@@ -0,0 +1,13 @@
+-- emails are looked up case-insensitively, so they have to be unique that
+-- way too. Where accounts already share an email up to case the oldest one
+-- keeps it and the others get a marked, undeliverable address
+UPDATE users SET email = email || '.duplicate-' || id, updated_at = CURRENT_TIMESTAMP
+WHERE id IN (
+    SELECT id FROM (
+        SELECT id, row_number() OVER (PARTITION BY lower(email) ORDER BY created_at, id) AS position
+        FROM users
+    ) ranked
+    WHERE position > 1
+);
+
+CREATE UNIQUE INDEX users_lower_email_idx ON users (lower(email));
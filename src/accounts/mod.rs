@@ -0,0 +1,54 @@
+//module accounts
+// accounts scheduled for deletion are purged once their grace period is
+// over, together with their avatar files
+use crate::{config::AccountsConfig, db::account::AccountRepository, storage::avatar::Avatars};
+use chrono::{Duration, NaiveDateTime, Utc};
+use color_eyre::Result;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{error, info};
+
+#[derive(Clone)]
+pub struct Accounts {
+    config: AccountsConfig,
+    avatars: Avatars,
+    pool: Arc<PgPool>,
+}
+
+impl Accounts {
+    pub fn new(config: AccountsConfig, avatars: Avatars, pool: Arc<PgPool>) -> Self {
+        Accounts { config, avatars, pool }
+    }
+
+    /// when an account deleted now is purged
+    pub fn deletion_date(&self) -> NaiveDateTime {
+        (Utc::now() + Duration::days(self.config.deletion_grace_days)).naive_utc()
+    }
+
+    pub async fn purge_due(&self) -> Result<usize> {
+        let repository = AccountRepository::new(self.pool.clone());
+        let due = repository.find_due_deletions(Utc::now().naive_utc()).await?;
+
+        for user_id in &due {
+            let avatar_key = repository.purge(*user_id).await?;
+            if let Some(prefix) = avatar_key {
+                if let Err(e) = self.avatars.remove(&prefix).await {
+                    error!("Error removing avatar {} of deleted user. {:?}", prefix, e);
+                }
+            }
+            info!("Deleted user {}", user_id);
+        }
+
+        Ok(due.len())
+    }
+
+    pub async fn run(self) {
+        let mut interval = actix_rt::time::interval(std::time::Duration::from_secs(self.config.purge_interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.purge_due().await {
+                error!("Error purging deleted accounts. {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//module achievements
+// badges for milestones, defined in a json file loaded at startup. Every
+// player card with a new snapshot is evaluated and badges still missing are
+// awarded to its profile. Linking a profile proves no ownership, so users
+// show the badges of the profiles they link, not badges of their own
+use crate::{
+    config::AchievementsConfig,
+    db::achievement::AchievementRepository,
+    models::achievement::{Achievement, Metric},
+    tracker::events::TrackerEvent,
+    ubi::player_card::PlayerCard,
+};
+use color_eyre::Result;
+use eyre::{eyre, WrapErr};
+use sqlx::PgPool;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::broadcast::{self, RecvError};
+use tracing::{debug, error, info};
+
+#[derive(Clone)]
+pub struct Achievements {
+    definitions: Arc<Vec<Achievement>>,
+    pool: Arc<PgPool>,
+}
+
+impl Achievements {
+    pub fn new(config: &AchievementsConfig, pool: Arc<PgPool>) -> Result<Self> {
+        let json = std::fs::read_to_string(&config.definitions)
+            .wrap_err_with(|| format!("Reading achievements from {}", config.definitions))?;
+        let definitions = parse(&json)?;
+        info!("Loaded {} achievements from {}", definitions.len(), config.definitions);
+
+        Ok(Achievements {
+            definitions: Arc::new(definitions),
+            pool,
+        })
+    }
+
+    pub fn definitions(&self) -> &[Achievement] {
+        &self.definitions
+    }
+
+    pub fn find(&self, id: &str) -> Option<&Achievement> {
+        self.definitions.iter().find(|achievement| achievement.id == id)
+    }
+
+    /// awards every achievement the card meets, returns how many badges
+    /// were new
+    pub async fn evaluate(&self, card: &PlayerCard) -> Result<usize> {
+        let repository = AchievementRepository::new(self.pool.clone());
+        let mut metrics = metrics_of(card);
+        // only read from the snapshots when some achievement needs it
+        if self.uses(Metric::WinStreak) {
+            let streak = repository.find_win_streak(&card.profile.profile_id).await?;
+            metrics.insert(Metric::WinStreak, streak as f64);
+        }
+
+        let mut awarded = 0;
+        for achievement in self.definitions.iter().filter(|a| is_met(a, &metrics)) {
+            if repository.award(&card.profile.profile_id, &achievement.id).await? {
+                info!("{} earned {}", card.profile.name_on_platform, achievement.id);
+                awarded += 1;
+            }
+        }
+
+        Ok(awarded)
+    }
+
+    fn uses(&self, metric: Metric) -> bool {
+        self.definitions
+            .iter()
+            .any(|achievement| achievement.criteria.iter().any(|c| c.metric == metric))
+    }
+
+    /// evaluates the cards published by the tracker
+    pub async fn listen(self, mut events: broadcast::Receiver<TrackerEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(TrackerEvent::Card(card)) => match self.evaluate(&card).await {
+                    Ok(0) => {}
+                    Ok(awarded) => debug!("Awarded {} badges for {}", awarded, card.profile.name_on_platform),
+                    Err(e) => error!("Error evaluating achievements. {:?}", e),
+                },
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    error!("Achievements fell behind, {} tracker events dropped.", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// definitions file, ids have to be unique and made of a-z, 0-9 and _
+pub fn parse(json: &str) -> Result<Vec<Achievement>> {
+    let definitions: Vec<Achievement> = serde_json::from_str(json).wrap_err("Invalid achievements file")?;
+
+    let mut ids = HashSet::new();
+    for achievement in &definitions {
+        let valid_id = !achievement.id.is_empty()
+            && achievement
+                .id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_id {
+            return Err(eyre!("Invalid achievement id {:?}", achievement.id));
+        }
+        if !ids.insert(achievement.id.as_str()) {
+            return Err(eyre!("Duplicate achievement id {:?}", achievement.id));
+        }
+        if achievement.criteria.is_empty() {
+            return Err(eyre!("Achievement {:?} has no criteria", achievement.id));
+        }
+    }
+
+    Ok(definitions)
+}
+
+// a metric the card does not have (a part failed to load) fails its criteria
+fn metrics_of(card: &PlayerCard) -> HashMap<Metric, f64> {
+    let mut metrics = HashMap::new();
+
+    if !card.ranks.is_empty() {
+        let max_rank = card.ranks.values().map(|stats| stats.max_rank).max().unwrap_or_default();
+        let max_mmr = card.ranks.values().map(|stats| stats.max_mmr).fold(f32::MIN, f32::max);
+        metrics.insert(Metric::MaxRank, max_rank as f64);
+        metrics.insert(Metric::MaxMmr, max_mmr as f64);
+    }
+    if let Some(level) = card.level {
+        metrics.insert(Metric::Level, level as f64);
+    }
+    // career totals from the populations statistics
+    if let Some(summary) = &card.summary {
+        metrics.insert(Metric::RankedKills, summary.ranked.kills as f64);
+        metrics.insert(Metric::RankedWins, summary.ranked.wins as f64);
+        metrics.insert(Metric::RankedKd, summary.ranked.kd as f64);
+        metrics.insert(Metric::CasualKills, summary.casual.kills as f64);
+        metrics.insert(Metric::CasualWins, summary.casual.wins as f64);
+        let time_played_secs = summary.ranked.time_played_secs as f64 + summary.casual.time_played_secs as f64;
+        metrics.insert(Metric::HoursPlayed, time_played_secs / 3600.0);
+    }
+
+    metrics
+}
+
+fn is_met(achievement: &Achievement, metrics: &HashMap<Metric, f64>) -> bool {
+    achievement
+        .criteria
+        .iter()
+        .all(|criterion| matches!(metrics.get(&criterion.metric), Some(value) if *value >= criterion.min))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn definition(id: &str, criteria: serde_json::Value) -> serde_json::Value {
+        json!({ "id": id, "name": "Name", "description": "Description", "criteria": criteria })
+    }
+
+    fn parse_error(definitions: serde_json::Value) -> String {
+        parse(&definitions.to_string()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn the_shipped_definitions_parse() {
+        let definitions = parse(include_str!("../../assets/achievements/definitions.json")).unwrap();
+        assert!(definitions.iter().any(|achievement| achievement.id == "first_champion"));
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        let criteria = json!([{ "metric": "level", "min": 100 }]);
+
+        assert_eq!(
+            parse_error(json!([definition("Level-100", criteria.clone())])),
+            "Invalid achievement id \"Level-100\""
+        );
+        assert_eq!(
+            parse_error(json!([definition("", criteria.clone())])),
+            "Invalid achievement id \"\""
+        );
+        assert_eq!(
+            parse_error(json!([definition("level_100", criteria.clone()), definition("level_100", criteria.clone())])),
+            "Duplicate achievement id \"level_100\""
+        );
+        assert_eq!(
+            parse_error(json!([definition("level_100", json!([]))])),
+            "Achievement \"level_100\" has no criteria"
+        );
+        assert_eq!(
+            parse_error(json!([definition("level_100", json!([{ "metric": "headshots", "min": 1 }]))])),
+            "Invalid achievements file"
+        );
+    }
+
+    #[test]
+    fn every_criterion_has_to_be_met() {
+        let achievement = parse(
+            &json!([definition(
+                "veteran_champion",
+                json!([{ "metric": "max_rank", "min": 23 }, { "metric": "hours_played", "min": 1000 }])
+            )])
+            .to_string(),
+        )
+        .unwrap()
+        .remove(0);
+        let metrics = |values: &[(Metric, f64)]| values.iter().cloned().collect::<HashMap<_, _>>();
+
+        assert!(is_met(&achievement, &metrics(&[(Metric::MaxRank, 23.0), (Metric::HoursPlayed, 1000.0)])));
+        assert!(is_met(&achievement, &metrics(&[(Metric::MaxRank, 23.0), (Metric::HoursPlayed, 1500.5)])));
+        assert!(!is_met(&achievement, &metrics(&[(Metric::MaxRank, 22.0), (Metric::HoursPlayed, 1500.0)])));
+        assert!(!is_met(&achievement, &metrics(&[(Metric::MaxRank, 23.0), (Metric::HoursPlayed, 999.9)])));
+        // a metric the card could not load fails its criterion
+        assert!(!is_met(&achievement, &metrics(&[(Metric::MaxRank, 23.0)])));
+    }
+}
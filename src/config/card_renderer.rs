@@ -0,0 +1,271 @@
+//card renderer
+// fills the bundled svg template with a player snapshot and rasterizes it
+// to png with the bundled fonts. Renders are cached per snapshot and format
+
+use super::CardConfig;
+use crate::errors::AppError;
+use crate::handlers::AppResult;
+use crate::models::player_snapshot::PlayerSnapshot;
+use crate::ubi::player_card::{rank_name, ratio};
+use resvg::{tiny_skia, usvg};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+use uuid::Uuid;
+
+const TEMPLATE: &str = include_str!("../../assets/templates/player_card.svg");
+const FONT_REGULAR: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+const FONT_BOLD: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans-Bold.ttf");
+const FONT_FAMILY: &str = "DejaVu Sans";
+
+// trend polyline box inside the template
+const TREND_X: (f32, f32) = (300.0, 556.0);
+const TREND_Y: (f32, f32) = (150.0, 202.0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CardFormat {
+    Png,
+    Svg,
+}
+
+impl CardFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "png" => Some(CardFormat::Png),
+            "svg" => Some(CardFormat::Svg),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            CardFormat::Png => "image/png",
+            CardFormat::Svg => "image/svg+xml",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            CardFormat::Png => "png",
+            CardFormat::Svg => "svg",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<(Uuid, CardFormat), Arc<Vec<u8>>>,
+    order: VecDeque<(Uuid, CardFormat)>,
+}
+
+/// CardRenderer is shared by all workers, clones share fonts and cache
+#[derive(Clone)]
+pub struct CardRenderer {
+    config: CardConfig,
+    fontdb: Arc<usvg::fontdb::Database>,
+    cache: Arc<Mutex<Cache>>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn accent(rank: i32) -> &'static str {
+    match rank {
+        1..=5 => "#b4543c",   // copper
+        6..=10 => "#c08a5a",  // bronze
+        11..=15 => "#b8c2cc", // silver
+        16..=18 => "#e8c547", // gold
+        19..=21 => "#4fc3c8", // platinum
+        22 => "#b98cf0",      // diamond
+        23 => "#ff3d6e",      // champion
+        _ => "#8b93a7",
+    }
+}
+
+// polyline points scaled into the trend box, flat line without history
+fn trend_points(trend: &[f32]) -> String {
+    if trend.len() < 2 {
+        let y = (TREND_Y.0 + TREND_Y.1) / 2.0;
+        return format!("{},{} {},{}", TREND_X.0, y, TREND_X.1, y);
+    }
+
+    let min = trend.iter().cloned().fold(f32::MAX, f32::min);
+    let max = trend.iter().cloned().fold(f32::MIN, f32::max);
+    let span = (max - min).max(1.0);
+    let step = (TREND_X.1 - TREND_X.0) / (trend.len() - 1) as f32;
+
+    trend
+        .iter()
+        .enumerate()
+        .map(|(index, mmr)| {
+            let x = TREND_X.0 + step * index as f32;
+            let y = TREND_Y.1 - (mmr - min) / span * (TREND_Y.1 - TREND_Y.0);
+            format!("{:.1},{:.1}", x, y)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn trend_delta(trend: &[f32]) -> String {
+    match (trend.first(), trend.last()) {
+        (Some(first), Some(last)) if trend.len() > 1 => {
+            let delta = (last - first).round() as i32;
+            if delta > 0 {
+                format!("+{}", delta)
+            } else {
+                delta.to_string()
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+// replaces every {{key}} of `template` in one pass, so placeholders inside
+// the values (a player can be named "{{rank}}") stay as they are
+fn fill(template: &str, values: &HashMap<&str, String>) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        match placeholder.find("}}") {
+            Some(end) => {
+                let key = &placeholder[2..end];
+                match values.get(key) {
+                    Some(value) => filled.push_str(value),
+                    None => filled.push_str(&placeholder[..end + 2]),
+                }
+                rest = &placeholder[end + 2..];
+            }
+            None => {
+                rest = placeholder;
+                break;
+            }
+        }
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+impl CardRenderer {
+    pub fn new(config: CardConfig) -> Self {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_font_data(FONT_REGULAR.to_vec());
+        fontdb.load_font_data(FONT_BOLD.to_vec());
+
+        CardRenderer {
+            config,
+            fontdb: Arc::new(fontdb),
+            cache: Arc::new(Mutex::new(Cache::default())),
+        }
+    }
+
+    pub fn max_age_secs(&self) -> i64 {
+        self.config.max_age_secs
+    }
+
+    pub fn trend_points(&self) -> i64 {
+        self.config.trend_points
+    }
+
+    pub fn svg(&self, snapshot: &PlayerSnapshot, trend: &[f32]) -> String {
+        let win_rate = 100.0 * ratio(snapshot.wins, snapshot.wins + snapshot.losses).min(1.0);
+        let level = snapshot
+            .level
+            .map(|level| level.to_string())
+            .unwrap_or_else(|| "?".to_string());
+
+        let values: HashMap<&str, String> = vec![
+            ("name", escape(&snapshot.name_on_platform)),
+            ("platform", escape(&snapshot.platform.to_uppercase())),
+            ("region", escape(&snapshot.region.to_uppercase())),
+            ("level", level),
+            ("rank", rank_name(snapshot.rank).to_string()),
+            ("mmr", format!("{:.0}", snapshot.mmr)),
+            ("kd", format!("{:.2}", ratio(snapshot.kills, snapshot.deaths))),
+            ("win_rate", format!("{:.1}", win_rate)),
+            ("wins", snapshot.wins.to_string()),
+            ("losses", snapshot.losses.to_string()),
+            ("trend_points", trend_points(trend)),
+            ("trend_delta", trend_delta(trend)),
+            ("accent", accent(snapshot.rank).to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        fill(TEMPLATE, &values)
+    }
+
+    fn png(&self, svg: &str) -> AppResult<Vec<u8>> {
+        let options = usvg::Options {
+            font_family: FONT_FAMILY.to_string(),
+            fontdb: self.fontdb.clone(),
+            ..usvg::Options::default()
+        };
+        let tree = usvg::Tree::from_str(svg, &options).map_err(|op| {
+            debug!("Error parsing card svg. {:?}", op);
+            AppError::INTERNAL_ERROR.default()
+        })?;
+
+        let size = tree.size().to_int_size();
+        let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+            .ok_or_else(|| AppError::INTERNAL_ERROR.default())?;
+        resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+        pixmap.encode_png().map_err(|op| {
+            debug!("Error encoding card png. {:?}", op);
+            AppError::INTERNAL_ERROR.default()
+        })
+    }
+
+    /// rendered card for a snapshot, served from cache when possible.
+    /// Blocking, call it from `web::block`
+    pub fn render(&self, snapshot: &PlayerSnapshot, trend: &[f32], format: CardFormat) -> AppResult<Arc<Vec<u8>>> {
+        let key = (snapshot.id, format);
+        if let Some(bytes) = self.lock().entries.get(&key) {
+            return Ok(bytes.clone());
+        }
+
+        let svg = self.svg(snapshot, trend);
+        let bytes = Arc::new(match format {
+            CardFormat::Svg => svg.into_bytes(),
+            CardFormat::Png => self.png(&svg)?,
+        });
+
+        let mut cache = self.lock();
+        if cache.entries.insert(key, bytes.clone()).is_none() {
+            cache.order.push_back(key);
+        }
+        while cache.order.len() > self.config.cache_entries {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.entries.remove(&oldest);
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache.lock().expect("card cache lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placeholders_inside_values_are_not_filled() {
+        let values: HashMap<&str, String> = vec![("name", "{{rank}}".to_string()), ("rank", "Champion".to_string())]
+            .into_iter()
+            .collect();
+
+        assert_eq!(fill("{{name}} is {{rank}}", &values), "{{rank}} is Champion");
+        assert_eq!(fill("{{unknown}} {{name", &values), "{{unknown}} {{name");
+    }
+}
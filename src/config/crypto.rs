@@ -8,33 +8,75 @@ use chrono::{Duration, Utc};
 use color_eyre::Result;
 use eyre::eyre;
 use futures::compat::Future01CompatExt;
+use rand::Rng;
+use sha2::{Digest, Sha256};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, TokenData, Validation};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tracing::instrument;
 use uuid::Uuid;
 
+/// lifetime of a login, shared by the jwt and its session row
+pub const TOKEN_TTL_HOURS: i64 = 24;
+
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
     pub exp: i64,
+    pub jti: Uuid, // sessions.id, lets us revoke tokens
     // aud
     // role
     // perms
 }
 
+/// short lived proof that the password step of a 2fa login succeeded,
+/// it can not be decoded as Claims (no jti) and vice versa (no purpose)
+#[derive(Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub sub: Uuid,
+    pub exp: i64,
+    pub purpose: String,
+    pub jti: String, // challenges are single use, see used_challenges
+}
+
+const CHALLENGE_PURPOSE: &str = "2fa";
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+
 #[derive(Serialize)]
 pub struct Auth {
     pub token: String,
 }
 
+#[derive(Serialize)]
+pub struct TwoFactorChallenge {
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct CryptoService {
     pub key: Arc<String>,
     pub jwt_secret: Arc<String>,
+    // hash verified against when a login names an unknown user,
+    // so both paths cost the same
+    pub dummy_hash: Arc<String>,
 }
 
 impl CryptoService {
+    pub fn new(key: String, jwt_secret: String) -> Result<Self> {
+        let dummy_hash = Hasher::default()
+            .with_secret_key(&key)
+            .with_password("zbot-dummy-password")
+            .hash()
+            .map_err(|err| eyre!("Hashing error: {:?}", err))?;
+
+        Ok(CryptoService {
+            key: Arc::new(key),
+            jwt_secret: Arc::new(jwt_secret),
+            dummy_hash: Arc::new(dummy_hash),
+        })
+    }
+
     #[instrument(skip(self, password))]
     pub async fn hash_password(&self, password: String) -> Result<String> {
         Hasher::default()
@@ -59,15 +101,16 @@ impl CryptoService {
     }
 
     #[instrument(skip(self))]
-    pub async fn generate_jwt(&self, user_id: Uuid) -> Result<String> {
+    pub async fn generate_jwt(&self, user_id: Uuid, session_id: Uuid) -> Result<String> {
         let jwt_key = self.jwt_secret.clone();
         block(move || {
             let headers = Header::default();
             let encoding_key = EncodingKey::from_secret(jwt_key.as_bytes());
-            let now = Utc::now() + Duration::days(1); // Expires in 1 day
+            let now = Utc::now() + Duration::hours(TOKEN_TTL_HOURS);
             let claims = Claims {
                 sub: user_id,
                 exp: now.timestamp(),
+                jti: session_id,
             };
             encode(&headers, &claims, &encoding_key)
         })
@@ -86,4 +129,50 @@ impl CryptoService {
         .await
         .map_err(|err| eyre!("Verifying jwt token: {}", err))
     }
+
+    #[instrument(skip(self))]
+    pub async fn generate_challenge(&self, user_id: Uuid) -> Result<String> {
+        let jwt_key = self.jwt_secret.clone();
+        let jti = self.random_token();
+        block(move || {
+            let encoding_key = EncodingKey::from_secret(jwt_key.as_bytes());
+            let claims = ChallengeClaims {
+                sub: user_id,
+                exp: (Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES)).timestamp(),
+                purpose: CHALLENGE_PURPOSE.to_string(),
+                jti,
+            };
+            encode(&Header::default(), &claims, &encoding_key)
+        })
+        .await
+        .map_err(|err| eyre!("Creating challenge token: {}", err))
+    }
+
+    /// user id of a valid, unexpired 2fa challenge
+    #[instrument(skip(self, token))]
+    pub async fn verify_challenge(&self, token: String) -> Result<ChallengeClaims> {
+        let jwt_key = self.jwt_secret.clone();
+        let data = block(move || {
+            let decoding_key = DecodingKey::from_secret(jwt_key.as_bytes());
+            decode::<ChallengeClaims>(&token, &decoding_key, &Validation::default())
+        })
+        .await
+        .map_err(|err| eyre!("Verifying challenge token: {}", err))?;
+
+        if data.claims.purpose != CHALLENGE_PURPOSE {
+            return Err(eyre!("Unexpected challenge purpose {}", data.claims.purpose));
+        }
+        Ok(data.claims)
+    }
+
+    /// random url safe token, for confirmation links etc.
+    pub fn random_token(&self) -> String {
+        let bytes: [u8; 32] = rand::thread_rng().gen();
+        hex::encode(bytes)
+    }
+
+    /// tokens are stored as sha256, they carry enough entropy to not need argon
+    pub fn hash_token(&self, token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
 }
@@ -0,0 +1,194 @@
+//login guard
+// tracks failed logins per username and per client ip,
+// applies exponential backoff and temporary lockouts
+
+use super::LoginConfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct FailedAttempts {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// LoginGuard is shared by all workers, clones point to the same counters
+#[derive(Debug, Clone)]
+pub struct LoginGuard {
+    config: LoginConfig,
+    attempts: Arc<Mutex<HashMap<String, FailedAttempts>>>,
+}
+
+fn username_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+impl LoginGuard {
+    pub fn new(config: LoginConfig) -> Self {
+        LoginGuard {
+            config,
+            attempts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// remaining lockout for this username or ip, if any
+    pub fn locked_for(&self, username: &str, ip: &str) -> Option<Duration> {
+        let now = Instant::now();
+        let attempts = self.attempts.lock().expect("login guard lock poisoned");
+
+        [username_key(username), ip_key(ip)]
+            .iter()
+            .filter_map(|key| attempts.get(key))
+            .filter_map(|entry| entry.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max()
+    }
+
+    /// counts a failed login, returns the lockout it triggered (if any)
+    pub fn record_failure(&self, username: &str, ip: &str) -> Option<Duration> {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().expect("login guard lock poisoned");
+        self.prune(&mut attempts, now);
+
+        let user_lockout = self.register(
+            &mut attempts,
+            username_key(username),
+            self.config.max_failures,
+            now,
+        );
+        let ip_lockout = self.register(&mut attempts, ip_key(ip), self.config.ip_max_failures, now);
+
+        user_lockout.max(ip_lockout)
+    }
+
+    /// successful login clears the username counter, the ip counter is kept
+    /// so one valid account can't be used to reset a credential stuffing run
+    pub fn record_success(&self, username: &str) {
+        let mut attempts = self.attempts.lock().expect("login guard lock poisoned");
+        attempts.remove(&username_key(username));
+    }
+
+    fn register(
+        &self,
+        attempts: &mut HashMap<String, FailedAttempts>,
+        key: String,
+        threshold: u32,
+        now: Instant,
+    ) -> Option<Duration> {
+        let reset_after = Duration::from_secs(self.config.reset_after_secs);
+        let entry = attempts.entry(key).or_insert(FailedAttempts {
+            count: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+
+        if now.duration_since(entry.last_failure) > reset_after {
+            entry.count = 0;
+        }
+        entry.count += 1;
+        entry.last_failure = now;
+
+        if entry.count < threshold.max(1) {
+            return None;
+        }
+
+        let lockout = self.lockout_for(entry.count - threshold.max(1));
+        entry.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+
+    // base * 2^excess, capped at max_lockout_secs
+    fn lockout_for(&self, excess: u32) -> Duration {
+        let factor = 1u64 << excess.min(32);
+        let secs = self
+            .config
+            .base_lockout_secs
+            .saturating_mul(factor)
+            .min(self.config.max_lockout_secs);
+
+        Duration::from_secs(secs)
+    }
+
+    fn prune(&self, attempts: &mut HashMap<String, FailedAttempts>, now: Instant) {
+        let reset_after = Duration::from_secs(self.config.reset_after_secs);
+        attempts.retain(|_, entry| {
+            matches!(entry.locked_until, Some(until) if until > now)
+                || now.duration_since(entry.last_failure) <= reset_after
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(max_failures: u32, ip_max_failures: u32) -> LoginGuard {
+        LoginGuard::new(LoginConfig {
+            max_failures,
+            ip_max_failures,
+            base_lockout_secs: 30,
+            max_lockout_secs: 100,
+            reset_after_secs: 900,
+        })
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let guard = guard(3, 100);
+        assert_eq!(guard.record_failure("player", "10.0.0.1"), None);
+        assert_eq!(guard.record_failure("player", "10.0.0.1"), None);
+        assert_eq!(guard.locked_for("player", "10.0.0.2"), None);
+
+        assert_eq!(guard.record_failure("player", "10.0.0.1"), Some(Duration::from_secs(30)));
+        let locked_for = guard.locked_for("PLAYER", "10.0.0.2").unwrap();
+        assert!(locked_for <= Duration::from_secs(30) && locked_for > Duration::from_secs(29));
+
+        assert_eq!(guard.record_failure("player", "10.0.0.1"), Some(Duration::from_secs(60)));
+        assert_eq!(guard.record_failure("Player", "10.0.0.1"), Some(Duration::from_secs(100)));
+        assert_eq!(guard.lockout_for(64), Duration::from_secs(100));
+
+        assert_eq!(guard.locked_for("someone", "10.0.0.2"), None);
+    }
+
+    #[test]
+    fn an_ip_is_locked_out_across_usernames() {
+        let guard = guard(100, 3);
+        assert_eq!(guard.record_failure("a", "10.0.0.1"), None);
+        assert_eq!(guard.record_failure("b", "10.0.0.1"), None);
+        assert_eq!(guard.record_failure("c", "10.0.0.1"), Some(Duration::from_secs(30)));
+
+        assert!(guard.locked_for("d", "10.0.0.1").is_some());
+        assert_eq!(guard.locked_for("d", "10.0.0.2"), None);
+    }
+
+    #[test]
+    fn success_clears_the_username_but_not_the_ip() {
+        let guard = guard(2, 3);
+        guard.record_failure("player", "10.0.0.1");
+        guard.record_failure("other", "10.0.0.1");
+        guard.record_success("player");
+
+        // a fresh count for the username, the third failure of the ip
+        assert_eq!(guard.record_failure("player", "10.0.0.1"), Some(Duration::from_secs(30)));
+        assert_eq!(guard.locked_for("player", "10.0.0.2"), None);
+        assert!(guard.locked_for("anyone", "10.0.0.1").is_some());
+    }
+
+    #[test]
+    fn old_failures_are_forgotten() {
+        let guard = LoginGuard::new(LoginConfig {
+            reset_after_secs: 0,
+            ..guard(2, 2).config
+        });
+        assert_eq!(guard.record_failure("player", "10.0.0.1"), None);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(guard.record_failure("player", "10.0.0.1"), None);
+    }
+}
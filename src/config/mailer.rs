@@ -0,0 +1,27 @@
+//mailer
+// outgoing account emails (confirmation links, notices)
+// there is no smtp transport yet, messages are written to the log
+
+use tracing::{info, instrument};
+
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    from: String,
+    pub public_url: String,
+}
+
+impl Mailer {
+    pub fn new(from: String, public_url: String) -> Self {
+        Mailer { from, public_url }
+    }
+
+    /// absolute link to one of our routes, path starts with "/"
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.public_url.trim_end_matches('/'), path)
+    }
+
+    #[instrument(skip(self, body))]
+    pub async fn send(&self, to: &str, subject: &str, body: &str) {
+        info!("MAIL from: {} to: {} subject: {}\n{}", self.from, to, subject, body);
+    }
+}
@@ -1,13 +1,35 @@
 //module config
+pub mod card_renderer;
 pub mod crypto;
+pub mod login_guard;
+pub mod mailer;
+pub mod rate_limit;
+pub mod totp;
+pub mod trusted_proxies;
 
 use color_eyre::Result;
+use crate::accounts::Accounts;
+use crate::achievements::Achievements;
+use crate::discord::DiscordBot;
+use crate::notifications::Notifier;
+use crate::oauth::OAuthService;
+use crate::realtime::Realtime;
+use crate::storage::{avatar::Avatars, local::LocalStorage, s3::S3Storage, Storage};
+use crate::tracker::Tracker;
+use crate::ubi::ubi_api::UbiApi;
+use crate::webhooks::WebhookDispatcher;
+use card_renderer::CardRenderer;
 use crypto::CryptoService;
 use dotenv::dotenv;
-use eyre::WrapErr;
+use login_guard::LoginGuard;
+use mailer::Mailer;
+use rate_limit::RateLimiter;
+use trusted_proxies::TrustedProxies;
+use eyre::{eyre, WrapErr};
 use serde::Deserialize;
 use sqlx::postgres::PgPool;
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{info, instrument}; //macro
 use tracing_subscriber::EnvFilter;
 
@@ -17,6 +39,9 @@ pub struct AuthConfig {
     pub password: String,
 }
 
+// spaces ids and sandboxes are still required in env but not read,
+// UbiApi::get_ubi_spaces_url has the urls hard-coded
+#[allow(dead_code)]
 #[derive(Deserialize, Debug, Clone)]
 pub struct UbiConfig {
     pub appid: String,                // ubi-appid
@@ -27,6 +52,426 @@ pub struct UbiConfig {
     pub sandbox_pc: String,
     pub sandbox_xbox: String,
     pub sandbox_ps4: String,
+    #[serde(default)]
+    pub scheduler: UbiSchedulerConfig,
+    #[serde(default)]
+    pub pool: UbiPoolConfig,
+}
+
+/// service account pool. `AUTH.EMAIL` is always part of it, ubi_users rows
+/// added through POST /ubi/accounts join at startup.
+/// Env: UBI.POOL.STRATEGY (round_robin | least_loaded), UBI.POOL.QUARANTINE_SECS ..
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UbiPoolConfig {
+    pub strategy: String,
+    pub quarantine_secs: u64,
+    pub max_quarantine_secs: u64,
+    pub refresh_before_secs: i64, // renew sessions this close to expiry
+}
+
+impl Default for UbiPoolConfig {
+    fn default() -> Self {
+        UbiPoolConfig {
+            strategy: "round_robin".to_string(),
+            quarantine_secs: 60,
+            max_quarantine_secs: 3600,
+            refresh_before_secs: 600,
+        }
+    }
+}
+
+/// requests per minute allowed for each Ubisoft endpoint
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EndpointBudgets {
+    pub sessions: u32,
+    pub profiles: u32,
+    pub rank_stats: u32,
+    pub player_xp: u32,
+    pub statistics: u32,
+}
+
+impl Default for EndpointBudgets {
+    fn default() -> Self {
+        EndpointBudgets {
+            sessions: 10,
+            profiles: 120,
+            rank_stats: 120,
+            player_xp: 120,
+            statistics: 120,
+        }
+    }
+}
+
+/// outbound limits towards Ubisoft.
+/// Env: UBI.SCHEDULER.REQUESTS_PER_SECOND, UBI.SCHEDULER.ENDPOINT_BUDGETS.PROFILES ..
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UbiSchedulerConfig {
+    pub requests_per_second: f64,
+    pub burst: u32,
+    pub endpoint_budgets: EndpointBudgets,
+    pub interactive_queue: usize,
+    pub interactive_timeout_ms: u64,
+    pub background_queue: usize,
+    pub background_timeout_ms: u64,
+}
+
+impl Default for UbiSchedulerConfig {
+    fn default() -> Self {
+        UbiSchedulerConfig {
+            requests_per_second: 5.0,
+            burst: 10,
+            endpoint_budgets: EndpointBudgets::default(),
+            interactive_queue: 50,
+            interactive_timeout_ms: 5_000,
+            background_queue: 500,
+            background_timeout_ms: 120_000,
+        }
+    }
+}
+
+/// Brute-force protection for `/auth`.
+/// Env: LOGIN.MAX_FAILURES, LOGIN.IP_MAX_FAILURES, LOGIN.BASE_LOCKOUT_SECS ..
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LoginConfig {
+    pub max_failures: u32,       // failures per username before lockout
+    pub ip_max_failures: u32,    // failures per client ip before lockout
+    pub base_lockout_secs: u64,  // first lockout, doubles on every further failure
+    pub max_lockout_secs: u64,   // upper bound for the lockout
+    pub reset_after_secs: u64,   // failures older than this are forgotten
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        LoginConfig {
+            max_failures: 5,
+            ip_max_failures: 20,
+            base_lockout_secs: 30,
+            max_lockout_secs: 3600,
+            reset_after_secs: 900,
+        }
+    }
+}
+
+/// reverse proxies allowed to report the client ip in X-Forwarded-For,
+/// without any the socket peer is the client.
+/// Env: PROXY.TRUSTED (comma separated ips or networks, e.g. 127.0.0.1,10.0.0.0/8)
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub trusted: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MailConfig {
+    pub from: String,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            from: "zbot <no-reply@zbot.local>".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RoleLimit {
+    pub capacity: u32,          // burst size
+    pub refill_per_minute: u32, // sustained rate
+}
+
+/// quotas for the /ubi routes and Discord commands, per role and per api key.
+/// Env: RATE_LIMIT.USER.CAPACITY, RATE_LIMIT.ADMIN.REFILL_PER_MINUTE, RATE_LIMIT.PERSIST ..
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub user: RoleLimit,
+    pub admin: RoleLimit,
+    pub api_key: RoleLimit,
+    pub discord: RoleLimit, // Discord users without a linked zbot account
+    pub persist: bool, // keep buckets in postgres across restarts
+    pub persist_interval_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            user: RoleLimit {
+                capacity: 30,
+                refill_per_minute: 30,
+            },
+            admin: RoleLimit {
+                capacity: 300,
+                refill_per_minute: 300,
+            },
+            api_key: RoleLimit {
+                capacity: 60,
+                refill_per_minute: 60,
+            },
+            discord: RoleLimit {
+                capacity: 10,
+                refill_per_minute: 10,
+            },
+            persist: false,
+            persist_interval_secs: 60,
+        }
+    }
+}
+
+/// OAUTH.DISCORD.CLIENT_ID, OAUTH.GOOGLE.CLIENT_SECRET, OAUTH.OIDC.TOKEN_URL ..
+#[derive(Deserialize, Debug, Clone)]
+pub struct OAuthProviderConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    // defaults exist for discord and google, required for the generic oidc provider
+    pub authorize_url: Option<String>,
+    pub token_url: Option<String>,
+    pub userinfo_url: Option<String>,
+    pub scopes: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct OAuthConfig {
+    pub discord: Option<OAuthProviderConfig>,
+    pub google: Option<OAuthProviderConfig>,
+    pub oidc: Option<OAuthProviderConfig>,
+}
+
+/// Firebase service account, the fields of its json key file
+#[derive(Deserialize, Debug, Clone)]
+pub struct FcmConfig {
+    pub project_id: String,
+    pub client_email: String,
+    pub private_key: String, // pem, "\n" escapes allowed
+    #[serde(default = "default_fcm_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_fcm_token_uri() -> String {
+    "https://oauth2.googleapis.com/token".to_string()
+}
+
+/// APNs auth key (.p8) for token based connections
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApnsConfig {
+    pub team_id: String,
+    pub key_id: String,
+    pub private_key: String, // pem, "\n" escapes allowed
+    pub topic: String, // the app bundle id
+    #[serde(default)]
+    pub sandbox: bool,
+}
+
+/// push notification providers. An unconfigured provider is skipped, its
+/// devices get no pushes, unless NOTIFICATIONS.RECORD (tests and local
+/// development only) records them instead.
+/// Env: NOTIFICATIONS.FCM.PROJECT_ID, NOTIFICATIONS.APNS.TEAM_ID, NOTIFICATIONS.RECORD ..
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    pub fcm: Option<FcmConfig>,
+    pub apns: Option<ApnsConfig>,
+    pub record: bool, // unconfigured providers keep pushes in memory instead
+}
+
+/// Discord application for slash command interactions.
+/// Env: DISCORD.PUBLIC_KEY (hex, from the developer portal), DISCORD.API_BASE
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DiscordConfig {
+    pub public_key: Option<String>,
+    pub api_base: String,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        DiscordConfig {
+            public_key: None,
+            api_base: "https://discord.com/api/v10".to_string(),
+        }
+    }
+}
+
+/// background refresh of linked profiles.
+/// Env: TRACKER.ENABLED, TRACKER.INTERVAL_SECS
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TrackerConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    pub event_buffer: usize, // events a slow subscriber may fall behind
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        TrackerConfig {
+            enabled: true,
+            interval_secs: 900,
+            event_buffer: 1024,
+        }
+    }
+}
+
+/// websocket / sse pushes.
+/// Env: REALTIME.MAX_CONNECTIONS_PER_USER, REALTIME.MAX_SUBSCRIPTIONS ..
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RealtimeConfig {
+    pub heartbeat_secs: u64,
+    pub client_timeout_secs: u64, // websocket clients silent for longer are dropped
+    pub max_connections_per_user: usize,
+    pub max_subscriptions: usize, // profile ids per connection
+    pub buffer: usize, // queued pushes per connection before dropping
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        RealtimeConfig {
+            heartbeat_secs: 15,
+            client_timeout_secs: 45,
+            max_connections_per_user: 3,
+            max_subscriptions: 25,
+            buffer: 64,
+        }
+    }
+}
+
+/// outbound webhooks.
+/// Env: WEBHOOKS.ALLOW_HTTP, WEBHOOKS.ALLOW_PRIVATE_HOSTS (both local testing only),
+/// WEBHOOKS.MAX_ATTEMPTS ..
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub allow_http: bool,
+    pub allow_private_hosts: bool, // loopback and internal networks
+    pub max_per_user: i64,
+    pub max_attempts: i32,
+    pub base_backoff_secs: i64,
+    pub max_backoff_secs: i64,
+    pub poll_interval_secs: u64,
+    pub batch_size: i64,
+    pub timeout_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            allow_http: false,
+            allow_private_hosts: false,
+            max_per_user: 10,
+            max_attempts: 6,
+            base_backoff_secs: 30,
+            max_backoff_secs: 3600,
+            poll_interval_secs: 10,
+            batch_size: 50,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// S3 compatible bucket, e.g. a local MinIO at http://127.0.0.1:9000
+#[derive(Deserialize, Debug, Clone)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    #[serde(default = "default_true")]
+    pub path_style: bool, // false for bucket.endpoint urls
+    pub public_url: Option<String>, // base url objects are served from, when not the bucket itself
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// uploaded avatars. STORAGE.BACKEND is "local" or "s3".
+/// Env: STORAGE.LOCAL_DIR, STORAGE.MAX_UPLOAD_BYTES, STORAGE.S3.ENDPOINT, STORAGE.S3.BUCKET ..
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub backend: String,
+    pub local_dir: String,
+    pub max_upload_bytes: usize,
+    pub max_dimension: u32, // width and height limit of uploaded images
+    pub s3: Option<S3Config>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: "local".to_string(),
+            local_dir: "./uploads".to_string(),
+            max_upload_bytes: 5 * 1024 * 1024,
+            max_dimension: 4096,
+            s3: None,
+        }
+    }
+}
+
+/// self-service account deletion.
+/// Env: ACCOUNTS.DELETION_GRACE_DAYS, ACCOUNTS.PURGE_INTERVAL_SECS
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AccountsConfig {
+    pub deletion_grace_days: i64, // the account can be restored until then
+    pub purge_interval_secs: u64,
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig {
+            deletion_grace_days: 30,
+            purge_interval_secs: 3600,
+        }
+    }
+}
+
+/// milestone badges.
+/// Env: ACHIEVEMENTS.DEFINITIONS (path of the json definitions file)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AchievementsConfig {
+    pub definitions: String,
+}
+
+impl Default for AchievementsConfig {
+    fn default() -> Self {
+        AchievementsConfig {
+            definitions: "./assets/achievements/definitions.json".to_string(),
+        }
+    }
+}
+
+/// player card images.
+/// Env: CARD.MAX_AGE_SECS, CARD.CACHE_ENTRIES
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CardConfig {
+    pub max_age_secs: i64, // snapshots younger than this are rendered without asking Ubisoft
+    pub cache_entries: usize,
+    pub trend_points: i64,
+}
+
+impl Default for CardConfig {
+    fn default() -> Self {
+        CardConfig {
+            max_age_secs: 300,
+            cache_entries: 256,
+            trend_points: 10,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -38,6 +483,35 @@ pub struct Config {
     pub port: i32,
     pub secret_key: String,
     pub jwt_secret: String,
+    #[serde(default)]
+    pub login: LoginConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    pub public_url: Option<String>, // base url used in emailed links
+    #[serde(default)]
+    pub mail: MailConfig,
+    #[serde(default)]
+    pub oauth: OAuthConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub card: CardConfig,
+    #[serde(default)]
+    pub discord: DiscordConfig,
+    #[serde(default)]
+    pub tracker: TrackerConfig,
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+    #[serde(default)]
+    pub realtime: RealtimeConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub accounts: AccountsConfig,
+    #[serde(default)]
+    pub achievements: AchievementsConfig,
 }
 
 impl Config {
@@ -67,10 +541,79 @@ impl Config {
             .context("Creating database connection pool!") //context converts Result error to eyre Report
     }
 
-    pub fn crypto_service(&self) -> CryptoService {
-        CryptoService {
-            key: Arc::new(self.secret_key.clone()),
-            jwt_secret: Arc::new(self.jwt_secret.clone()),
-        }
+    pub fn crypto_service(&self) -> Result<CryptoService> {
+        CryptoService::new(self.secret_key.clone(), self.jwt_secret.clone())
+    }
+
+    /// externally reachable base url, used for links and oauth redirects
+    pub fn public_url(&self) -> String {
+        self.public_url
+            .clone()
+            .unwrap_or_else(|| format!("http://{}:{}", self.host, self.port))
+    }
+
+    pub fn mailer(&self) -> Mailer {
+        Mailer::new(self.mail.from.clone(), self.public_url())
+    }
+
+    pub fn oauth_service(&self, client: reqwest::Client) -> Result<OAuthService> {
+        OAuthService::new(&self.oauth, client, self.public_url())
+    }
+
+    pub fn rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.rate_limit.clone())
+    }
+
+    pub fn discord_bot(&self, client: reqwest::Client) -> Result<DiscordBot> {
+        DiscordBot::new(&self.discord, client)
+    }
+
+    pub fn tracker(&self, ubi_api: UbiApi, pool: Arc<PgPool>) -> Tracker {
+        Tracker::new(self.tracker.clone(), ubi_api, pool)
+    }
+
+    pub fn realtime(&self, tracker: Tracker) -> Realtime {
+        Realtime::new(self.realtime.clone(), tracker)
+    }
+
+    pub fn notifier(&self, client: reqwest::Client, pool: Arc<PgPool>) -> Result<Notifier> {
+        Notifier::new(&self.notifications, client, pool)
+    }
+
+    pub fn webhook_dispatcher(&self, pool: Arc<PgPool>) -> Result<WebhookDispatcher> {
+        WebhookDispatcher::new(self.webhooks.clone(), pool)
+    }
+
+    pub fn avatars(&self, client: reqwest::Client) -> Result<Avatars> {
+        let storage: Arc<dyn Storage> = match (self.storage.backend.as_str(), &self.storage.s3) {
+            ("local", _) => Ok(Arc::new(LocalStorage::new(
+                &self.storage.local_dir,
+                format!("{}/uploads", self.public_url()),
+            )) as Arc<dyn Storage>),
+            ("s3", Some(s3)) => Ok(Arc::new(S3Storage::new(s3, client)?) as Arc<dyn Storage>),
+            ("s3", None) => Err(eyre!("STORAGE.BACKEND is s3 but STORAGE.S3 is not configured")),
+            (backend, _) => Err(eyre!("Unknown STORAGE.BACKEND {:?}, use local or s3", backend)),
+        }?;
+        Ok(Avatars::new(&self.storage, storage))
+    }
+
+    pub fn accounts(&self, avatars: Avatars, pool: Arc<PgPool>) -> Accounts {
+        Accounts::new(self.accounts.clone(), avatars, pool)
+    }
+
+    pub fn achievements(&self, pool: Arc<PgPool>) -> Result<Achievements> {
+        Achievements::new(&self.achievements, pool)
+    }
+
+    pub fn card_renderer(&self) -> CardRenderer {
+        CardRenderer::new(self.card.clone())
+    }
+
+    pub fn login_guard(&self) -> LoginGuard {
+        LoginGuard::new(self.login.clone())
+    }
+
+    pub fn trusted_proxies(&self) -> Result<TrustedProxies> {
+        TrustedProxies::new(&self.proxy)
     }
 }
@@ -0,0 +1,283 @@
+//rate limit
+// per user and per api key token buckets for the /ubi routes, Discord
+// commands charge the linked user or a bucket per Discord user
+
+use super::{RateLimitConfig, RoleLimit};
+use crate::models::{rate_limit::RateLimitBucket, user::ROLE_ADMIN};
+use chrono::{NaiveDateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated_at: NaiveDateTime,
+    limit: RoleLimit, // of the role it was last charged for, admins share the user: prefix
+}
+
+/// state of the most restrictive bucket after a request,
+/// sent back as X-RateLimit-* headers
+#[derive(Debug, Clone)]
+pub struct Quota {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_secs: u64,       // until the bucket is full again
+    pub retry_after_secs: u64, // until the next token, 0 while tokens are left
+}
+
+/// RateLimiter is shared by all workers, clones point to the same buckets
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn persist(&self) -> bool {
+        self.config.persist
+    }
+
+    pub fn persist_interval_secs(&self) -> u64 {
+        self.config.persist_interval_secs.max(1)
+    }
+
+    fn role_limit(&self, role: &str) -> &RoleLimit {
+        match role {
+            ROLE_ADMIN => &self.config.admin,
+            _ => &self.config.user,
+        }
+    }
+
+    /// takes one token from the user bucket, and from the key bucket when
+    /// the request used an api key. Nothing is taken unless all buckets allow it
+    pub fn acquire(&self, user_id: Uuid, role: &str, api_key_id: Option<Uuid>) -> Result<Quota, Quota> {
+        self.acquire_many(user_id, role, api_key_id, 1)
+    }
+
+    /// like `acquire`, for requests that cost more than one token
+    pub fn acquire_many(&self, user_id: Uuid, role: &str, api_key_id: Option<Uuid>, tokens: u32) -> Result<Quota, Quota> {
+        let mut checks = vec![(format!("user:{}", user_id), self.role_limit(role).clone())];
+        if let Some(api_key_id) = api_key_id {
+            checks.push((format!("key:{}", api_key_id), self.config.api_key.clone()));
+        }
+
+        self.take(checks, tokens)
+    }
+
+    /// takes one token for a Discord user who has no linked zbot account
+    pub fn acquire_discord(&self, discord_user_id: &str) -> Result<Quota, Quota> {
+        self.take(vec![(format!("discord:{}", discord_user_id), self.config.discord.clone())], 1)
+    }
+
+    fn take(&self, checks: Vec<(String, RoleLimit)>, tokens: u32) -> Result<Quota, Quota> {
+        let cost = tokens as f64;
+        let now = Utc::now().naive_utc();
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+
+        for (key, limit) in &checks {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: limit.capacity as f64,
+                updated_at: now,
+                limit: limit.clone(),
+            });
+            // the time since the last request refills at the old rate,
+            // a role change applies from here on
+            bucket.tokens = tokens_at(bucket, now);
+            bucket.updated_at = now;
+            bucket.limit = limit.clone();
+            bucket.tokens = bucket.tokens.min(limit.capacity as f64);
+        }
+
+        let allowed = checks.iter().all(|(key, _)| buckets[key].tokens >= cost);
+        if allowed {
+            for (key, _) in &checks {
+                if let Some(bucket) = buckets.get_mut(key) {
+                    bucket.tokens -= cost;
+                }
+            }
+        }
+
+        let quota = checks
+            .iter()
+            .map(|(key, limit)| quota_of(&buckets[key], limit, cost))
+            .min_by_key(|quota| quota.remaining)
+            .expect("at least one bucket");
+
+        if allowed {
+            Ok(quota)
+        } else {
+            Err(quota)
+        }
+    }
+
+    /// buckets that are not full, full ones are the default anyway.
+    /// Read only, the buckets themselves refill on their next request
+    pub fn snapshot(&self) -> Vec<RateLimitBucket> {
+        let now = Utc::now().naive_utc();
+        let buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+
+        buckets
+            .iter()
+            .map(|(key, bucket)| (key, bucket, tokens_at(bucket, now)))
+            .filter(|(_, bucket, tokens)| *tokens < bucket.limit.capacity as f64)
+            .map(|(key, bucket, tokens)| RateLimitBucket {
+                bucket_key: key.clone(),
+                tokens,
+                updated_at: now,
+                capacity: bucket.limit.capacity as i32,
+                refill_per_minute: bucket.limit.refill_per_minute as i32,
+            })
+            .collect()
+    }
+
+    pub fn restore(&self, snapshot: Vec<RateLimitBucket>) {
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+        for bucket in snapshot {
+            buckets.insert(
+                bucket.bucket_key,
+                Bucket {
+                    tokens: bucket.tokens,
+                    updated_at: bucket.updated_at,
+                    limit: RoleLimit {
+                        capacity: bucket.capacity.max(0) as u32,
+                        refill_per_minute: bucket.refill_per_minute.max(0) as u32,
+                    },
+                },
+            );
+        }
+    }
+}
+
+// tokens of `bucket` at `now`, refilled at the rate of its own limit
+fn tokens_at(bucket: &Bucket, now: NaiveDateTime) -> f64 {
+    let elapsed = (now - bucket.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+    let per_second = bucket.limit.refill_per_minute as f64 / 60.0;
+
+    (bucket.tokens + elapsed * per_second).min(bucket.limit.capacity as f64)
+}
+
+fn quota_of(bucket: &Bucket, limit: &RoleLimit, cost: f64) -> Quota {
+    let per_second = (limit.refill_per_minute as f64 / 60.0).max(f64::EPSILON);
+    let missing = (limit.capacity as f64 - bucket.tokens).max(0.0);
+
+    Quota {
+        limit: limit.capacity,
+        remaining: bucket.tokens.max(0.0).floor() as u32,
+        reset_secs: (missing / per_second).ceil() as u64,
+        retry_after_secs: if bucket.tokens >= cost {
+            0
+        } else {
+            ((cost - bucket.tokens) / per_second).ceil() as u64
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn limit(capacity: u32, refill_per_minute: u32) -> RoleLimit {
+        RoleLimit {
+            capacity,
+            refill_per_minute,
+        }
+    }
+
+    fn limiter(user: RoleLimit, api_key: RoleLimit) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            user,
+            api_key,
+            ..RateLimitConfig::default()
+        })
+    }
+
+    fn bucket(key: String, tokens: f64, age_secs: i64, limit: RoleLimit) -> RateLimitBucket {
+        RateLimitBucket {
+            bucket_key: key,
+            tokens,
+            updated_at: Utc::now().naive_utc() - Duration::seconds(age_secs),
+            capacity: limit.capacity as i32,
+            refill_per_minute: limit.refill_per_minute as i32,
+        }
+    }
+
+    #[test]
+    fn buckets_refill_over_time_up_to_their_capacity() {
+        let limiter = limiter(limit(30, 60), limit(60, 60));
+        let user_id = Uuid::from_u128(1);
+        let other_id = Uuid::from_u128(2);
+        limiter.restore(vec![
+            bucket(format!("user:{}", user_id), 0.0, 10, limit(30, 60)),
+            bucket(format!("user:{}", other_id), 0.0, 3600, limit(30, 60)),
+        ]);
+
+        // one token per second for 10 seconds
+        let quota = limiter.acquire(user_id, "user", None).unwrap();
+        assert_eq!(quota.remaining, 9);
+        assert_eq!(quota.retry_after_secs, 0);
+        assert_eq!(quota.reset_secs, 21);
+
+        let quota = limiter.acquire(other_id, "user", None).unwrap();
+        assert_eq!(quota.remaining, 29);
+        assert_eq!(quota.limit, 30);
+    }
+
+    #[test]
+    fn requests_take_their_cost_or_nothing() {
+        let limiter = limiter(limit(5, 1), limit(60, 1));
+        let user_id = Uuid::from_u128(3);
+
+        assert_eq!(limiter.acquire_many(user_id, "user", None, 3).unwrap().remaining, 2);
+        let quota = limiter.acquire_many(user_id, "user", None, 3).unwrap_err();
+        assert_eq!(quota.remaining, 2);
+        // one token missing, at one per minute
+        assert_eq!(quota.retry_after_secs, 60);
+
+        assert_eq!(limiter.acquire_many(user_id, "user", None, 2).unwrap().remaining, 0);
+        assert!(limiter.acquire(user_id, "user", None).is_err());
+    }
+
+    #[test]
+    fn api_key_requests_need_both_buckets() {
+        let limiter = limiter(limit(10, 1), limit(2, 1));
+        let user_id = Uuid::from_u128(4);
+        let api_key_id = Uuid::from_u128(5);
+
+        // the key bucket is the tighter one
+        let quota = limiter.acquire_many(user_id, "user", Some(api_key_id), 2).unwrap();
+        assert_eq!((quota.limit, quota.remaining), (2, 0));
+        assert!(limiter.acquire(user_id, "user", Some(api_key_id)).is_err());
+
+        // the refused request took nothing from the user bucket
+        assert_eq!(limiter.acquire(user_id, "user", None).unwrap().remaining, 7);
+    }
+
+    #[test]
+    fn snapshots_restore_the_buckets_that_are_not_full() {
+        let limiter = limiter(limit(5, 1), limit(60, 1));
+        let user_id = Uuid::from_u128(6);
+        limiter.acquire_many(user_id, "user", None, 4).unwrap();
+        limiter.acquire_discord("discord").unwrap();
+        limiter.restore(vec![bucket("user:full".to_string(), 5.0, 0, limit(5, 1))]);
+
+        let mut snapshot = limiter.snapshot();
+        snapshot.sort_by(|a, b| a.bucket_key.cmp(&b.bucket_key));
+        let keys: Vec<&str> = snapshot.iter().map(|bucket| bucket.bucket_key.as_str()).collect();
+        assert_eq!(keys, vec!["discord:discord".to_string(), format!("user:{}", user_id)]);
+        assert_eq!((snapshot[1].capacity, snapshot[1].refill_per_minute), (5, 1));
+
+        let restarted = RateLimiter::new(limiter.config.clone());
+        restarted.restore(snapshot);
+        assert_eq!(restarted.acquire(user_id, "user", None).unwrap().remaining, 0);
+        assert!(restarted.acquire(user_id, "user", None).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//trusted proxies
+// the client ip is the socket peer unless that peer is a configured reverse
+// proxy, only then X-Forwarded-For is read, right to left, skipping proxies
+
+use super::ProxyConfig;
+use color_eyre::Result;
+use eyre::eyre;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Debug, Clone, Copy)]
+struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    // "10.0.0.1" or "10.0.0.0/8"
+    fn parse(value: &str) -> Result<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| eyre!("Invalid trusted proxy {:?}", value))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| eyre!("Invalid trusted proxy prefix {:?}", value))?,
+            None => max,
+        };
+
+        Ok(Network { addr, prefix })
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrustedProxies {
+    networks: Vec<Network>,
+}
+
+impl TrustedProxies {
+    pub fn new(config: &ProxyConfig) -> Result<Self> {
+        let networks = config
+            .trusted
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(Network::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(TrustedProxies { networks })
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// client ip of a request from `peer` carrying `forwarded_for`
+    pub fn client_ip(&self, peer: Option<SocketAddr>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let peer = peer?.ip();
+        if !self.is_trusted(&peer) {
+            return Some(peer);
+        }
+
+        let mut client = peer;
+        let hops = forwarded_for.into_iter().flat_map(|value| value.rsplit(','));
+        for hop in hops {
+            match hop.trim().parse::<IpAddr>() {
+                Ok(ip) => {
+                    client = ip;
+                    if !self.is_trusted(&ip) {
+                        break;
+                    }
+                }
+                // anything left of a hop we can not read is client controlled
+                Err(_) => break,
+            }
+        }
+
+        Some(client)
+    }
+}
@@ -0,0 +1,183 @@
+// db account
+use super::{
+    api_key::ApiKeyRepository, follow::FollowRepository, identity::IdentityRepository,
+    notification::NotificationRepository, profile_link::ProfileLinkRepository, user::UserRepository,
+    webhook::WebhookRepository,
+};
+use crate::{
+    errors::AppError,
+    models::{
+        account::AccountExport,
+        audit::AuditEvent,
+        email_change::EmailChange,
+        player_snapshot::PlayerSnapshot,
+        session::UserSession,
+        user::User,
+    },
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::Result;
+use eyre::eyre;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct AccountRepository {
+    pool: Arc<PgPool>,
+}
+
+impl AccountRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        AccountRepository { pool }
+    }
+
+    /// None cancels a scheduled deletion
+    #[instrument(skip(self))]
+    pub async fn schedule_deletion(&self, user_id: Uuid, at: Option<NaiveDateTime>) -> Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            "update users set deletion_scheduled_for = $2 where id = $1 returning *",
+        )
+        .bind(user_id)
+        .bind(at)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_due_deletions(&self, now: NaiveDateTime) -> Result<Vec<Uuid>> {
+        let due = sqlx::query_as::<_, (Uuid,)>(
+            "select id from users where deletion_scheduled_for <= $1 order by deletion_scheduled_for",
+        )
+        .bind(now)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(due.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// removes the user for good, owned rows go with it (ON DELETE CASCADE).
+    /// Returns the avatar prefix whose files still have to be removed
+    #[instrument(skip(self))]
+    pub async fn purge(&self, user_id: Uuid) -> Result<Option<String>> {
+        let mut tx = self.pool.begin().await?;
+
+        let (username, email, avatar_key) = sqlx::query_as::<_, (String, String, Option<String>)>(
+            "select username, email, avatar_key from users where id = $1 for update",
+        )
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?;
+
+        // a profile also linked from discord stays linked there
+        sqlx::query("update ubi_profile_links set user_id = null where user_id = $1 and discord_user_id is not null")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+        // audit events about the user go too, failed logins are also matched
+        // by the username or email that was typed. Events the user did to
+        // others (as an admin) stay, they belong to the other account
+        sqlx::query("set local zbot.purging = 'on'").execute(&mut tx).await?;
+        sqlx::query(
+            "delete from audit_events where target_user_id = $1 \
+             or (event_type = 'login_failed' and (details->>'username' = $2 or lower(details->>'username') = lower($3)))",
+        )
+        .bind(user_id)
+        .bind(username)
+        .bind(email)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("delete from users where id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(avatar_key)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn export(&self, user_id: Uuid) -> Result<AccountExport> {
+        let users = UserRepository::new(self.pool.clone());
+        let user = users
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| eyre!("User {} not found", user_id))?;
+        let locations = users.find_locations(user_id).await?;
+        let ubi_profiles = ProfileLinkRepository::new(self.pool.clone())
+            .find_by_user_id(user_id)
+            .await?;
+        let notifications = NotificationRepository::new(self.pool.clone());
+        let follows = FollowRepository::new(self.pool.clone());
+
+        let sessions = sqlx::query_as::<_, UserSession>("select * from sessions where user_id = $1 order by created_at")
+            .bind(user_id)
+            .fetch_all(&*self.pool)
+            .await?;
+        let email_changes = sqlx::query_as::<_, EmailChange>(
+            "select * from email_changes where user_id = $1 order by created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        let audit_events = sqlx::query_as::<_, AuditEvent>(
+            "select * from audit_events where target_user_id = $1 or actor_id = $1 order by created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+        let snapshots = sqlx::query_as::<_, PlayerSnapshot>(
+            "select * from player_snapshots where profile_id in \
+             (select profile_id from ubi_profile_links where user_id = $1) order by profile_id, created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(AccountExport {
+            exported_at: Utc::now().naive_utc(),
+            privacy: users.find_privacy(user_id).await?,
+            badges: users.find_badges(user_id).await?,
+            sessions,
+            identities: IdentityRepository::new(self.pool.clone())
+                .find_by_user_id(user_id)
+                .await?,
+            api_keys: ApiKeyRepository::new(self.pool.clone()).find_by_user_id(user_id).await?,
+            email_changes,
+            audit_events,
+            ubi_profiles,
+            following: follows.find_following(user_id, i64::MAX, 0).await?,
+            followers: follows.find_followers(user_id, i64::MAX, 0).await?,
+            snapshots,
+            webhooks: WebhookRepository::new(self.pool.clone())
+                .find_by_user_id(user_id)
+                .await?,
+            devices: notifications.find_devices(user_id).await?,
+            notification_preferences: notifications.find_preferences(user_id).await?,
+            user: user.with_locations(locations.iter().map(|l| l.to_location()).collect()),
+        })
+    }
+}
+
+impl FromRequest for AccountRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(AccountRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// db achievement
+use color_eyre::Result;
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::sync::Arc;
+use tracing::instrument;
+
+pub struct AchievementRepository {
+    pool: Arc<PgPool>,
+}
+
+impl AchievementRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        AchievementRepository { pool }
+    }
+
+    /// longest current ranked win streak over the regions of a profile: wins
+    /// since the first snapshot of the season with today's loss count.
+    /// Losses between two snapshots end the streak, so it is never overcounted
+    #[instrument(skip(self))]
+    pub async fn find_win_streak(&self, profile_id: &str) -> Result<i32> {
+        let (streak,) = sqlx::query_as::<_, (i32,)>(
+            "select coalesce(max(latest.wins - first.wins), 0) from \
+             (select distinct on (region) region, season, wins, losses from player_snapshots \
+              where profile_id = $1 order by region, created_at desc) latest \
+             join lateral (select wins from player_snapshots s where s.profile_id = $1 and s.region = latest.region \
+              and s.season = latest.season and s.losses = latest.losses order by s.created_at limit 1) first on true",
+        )
+        .bind(profile_id)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(streak)
+    }
+
+    /// awards the badge to the profile, false when it already had it
+    #[instrument(skip(self))]
+    pub async fn award(&self, profile_id: &str, badge: &str) -> Result<bool> {
+        let awarded = sqlx::query("insert into profile_badges (profile_id, badge) values ($1, $2) on conflict do nothing")
+            .bind(profile_id)
+            .bind(badge)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(awarded > 0)
+    }
+}
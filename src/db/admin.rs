@@ -0,0 +1,201 @@
+// db admin
+use super::{audit, PgTransaction};
+use crate::{
+    errors::AppError,
+    models::{
+        admin::UserSearch,
+        audit::NewAuditEvent,
+        password_reset::{NewPasswordReset, PasswordReset},
+        session::{NewUserSession, UserSession},
+        user::User,
+    },
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use serde_json::json;
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+// shared by the page and its count
+const SEARCH_FILTER: &str = "($1::varchar is null or username ilike $1 or email ilike $1 or full_name ilike $1) \
+     and ($2::varchar is null or role = $2) \
+     and ($3::varchar is null \
+          or ($3 = 'suspended' and suspended_at is not null) \
+          or ($3 = 'pending_deletion' and deletion_scheduled_for is not null) \
+          or ($3 = 'active' and suspended_at is null and deletion_scheduled_for is null))";
+
+pub struct AdminRepository {
+    pool: Arc<PgPool>,
+}
+
+impl AdminRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        AdminRepository { pool }
+    }
+
+    /// newest users first, with the total of all matches
+    #[instrument(skip(self))]
+    pub async fn search_users(&self, search: &UserSearch, limit: i64, offset: i64) -> Result<(Vec<User>, i64)> {
+        // q is matched literally, anywhere in the field
+        let pattern = search.q.as_ref().map(|q| {
+            format!(
+                "%{}%",
+                q.trim().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+            )
+        });
+
+        let users = sqlx::query_as::<_, User>(&format!(
+            "select * from users where {} order by created_at desc limit $4 offset $5",
+            SEARCH_FILTER
+        ))
+        .bind(&pattern)
+        .bind(&search.role)
+        .bind(&search.status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&*self.pool)
+        .await?;
+        let (total,) = sqlx::query_as::<_, (i64,)>(&format!("select count(*) from users where {}", SEARCH_FILTER))
+            .bind(&pattern)
+            .bind(&search.role)
+            .bind(&search.status)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok((users, total))
+    }
+
+    /// suspends the user and revokes every session. The audit event is
+    /// written in the same transaction, with `revoked_sessions` added to its
+    /// details
+    #[instrument(skip(self, event))]
+    pub async fn suspend(&self, user_id: Uuid, reason: &str, mut event: NewAuditEvent) -> Result<(User, u64)> {
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now().naive_utc();
+
+        let user = sqlx::query_as::<_, User>(
+            "update users set suspended_at = $3, suspended_reason = $2 where id = $1 returning *",
+        )
+        .bind(user_id)
+        .bind(reason)
+        .bind(now)
+        .fetch_one(&mut tx)
+        .await?;
+        let revoked = revoke_sessions(&mut tx, user_id, now).await?;
+        event.details["revoked_sessions"] = json!(revoked);
+        audit::create_in(&mut tx, event).await?;
+
+        tx.commit().await?;
+        Ok((user, revoked))
+    }
+
+    #[instrument(skip(self, event))]
+    pub async fn unsuspend(&self, user_id: Uuid, event: NewAuditEvent) -> Result<User> {
+        let mut tx = self.pool.begin().await?;
+
+        let user = sqlx::query_as::<_, User>(
+            "update users set suspended_at = null, suspended_reason = null where id = $1 returning *",
+        )
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?;
+        audit::create_in(&mut tx, event).await?;
+
+        tx.commit().await?;
+        Ok(user)
+    }
+
+    /// replaces any unused reset link with `password_reset`, requires a new
+    /// password and revokes every session, audited like `suspend`
+    #[instrument(skip(self, password_reset, event))]
+    pub async fn force_password_reset(
+        &self,
+        password_reset: NewPasswordReset,
+        mut event: NewAuditEvent,
+    ) -> Result<(User, PasswordReset, u64)> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = password_reset.user_id;
+
+        sqlx::query("delete from password_resets where user_id = $1 and used_at is null")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+        let password_reset = sqlx::query_as::<_, PasswordReset>(
+            "insert into password_resets (user_id, token_hash, expires_at) values ($1, $2, $3) returning *",
+        )
+        .bind(user_id)
+        .bind(password_reset.token_hash)
+        .bind(password_reset.expires_at)
+        .fetch_one(&mut tx)
+        .await?;
+        let user = sqlx::query_as::<_, User>(
+            "update users set password_reset_required = true where id = $1 returning *",
+        )
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?;
+        let revoked = revoke_sessions(&mut tx, user_id, Utc::now().naive_utc()).await?;
+        event.details["revoked_sessions"] = json!(revoked);
+        audit::create_in(&mut tx, event).await?;
+
+        tx.commit().await?;
+        Ok((user, password_reset, revoked))
+    }
+
+    /// opens the impersonation session, `session_id` is added to the details
+    /// of the audit event written with it
+    #[instrument(skip(self, session, event))]
+    pub async fn impersonate(&self, session: NewUserSession, mut event: NewAuditEvent) -> Result<UserSession> {
+        let mut tx = self.pool.begin().await?;
+
+        let session = sqlx::query_as::<_, UserSession>(
+            "insert into sessions (user_id, ip_address, user_agent, expires_at, impersonated_by) \
+             values ($1, $2, $3, $4, $5) returning *",
+        )
+        .bind(session.user_id)
+        .bind(session.ip_address)
+        .bind(session.user_agent)
+        .bind(session.expires_at)
+        .bind(session.impersonated_by)
+        .fetch_one(&mut tx)
+        .await?;
+        event.details["session_id"] = json!(session.id);
+        audit::create_in(&mut tx, event).await?;
+
+        tx.commit().await?;
+        Ok(session)
+    }
+}
+
+async fn revoke_sessions(tx: &mut PgTransaction, user_id: Uuid, now: NaiveDateTime) -> Result<u64> {
+    let revoked = sqlx::query("update sessions set revoked_at = $2 where user_id = $1 and revoked_at is null")
+        .bind(user_id)
+        .bind(now)
+        .execute(tx)
+        .await?;
+
+    Ok(revoked)
+}
+
+impl FromRequest for AdminRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(AdminRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// db api_key
+use crate::{
+    errors::AppError,
+    models::api_key::{ApiKey, NewApiKey},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::Utc;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct ApiKeyRepository {
+    pool: Arc<PgPool>,
+}
+
+impl ApiKeyRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        ApiKeyRepository { pool }
+    }
+
+    #[instrument(skip(self, api_key))]
+    pub async fn create(&self, api_key: NewApiKey) -> Result<ApiKey> {
+        let api_key = sqlx::query_as::<_, ApiKey>(
+            "insert into api_keys (user_id, name, prefix, key_hash, scopes, expires_at) values ($1, $2, $3, $4, $5, $6) returning *",
+        )
+        .bind(api_key.user_id)
+        .bind(api_key.name)
+        .bind(api_key.prefix)
+        .bind(api_key.key_hash)
+        .bind(api_key.scopes)
+        .bind(api_key.expires_at)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(api_key)
+    }
+
+    /// finds a usable key and records that it was used
+    #[instrument(skip(self, key_hash))]
+    pub async fn use_key(&self, prefix: &str, key_hash: &str) -> Result<Option<ApiKey>> {
+        let maybe_key = sqlx::query_as::<_, ApiKey>(
+            "update api_keys set last_used_at = $3 where prefix = $1 and key_hash = $2 and revoked_at is null and (expires_at is null or expires_at > $3) returning *",
+        )
+        .bind(prefix)
+        .bind(key_hash)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_key)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_user_id(&self, user_id: Uuid) -> Result<Vec<ApiKey>> {
+        let api_keys = sqlx::query_as::<_, ApiKey>(
+            "select * from api_keys where user_id = $1 order by created_at desc",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(api_keys)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn revoke(&self, id: Uuid, user_id: Uuid) -> Result<Option<ApiKey>> {
+        let maybe_key = sqlx::query_as::<_, ApiKey>(
+            "update api_keys set revoked_at = coalesce(revoked_at, $3) where id = $1 and user_id = $2 returning *",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_key)
+    }
+}
+
+impl FromRequest for ApiKeyRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(ApiKeyRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// db audit
+use super::PgTransaction;
+use crate::{
+    errors::AppError,
+    models::audit::{AuditEvent, AuditSearch, NewAuditEvent},
+};
+use actix_web::{web::Data, FromRequest};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::{error, instrument};
+use uuid::Uuid;
+
+// shared by the page and its count
+const SEARCH_FILTER: &str = "($1::varchar is null or event_type = $1) \
+     and ($2::uuid is null or actor_id = $2) \
+     and ($3::uuid is null or target_user_id = $3) \
+     and ($4::varchar is null or ip_address = $4) \
+     and ($5::timestamp is null or created_at >= $5) \
+     and ($6::timestamp is null or created_at < $6)";
+
+const INSERT_EVENT: &str = "insert into audit_events \
+     (event_type, actor_id, target_user_id, ip_address, user_agent, details) \
+     values ($1, $2, $3, $4, $5, $6) returning *";
+
+/// records `event` as part of `tx`, it is kept only if the action it
+/// describes commits
+pub async fn create_in(tx: &mut PgTransaction, event: NewAuditEvent) -> Result<AuditEvent> {
+    let event = sqlx::query_as::<_, AuditEvent>(INSERT_EVENT)
+        .bind(event.event_type)
+        .bind(event.actor_id)
+        .bind(event.target_user_id)
+        .bind(event.ip_address)
+        .bind(event.user_agent)
+        .bind(event.details)
+        .fetch_one(tx)
+        .await?;
+
+    Ok(event)
+}
+
+/// the only writer of audit_events, rows are never updated
+pub struct AuditRepository {
+    pool: Arc<PgPool>,
+}
+
+impl AuditRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        AuditRepository { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn create(&self, event: NewAuditEvent) -> Result<AuditEvent> {
+        let event = sqlx::query_as::<_, AuditEvent>(INSERT_EVENT)
+            .bind(event.event_type)
+            .bind(event.actor_id)
+            .bind(event.target_user_id)
+            .bind(event.ip_address)
+            .bind(event.user_agent)
+            .bind(event.details)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(event)
+    }
+
+    /// like `create`, but a failure is only logged so it never fails
+    /// the request being audited
+    pub async fn record(&self, event: NewAuditEvent) {
+        let event_type = event.event_type;
+        if let Err(e) = self.create(event).await {
+            error!("Error recording {} audit event. {:?}", event_type, e);
+        }
+    }
+
+    /// newest events first, with the total of all matches
+    #[instrument(skip(self))]
+    pub async fn search(&self, search: &AuditSearch, limit: i64, offset: i64) -> Result<(Vec<AuditEvent>, i64)> {
+        let events = sqlx::query_as::<_, AuditEvent>(&format!(
+            "select * from audit_events where {} order by created_at desc limit $7 offset $8",
+            SEARCH_FILTER
+        ))
+        .bind(&search.event_type)
+        .bind(search.actor_id)
+        .bind(search.target_user_id)
+        .bind(&search.ip_address)
+        .bind(search.from)
+        .bind(search.to)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&*self.pool)
+        .await?;
+        let (total,) = sqlx::query_as::<_, (i64,)>(&format!(
+            "select count(*) from audit_events where {}",
+            SEARCH_FILTER
+        ))
+        .bind(&search.event_type)
+        .bind(search.actor_id)
+        .bind(search.target_user_id)
+        .bind(&search.ip_address)
+        .bind(search.from)
+        .bind(search.to)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok((events, total))
+    }
+
+    /// events done by or to the user, newest first
+    #[instrument(skip(self))]
+    pub async fn find_by_user_id(&self, user_id: Uuid, limit: i64) -> Result<Vec<AuditEvent>> {
+        let events = sqlx::query_as::<_, AuditEvent>(
+            "select * from audit_events where target_user_id = $1 or actor_id = $1 \
+             order by created_at desc limit $2",
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(events)
+    }
+}
+
+impl FromRequest for AuditRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(AuditRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
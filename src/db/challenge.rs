@@ -0,0 +1,67 @@
+// db challenge
+use crate::errors::AppError;
+use actix_web::{web::Data, FromRequest};
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+
+/// ChallengeRepository, 2fa login challenges that were already used
+pub struct ChallengeRepository {
+    pool: Arc<PgPool>,
+}
+
+impl ChallengeRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        ChallengeRepository { pool }
+    }
+
+    #[instrument(skip(self, jti))]
+    pub async fn is_used(&self, jti: &str) -> Result<bool> {
+        let used = sqlx::query_as::<_, (String,)>("select jti from used_challenges where jti = $1")
+            .bind(jti)
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(used.is_some())
+    }
+
+    /// marks the challenge used, false when it was used before
+    #[instrument(skip(self, jti))]
+    pub async fn consume(&self, jti: &str, expires_at: NaiveDateTime) -> Result<bool> {
+        // opportunistic cleanup, expired challenges are refused anyway
+        sqlx::query("delete from used_challenges where expires_at < $1")
+            .bind(Utc::now().naive_utc())
+            .execute(&*self.pool)
+            .await?;
+
+        let inserted = sqlx::query("insert into used_challenges (jti, expires_at) values ($1, $2) on conflict do nothing")
+            .bind(jti)
+            .bind(expires_at)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(inserted > 0)
+    }
+}
+
+impl FromRequest for ChallengeRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(ChallengeRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// db email_change
+use crate::{
+    errors::AppError,
+    models::email_change::{EmailChange, NewEmailChange},
+    models::user::User,
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::Utc;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct EmailChangeRepository {
+    pool: Arc<PgPool>,
+}
+
+impl EmailChangeRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        EmailChangeRepository { pool }
+    }
+
+    /// a new request replaces any pending one of the same user
+    #[instrument(skip(self))]
+    pub async fn create(&self, email_change: NewEmailChange) -> Result<EmailChange> {
+        sqlx::query("delete from email_changes where user_id = $1 and confirmed_at is null")
+            .bind(email_change.user_id)
+            .execute(&*self.pool)
+            .await?;
+
+        let email_change = sqlx::query_as::<_, EmailChange>(
+            "insert into email_changes (user_id, new_email, token_hash, expires_at) values ($1, $2, $3, $4) returning *",
+        )
+        .bind(email_change.user_id)
+        .bind(email_change.new_email)
+        .bind(email_change.token_hash)
+        .bind(email_change.expires_at)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(email_change)
+    }
+
+    #[instrument(skip(self, token_hash))]
+    pub async fn find_pending(&self, token_hash: &str) -> Result<Option<EmailChange>> {
+        let maybe_change = sqlx::query_as::<_, EmailChange>(
+            "select * from email_changes where token_hash = $1 and confirmed_at is null and expires_at > $2",
+        )
+        .bind(token_hash)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_change)
+    }
+
+    /// marks the change confirmed and sets the new email in one transaction,
+    /// None when it was confirmed or expired meanwhile
+    #[instrument(skip(self))]
+    pub async fn confirm(&self, id: Uuid) -> Result<Option<User>> {
+        let mut tx = self.pool.begin().await?;
+
+        let email_change = sqlx::query_as::<_, EmailChange>(
+            "update email_changes set confirmed_at = $2 where id = $1 and confirmed_at is null and expires_at > $2 \
+             returning *",
+        )
+        .bind(id)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&mut tx)
+        .await?;
+        let email_change = match email_change {
+            Some(email_change) => email_change,
+            None => return Ok(None),
+        };
+
+        let user = sqlx::query_as::<_, User>(
+            "update users set email = $2, updated_at = CURRENT_TIMESTAMP where id = $1 returning *",
+        )
+        .bind(email_change.user_id)
+        .bind(&email_change.new_email)
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+}
+
+impl FromRequest for EmailChangeRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(EmailChangeRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
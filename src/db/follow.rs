@@ -0,0 +1,191 @@
+// db follow
+use crate::{
+    errors::AppError,
+    models::follow::{FeedRow, FollowUser},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::NaiveDateTime;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+// users gone for everyone else are left out of lists and feeds
+const VISIBLE_USER: &str = "u.deletion_scheduled_for is null and u.suspended_at is null";
+
+pub struct FollowRepository {
+    pool: Arc<PgPool>,
+}
+
+impl FollowRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        FollowRepository { pool }
+    }
+
+    /// following twice is a no-op
+    #[instrument(skip(self))]
+    pub async fn follow(&self, follower_id: Uuid, followee_id: Uuid) -> Result<()> {
+        sqlx::query("insert into follows (follower_id, followee_id) values ($1, $2) on conflict do nothing")
+            .bind(follower_id)
+            .bind(followee_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn unfollow(&self, follower_id: Uuid, followee_id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query("delete from follows where follower_id = $1 and followee_id = $2")
+            .bind(follower_id)
+            .bind(followee_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn is_following(&self, follower_id: Uuid, followee_id: Uuid) -> Result<bool> {
+        let (following,) = sqlx::query_as::<_, (bool,)>(
+            "select exists(select 1 from follows where follower_id = $1 and followee_id = $2)",
+        )
+        .bind(follower_id)
+        .bind(followee_id)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(following)
+    }
+
+    /// (followers, following)
+    #[instrument(skip(self))]
+    pub async fn count(&self, user_id: Uuid) -> Result<(i64, i64)> {
+        let counts = sqlx::query_as::<_, (i64, i64)>(&format!(
+            "select \
+             (select count(*) from follows f join users u on u.id = f.follower_id where f.followee_id = $1 and {0}), \
+             (select count(*) from follows f join users u on u.id = f.followee_id where f.follower_id = $1 and {0})",
+            VISIBLE_USER
+        ))
+        .bind(user_id)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+
+    /// users following `user_id`, newest first
+    #[instrument(skip(self))]
+    pub async fn find_followers(&self, user_id: Uuid, limit: i64, offset: i64) -> Result<Vec<FollowUser>> {
+        self.find_list("f.follower_id", "f.followee_id", false, user_id, limit, offset)
+            .await
+    }
+
+    /// users `user_id` follows, newest first
+    #[instrument(skip(self))]
+    pub async fn find_following(&self, user_id: Uuid, limit: i64, offset: i64) -> Result<Vec<FollowUser>> {
+        self.find_list("f.followee_id", "f.follower_id", false, user_id, limit, offset)
+            .await
+    }
+
+    /// users following `user_id` back, newest follow first
+    #[instrument(skip(self))]
+    pub async fn find_friends(&self, user_id: Uuid, limit: i64, offset: i64) -> Result<Vec<FollowUser>> {
+        self.find_list("f.followee_id", "f.follower_id", true, user_id, limit, offset)
+            .await
+    }
+
+    // `listed` is the column of the users in the list, `owner` the one of `user_id`
+    async fn find_list(
+        &self,
+        listed: &str,
+        owner: &str,
+        mutual_only: bool,
+        user_id: Uuid,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<FollowUser>> {
+        let users = sqlx::query_as::<_, FollowUser>(&format!(
+            "select * from (select u.username, \
+             case when u.show_full_name then u.full_name end as full_name, \
+             case when u.show_image then u.image end as image, \
+             f.created_at as followed_at, \
+             exists(select 1 from follows back where back.follower_id = {listed} and back.followee_id = {owner}) \
+             and exists(select 1 from follows forth where forth.follower_id = {owner} and forth.followee_id = {listed}) \
+             as mutual \
+             from follows f join users u on u.id = {listed} \
+             where {owner} = $1 and {visible}) list \
+             where $2 = false or mutual \
+             order by followed_at desc limit $3 offset $4",
+            listed = listed,
+            owner = owner,
+            visible = VISIBLE_USER,
+        ))
+        .bind(user_id)
+        .bind(mutual_only)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// snapshots of profiles linked by followed users that changed the rank
+    /// or level since the snapshot before, newest first. Users hiding their
+    /// ubi profiles are left out. A level up is only on the first snapshot
+    /// of the profile with that level, not on the one of every region
+    #[instrument(skip(self))]
+    pub async fn feed(&self, follower_id: Uuid, before: Option<NaiveDateTime>, limit: i64) -> Result<Vec<FeedRow>> {
+        let rows = sqlx::query_as::<_, FeedRow>(&format!(
+            "select u.username, s.id as snapshot_id, s.profile_id, s.platform, s.name_on_platform, \
+             s.region, s.season, s.rank, s.mmr, s.level, s.wins, s.losses, s.created_at, \
+             p.season as previous_season, p.rank as previous_rank, p.mmr as previous_mmr, \
+             p.level as previous_level, p.wins as previous_wins, p.losses as previous_losses, \
+             first.first_at_level \
+             from follows f \
+             join users u on u.id = f.followee_id \
+             join ubi_profile_links l on l.user_id = u.id \
+             join player_snapshots s on s.profile_id = l.profile_id \
+             cross join lateral (select season, rank, mmr, level, wins, losses from player_snapshots p \
+             where p.profile_id = s.profile_id and p.region = s.region and p.created_at < s.created_at \
+             order by p.created_at desc limit 1) p \
+             cross join lateral (select not exists(select 1 from player_snapshots o \
+             where o.profile_id = s.profile_id and o.level >= s.level and o.created_at < s.created_at) \
+             as first_at_level) first \
+             where f.follower_id = $1 and u.show_ubi_profiles and {visible} \
+             and ($2::timestamp is null or s.created_at < $2) \
+             and ((s.season = p.season and s.rank <> p.rank) or (s.level > p.level and first.first_at_level)) \
+             order by s.created_at desc limit $3",
+            visible = VISIBLE_USER,
+        ))
+        .bind(follower_id)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+}
+
+impl FromRequest for FollowRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(FollowRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
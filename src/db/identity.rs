@@ -0,0 +1,126 @@
+// db identity
+use crate::{
+    errors::AppError,
+    models::identity::{Identity, NewIdentity, NewOAuthState, OAuthState},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::Utc;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+/// IdentityRepository, external identities and pending oauth flows
+pub struct IdentityRepository {
+    pool: Arc<PgPool>,
+}
+
+impl IdentityRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        IdentityRepository { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn create(&self, identity: NewIdentity) -> Result<Identity> {
+        let identity = sqlx::query_as::<_, Identity>(
+            "insert into identities (user_id, provider, subject, email, username) values ($1, $2, $3, $4, $5) returning *",
+        )
+        .bind(identity.user_id)
+        .bind(identity.provider)
+        .bind(identity.subject)
+        .bind(identity.email)
+        .bind(identity.username)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(identity)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find(&self, provider: &str, subject: &str) -> Result<Option<Identity>> {
+        let maybe_identity = sqlx::query_as::<_, Identity>(
+            "select * from identities where provider = $1 and subject = $2",
+        )
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_identity)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_user_id(&self, user_id: Uuid) -> Result<Vec<Identity>> {
+        let identities = sqlx::query_as::<_, Identity>(
+            "select * from identities where user_id = $1 order by created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(identities)
+    }
+
+    #[instrument(skip(self, state))]
+    pub async fn create_state(&self, state: NewOAuthState) -> Result<OAuthState> {
+        // opportunistic cleanup of abandoned flows
+        sqlx::query("delete from oauth_states where expires_at < $1")
+            .bind(Utc::now().naive_utc())
+            .execute(&*self.pool)
+            .await?;
+
+        let state = sqlx::query_as::<_, OAuthState>(
+            "insert into oauth_states (state, provider, code_verifier, user_id, browser_hash, expires_at) \
+             values ($1, $2, $3, $4, $5, $6) returning *",
+        )
+        .bind(state.state)
+        .bind(state.provider)
+        .bind(state.code_verifier)
+        .bind(state.user_id)
+        .bind(state.browser_hash)
+        .bind(state.expires_at)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(state)
+    }
+
+    /// states are single use, taking one deletes it. Only the browser that
+    /// started the flow can take it
+    #[instrument(skip(self, state, browser_hash))]
+    pub async fn take_state(&self, provider: &str, state: &str, browser_hash: &str) -> Result<Option<OAuthState>> {
+        let maybe_state = sqlx::query_as::<_, OAuthState>(
+            "delete from oauth_states where state = $1 and provider = $2 and browser_hash = $3 and expires_at > $4 \
+             returning *",
+        )
+        .bind(state)
+        .bind(provider)
+        .bind(browser_hash)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_state)
+    }
+}
+
+impl FromRequest for IdentityRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(IdentityRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -1,5 +1,25 @@
 // module DB
+pub mod account;
+pub mod achievement;
+pub mod admin;
+pub mod api_key;
+pub mod challenge;
+pub mod audit;
+pub mod email_change;
+pub mod follow;
+pub mod identity;
+pub mod notification;
+pub mod password_reset;
+pub mod player_snapshot;
+pub mod profile_link;
+pub mod rate_limit;
+pub mod recovery_code;
+pub mod session;
 pub mod ubi_user;
 pub mod user;
+pub mod webhook;
 
 pub const UNIQUE_VIOLATION_CODE: &str = "23505";
+
+/// a transaction of writes that spread over several tables
+pub type PgTransaction = sqlx::Transaction<sqlx::pool::PoolConnection<sqlx::PgConnection>>;
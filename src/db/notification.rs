@@ -0,0 +1,169 @@
+// db notification
+use crate::{
+    errors::AppError,
+    models::notification::{DeviceToken, NotificationPreferences},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{NaiveTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+// preferences of a user, defaults when there is no row
+const PREFERENCES_COLUMNS: &str = "coalesce(p.rank_up, true) as rank_up, coalesce(p.rank_down, true) as rank_down, \
+     coalesce(p.level_up, true) as level_up, p.quiet_hours_start, p.quiet_hours_end, \
+     coalesce(p.utc_offset_minutes, 0) as utc_offset_minutes";
+
+pub struct NotificationRepository {
+    pool: Arc<PgPool>,
+}
+
+impl NotificationRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        NotificationRepository { pool }
+    }
+
+    /// a token belongs to one user, registering it again moves it over
+    #[instrument(skip(self, token))]
+    pub async fn register_device(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        token: &str,
+        name: Option<String>,
+    ) -> Result<DeviceToken> {
+        let device = sqlx::query_as::<_, DeviceToken>(
+            "insert into device_tokens (user_id, provider, token, name) values ($1, $2, $3, $4) \
+             on conflict (token) do update set user_id = excluded.user_id, provider = excluded.provider, \
+             name = excluded.name, last_seen_at = $5 returning *",
+        )
+        .bind(user_id)
+        .bind(provider)
+        .bind(token)
+        .bind(name)
+        .bind(Utc::now().naive_utc())
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(device)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_devices(&self, user_id: Uuid) -> Result<Vec<DeviceToken>> {
+        let devices = sqlx::query_as::<_, DeviceToken>(
+            "select * from device_tokens where user_id = $1 order by created_at desc",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(devices)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete_device(&self, id: Uuid, user_id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query("delete from device_tokens where id = $1 and user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// drops a token the provider no longer accepts
+    #[instrument(skip(self))]
+    pub async fn delete_token(&self, id: Uuid) -> Result<()> {
+        sqlx::query("delete from device_tokens where id = $1")
+            .bind(id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_preferences(&self, user_id: Uuid) -> Result<NotificationPreferences> {
+        let preferences = sqlx::query_as::<_, NotificationPreferences>(&format!(
+            "select u.id as user_id, {} from users u \
+             left join notification_preferences p on p.user_id = u.id where u.id = $1",
+            PREFERENCES_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(preferences)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn set_preferences(
+        &self,
+        user_id: Uuid,
+        rank_up: bool,
+        rank_down: bool,
+        level_up: bool,
+        quiet_hours: Option<(NaiveTime, NaiveTime)>,
+        utc_offset_minutes: i32,
+    ) -> Result<NotificationPreferences> {
+        let preferences = sqlx::query_as::<_, NotificationPreferences>(
+            "insert into notification_preferences \
+             (user_id, rank_up, rank_down, level_up, quiet_hours_start, quiet_hours_end, utc_offset_minutes, updated_at) \
+             values ($1, $2, $3, $4, $5, $6, $7, $8) on conflict (user_id) do update set \
+             rank_up = excluded.rank_up, rank_down = excluded.rank_down, level_up = excluded.level_up, \
+             quiet_hours_start = excluded.quiet_hours_start, quiet_hours_end = excluded.quiet_hours_end, \
+             utc_offset_minutes = excluded.utc_offset_minutes, updated_at = excluded.updated_at \
+             returning user_id, rank_up, rank_down, level_up, quiet_hours_start, quiet_hours_end, utc_offset_minutes",
+        )
+        .bind(user_id)
+        .bind(rank_up)
+        .bind(rank_down)
+        .bind(level_up)
+        .bind(quiet_hours.map(|(start, _)| start))
+        .bind(quiet_hours.map(|(_, end)| end))
+        .bind(utc_offset_minutes)
+        .bind(Utc::now().naive_utc())
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(preferences)
+    }
+
+    /// users that linked the profile, with their preferences
+    #[instrument(skip(self))]
+    pub async fn find_recipients(&self, profile_id: &str) -> Result<Vec<NotificationPreferences>> {
+        let recipients = sqlx::query_as::<_, NotificationPreferences>(&format!(
+            "select distinct on (l.user_id) l.user_id, {} from ubi_profile_links l \
+             left join notification_preferences p on p.user_id = l.user_id \
+             where l.profile_id = $1 and l.user_id is not null",
+            PREFERENCES_COLUMNS
+        ))
+        .bind(profile_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(recipients)
+    }
+}
+
+impl FromRequest for NotificationRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(NotificationRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
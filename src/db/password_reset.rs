@@ -0,0 +1,76 @@
+// db password_reset
+use crate::{
+    errors::AppError,
+    models::password_reset::{NewPasswordReset, PasswordReset},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::Utc;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+
+pub struct PasswordResetRepository {
+    pool: Arc<PgPool>,
+}
+
+impl PasswordResetRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        PasswordResetRepository { pool }
+    }
+
+    /// a new link replaces any unused one of the same user
+    #[instrument(skip(self, password_reset))]
+    pub async fn create(&self, password_reset: NewPasswordReset) -> Result<PasswordReset> {
+        sqlx::query("delete from password_resets where user_id = $1 and used_at is null")
+            .bind(password_reset.user_id)
+            .execute(&*self.pool)
+            .await?;
+
+        let password_reset = sqlx::query_as::<_, PasswordReset>(
+            "insert into password_resets (user_id, token_hash, expires_at) values ($1, $2, $3) returning *",
+        )
+        .bind(password_reset.user_id)
+        .bind(password_reset.token_hash)
+        .bind(password_reset.expires_at)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(password_reset)
+    }
+
+    /// marks a valid link used, so it works once
+    #[instrument(skip(self, token_hash))]
+    pub async fn take(&self, token_hash: &str) -> Result<Option<PasswordReset>> {
+        let maybe_reset = sqlx::query_as::<_, PasswordReset>(
+            "update password_resets set used_at = $2 \
+             where token_hash = $1 and used_at is null and expires_at > $2 returning *",
+        )
+        .bind(token_hash)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_reset)
+    }
+}
+
+impl FromRequest for PasswordResetRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(PasswordResetRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
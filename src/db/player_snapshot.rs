@@ -0,0 +1,159 @@
+// db player_snapshot
+use crate::{
+    errors::AppError,
+    models::player_snapshot::{NewPlayerSnapshot, PlayerSnapshot, RecordedSnapshot},
+    ubi::player_card::PlayerCard,
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::NaiveDateTime;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+
+pub struct PlayerSnapshotRepository {
+    pool: Arc<PgPool>,
+}
+
+impl PlayerSnapshotRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        PlayerSnapshotRepository { pool }
+    }
+
+    /// latest snapshot of a profile in one region
+    #[instrument(skip(self))]
+    pub async fn find_latest(&self, profile_id: &str, region: &str) -> Result<Option<PlayerSnapshot>> {
+        let snapshot = sqlx::query_as::<_, PlayerSnapshot>(
+            "select * from player_snapshots where profile_id = $1 and region = $2 order by created_at desc limit 1",
+        )
+        .bind(profile_id)
+        .bind(region)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    /// latest snapshot per region for a player name, if checked since `since`
+    #[instrument(skip(self))]
+    pub async fn find_fresh_by_name(
+        &self,
+        platform: &str,
+        name: &str,
+        since: NaiveDateTime,
+    ) -> Result<Vec<PlayerSnapshot>> {
+        let snapshots = sqlx::query_as::<_, PlayerSnapshot>(
+            "select distinct on (region) * from player_snapshots \
+             where platform = $1 and lower(name_on_platform) = lower($2) and checked_at >= $3 \
+             order by region, created_at desc",
+        )
+        .bind(platform)
+        .bind(name)
+        .bind(since)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(snapshots)
+    }
+
+    /// stores `snapshot` unless nothing changed since the latest one, which
+    /// then only gets its checked_at bumped
+    pub async fn record(&self, snapshot: NewPlayerSnapshot) -> Result<RecordedSnapshot> {
+        let latest = self.find_latest(&snapshot.profile_id, &snapshot.region).await?;
+        if let Some(latest) = &latest {
+            if snapshot.unchanged_since(latest) {
+                let touched = sqlx::query_as::<_, PlayerSnapshot>(
+                    "update player_snapshots set checked_at = CURRENT_TIMESTAMP, name_on_platform = $2 \
+                     where id = $1 returning *",
+                )
+                .bind(latest.id)
+                .bind(snapshot.name_on_platform)
+                .fetch_one(&*self.pool)
+                .await?;
+
+                return Ok(RecordedSnapshot {
+                    current: touched,
+                    previous: None,
+                    created: false,
+                });
+            }
+        }
+
+        let created = sqlx::query_as::<_, PlayerSnapshot>(
+            "insert into player_snapshots (profile_id, platform, name_on_platform, region, season, rank, max_rank, \
+             mmr, max_mmr, kills, deaths, wins, losses, abandons, level) \
+             values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) returning *",
+        )
+        .bind(snapshot.profile_id)
+        .bind(snapshot.platform)
+        .bind(snapshot.name_on_platform)
+        .bind(snapshot.region)
+        .bind(snapshot.season)
+        .bind(snapshot.rank)
+        .bind(snapshot.max_rank)
+        .bind(snapshot.mmr)
+        .bind(snapshot.max_mmr)
+        .bind(snapshot.kills)
+        .bind(snapshot.deaths)
+        .bind(snapshot.wins)
+        .bind(snapshot.losses)
+        .bind(snapshot.abandons)
+        .bind(snapshot.level)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(RecordedSnapshot {
+            current: created,
+            previous: latest,
+            created: true,
+        })
+    }
+
+    /// records every region of a freshly fetched card
+    pub async fn record_card(&self, card: &PlayerCard) -> Result<Vec<RecordedSnapshot>> {
+        let mut snapshots = Vec::new();
+        for stats in card.ranks.values() {
+            let snapshot = NewPlayerSnapshot::from_stats(&card.profile, stats, card.level);
+            snapshots.push(self.record(snapshot).await?);
+        }
+
+        Ok(snapshots)
+    }
+
+    /// last `limit` mmr values of a profile in one region, oldest first
+    #[instrument(skip(self))]
+    pub async fn find_mmr_trend(&self, profile_id: &str, region: &str, limit: i64) -> Result<Vec<f32>> {
+        let rows = sqlx::query_as::<_, (f32,)>(
+            "select mmr from (select mmr, created_at from player_snapshots \
+             where profile_id = $1 and region = $2 order by created_at desc limit $3) recent \
+             order by created_at",
+        )
+        .bind(profile_id)
+        .bind(region)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(mmr,)| mmr).collect())
+    }
+}
+
+impl FromRequest for PlayerSnapshotRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(PlayerSnapshotRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
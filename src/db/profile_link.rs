@@ -0,0 +1,166 @@
+// db profile_link
+use crate::{
+    errors::AppError,
+    models::profile_link::{LeaderboardEntry, NewProfileLink, ProfileLink, TrackedProfile},
+};
+use actix_web::{web::Data, FromRequest};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct ProfileLinkRepository {
+    pool: Arc<PgPool>,
+}
+
+impl ProfileLinkRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        ProfileLinkRepository { pool }
+    }
+
+    /// links a profile to a user, linking it again only refreshes the name
+    pub async fn link_user(&self, link: NewProfileLink) -> Result<ProfileLink> {
+        let link = sqlx::query_as::<_, ProfileLink>(
+            "insert into ubi_profile_links (user_id, profile_id, platform, name_on_platform) values ($1, $2, $3, $4) \
+             on conflict (user_id, profile_id) where user_id is not null \
+             do update set name_on_platform = excluded.name_on_platform returning *",
+        )
+        .bind(link.user_id)
+        .bind(link.profile_id)
+        .bind(link.platform)
+        .bind(link.name_on_platform)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    /// makes this the Discord user's profile for slash commands, replacing
+    /// the previous one. Known zbot users get the profile linked as well
+    pub async fn link_discord(&self, link: NewProfileLink) -> Result<ProfileLink> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("update ubi_profile_links set discord_user_id = null where discord_user_id = $1 and user_id is not null")
+            .bind(&link.discord_user_id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("delete from ubi_profile_links where discord_user_id = $1")
+            .bind(&link.discord_user_id)
+            .execute(&mut tx)
+            .await?;
+
+        let link = match link.user_id {
+            Some(user_id) => sqlx::query_as::<_, ProfileLink>(
+                "insert into ubi_profile_links (user_id, discord_user_id, profile_id, platform, name_on_platform) \
+                 values ($1, $2, $3, $4, $5) on conflict (user_id, profile_id) where user_id is not null \
+                 do update set discord_user_id = excluded.discord_user_id, name_on_platform = excluded.name_on_platform \
+                 returning *",
+            )
+            .bind(user_id),
+            None => sqlx::query_as::<_, ProfileLink>(
+                "insert into ubi_profile_links (user_id, discord_user_id, profile_id, platform, name_on_platform) \
+                 values ($1, $2, $3, $4, $5) returning *",
+            )
+            .bind(Option::<Uuid>::None),
+        }
+        .bind(link.discord_user_id)
+        .bind(link.profile_id)
+        .bind(link.platform)
+        .bind(link.name_on_platform)
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(link)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_user_id(&self, user_id: Uuid) -> Result<Vec<ProfileLink>> {
+        let links = sqlx::query_as::<_, ProfileLink>(
+            "select * from ubi_profile_links where user_id = $1 order by created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(links)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_discord_user(&self, discord_user_id: &str) -> Result<Option<ProfileLink>> {
+        let link = sqlx::query_as::<_, ProfileLink>(
+            "select * from ubi_profile_links where discord_user_id = $1",
+        )
+        .bind(discord_user_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    /// returns false when the link does not exist or belongs to someone else
+    pub async fn unlink_user(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query("delete from ubi_profile_links where id = $1 and user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// every linked profile once, these are tracked in the background
+    #[instrument(skip(self))]
+    pub async fn find_tracked(&self) -> Result<Vec<TrackedProfile>> {
+        let tracked = sqlx::query_as::<_, TrackedProfile>(
+            "select distinct on (profile_id) profile_id, platform, name_on_platform \
+             from ubi_profile_links order by profile_id, created_at desc",
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(tracked)
+    }
+
+    /// linked profiles ranked by the mmr of their best region
+    #[instrument(skip(self))]
+    pub async fn leaderboard(&self, limit: i64) -> Result<Vec<LeaderboardEntry>> {
+        let entries = sqlx::query_as::<_, LeaderboardEntry>(
+            "select * from ( \
+                select distinct on (l.profile_id) l.profile_id, l.platform, l.name_on_platform, s.region, s.rank, s.mmr \
+                from ubi_profile_links l \
+                join lateral ( \
+                    select distinct on (region) region, rank, mmr from player_snapshots p \
+                    where p.profile_id = l.profile_id order by region, created_at desc \
+                ) s on true \
+                order by l.profile_id, s.mmr desc \
+             ) best order by mmr desc limit $1",
+        )
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+}
+
+impl FromRequest for ProfileLinkRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(ProfileLinkRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
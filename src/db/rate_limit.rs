@@ -0,0 +1,53 @@
+// db rate_limit
+use crate::models::rate_limit::RateLimitBucket;
+use color_eyre::Result;
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::sync::Arc;
+use tracing::instrument;
+
+/// RateLimitRepository, snapshot of the RateLimiter buckets
+pub struct RateLimitRepository {
+    pool: Arc<PgPool>,
+}
+
+impl RateLimitRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        RateLimitRepository { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_all(&self) -> Result<Vec<RateLimitBucket>> {
+        let buckets = sqlx::query_as::<_, RateLimitBucket>("select * from rate_limit_buckets")
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(buckets)
+    }
+
+    /// replaces the stored snapshot, buckets that refilled completely are not passed in
+    #[instrument(skip(self, buckets))]
+    pub async fn replace_all(&self, buckets: Vec<RateLimitBucket>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("delete from rate_limit_buckets")
+            .execute(&mut tx)
+            .await?;
+
+        for bucket in buckets {
+            sqlx::query(
+                "insert into rate_limit_buckets (bucket_key, tokens, updated_at, capacity, refill_per_minute) \
+                 values ($1, $2, $3, $4, $5)",
+            )
+            .bind(bucket.bucket_key)
+            .bind(bucket.tokens)
+            .bind(bucket.updated_at)
+            .bind(bucket.capacity)
+            .bind(bucket.refill_per_minute)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+// db recovery_code
+use crate::errors::AppError;
+use actix_web::{web::Data, FromRequest};
+use chrono::Utc;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::PgPool;
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct RecoveryCodeRepository {
+    pool: Arc<PgPool>,
+}
+
+impl RecoveryCodeRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        RecoveryCodeRepository { pool }
+    }
+
+    /// drops previous codes of the user and stores the new hashes
+    #[instrument(skip(self, code_hashes))]
+    pub async fn replace_all(&self, user_id: Uuid, code_hashes: Vec<String>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("delete from recovery_codes where user_id = $1")
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+
+        for code_hash in code_hashes {
+            sqlx::query("insert into recovery_codes (user_id, code_hash) values ($1, $2)")
+                .bind(user_id)
+                .bind(code_hash)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// marks a matching unused code as used, codes work once
+    #[instrument(skip(self, code_hash))]
+    pub async fn use_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool> {
+        let updated = sqlx::query(
+            "update recovery_codes set used_at = $3 where user_id = $1 and code_hash = $2 and used_at is null",
+        )
+        .bind(user_id)
+        .bind(code_hash)
+        .bind(Utc::now().naive_utc())
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(updated > 0)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete_all(&self, user_id: Uuid) -> Result<u64> {
+        let deleted = sqlx::query("delete from recovery_codes where user_id = $1")
+            .bind(user_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(deleted)
+    }
+}
+
+impl FromRequest for RecoveryCodeRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(RecoveryCodeRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// db session
+use crate::{
+    errors::AppError,
+    models::session::{NewUserSession, UserSession},
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::Utc;
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct SessionRepository {
+    pool: Arc<PgPool>,
+}
+
+impl SessionRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        SessionRepository { pool }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn create(&self, session: NewUserSession) -> Result<UserSession> {
+        let session = sqlx::query_as::<_, UserSession>(
+            "insert into sessions (user_id, ip_address, user_agent, expires_at, impersonated_by) \
+             values ($1, $2, $3, $4, $5) returning *",
+        )
+        .bind(session.user_id)
+        .bind(session.ip_address)
+        .bind(session.user_agent)
+        .bind(session.expires_at)
+        .bind(session.impersonated_by)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    /// session exists, belongs to user, is not revoked and not expired
+    #[instrument(skip(self))]
+    pub async fn find_active(&self, id: Uuid, user_id: Uuid) -> Result<Option<UserSession>> {
+        let maybe_session = sqlx::query_as::<_, UserSession>(
+            "select * from sessions where id = $1 and user_id = $2 and revoked_at is null and expires_at > $3",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_session)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_user_id(&self, user_id: Uuid, limit: i64) -> Result<Vec<UserSession>> {
+        let sessions = sqlx::query_as::<_, UserSession>(
+            "select * from sessions where user_id = $1 order by created_at desc limit $2",
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    /// revokes every active session of the user, except `keep` when given
+    #[instrument(skip(self))]
+    pub async fn revoke_all(&self, user_id: Uuid, keep: Option<Uuid>) -> Result<u64> {
+        let revoked = sqlx::query(
+            "update sessions set revoked_at = $3 where user_id = $1 and revoked_at is null and ($2::uuid is null or id <> $2)",
+        )
+        .bind(user_id)
+        .bind(keep)
+        .bind(Utc::now().naive_utc())
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(revoked)
+    }
+}
+
+impl FromRequest for SessionRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(SessionRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
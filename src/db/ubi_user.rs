@@ -1,6 +1,6 @@
 // db ubi_user.rs
 use crate::errors::AppError;
-use crate::models::ubi_user::{NewUbiUser, UbiUser, UpdateUbiUser};
+use crate::models::ubi_user::{UbiSessionHistory, UbiUser, UbiUserSession};
 use actix_web::{web::Data, FromRequest};
 use color_eyre::Result;
 use futures::future::{ready, Ready};
@@ -19,32 +19,46 @@ impl UbiUserRepository {
         UbiUserRepository { pool }
     }
 
-    pub async fn create(&self, new_user: NewUbiUser) -> Result<UbiUser> {
-        //insert into DB uses trait PgQueryAs
+    /// stores a freshly issued or renewed session, one row per email, and
+    /// records it in ubi_session_history
+    pub async fn upsert_session(&self, session: UbiUserSession) -> Result<UbiUser> {
+        let mut tx = self.pool.begin().await?;
+
         let user = sqlx::query_as::<_, UbiUser>(
-            "insert into ubi_users (email, password, token, expiration) values ($1, $2, $3, $4) returning *",
+            "insert into ubi_users (email, password, token, expiration) values ($1, $2, $3, $4) \
+             on conflict (email) do update set password = excluded.password, token = excluded.token, \
+             expiration = excluded.expiration returning *",
         )
-        .bind(new_user.email)
-        .bind(new_user.password)
-        .bind(new_user.token)
-        .bind(new_user.expiration)
-        .fetch_one(&*self.pool)
+        .bind(session.email)
+        .bind(session.password)
+        .bind(session.token)
+        .bind(session.expiration)
+        .fetch_one(&mut tx)
         .await?;
 
+        sqlx::query("insert into ubi_session_history (ubi_user_id, kind, expiration) values ($1, $2, $3)")
+            .bind(user.id)
+            .bind(session.kind)
+            .bind(user.expiration)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
         Ok(user)
     }
 
-    pub async fn update_ubi_user(&self, user_id: Uuid, profile: UpdateUbiUser) -> Result<UbiUser> {
-        let user = sqlx::query_as::<_, UbiUser>(
-            "update ubi_users set token = $2, expiration = $3 where id = $1 returning *",
+    /// latest sessions issued for a service account
+    pub async fn find_history(&self, email: &str, limit: i64) -> Result<Vec<UbiSessionHistory>> {
+        let history = sqlx::query_as::<_, UbiSessionHistory>(
+            "select h.* from ubi_session_history h join ubi_users u on u.id = h.ubi_user_id \
+             where u.email = $1 order by h.created_at desc limit $2",
         )
-        .bind(user_id)
-        .bind(profile.token)
-        .bind(profile.expiration)
-        .fetch_one(&*self.pool)
+        .bind(email)
+        .bind(limit)
+        .fetch_all(&*self.pool)
         .await?;
 
-        Ok(user)
+        Ok(history)
     }
 
     #[instrument(skip(self))]
@@ -57,18 +71,29 @@ impl UbiUserRepository {
         Ok(maybe_user)
     }
 
+    /// stored service accounts that were added to the pool
+    pub async fn find_pool_members(&self) -> Result<Vec<UbiUser>> {
+        let users = sqlx::query_as::<_, UbiUser>("select * from ubi_users where pool_member order by created_at")
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(users)
+    }
+
     #[instrument(skip(self))]
-    pub async fn find_by_id(&self, id: Uuid) -> Result<Option<UbiUser>> {
-        let maybe_user = sqlx::query_as::<_, UbiUser>("select * from ubi_users where id = $1")
-            .bind(id)
-            .fetch_optional(&*self.pool)
+    pub async fn set_pool_member(&self, email: &str, pool_member: bool) -> Result<()> {
+        sqlx::query("update ubi_users set pool_member = $2 where email = $1")
+            .bind(email)
+            .bind(pool_member)
+            .execute(&*self.pool)
             .await?;
 
-        Ok(maybe_user)
+        Ok(())
     }
 
-    pub async fn delete_by_id(&self, id: Uuid) -> Result<Option<UbiUser>> {
-        let maybe_user = sqlx::query_as::<_, UbiUser>("delete from ubi_users where id = $1")
+    #[instrument(skip(self))]
+    pub async fn find_by_id(&self, id: Uuid) -> Result<Option<UbiUser>> {
+        let maybe_user = sqlx::query_as::<_, UbiUser>("select * from ubi_users where id = $1")
             .bind(id)
             .fetch_optional(&*self.pool)
             .await?;
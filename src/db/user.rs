@@ -2,7 +2,8 @@
 use crate::{
     config::crypto::CryptoService,
     errors::AppError,
-    models::user::UpdateProfile,
+    models::location::{LocationInput, UserLocation},
+    models::user::{Badge, ProfilePrivacy, UpdateProfile},
     models::user::{NewUser, User},
 };
 use actix_web::{web::Data, FromRequest};
@@ -12,7 +13,6 @@ use sqlx::{postgres::PgQueryAs, PgPool};
 use std::{ops::Deref, sync::Arc};
 use tracing::instrument;
 use uuid::Uuid;
-use crate::models::user::{UserLocation, UpdateUserLocation};
 
 pub struct UserRepository {
     pool: Arc<PgPool>,
@@ -38,36 +38,92 @@ impl UserRepository {
         Ok(user)
     }
 
-    pub async fn update_profile(&self, user_id: Uuid, profile: UpdateProfile) -> Result<User> {
+    /// user and locations change together. The row is locked first, so with
+    /// `if_match` a concurrent write makes this return None instead
+    #[instrument(skip(self, profile))]
+    pub async fn update_profile(
+        &self,
+        user_id: Uuid,
+        profile: UpdateProfile,
+        if_match: Option<&str>,
+    ) -> Result<Option<User>> {
+        let mut tx = self.pool.begin().await?;
 
-        if let Some(mut locations) = profile.locations {
-            println!("updating location {:#?}", &locations);
-            let update_location = UpdateUserLocation::create_from_vec(locations.as_mut());
+        let user = sqlx::query_as::<_, User>("select * from users where id = $1 for update")
+            .bind(user_id)
+            .fetch_one(&mut tx)
+            .await?;
+        if let Some(if_match) = if_match {
+            if !user.matches(if_match) {
+                return Ok(None);
+            }
+        }
+        if profile.is_empty() {
+            return Ok(Some(user));
+        }
 
-            let could_have_location = self.find_location_by_user_id(user_id)
+        if let Some(locations) = profile.locations {
+            sqlx::query("delete from user_locations where user_id = $1")
+                .bind(user_id)
+                .execute(&mut tx)
                 .await?;
 
-            match could_have_location {
-                Some(location) => {
-                    self.update_user_location(location.id, update_location).await?;
-                },
-                None => {
-                    self.create_user_location(user_id, update_location).await?;
-                },
+            for location in locations.unwrap_or_default() {
+                let LocationInput { label, address } = location;
+                sqlx::query(
+                    "insert into user_locations (user_id, label, street, city, state, postal_code, country) \
+                     values ($1, $2, $3, $4, $5, $6, $7)",
+                )
+                .bind(user_id)
+                .bind(label)
+                .bind(address.street)
+                .bind(address.city)
+                .bind(address.state)
+                .bind(address.postal_code)
+                .bind(address.country)
+                .execute(&mut tx)
+                .await?;
             }
         }
 
+        // always written, so a locations only change still moves updated_at
         let user = sqlx::query_as::<_, User>(
             "update users set full_name = $2, bio = $3, image = $4 where id = $1 returning *",
         )
         .bind(user_id)
-        .bind(profile.full_name)
-        .bind(profile.bio)
-        .bind(profile.image)
-        .fetch_one(&*self.pool)
+        .bind(profile.full_name.unwrap_or(user.full_name))
+        .bind(profile.bio.unwrap_or(user.bio))
+        .bind(profile.image.unwrap_or(user.image))
+        .fetch_one(&mut tx)
         .await?;
 
-        Ok(user)
+        tx.commit().await?;
+        Ok(Some(user))
+    }
+
+    /// points users.image at new avatar thumbnails, or clears it with None.
+    /// Returns the prefix of the replaced upload, whose files can go
+    #[instrument(skip(self))]
+    pub async fn set_avatar(&self, user_id: Uuid, avatar: Option<(String, String)>) -> Result<(User, Option<String>)> {
+        let mut tx = self.pool.begin().await?;
+
+        let (previous,) = sqlx::query_as::<_, (Option<String>,)>("select avatar_key from users where id = $1 for update")
+            .bind(user_id)
+            .fetch_one(&mut tx)
+            .await?;
+        let (image, avatar_key) = match avatar {
+            Some((image, avatar_key)) => (Some(image), Some(avatar_key)),
+            None => (None, None),
+        };
+        let user = sqlx::query_as::<_, User>("update users set image = $2, avatar_key = $3 where id = $1 returning *")
+            .bind(user_id)
+            .bind(image)
+            .bind(avatar_key)
+            .fetch_one(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok((user, previous))
     }
 
     #[instrument(skip(self))]
@@ -80,6 +136,72 @@ impl UserRepository {
         Ok(maybe_user)
     }
 
+    /// login accepts either the username or the email address,
+    /// usernames never contain '@' so the two can not be confused
+    #[instrument(skip(self))]
+    pub async fn find_by_login(&self, login: &str) -> Result<Option<User>> {
+        if login.contains('@') {
+            self.find_by_email(login).await
+        } else {
+            self.find_by_username(login).await
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        let maybe_user = sqlx::query_as::<_, User>("select * from users where lower(email) = lower($1)")
+            .bind(email)
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(maybe_user)
+    }
+
+    #[instrument(skip(self, password, crypto_service))]
+    pub async fn update_password(&self, user_id: Uuid, password: String, crypto_service: &CryptoService) -> Result<User> {
+        let password_hash = crypto_service.hash_password(password).await?;
+        let user = sqlx::query_as::<_, User>(
+            "update users set password_hash = $2, password_reset_required = false, updated_at = CURRENT_TIMESTAMP \
+             where id = $1 returning *",
+        )
+        .bind(user_id)
+        .bind(password_hash)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// accepts a TOTP time step once. Steps up to the last accepted one are
+    /// replays of a code already used (or older than it)
+    #[instrument(skip(self))]
+    pub async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool> {
+        let updated = sqlx::query(
+            "update users set totp_last_step = $2 where id = $1 and (totp_last_step is null or totp_last_step < $2)",
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(updated > 0)
+    }
+
+    /// stores a pending secret (enabled = false) or turns 2fa on / off
+    #[instrument(skip(self, totp_secret))]
+    pub async fn update_totp(&self, user_id: Uuid, totp_secret: Option<String>, enabled: bool) -> Result<User> {
+        let user = sqlx::query_as::<_, User>(
+            "update users set totp_secret = $2, totp_enabled = $3, updated_at = CURRENT_TIMESTAMP where id = $1 returning *",
+        )
+        .bind(user_id)
+        .bind(totp_secret)
+        .bind(enabled)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
     #[instrument(skip(self))]
     pub async fn find_by_id(&self, id: Uuid) -> Result<Option<User>> {
         let maybe_user = sqlx::query_as::<_, User>("select * from users where id = $1")
@@ -90,44 +212,60 @@ impl UserRepository {
         Ok(maybe_user)
     }
 
-    pub async fn create_user_location(&self, user_id: Uuid, update_location: UpdateUserLocation) -> Result<UserLocation> {
-        let user_location = sqlx::query_as::<_, UserLocation>(
-            "insert into user_locations (user_id, street, city, state, country) values ($1, $2, $3, $4, $5) returning *",
-        )
+    #[instrument(skip(self))]
+    pub async fn find_privacy(&self, user_id: Uuid) -> Result<ProfilePrivacy> {
+        let privacy = sqlx::query_as::<_, ProfilePrivacy>("select * from users where id = $1")
             .bind(user_id)
-            .bind(update_location.street)
-            .bind(update_location.city)
-            .bind(update_location.state)
-            .bind(update_location.country)
             .fetch_one(&*self.pool)
             .await?;
 
-        Ok(user_location)
+        Ok(privacy)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn set_privacy(&self, user_id: Uuid, privacy: ProfilePrivacy) -> Result<ProfilePrivacy> {
+        let privacy = sqlx::query_as::<_, ProfilePrivacy>(
+            "update users set show_full_name = $2, show_bio = $3, show_image = $4, show_locations = $5, \
+             show_ubi_profiles = $6, show_badges = $7 where id = $1 returning *",
+        )
+        .bind(user_id)
+        .bind(privacy.show_full_name)
+        .bind(privacy.show_bio)
+        .bind(privacy.show_image)
+        .bind(privacy.show_locations)
+        .bind(privacy.show_ubi_profiles)
+        .bind(privacy.show_badges)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(privacy)
     }
 
-    pub async fn update_user_location(&self, location_id: Uuid, location: UpdateUserLocation) -> Result<UserLocation> {
-        let user_location = sqlx::query_as::<_, UserLocation>(
-            "update user_locations set street = $2, city = $3, state = $4, country = $5 where id = $1 returning *",
+    /// badges of the profiles the user links
+    #[instrument(skip(self))]
+    pub async fn find_badges(&self, user_id: Uuid) -> Result<Vec<Badge>> {
+        let badges = sqlx::query_as::<_, Badge>(
+            "select b.badge, l.profile_id, l.platform, l.name_on_platform, b.awarded_at \
+             from ubi_profile_links l join profile_badges b on b.profile_id = l.profile_id \
+             where l.user_id = $1 order by b.awarded_at, l.profile_id",
         )
-            .bind(location_id)
-            .bind(location.street)
-            .bind(location.city)
-            .bind(location.state)
-            .bind(location.country)
-            .fetch_one(&*self.pool)
-            .await?;
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
 
-        Ok(user_location)
+        Ok(badges)
     }
 
     #[instrument(skip(self))]
-    pub async fn find_location_by_user_id(&self, user_id: Uuid) -> Result<Option<UserLocation>> {
-        let maybe_user = sqlx::query_as::<_, UserLocation>("select * from user_locations where user_id = $1")
-            .bind(user_id)
-            .fetch_optional(&*self.pool)
-            .await?;
+    pub async fn find_locations(&self, user_id: Uuid) -> Result<Vec<UserLocation>> {
+        let locations = sqlx::query_as::<_, UserLocation>(
+            "select * from user_locations where user_id = $1 order by created_at, label",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
 
-        Ok(maybe_user)
+        Ok(locations)
     }
 }
 
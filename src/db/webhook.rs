@@ -0,0 +1,235 @@
+// db webhook
+use crate::{
+    errors::AppError,
+    models::webhook::{
+        DueDelivery, NewWebhook, Webhook, WebhookDelivery, DELIVERY_DELIVERED, DELIVERY_FAILED, DELIVERY_PENDING,
+    },
+};
+use actix_web::{web::Data, FromRequest};
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::Result;
+use futures::future::{ready, Ready};
+use sqlx::{postgres::PgQueryAs, PgPool};
+use std::{ops::Deref, sync::Arc};
+use tracing::instrument;
+use uuid::Uuid;
+
+pub struct WebhookRepository {
+    pool: Arc<PgPool>,
+}
+
+impl WebhookRepository {
+    pub fn new(pool: Arc<PgPool>) -> Self {
+        WebhookRepository { pool }
+    }
+
+    #[instrument(skip(self, webhook))]
+    pub async fn create(&self, webhook: NewWebhook) -> Result<Webhook> {
+        let webhook = sqlx::query_as::<_, Webhook>(
+            "insert into webhooks (user_id, url, secret, events, profile_id, mmr_threshold) \
+             values ($1, $2, $3, $4, $5, $6) returning *",
+        )
+        .bind(webhook.user_id)
+        .bind(webhook.url)
+        .bind(webhook.secret)
+        .bind(webhook.events)
+        .bind(webhook.profile_id)
+        .bind(webhook.mmr_threshold)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(webhook)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn count_by_user_id(&self, user_id: Uuid) -> Result<i64> {
+        let (count,) = sqlx::query_as::<_, (i64,)>("select count(*) from webhooks where user_id = $1")
+            .bind(user_id)
+            .fetch_one(&*self.pool)
+            .await?;
+
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_user_id(&self, user_id: Uuid) -> Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, Webhook>(
+            "select * from webhooks where user_id = $1 order by created_at desc",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(webhooks)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_by_id(&self, id: Uuid, user_id: Uuid) -> Result<Option<Webhook>> {
+        let maybe_webhook = sqlx::query_as::<_, Webhook>("select * from webhooks where id = $1 and user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&*self.pool)
+            .await?;
+
+        Ok(maybe_webhook)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn delete(&self, id: Uuid, user_id: Uuid) -> Result<bool> {
+        let deleted = sqlx::query("delete from webhooks where id = $1 and user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// active webhooks interested in a profile, either by id or because
+    /// their owner linked it
+    #[instrument(skip(self))]
+    pub async fn find_for_profile(&self, profile_id: &str) -> Result<Vec<Webhook>> {
+        let webhooks = sqlx::query_as::<_, Webhook>(
+            "select w.* from webhooks w where w.active and (w.profile_id = $1 or (w.profile_id is null and exists ( \
+                select 1 from ubi_profile_links l where l.user_id = w.user_id and l.profile_id = $1 \
+             )))",
+        )
+        .bind(profile_id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(webhooks)
+    }
+
+    #[instrument(skip(self, payload))]
+    pub async fn create_delivery(
+        &self,
+        webhook_id: Uuid,
+        event: &str,
+        payload: &str,
+        replay_of: Option<Uuid>,
+    ) -> Result<WebhookDelivery> {
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(
+            "insert into webhook_deliveries (webhook_id, event, payload, replay_of) values ($1, $2, $3, $4) returning *",
+        )
+        .bind(webhook_id)
+        .bind(event)
+        .bind(payload)
+        .bind(replay_of)
+        .fetch_one(&*self.pool)
+        .await?;
+
+        Ok(delivery)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_deliveries(&self, webhook_id: Uuid, limit: i64) -> Result<Vec<WebhookDelivery>> {
+        let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+            "select * from webhook_deliveries where webhook_id = $1 order by created_at desc limit $2",
+        )
+        .bind(webhook_id)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_delivery(&self, id: Uuid, webhook_id: Uuid) -> Result<Option<WebhookDelivery>> {
+        let maybe_delivery = sqlx::query_as::<_, WebhookDelivery>(
+            "select * from webhook_deliveries where id = $1 and webhook_id = $2",
+        )
+        .bind(id)
+        .bind(webhook_id)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(maybe_delivery)
+    }
+
+    /// takes up to `limit` pending deliveries that are due and pushes their
+    /// next attempt back by `lease_until`, so other workers skip them meanwhile
+    #[instrument(skip(self))]
+    pub async fn claim_due(&self, limit: i64, lease_until: NaiveDateTime) -> Result<Vec<DueDelivery>> {
+        let due = sqlx::query_as::<_, DueDelivery>(
+            "with claimed as ( \
+                update webhook_deliveries set next_attempt_at = $3 where id in ( \
+                    select id from webhook_deliveries where status = $4 and next_attempt_at <= $2 \
+                    order by next_attempt_at limit $1 for update skip locked \
+                ) returning * \
+             ) \
+             select c.id, c.webhook_id, c.event, c.payload, c.attempts, w.url, w.secret \
+             from claimed c join webhooks w on w.id = c.webhook_id",
+        )
+        .bind(limit)
+        .bind(Utc::now().naive_utc())
+        .bind(lease_until)
+        .bind(DELIVERY_PENDING)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(due)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn mark_delivered(&self, id: Uuid, status_code: i32) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        sqlx::query(
+            "update webhook_deliveries set status = $2, attempts = attempts + 1, last_status_code = $3, \
+             last_error = null, delivered_at = $4, next_attempt_at = $4 where id = $1",
+        )
+        .bind(id)
+        .bind(DELIVERY_DELIVERED)
+        .bind(status_code)
+        .bind(now)
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// records a failed attempt, without `retry_at` the delivery is given up
+    #[instrument(skip(self))]
+    pub async fn mark_attempt_failed(
+        &self,
+        id: Uuid,
+        status_code: Option<i32>,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+    ) -> Result<()> {
+        sqlx::query(
+            "update webhook_deliveries set attempts = attempts + 1, last_status_code = $2, last_error = $3, \
+             status = case when $4::timestamp is null then $5 else status end, \
+             next_attempt_at = coalesce($4, next_attempt_at) where id = $1",
+        )
+        .bind(id)
+        .bind(status_code)
+        .bind(error)
+        .bind(retry_at)
+        .bind(DELIVERY_FAILED)
+        .execute(&*self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl FromRequest for WebhookRepository {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let pool_result = Data::<PgPool>::from_request(req, payload).into_inner();
+
+        match pool_result {
+            Ok(pool) => ready(Ok(WebhookRepository::new(pool.deref().clone()))),
+            _ => ready(Err(AppError::NOT_AUTHORIZED.default())),
+        }
+    }
+}
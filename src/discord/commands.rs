@@ -0,0 +1,215 @@
+//discord slash commands
+// /stats, /rank and /link talk to Ubisoft and are answered through a deferred
+// response, /leaderboard only reads stored snapshots and answers right away
+
+use super::interaction::Interaction;
+use crate::{
+    db::{identity::IdentityRepository, profile_link::ProfileLinkRepository},
+    errors::AppError,
+    handlers::AppResult,
+    models::profile_link::NewProfileLink,
+    tracker::Tracker,
+    ubi::{
+        player_card::{rank_name, PlayerCard, PLATFORMS},
+        ubi_api::UbiApi,
+    },
+};
+use tracing::debug;
+
+const LEADERBOARD_SIZE: i64 = 10;
+
+/// everything a command needs, built per interaction
+pub struct CommandContext {
+    pub ubi_api: UbiApi,
+    pub links: ProfileLinkRepository,
+    pub tracker: Tracker,
+    pub identities: IdentityRepository,
+}
+
+/// commands that wait on Ubisoft and must be deferred
+pub fn is_slow(command: &str) -> bool {
+    matches!(command, "stats" | "rank" | "link")
+}
+
+/// runs a command and returns the message to post, errors included
+pub async fn run(context: &CommandContext, interaction: &Interaction) -> String {
+    let command = interaction
+        .data
+        .as_ref()
+        .map(|data| data.name.as_str())
+        .unwrap_or_default();
+
+    let result = match command {
+        "stats" => stats(context, interaction).await,
+        "rank" => rank(context, interaction).await,
+        "link" => link(context, interaction).await,
+        "leaderboard" => leaderboard(context).await,
+        _ => Err(AppError::NOT_FOUND.message(format!("Unknown command /{}", command))),
+    };
+
+    result.unwrap_or_else(|e| {
+        debug!("Discord command /{} failed. {}", command, e);
+        format!(":warning: {}", e.message())
+    })
+}
+
+fn platform_option(interaction: &Interaction) -> AppResult<String> {
+    let platform = interaction
+        .option("platform")
+        .unwrap_or_else(|| "uplay".to_string());
+    if !PLATFORMS.contains(&platform.as_str()) {
+        return Err(AppError::INVALID_INPUT.message("Platform must be one of uplay, psn or xbl.".to_string()));
+    }
+    Ok(platform)
+}
+
+// explicit name option, otherwise the invoker's linked profile
+async fn target(context: &CommandContext, interaction: &Interaction) -> AppResult<(String, String)> {
+    if let Some(name) = interaction.option("name") {
+        return Ok((platform_option(interaction)?, name));
+    }
+
+    let discord_user = interaction
+        .invoker()
+        .ok_or_else(|| AppError::INVALID_INPUT.default())?;
+    let link = context
+        .links
+        .find_by_discord_user(&discord_user.id)
+        .await?
+        .ok_or_else(|| {
+            AppError::INVALID_INPUT.message("Pass a name, or use /link to set your profile first.".to_string())
+        })?;
+
+    Ok((link.platform, link.name_on_platform))
+}
+
+// fetches the card and keeps the snapshots for trends and the leaderboard
+async fn card(context: &CommandContext, interaction: &Interaction) -> AppResult<PlayerCard> {
+    let (platform, name) = target(context, interaction).await?;
+    let card = context.ubi_api.player_card(&platform, &name).await?;
+
+    if let Err(e) = context.tracker.record_card(&card).await {
+        debug!("Error recording snapshots for {}. {:?}", card.profile.profile_id, e);
+    }
+    Ok(card)
+}
+
+async fn stats(context: &CommandContext, interaction: &Interaction) -> AppResult<String> {
+    let card = card(context, interaction).await?;
+
+    let mut lines = vec![format!(
+        "**{}** ({}) · Level {}",
+        card.profile.name_on_platform,
+        card.profile.platform_type,
+        card.level.map_or_else(|| "?".to_string(), |level| level.to_string())
+    )];
+    match &card.summary {
+        Some(summary) => {
+            for (queue, stats) in [("Ranked", &summary.ranked), ("Casual", &summary.casual)].iter() {
+                lines.push(format!(
+                    "{}: K/D {:.2} · Win rate {:.1}% · {}W {}L · {}h played",
+                    queue,
+                    stats.kd,
+                    stats.win_rate,
+                    stats.wins,
+                    stats.losses,
+                    stats.time_played_secs / 3600
+                ));
+            }
+        }
+        None => lines.push("Summary stats are unavailable right now.".to_string()),
+    }
+
+    Ok(lines.join("\n"))
+}
+
+async fn rank(context: &CommandContext, interaction: &Interaction) -> AppResult<String> {
+    let card = card(context, interaction).await?;
+
+    let mut regions: Vec<_> = card.ranks.iter().collect();
+    regions.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut lines = vec![format!("**{}** ({})", card.profile.name_on_platform, card.profile.platform_type)];
+    for (region, stats) in regions {
+        lines.push(format!(
+            "{}: {} · {:.0} MMR (max {:.0}) · {}W {}L",
+            region.to_uppercase(),
+            rank_name(stats.rank),
+            stats.mmr,
+            stats.max_mmr,
+            stats.wins,
+            stats.losses
+        ));
+    }
+    if card.ranks.is_empty() {
+        lines.push("No ranked stats this season.".to_string());
+    }
+
+    Ok(lines.join("\n"))
+}
+
+async fn link(context: &CommandContext, interaction: &Interaction) -> AppResult<String> {
+    let discord_user = interaction
+        .invoker()
+        .ok_or_else(|| AppError::INVALID_INPUT.default())?;
+    let name = interaction
+        .option("name")
+        .ok_or_else(|| AppError::INVALID_INPUT.message("Pass the name of your profile.".to_string()))?;
+    let platform = platform_option(interaction)?;
+
+    let profile = context
+        .ubi_api
+        .find_profile(name.clone(), platform.clone())
+        .await?
+        .profiles
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::NOT_FOUND.message(format!("Profile {:?} not found on {}", name, platform)))?;
+
+    // Discord users who logged in to zbot with Discord get it on their account too
+    let user_id = context
+        .identities
+        .find("discord", &discord_user.id)
+        .await?
+        .map(|identity| identity.user_id);
+
+    let link = context
+        .links
+        .link_discord(NewProfileLink {
+            user_id,
+            discord_user_id: Some(discord_user.id.clone()),
+            profile_id: profile.profile_id,
+            platform: profile.platform_type,
+            name_on_platform: profile.name_on_platform,
+        })
+        .await?;
+
+    Ok(format!(
+        "Linked **{}** ({}) to {}.",
+        link.name_on_platform, link.platform, discord_user.username
+    ))
+}
+
+async fn leaderboard(context: &CommandContext) -> AppResult<String> {
+    let entries = context.links.leaderboard(LEADERBOARD_SIZE).await?;
+    if entries.is_empty() {
+        return Ok("Nobody is on the leaderboard yet, use /link and /rank.".to_string());
+    }
+
+    let lines: Vec<String> = entries
+        .iter()
+        .enumerate()
+        .map(|(position, entry)| {
+            format!(
+                "{}. **{}** ({}) · {} · {:.0} MMR",
+                position + 1,
+                entry.name_on_platform,
+                entry.region.to_uppercase(),
+                rank_name(entry.rank),
+                entry.mmr
+            )
+        })
+        .collect();
+
+    Ok(lines.join("\n"))
+}
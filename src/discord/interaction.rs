@@ -0,0 +1,107 @@
+//discord interaction payloads, only the fields the bot reads
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PING: u8 = 1;
+pub const APPLICATION_COMMAND: u8 = 2;
+
+pub const PONG: u8 = 1;
+pub const CHANNEL_MESSAGE: u8 = 4;
+pub const DEFERRED_CHANNEL_MESSAGE: u8 = 5;
+
+#[derive(Debug, Deserialize)]
+pub struct DiscordUser {
+    pub id: String,
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Member {
+    pub user: DiscordUser,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommandOption {
+    pub name: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommandData {
+    pub name: String,
+    #[serde(default)]
+    pub options: Vec<CommandOption>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Interaction {
+    #[serde(rename = "type")]
+    pub kind: u8,
+    pub application_id: String,
+    pub token: String,
+    pub data: Option<CommandData>,
+    pub member: Option<Member>, // set in guilds
+    pub user: Option<DiscordUser>, // set in DMs
+}
+
+impl Interaction {
+    pub fn invoker(&self) -> Option<&DiscordUser> {
+        self.member
+            .as_ref()
+            .map(|member| &member.user)
+            .or(self.user.as_ref())
+    }
+
+    /// string value of a command option
+    pub fn option(&self, name: &str) -> Option<String> {
+        self.data
+            .as_ref()?
+            .options
+            .iter()
+            .find(|option| option.name == name)
+            .and_then(|option| option.value.as_str())
+            .map(|value| value.to_string())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageData {
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InteractionResponse {
+    #[serde(rename = "type")]
+    pub kind: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<MessageData>,
+}
+
+// only the invoking user sees the message
+const EPHEMERAL: u32 = 1 << 6;
+
+impl InteractionResponse {
+    pub fn pong() -> Self {
+        InteractionResponse { kind: PONG, data: None }
+    }
+
+    pub fn deferred() -> Self {
+        InteractionResponse { kind: DEFERRED_CHANNEL_MESSAGE, data: None }
+    }
+
+    pub fn message(content: String) -> Self {
+        InteractionResponse {
+            kind: CHANNEL_MESSAGE,
+            data: Some(MessageData { content, flags: None }),
+        }
+    }
+
+    pub fn ephemeral(content: String) -> Self {
+        InteractionResponse {
+            kind: CHANNEL_MESSAGE,
+            data: Some(MessageData { content, flags: Some(EPHEMERAL) }),
+        }
+    }
+}
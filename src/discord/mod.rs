@@ -0,0 +1,96 @@
+//module discord
+// slash command interactions: signature verification, command handling
+// and follow-up messages for deferred responses
+pub mod commands;
+pub mod interaction;
+
+use crate::{config::DiscordConfig, errors::AppError, handlers::AppResult};
+use chrono::Utc;
+use color_eyre::Result;
+use eyre::eyre;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::debug;
+
+// signed requests older (or newer) than this are replays
+const MAX_TIMESTAMP_SKEW_SECS: i64 = 300;
+
+#[derive(Clone)]
+pub struct DiscordBot {
+    public_key: Option<Arc<Vec<u8>>>,
+    api_base: String,
+    client: reqwest::Client,
+}
+
+impl DiscordBot {
+    pub fn new(config: &DiscordConfig, client: reqwest::Client) -> Result<Self> {
+        let public_key = match &config.public_key {
+            Some(key) => {
+                let key = hex::decode(key).map_err(|_| eyre!("DISCORD.PUBLIC_KEY is not hex"))?;
+                if key.len() != 32 {
+                    return Err(eyre!("DISCORD.PUBLIC_KEY must be 32 bytes"));
+                }
+                Some(Arc::new(key))
+            }
+            None => None,
+        };
+
+        Ok(DiscordBot {
+            public_key,
+            api_base: config.api_base.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    /// Ed25519 over timestamp + body with the application public key, the
+    /// timestamp must be recent. Without a configured key every request is rejected
+    pub fn verify(&self, signature: &str, timestamp: &str, body: &[u8]) -> AppResult<()> {
+        let public_key = self.public_key.as_ref().ok_or_else(|| {
+            debug!("Discord interaction received but DISCORD.PUBLIC_KEY is not set.");
+            AppError::NOT_AUTHORIZED.default()
+        })?;
+        let signature = hex::decode(signature).map_err(|_| AppError::NOT_AUTHORIZED.default())?;
+
+        let signed_at = timestamp.parse::<i64>().map_err(|_| AppError::NOT_AUTHORIZED.default())?;
+        if (Utc::now().timestamp() - signed_at).abs() > MAX_TIMESTAMP_SKEW_SECS {
+            debug!("Stale Discord interaction timestamp {}.", signed_at);
+            return Err(AppError::NOT_AUTHORIZED.message("Request timestamp is too old.".to_string()));
+        }
+
+        let mut message = timestamp.as_bytes().to_vec();
+        message.extend_from_slice(body);
+
+        UnparsedPublicKey::new(&ED25519, public_key.as_slice())
+            .verify(&message, &signature)
+            .map_err(|_| {
+                debug!("Invalid Discord interaction signature.");
+                AppError::NOT_AUTHORIZED.message("Invalid request signature.".to_string())
+            })
+    }
+
+    /// replaces the "thinking..." placeholder of a deferred response
+    pub async fn edit_original(&self, application_id: &str, token: &str, content: &str) -> AppResult<()> {
+        let url = format!(
+            "{}/webhooks/{}/{}/messages/@original",
+            self.api_base, application_id, token
+        );
+
+        let response = self
+            .client
+            .patch(&url)
+            .json(&json!({ "content": content }))
+            .send()
+            .await
+            .map_err(|op| {
+                debug!("Error sending Discord follow-up. {:?}", op);
+                AppError::INTERNAL_ERROR.default()
+            })?;
+
+        if !response.status().is_success() {
+            debug!("Discord follow-up rejected with {}", response.status());
+            return Err(AppError::INTERNAL_ERROR.default());
+        }
+        Ok(())
+    }
+}
@@ -29,7 +29,12 @@ impl AppErrorCode {
             AppError::INVALID_INPUT => "Invalid input.",
             AppError::INVALID_CREDENTIALS => "Invalid username or password provided",
             AppError::NOT_AUTHORIZED => "Not authorized.",
+            AppError::ACCOUNT_LOCKED => "Too many failed login attempts. Try again later.",
+            AppError::FORBIDDEN => "Not allowed.",
+            AppError::RATE_LIMITED => "Too many requests. Slow down.",
+            AppError::UPSTREAM_BUSY => "Ubisoft services are busy. Try again shortly.",
             AppError::NOT_FOUND => "Item not found.",
+            AppError::PRECONDITION_FAILED => "The item was changed in the meantime. Fetch it again.",
             _ => "An unexpected error has occurred.",
         };
         AppError {
@@ -50,7 +55,17 @@ impl AppError {
     pub const INVALID_INPUT: AppErrorCode = AppErrorCode(2001);
     pub const INVALID_CREDENTIALS: AppErrorCode = AppErrorCode(3001);
     pub const NOT_AUTHORIZED: AppErrorCode = AppErrorCode(3002);
+    pub const ACCOUNT_LOCKED: AppErrorCode = AppErrorCode(3003);
+    pub const FORBIDDEN: AppErrorCode = AppErrorCode(3004);
     pub const NOT_FOUND: AppErrorCode = AppErrorCode(4001);
+    pub const PRECONDITION_FAILED: AppErrorCode = AppErrorCode(4002);
+    pub const RATE_LIMITED: AppErrorCode = AppErrorCode(5001);
+    pub const UPSTREAM_BUSY: AppErrorCode = AppErrorCode(5002);
+
+    /// message shown to the client
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 
 impl Serialize for AppErrorCode {
@@ -74,8 +89,13 @@ impl ResponseError for AppError {
         match self.code {
             AppError::INVALID_INPUT => StatusCode::BAD_REQUEST,
             AppError::NOT_FOUND => StatusCode::NOT_FOUND,
+            AppError::PRECONDITION_FAILED => StatusCode::PRECONDITION_FAILED,
             AppError::INVALID_CREDENTIALS => StatusCode::UNAUTHORIZED,
             AppError::NOT_AUTHORIZED => StatusCode::UNAUTHORIZED,
+            AppError::ACCOUNT_LOCKED => StatusCode::TOO_MANY_REQUESTS,
+            AppError::FORBIDDEN => StatusCode::FORBIDDEN,
+            AppError::RATE_LIMITED => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UPSTREAM_BUSY => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -0,0 +1,78 @@
+//handlers account
+use super::{auth::AuthenticatedUser, AppResponse};
+use crate::{
+    accounts::Accounts,
+    config::crypto::CryptoService,
+    db::{account::AccountRepository, user::UserRepository},
+    errors::AppError,
+    models::account::{DeleteAccount, ScheduledDeletion},
+};
+use actix_web::{
+    http::header::CONTENT_DISPOSITION,
+    web::{Data, Json},
+    HttpResponse,
+};
+use tracing::{debug, instrument};
+
+/// DELETE /me, the account is purged after the grace period unless
+/// restored before
+#[instrument(skip(user, payload, repository, accounts_db, accounts, crypto_service))]
+pub async fn delete_account(
+    user: AuthenticatedUser,
+    payload: Json<DeleteAccount>,
+    repository: UserRepository,
+    accounts_db: AccountRepository,
+    accounts: Data<Accounts>,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    user.require_own_login()?;
+
+    let user = repository
+        .find_by_id(user.0)
+        .await?
+        .ok_or(AppError::INTERNAL_ERROR)?;
+    let valid = crypto_service
+        .verify_password(&payload.password, &user.password_hash)
+        .await?;
+    if !valid {
+        debug!("Invalid password.");
+        return Err(AppError::INVALID_CREDENTIALS.message("Invalid password.".to_string()));
+    }
+
+    let deletion_scheduled_for = match user.deletion_scheduled_for {
+        Some(scheduled) => scheduled,
+        None => accounts_db
+            .schedule_deletion(user.id, Some(accounts.deletion_date()))
+            .await?
+            .deletion_scheduled_for
+            .ok_or(AppError::INTERNAL_ERROR)?,
+    };
+
+    Ok(HttpResponse::Accepted().json(ScheduledDeletion { deletion_scheduled_for }))
+}
+
+/// POST /me/restore, cancels a scheduled deletion
+#[instrument(skip(user, accounts_db))]
+pub async fn restore_account(user: AuthenticatedUser, accounts_db: AccountRepository) -> AppResponse {
+    user.require_session()?;
+
+    let user = accounts_db.schedule_deletion(user.0, None).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// GET /me/export, a json file of everything stored about the user
+#[instrument(skip(user, accounts_db))]
+pub async fn export_account(user: AuthenticatedUser, accounts_db: AccountRepository) -> AppResponse {
+    user.require_session()?;
+
+    let export = accounts_db.export(user.0).await?;
+    let filename = format!(
+        "zbot-export-{}-{}.json",
+        export.user.username,
+        export.exported_at.format("%Y%m%d")
+    );
+
+    Ok(HttpResponse::Ok()
+        .header(CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .json(export))
+}
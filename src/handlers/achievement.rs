@@ -0,0 +1,88 @@
+//handlers achievement
+use super::{auth::AuthenticatedUser, AppResponse};
+use crate::{
+    achievements::Achievements,
+    db::user::UserRepository,
+    errors::AppError,
+    models::{
+        achievement::EarnedBadge,
+        api_key::PROFILE_READ,
+        user::{Badge, ROLE_ADMIN},
+    },
+};
+use actix_web::{
+    web::{Data, Path},
+    HttpResponse,
+};
+use tracing::instrument;
+
+/// GET /achievements, every badge that can be earned
+#[instrument(skip(achievements))]
+pub async fn list_achievements(achievements: Data<Achievements>) -> AppResponse {
+    Ok(HttpResponse::Ok().json(achievements.definitions()))
+}
+
+/// GET /me/badges
+#[instrument(skip(user, repository, achievements))]
+pub async fn my_badges(
+    user: AuthenticatedUser,
+    repository: UserRepository,
+    achievements: Data<Achievements>,
+) -> AppResponse {
+    user.require_scope(PROFILE_READ)?;
+
+    let badges = repository.find_badges(user.0).await?;
+    Ok(HttpResponse::Ok().json(earned(badges, &achievements)))
+}
+
+/// GET /users/{username}/badges, hidden like on the profile unless
+/// the owner or an admin asks. Badges name the linked profiles, so they are
+/// hidden with them too
+#[instrument(skip(viewer, repository, achievements))]
+pub async fn user_badges(
+    username: Path<String>,
+    viewer: Option<AuthenticatedUser>,
+    repository: UserRepository,
+    achievements: Data<Achievements>,
+) -> AppResponse {
+    let not_found = || AppError::NOT_FOUND.message(format!("User {:?} not found.", username.as_str()));
+    let user = repository
+        .find_by_username(&username)
+        .await?
+        .filter(|user| user.deletion_scheduled_for.is_none())
+        .ok_or_else(not_found)?;
+
+    let full_view = match viewer.filter(|viewer| viewer.require_scope(PROFILE_READ).is_ok()) {
+        Some(viewer) if viewer.0 == user.id => true,
+        Some(viewer) if viewer.require_session().is_ok() => {
+            matches!(repository.find_by_id(viewer.0).await?, Some(found) if found.role == ROLE_ADMIN)
+        }
+        _ => false,
+    };
+    let privacy = repository.find_privacy(user.id).await?;
+    let shown = privacy.show_badges && privacy.show_ubi_profiles;
+    if !full_view && !shown {
+        return Err(AppError::FORBIDDEN.message(format!("{} keeps their badges private.", user.username)));
+    }
+
+    let badges = repository.find_badges(user.id).await?;
+    Ok(HttpResponse::Ok().json(earned(badges, &achievements)))
+}
+
+fn earned(badges: Vec<Badge>, achievements: &Achievements) -> Vec<EarnedBadge> {
+    badges
+        .into_iter()
+        .map(|badge| {
+            let definition = achievements.find(&badge.badge);
+            EarnedBadge {
+                name: definition.map(|d| d.name.clone()),
+                description: definition.map(|d| d.description.clone()),
+                badge: badge.badge,
+                profile_id: badge.profile_id,
+                platform: badge.platform,
+                name_on_platform: badge.name_on_platform,
+                awarded_at: badge.awarded_at,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,284 @@
+//handlers admin
+use super::{audit_event, auth::AdminUser, client_ip, paging, user_agent, AppResponse, AppResult};
+use crate::{
+    config::{crypto::{Auth, CryptoService}, mailer::Mailer},
+    db::{
+        admin::AdminRepository, audit::AuditRepository,
+        profile_link::ProfileLinkRepository, session::SessionRepository, user::UserRepository,
+    },
+    errors::AppError,
+    models::{
+        admin::{AdminReason, UserPage, UserSearch, STATUSES},
+        audit::{
+            AuditPage, AuditSearch, NewAuditEvent, EVENT_ADMIN_FORCE_PASSWORD_RESET, EVENT_ADMIN_IMPERSONATE, EVENT_ADMIN_SUSPEND,
+            EVENT_ADMIN_UNSUSPEND,
+        },
+        password_reset::NewPasswordReset,
+        session::NewUserSession,
+        user::{User, ROLE_ADMIN},
+    },
+};
+use actix_web::{
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+// sessions and audit entries shown per user
+const HISTORY_LIMIT: i64 = 100;
+const PASSWORD_RESET_TTL_HOURS: i64 = 24;
+const IMPERSONATION_TTL_MINUTES: i64 = 60;
+
+/// GET /admin/users, newest first
+#[instrument(skip(_admin, admin_db))]
+pub async fn search_users(
+    _admin: AdminUser,
+    Query(search): Query<UserSearch>,
+    admin_db: AdminRepository,
+) -> AppResponse {
+    if let Some(status) = &search.status {
+        if !STATUSES.contains(&status.as_str()) {
+            return Err(AppError::INVALID_INPUT.message(format!(
+                "Unknown status {:?}, expected one of {:?}.",
+                status, STATUSES
+            )));
+        }
+    }
+
+    let (page, per_page) = paging(search.page, search.per_page);
+    let (users, total) = admin_db
+        .search_users(&search, per_page, (page - 1) * per_page)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(UserPage {
+        users,
+        total,
+        page,
+        per_page,
+    }))
+}
+
+#[instrument(skip(_admin, repository))]
+pub async fn get_user(_admin: AdminUser, id: Path<Uuid>, repository: UserRepository) -> AppResponse {
+    let user = find_user(&repository, *id).await?;
+    let locations = repository.find_locations(user.id).await?;
+
+    Ok(HttpResponse::Ok().json(user.with_locations(locations.iter().map(|l| l.to_location()).collect())))
+}
+
+#[instrument(skip(_admin, repository, sessions))]
+pub async fn list_sessions(
+    _admin: AdminUser,
+    id: Path<Uuid>,
+    repository: UserRepository,
+    sessions: SessionRepository,
+) -> AppResponse {
+    let user = find_user(&repository, *id).await?;
+
+    let sessions = sessions.find_by_user_id(user.id, HISTORY_LIMIT).await?;
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[instrument(skip(_admin, repository, links))]
+pub async fn list_ubi_profiles(
+    _admin: AdminUser,
+    id: Path<Uuid>,
+    repository: UserRepository,
+    links: ProfileLinkRepository,
+) -> AppResponse {
+    let user = find_user(&repository, *id).await?;
+
+    let profiles = links.find_by_user_id(user.id).await?;
+    Ok(HttpResponse::Ok().json(profiles))
+}
+
+/// audit events done by or to the user, newest first
+#[instrument(skip(_admin, repository, audit))]
+pub async fn user_audit(
+    _admin: AdminUser,
+    id: Path<Uuid>,
+    repository: UserRepository,
+    audit: AuditRepository,
+) -> AppResponse {
+    let user = find_user(&repository, *id).await?;
+
+    let events = audit.find_by_user_id(user.id, HISTORY_LIMIT).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+/// GET /admin/audit, every filter is optional, newest first
+#[instrument(skip(_admin, audit))]
+pub async fn search_audit(
+    _admin: AdminUser,
+    Query(search): Query<AuditSearch>,
+    audit: AuditRepository,
+) -> AppResponse {
+    let (page, per_page) = paging(search.page, search.per_page);
+    let (events, total) = audit.search(&search, per_page, (page - 1) * per_page).await?;
+
+    Ok(HttpResponse::Ok().json(AuditPage {
+        events,
+        total,
+        page,
+        per_page,
+    }))
+}
+
+/// POST /admin/users/{id}/suspend, blocks logins and api keys
+/// and revokes every session
+#[instrument(skip(req, admin, payload, repository, admin_db))]
+pub async fn suspend_user(
+    req: HttpRequest,
+    admin: AdminUser,
+    id: Path<Uuid>,
+    payload: Json<AdminReason>,
+    repository: UserRepository,
+    admin_db: AdminRepository,
+) -> AppResponse {
+    payload.validate().map_err(|_| {
+        AppError::INVALID_INPUT.message("A reason of 3 to 500 characters is required.".to_string())
+    })?;
+    let user = find_user(&repository, *id).await?;
+    if user.id == admin.0 {
+        return Err(AppError::INVALID_INPUT.message("Admins can not suspend themselves.".to_string()));
+    }
+
+    let reason = payload.into_inner().reason;
+    let event = admin_event(&req, EVENT_ADMIN_SUSPEND, &admin, user.id).details(json!({ "reason": reason }));
+    let (user, revoked) = admin_db.suspend(user.id, &reason, event).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user": user,
+        "revoked_sessions": revoked,
+    })))
+}
+
+#[instrument(skip(req, admin, repository, admin_db))]
+pub async fn unsuspend_user(
+    req: HttpRequest,
+    admin: AdminUser,
+    id: Path<Uuid>,
+    repository: UserRepository,
+    admin_db: AdminRepository,
+) -> AppResponse {
+    let user = find_user(&repository, *id).await?;
+    if user.suspended_at.is_none() {
+        return Err(AppError::INVALID_INPUT.message("User is not suspended.".to_string()));
+    }
+
+    let user = admin_db
+        .unsuspend(user.id, admin_event(&req, EVENT_ADMIN_UNSUSPEND, &admin, user.id))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// POST /admin/users/{id}/password_reset, the user can only log in
+/// again after choosing a new password through the emailed link
+#[instrument(skip(req, admin, repository, admin_db, crypto_service, mailer))]
+pub async fn force_password_reset(
+    req: HttpRequest,
+    admin: AdminUser,
+    id: Path<Uuid>,
+    repository: UserRepository,
+    admin_db: AdminRepository,
+    crypto_service: Data<CryptoService>,
+    mailer: Data<Mailer>,
+) -> AppResponse {
+    let user = find_user(&repository, *id).await?;
+
+    let token = crypto_service.random_token();
+    let (user, password_reset, revoked) = admin_db
+        .force_password_reset(
+            NewPasswordReset {
+                user_id: user.id,
+                token_hash: crypto_service.hash_token(&token),
+                expires_at: (Utc::now() + Duration::hours(PASSWORD_RESET_TTL_HOURS)).naive_utc(),
+            },
+            admin_event(&req, EVENT_ADMIN_FORCE_PASSWORD_RESET, &admin, user.id),
+        )
+        .await?;
+
+    let link = mailer.link(&format!("/auth/password_reset?token={}", token));
+    mailer
+        .send(
+            &user.email,
+            "Reset your zbot password",
+            &format!(
+                "Hi {},\n\nan administrator asked you to choose a new password.\n\
+                 Open this link within {} hours to set it:\n{}\n",
+                user.username, PASSWORD_RESET_TTL_HOURS, link
+            ),
+        )
+        .await;
+
+    Ok(HttpResponse::Accepted().json(json!({
+        "message": format!("Password reset sent to {}", user.email),
+        "expires_at": password_reset.expires_at,
+        "revoked_sessions": revoked,
+    })))
+}
+
+/// POST /admin/users/{id}/impersonate, a short session as the user
+/// for support, marked with the admin and never for other admins
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(req, admin, payload, repository, admin_db, crypto_service))]
+pub async fn impersonate_user(
+    req: HttpRequest,
+    admin: AdminUser,
+    id: Path<Uuid>,
+    payload: Json<AdminReason>,
+    repository: UserRepository,
+    admin_db: AdminRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    payload.validate().map_err(|_| {
+        AppError::INVALID_INPUT.message("A reason of 3 to 500 characters is required.".to_string())
+    })?;
+    let user = find_user(&repository, *id).await?;
+    if user.role == ROLE_ADMIN {
+        return Err(AppError::FORBIDDEN.message("Admins can not be impersonated.".to_string()));
+    }
+    if user.suspended_at.is_some() {
+        return Err(AppError::INVALID_INPUT.message("User is suspended.".to_string()));
+    }
+
+    let session = admin_db
+        .impersonate(
+            NewUserSession {
+                user_id: user.id,
+                ip_address: Some(client_ip(&req)),
+                user_agent: user_agent(&req),
+                expires_at: (Utc::now() + Duration::minutes(IMPERSONATION_TTL_MINUTES)).naive_utc(),
+                impersonated_by: Some(admin.0),
+            },
+            admin_event(&req, EVENT_ADMIN_IMPERSONATE, &admin, user.id).details(json!({
+                "reason": payload.into_inner().reason,
+            })),
+        )
+        .await?;
+    debug!("Admin {} impersonating {} in session {}", admin.0, user.id, session.id);
+
+    let token = crypto_service.generate_jwt(user.id, session.id).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "auth": Auth { token },
+        "expires_at": session.expires_at,
+    })))
+}
+
+// admin actions are audited in the transaction of the action itself, a
+// failed record rolls the action back
+fn admin_event(req: &HttpRequest, event_type: &'static str, admin: &AdminUser, user_id: Uuid) -> NewAuditEvent {
+    audit_event(req, event_type).actor(admin.0).target(Some(user_id))
+}
+
+async fn find_user(repository: &UserRepository, id: Uuid) -> AppResult<User> {
+    repository
+        .find_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::NOT_FOUND.message("User not found.".to_string()))
+}
@@ -0,0 +1,119 @@
+//handlers api_key
+use super::{auth::AuthenticatedUser, AppResponse};
+use crate::{
+    config::crypto::CryptoService,
+    db::{self, api_key::ApiKeyRepository},
+    errors::AppError,
+    models::api_key::{CreateApiKey, CreatedApiKey, NewApiKey, SCOPES},
+};
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use chrono::{Duration, Utc};
+use sqlx::{error::DatabaseError, postgres::PgError};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+// hex characters of the key that are stored in clear for the lookup, 64 bits
+const PREFIX_LEN: usize = 16;
+const CREATE_ATTEMPTS: usize = 3;
+
+#[instrument(skip(user, api_keys))]
+pub async fn list_api_keys(user: AuthenticatedUser, api_keys: ApiKeyRepository) -> AppResponse {
+    user.require_session()?;
+
+    let api_keys = api_keys.find_by_user_id(user.0).await?;
+    Ok(HttpResponse::Ok().json(api_keys))
+}
+
+/// the generated key is only part of this response, we keep its hash
+#[instrument(skip(user, payload, api_keys, crypto_service))]
+pub async fn create_api_key(
+    user: AuthenticatedUser,
+    payload: Json<CreateApiKey>,
+    api_keys: ApiKeyRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    user.require_own_login()?;
+
+    match payload.validate() {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let error_map = e.field_errors();
+
+            let message = if error_map.contains_key("name") {
+                "Invalid name. Use 1 to 64 characters.".to_string()
+            } else if error_map.contains_key("expires_in_days") {
+                "Invalid expires_in_days. Use 1 to 365 days.".to_string()
+            } else {
+                "Invalid input.".to_string()
+            };
+
+            Err(AppError::INVALID_INPUT.message(message))
+        }
+    }?;
+
+    if payload.scopes.is_empty() {
+        return Err(AppError::INVALID_INPUT.message(format!("Pick at least one scope of {:?}", SCOPES)));
+    }
+    if let Some(unknown) = payload.scopes.iter().find(|scope| !SCOPES.contains(&scope.as_str())) {
+        return Err(AppError::INVALID_INPUT.message(format!(
+            "Unknown scope {:?}, expected one of {:?}",
+            unknown, SCOPES
+        )));
+    }
+
+    let payload = payload.into_inner();
+    let expires_at = payload
+        .expires_in_days
+        .map(|days| (Utc::now() + Duration::days(days)).naive_utc());
+
+    // prefixes are unique, a collision gets a fresh key
+    for _ in 0..CREATE_ATTEMPTS {
+        let secret = crypto_service.random_token();
+        let prefix = secret[..PREFIX_LEN].to_string();
+        let key = format!("zbot_{}_{}", prefix, &secret[PREFIX_LEN..]);
+
+        let created = api_keys
+            .create(NewApiKey {
+                user_id: user.0,
+                name: payload.name.clone(),
+                prefix,
+                key_hash: crypto_service.hash_token(&key),
+                scopes: payload.scopes.join(" "),
+                expires_at,
+            })
+            .await;
+
+        match created {
+            Ok(api_key) => return Ok(HttpResponse::Created().json(CreatedApiKey { key, api_key })),
+            Err(e) => {
+                let pg_error = e.root_cause().downcast_ref::<PgError>();
+                if pg_error.and_then(|pg_error| pg_error.code()) != Some(db::UNIQUE_VIOLATION_CODE) {
+                    return Err(e.into());
+                }
+                debug!("Api key prefix collision, generating another key.");
+            }
+        }
+    }
+
+    Err(AppError::INTERNAL_ERROR.message("Could not generate an api key.".to_string()))
+}
+
+#[instrument(skip(user, api_keys))]
+pub async fn revoke_api_key(
+    user: AuthenticatedUser,
+    id: Path<Uuid>,
+    api_keys: ApiKeyRepository,
+) -> AppResponse {
+    user.require_session()?;
+
+    let api_key = api_keys
+        .revoke(*id, user.0)
+        .await?
+        .ok_or_else(|| AppError::NOT_FOUND.message("Api key not found.".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(api_key))
+}
@@ -1,49 +1,161 @@
-use super::AppResponse;
+use super::{audit_event, client_ip, user_agent, AppResponse, AppResult};
 use crate::{
-    config::crypto::{Auth, CryptoService},
-    db::user::UserRepository,
+    config::crypto::{Auth, CryptoService, TwoFactorChallenge, TOKEN_TTL_HOURS},
+    config::{login_guard::LoginGuard, totp},
+    db::{
+        api_key::ApiKeyRepository, audit::AuditRepository, challenge::ChallengeRepository,
+        password_reset::PasswordResetRepository,
+        recovery_code::RecoveryCodeRepository, session::SessionRepository, user::UserRepository,
+    },
     errors::AppError,
+    models::{
+        audit::{EVENT_LOGIN, EVENT_LOGIN_FAILED, EVENT_PASSWORD_RESET},
+        password_reset::ResetPassword,
+        session::NewUserSession,
+        two_factor::TwoFactorLogin,
+        user::{User, ROLE_ADMIN},
+    },
 };
-use actix_web::{web::Data, FromRequest, HttpResponse};
+use actix_web::{
+    web::{Data, Json},
+    FromRequest, HttpRequest, HttpResponse,
+};
+use chrono::{Duration, NaiveDateTime, Utc};
 use actix_web_httpauth::extractors::{basic::BasicAuth, bearer::BearerAuth};
-use futures::future::{ready, BoxFuture};
+use futures::future::{ready, LocalBoxFuture};
+use serde_json::json;
 use tracing::{debug, instrument};
 use uuid::Uuid;
+use validator::Validate;
+
+/// how the request proved who it is
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthMethod {
+    Session(Uuid), // sessions.id from the jwt
+    ApiKey { id: Uuid, scopes: Vec<String> },
+    Impersonation { session_id: Uuid, admin_id: Uuid }, // support session an admin opened as the user
+}
+
+/// request extension naming the admin behind an impersonation session,
+/// audit events of the request are theirs
+#[derive(Debug, Clone, Copy)]
+pub struct Impersonator(pub Uuid);
+
+/// header carrying a personal api key, `zbot_<prefix>_<secret>`
+pub const API_KEY_HEADER: &str = "X-Api-Key";
 
 #[derive(Debug)]
-pub struct AuthenticatedUser(pub Uuid);
+pub struct AuthenticatedUser(pub Uuid, pub AuthMethod);
+
+impl AuthenticatedUser {
+    pub fn session_id(&self) -> Option<Uuid> {
+        match self.1 {
+            AuthMethod::Session(session_id) | AuthMethod::Impersonation { session_id, .. } => Some(session_id),
+            AuthMethod::ApiKey { .. } => None,
+        }
+    }
+
+    /// sessions carry every scope, api keys only the granted ones
+    pub fn require_scope(&self, scope: &str) -> AppResult<()> {
+        match &self.1 {
+            AuthMethod::Session(_) | AuthMethod::Impersonation { .. } => Ok(()),
+            AuthMethod::ApiKey { scopes, .. } if scopes.iter().any(|s| s == scope) => Ok(()),
+            AuthMethod::ApiKey { .. } => Err(AppError::FORBIDDEN.message(format!(
+                "Api key is missing the {:?} scope.",
+                scope
+            ))),
+        }
+    }
+
+    /// account management (passwords, keys, linked logins) needs a real login
+    pub fn require_session(&self) -> AppResult<()> {
+        match self.1 {
+            AuthMethod::Session(_) | AuthMethod::Impersonation { .. } => Ok(()),
+            AuthMethod::ApiKey { .. } => Err(AppError::FORBIDDEN.message(
+                "Api keys can not be used for this, log in instead.".to_string(),
+            )),
+        }
+    }
+
+    /// credentials, api keys, 2fa and deletion stay with the user,
+    /// an admin impersonating them can not change those
+    pub fn require_own_login(&self) -> AppResult<()> {
+        self.require_session()?;
+        match self.1 {
+            AuthMethod::Impersonation { .. } => Err(AppError::FORBIDDEN.message(
+                "Not allowed while impersonating the user.".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
 
 impl FromRequest for AuthenticatedUser {
     type Error = AppError;
-    type Future = BoxFuture<'static, Result<Self, Self::Error>>;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
     type Config = ();
     #[instrument(skip(req, payload))]
     fn from_request(
         req: &actix_web::HttpRequest,
         payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
-        let bearer_result = BearerAuth::from_request(req, payload).into_inner();
+        let api_key = req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
         let repository_result = UserRepository::from_request(req, payload).into_inner();
         let crypto_service_result = Data::<CryptoService>::from_request(req, payload).into_inner();
 
-        match (bearer_result, repository_result, crypto_service_result) {
-            (Ok(bearer), Ok(repository), Ok(crypto_service)) => {
+        if let Some(api_key) = api_key {
+            let api_keys_result = ApiKeyRepository::from_request(req, payload).into_inner();
+
+            return match (api_keys_result, repository_result, crypto_service_result) {
+                (Ok(api_keys), Ok(repository), Ok(crypto_service)) => Box::pin(async move {
+                    authenticate_api_key(&api_key, &api_keys, &repository, &crypto_service).await
+                }),
+                _ => Box::pin(ready(Err(AppError::NOT_AUTHORIZED.into()))),
+            };
+        }
+
+        let bearer_result = BearerAuth::from_request(req, payload).into_inner();
+        let sessions_result = SessionRepository::from_request(req, payload).into_inner();
+        let req = req.clone();
+
+        match (bearer_result, repository_result, sessions_result, crypto_service_result) {
+            (Ok(bearer), Ok(repository), Ok(sessions), Ok(crypto_service)) => {
                 let future = async move {
-                    let user_id: Uuid = crypto_service
+                    let claims = crypto_service
                         .verify_jwt(bearer.token().to_string())
                         .await
-                        .map(|data| data.claims.sub)
+                        .map(|data| data.claims)
                         .map_err(|err| {
                             debug!("Cannot verify jwt. {:?}", err);
                             AppError::NOT_AUTHORIZED
                         })?;
 
-                    repository.find_by_id(user_id).await?.ok_or_else(|| {
-                        debug!("User {} not found", user_id);
+                    let session = sessions.find_active(claims.jti, claims.sub).await?.ok_or_else(|| {
+                        debug!("Session {} revoked or expired", claims.jti);
                         AppError::NOT_AUTHORIZED
                     })?;
 
-                    Ok(AuthenticatedUser(user_id))
+                    let user = repository.find_by_id(claims.sub).await?.ok_or_else(|| {
+                        debug!("User {} not found", claims.sub);
+                        AppError::NOT_AUTHORIZED
+                    })?;
+                    ensure_can_login(&user)?;
+
+                    let method = match session.impersonated_by {
+                        Some(admin_id) => {
+                            req.extensions_mut().insert(Impersonator(admin_id));
+                            AuthMethod::Impersonation {
+                                session_id: claims.jti,
+                                admin_id,
+                            }
+                        }
+                        None => AuthMethod::Session(claims.jti),
+                    };
+                    Ok(AuthenticatedUser(claims.sub, method))
                 };
                 Box::pin(future)
             }
@@ -55,36 +167,383 @@ impl FromRequest for AuthenticatedUser {
     }
 }
 
+/// logged in user with the admin role, api keys are never admins
+#[derive(Debug)]
+pub struct AdminUser(pub Uuid);
+
+impl FromRequest for AdminUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let user_future = AuthenticatedUser::from_request(req, payload);
+        let repository_result = UserRepository::from_request(req, payload).into_inner();
+
+        match repository_result {
+            Ok(repository) => Box::pin(async move {
+                let user = user_future.await?;
+                user.require_session()?;
+
+                let found = repository.find_by_id(user.0).await?;
+                if !matches!(found, Some(found) if found.role == ROLE_ADMIN) {
+                    return Err(AppError::FORBIDDEN.default());
+                }
+
+                Ok(AdminUser(user.0))
+            }),
+            Err(_) => Box::pin(ready(Err(AppError::NOT_AUTHORIZED.into()))),
+        }
+    }
+}
+
+async fn authenticate_api_key(
+    key: &str,
+    api_keys: &ApiKeyRepository,
+    repository: &UserRepository,
+    crypto_service: &CryptoService,
+) -> AppResult<AuthenticatedUser> {
+    let prefix = key
+        .strip_prefix("zbot_")
+        .and_then(|rest| rest.split('_').next())
+        .ok_or_else(|| {
+            debug!("Malformed api key.");
+            AppError::NOT_AUTHORIZED
+        })?;
+
+    let api_key = api_keys
+        .use_key(prefix, &crypto_service.hash_token(key))
+        .await?
+        .ok_or_else(|| {
+            debug!("Api key {} unknown, revoked or expired", prefix);
+            AppError::NOT_AUTHORIZED
+        })?;
+
+    let user = repository.find_by_id(api_key.user_id).await?.ok_or_else(|| {
+        debug!("User {} not found", api_key.user_id);
+        AppError::NOT_AUTHORIZED
+    })?;
+    ensure_can_login(&user)?;
+
+    Ok(AuthenticatedUser(
+        api_key.user_id,
+        AuthMethod::ApiKey {
+            id: api_key.id,
+            scopes: api_key.scope_list(),
+        },
+    ))
+}
+
+/// suspended accounts and forced password resets block every login,
+/// checked only once the credentials are known to be valid
+pub fn ensure_can_login(user: &User) -> AppResult<()> {
+    if user.suspended_at.is_some() {
+        debug!("User {} is suspended.", user.id);
+        return Err(AppError::FORBIDDEN.message("Account suspended.".to_string()));
+    }
+    if user.password_reset_required {
+        debug!("User {} has to reset the password.", user.id);
+        return Err(AppError::FORBIDDEN.message(
+            "A password reset is required, use the link sent to your email address.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// opens a session for the user and signs a jwt bound to it,
+/// `method` is how the user logged in, for the audit log
+pub async fn issue_token(
+    user_id: Uuid,
+    method: &str,
+    req: &HttpRequest,
+    sessions: &SessionRepository,
+    audit: &AuditRepository,
+    crypto_service: &CryptoService,
+) -> AppResult<Auth> {
+    let session = sessions
+        .create(NewUserSession {
+            user_id,
+            ip_address: Some(client_ip(req)),
+            user_agent: user_agent(req),
+            expires_at: (Utc::now() + Duration::hours(TOKEN_TTL_HOURS)).naive_utc(),
+            impersonated_by: None,
+        })
+        .await?;
+    audit
+        .record(audit_event(req, EVENT_LOGIN).by(user_id).details(json!({
+            "method": method,
+            "session_id": session.id,
+        })))
+        .await;
+
+    let token = crypto_service.generate_jwt(user_id, session.id).await?;
+    Ok(Auth { token })
+}
+
 /// auth create a new user credentials
-#[instrument(skip(basic, repository, hashing))]
+/// basic auth user id may be the username or the email address,
+/// failed attempts are throttled per account and per ip by LoginGuard
+#[instrument(skip(req, basic, repository, sessions, audit, hashing, login_guard))]
 pub async fn auth(
+    req: HttpRequest,
     basic: BasicAuth,
     repository: UserRepository,
+    sessions: SessionRepository,
+    audit: AuditRepository,
     hashing: Data<CryptoService>,
+    login_guard: Data<LoginGuard>,
 ) -> AppResponse {
-    let username = basic.user_id();
+    let login = basic.user_id();
     let password = basic.password().ok_or_else(|| {
         debug!("Invalid request. Missing Basic Auth.");
         AppError::INVALID_CREDENTIALS
     })?;
+    let ip_address = client_ip(&req);
+
+    let maybe_user = repository.find_by_login(login).await?;
+    // throttle by account, so username and email share one counter
+    let username = maybe_user
+        .as_ref()
+        .map(|user| user.username.as_str())
+        .unwrap_or(login);
+
+    if let Some(remaining) = login_guard.locked_for(username, &ip_address) {
+        debug!("Login locked for {} more seconds.", remaining.as_secs());
+        let user_id = maybe_user.as_ref().map(|user| user.id);
+        record_failed_login(&audit, &req, username, user_id, "locked").await;
+        return Err(AppError::ACCOUNT_LOCKED.message(format!(
+            "Too many failed login attempts. Try again in {} seconds.",
+            remaining.as_secs() + 1
+        )));
+    }
+
+    // always verify a hash, so unknown usernames take as long as known ones
+    let (valid, reason) = match &maybe_user {
+        Some(user) => (
+            hashing.verify_password(password, &user.password_hash).await?,
+            "invalid_password",
+        ),
+        None => {
+            hashing.verify_password(password, &hashing.dummy_hash).await?;
+            (false, "unknown_user")
+        }
+    };
+
+    match &maybe_user {
+        // the guard is only cleared once the second factor passed too
+        Some(user) if valid && user.totp_enabled => {
+            ensure_can_login(user)?;
+            let challenge_token = hashing.generate_challenge(user.id).await?;
+            Ok(HttpResponse::Ok().json(TwoFactorChallenge {
+                two_factor_required: true,
+                challenge_token,
+            }))
+        }
+        Some(user) if valid => {
+            ensure_can_login(user)?;
+            login_guard.record_success(username);
+            let auth = issue_token(user.id, "password", &req, &sessions, &audit, &hashing).await?;
+            Ok(HttpResponse::Ok().json(auth))
+        }
+        _ => {
+            debug!("Invalid username or password.");
+            let user_id = maybe_user.as_ref().map(|user| user.id);
+            record_failed_login(&audit, &req, username, user_id, reason).await;
 
+            match login_guard.record_failure(username, &ip_address) {
+                Some(lockout) => Err(AppError::ACCOUNT_LOCKED.message(format!(
+                    "Too many failed login attempts. Try again in {} seconds.",
+                    lockout.as_secs()
+                ))),
+                None => Err(AppError::INVALID_CREDENTIALS.into()),
+            }
+        }
+    }
+}
+
+/// re-verifies the password of a logged in user before a sensitive change.
+/// Failures count against the same LoginGuard counters as logins, so a
+/// stolen session can not be used to guess the password
+pub async fn verify_current_password(
+    req: &HttpRequest,
+    user: &User,
+    password: &str,
+    hashing: &CryptoService,
+    login_guard: &LoginGuard,
+) -> AppResult<()> {
+    let ip_address = client_ip(req);
+    if let Some(remaining) = login_guard.locked_for(&user.username, &ip_address) {
+        return Err(AppError::ACCOUNT_LOCKED.message(format!(
+            "Too many failed password attempts. Try again in {} seconds.",
+            remaining.as_secs() + 1
+        )));
+    }
+
+    if hashing.verify_password(password, &user.password_hash).await? {
+        login_guard.record_success(&user.username);
+        return Ok(());
+    }
+
+    debug!("Invalid current password.");
+    match login_guard.record_failure(&user.username, &ip_address) {
+        Some(lockout) => Err(AppError::ACCOUNT_LOCKED.message(format!(
+            "Too many failed password attempts. Try again in {} seconds.",
+            lockout.as_secs()
+        ))),
+        None => Err(AppError::INVALID_CREDENTIALS.message("Invalid password.".to_string())),
+    }
+}
+
+/// second login step, exchanges the challenge from `auth`
+/// and a TOTP or recovery code for the real jwt
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(req, payload, repository, recovery_codes, challenges, sessions, audit, hashing, login_guard))]
+pub async fn auth_two_factor(
+    req: HttpRequest,
+    payload: Json<TwoFactorLogin>,
+    repository: UserRepository,
+    recovery_codes: RecoveryCodeRepository,
+    challenges: ChallengeRepository,
+    sessions: SessionRepository,
+    audit: AuditRepository,
+    hashing: Data<CryptoService>,
+    login_guard: Data<LoginGuard>,
+) -> AppResponse {
+    let payload = payload.into_inner();
+    let invalid_challenge =
+        || AppError::NOT_AUTHORIZED.message("Invalid or expired challenge, log in again.".to_string());
+    let challenge = hashing
+        .verify_challenge(payload.challenge_token)
+        .await
+        .map_err(|err| {
+            debug!("Cannot verify challenge. {:?}", err);
+            invalid_challenge()
+        })?;
+    if challenges.is_used(&challenge.jti).await? {
+        return Err(invalid_challenge());
+    }
     let user = repository
-        .find_by_username(username)
+        .find_by_id(challenge.sub)
         .await?
-        .ok_or_else(|| {
-            debug!("User doesn't exist.");
-            AppError::INVALID_CREDENTIALS
-        })?;
+        .ok_or(AppError::NOT_AUTHORIZED)?;
+    let ip_address = client_ip(&req);
+
+    if let Some(remaining) = login_guard.locked_for(&user.username, &ip_address) {
+        record_failed_login(&audit, &req, &user.username, Some(user.id), "locked").await;
+        return Err(AppError::ACCOUNT_LOCKED.message(format!(
+            "Too many failed login attempts. Try again in {} seconds.",
+            remaining.as_secs() + 1
+        )));
+    }
 
-    let valid = hashing
-        .verify_password(password, &user.password_hash)
+    if verify_second_factor(&user, &payload.code, &repository, &recovery_codes, &hashing).await? {
+        ensure_can_login(&user)?;
+        // two requests racing with the same challenge, only one gets a session
+        let expires_at = NaiveDateTime::from_timestamp(challenge.exp, 0);
+        if !challenges.consume(&challenge.jti, expires_at).await? {
+            return Err(invalid_challenge());
+        }
+        login_guard.record_success(&user.username);
+        let auth = issue_token(user.id, "two_factor", &req, &sessions, &audit, &hashing).await?;
+        return Ok(HttpResponse::Ok().json(auth));
+    }
+
+    debug!("Invalid two factor code.");
+    record_failed_login(&audit, &req, &user.username, Some(user.id), "invalid_totp").await;
+    match login_guard.record_failure(&user.username, &ip_address) {
+        Some(lockout) => Err(AppError::ACCOUNT_LOCKED.message(format!(
+            "Too many failed login attempts. Try again in {} seconds.",
+            lockout.as_secs()
+        ))),
+        None => Err(AppError::INVALID_CREDENTIALS.message("Invalid two factor code.".to_string())),
+    }
+}
+
+/// POST /auth/password_reset, sets the password from an emailed
+/// reset link and logs the user out everywhere
+#[instrument(skip(req, payload, repository, password_resets, sessions, audit, crypto_service))]
+pub async fn reset_password(
+    req: HttpRequest,
+    payload: Json<ResetPassword>,
+    repository: UserRepository,
+    password_resets: PasswordResetRepository,
+    sessions: SessionRepository,
+    audit: AuditRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    payload.validate().map_err(|_| {
+        AppError::INVALID_INPUT.message("Invalid password. Too short".to_string())
+    })?;
+
+    let payload = payload.into_inner();
+    let password_reset = password_resets
+        .take(&crypto_service.hash_token(payload.token.trim()))
+        .await?
+        .ok_or_else(|| AppError::INVALID_INPUT.message("Invalid or expired reset link.".to_string()))?;
+
+    repository
+        .update_password(password_reset.user_id, payload.new_password, &crypto_service)
         .await?;
+    let revoked = sessions.revoke_all(password_reset.user_id, None).await?;
+    audit
+        .record(audit_event(&req, EVENT_PASSWORD_RESET).by(password_reset.user_id).details(json!({
+            "revoked_sessions": revoked,
+        })))
+        .await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Password changed.",
+        "revoked_sessions": revoked,
+    })))
+}
 
-    if valid {
-        let token = hashing.generate_jwt(user.id).await?;
-        Ok(HttpResponse::Ok().json(Auth { token }))
-    } else {
-        debug!("Invalid password.");
-        Err(AppError::INVALID_CREDENTIALS.into())
+/// 6 digits are checked as TOTP, anything else as a single use recovery code
+pub async fn verify_second_factor(
+    user: &User,
+    code: &str,
+    repository: &UserRepository,
+    recovery_codes: &RecoveryCodeRepository,
+    crypto_service: &CryptoService,
+) -> AppResult<bool> {
+    let secret = match (&user.totp_secret, user.totp_enabled) {
+        (Some(secret), true) => secret,
+        _ => return Ok(false),
+    };
+
+    let code = code.trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        return match totp::verify(secret, code, Utc::now().timestamp()) {
+            Some(step) => Ok(repository.use_totp_step(user.id, step).await?),
+            None => Ok(false),
+        };
     }
+
+    let code_hash = crypto_service.hash_token(&normalize_recovery_code(code));
+    let used = recovery_codes.use_code(user.id, &code_hash).await?;
+    Ok(used)
+}
+
+/// recovery codes are shown as xxxx-xxxx-xxxx-xxxx, accept any spacing or case
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// audit failures are logged, they never fail the login request itself
+async fn record_failed_login(
+    audit: &AuditRepository,
+    req: &HttpRequest,
+    username: &str,
+    user_id: Option<Uuid>,
+    reason: &'static str,
+) {
+    let event = audit_event(req, EVENT_LOGIN_FAILED).target(user_id).details(json!({
+        "username": username,
+        "reason": reason,
+    }));
+    audit.record(event).await;
 }
@@ -0,0 +1,123 @@
+//handlers avatar
+use super::{auth::AuthenticatedUser, AppResponse};
+use crate::{
+    db::user::UserRepository,
+    errors::AppError,
+    models::{api_key, user::Avatar},
+    storage::{
+        self,
+        avatar::{self, Avatars, AVATAR_SIZES},
+    },
+};
+use actix_multipart::Multipart;
+use actix_web::{
+    error::BlockingError,
+    http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG},
+    web::{self, Data, Path},
+    HttpResponse,
+};
+use futures::StreamExt;
+use tracing::{debug, error, instrument};
+
+// the multipart field holding the image
+const AVATAR_FIELD: &str = "avatar";
+
+/// POST /me/avatar, multipart/form-data with a png or jpeg in the "avatar" field
+#[instrument(skip(user, payload, repository, avatars))]
+pub async fn upload_avatar(
+    user: AuthenticatedUser,
+    mut payload: Multipart,
+    repository: UserRepository,
+    avatars: Data<Avatars>,
+) -> AppResponse {
+    user.require_scope(api_key::PROFILE_WRITE)?;
+
+    let mut upload: Option<(String, Vec<u8>)> = None;
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(|_| AppError::INVALID_INPUT.message("Invalid multipart body.".to_string()))?;
+        let name = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_name().map(str::to_string));
+        if name.as_deref() != Some(AVATAR_FIELD) || upload.is_some() {
+            return Err(AppError::INVALID_INPUT.message(format!("Send a single \"{}\" field.", AVATAR_FIELD)));
+        }
+
+        let content_type = field.content_type().essence_str().to_string();
+        let mut body = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|_| AppError::INVALID_INPUT.message("Invalid multipart body.".to_string()))?;
+            if body.len() + chunk.len() > avatars.max_upload_bytes() {
+                return Err(AppError::INVALID_INPUT.message(format!(
+                    "Image too large. At most {} bytes are allowed.",
+                    avatars.max_upload_bytes()
+                )));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        upload = Some((content_type, body));
+    }
+    let (content_type, body) =
+        upload.ok_or_else(|| AppError::INVALID_INPUT.message(format!("Missing \"{}\" field.", AVATAR_FIELD)))?;
+
+    let max_dimension = avatars.max_dimension();
+    let thumbnails = web::block(move || avatar::thumbnails(&body, &content_type, max_dimension))
+        .await
+        .map_err(|e| match e {
+            BlockingError::Error(message) => AppError::INVALID_INPUT.message(message),
+            BlockingError::Canceled => AppError::INTERNAL_ERROR.default(),
+        })?;
+
+    let prefix = avatars.store(user.0, thumbnails).await?;
+    let thumbnails = avatars.urls(&prefix);
+    let image = thumbnails[&AVATAR_SIZES[0]].clone();
+    let (updated_user, previous) = repository.set_avatar(user.0, Some((image.clone(), prefix))).await?;
+    remove_previous(&avatars, previous).await;
+
+    Ok(HttpResponse::Ok()
+        .header(ETAG, updated_user.etag())
+        .json(Avatar { image, thumbnails }))
+}
+
+/// DELETE /me/avatar, clears users.image as well
+#[instrument(skip(user, repository, avatars))]
+pub async fn delete_avatar(user: AuthenticatedUser, repository: UserRepository, avatars: Data<Avatars>) -> AppResponse {
+    user.require_scope(api_key::PROFILE_WRITE)?;
+
+    let (_, previous) = repository.set_avatar(user.0, None).await?;
+    remove_previous(&avatars, previous).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// the new avatar is already in place, leftovers are only logged
+async fn remove_previous(avatars: &Avatars, previous: Option<String>) {
+    if let Some(prefix) = previous {
+        if let Err(e) = avatars.remove(&prefix).await {
+            error!("Error removing avatar {}. {:?}", prefix, e);
+        }
+    }
+}
+
+/// GET /uploads/{key}, files of the local storage backend. Keys never get
+/// reused, so they are cached for good
+#[instrument(skip(avatars))]
+pub async fn serve_upload(key: Path<String>, avatars: Data<Avatars>) -> AppResponse {
+    if !storage::is_valid_key(&key) {
+        return Err(AppError::NOT_FOUND.default());
+    }
+
+    let body = avatars.storage().get(&key).await?.ok_or_else(|| {
+        debug!("Upload {} not found", key);
+        AppError::NOT_FOUND.default()
+    })?;
+    let content_type = if key.ends_with(".png") {
+        "image/png"
+    } else {
+        "application/octet-stream"
+    };
+
+    Ok(HttpResponse::Ok()
+        .header(CONTENT_TYPE, content_type)
+        .header(CACHE_CONTROL, "public, max-age=31536000, immutable")
+        .body(body))
+}
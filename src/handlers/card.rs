@@ -0,0 +1,85 @@
+use super::{quota::UbiAccess, AppResponse};
+use crate::{
+    config::card_renderer::{CardFormat, CardRenderer},
+    db::player_snapshot::PlayerSnapshotRepository,
+    errors::AppError,
+    models::player_snapshot::PlayerSnapshot,
+    tracker::Tracker,
+    ubi::{player_card::PLATFORMS, ubi_api::UbiApi},
+};
+use actix_web::{
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        StatusCode,
+    },
+    web::{self, Data, Path},
+    HttpRequest,
+};
+use chrono::{Duration, Utc};
+use tracing::debug;
+
+/// player card as png or svg. Recent snapshots are rendered without asking
+/// Ubisoft, the ETag is the snapshot so unchanged stats answer 304
+#[allow(clippy::too_many_arguments)]
+pub async fn player_card_image(
+    access: UbiAccess,
+    req: HttpRequest,
+    path: Path<(String, String, String)>,
+    ubi_api: Data<UbiApi>,
+    snapshots: PlayerSnapshotRepository,
+    tracker: Data<Tracker>,
+    renderer: Data<CardRenderer>,
+) -> AppResponse {
+    let (platform, name, extension) = path.into_inner();
+    if !PLATFORMS.contains(&platform.as_str()) {
+        return Err(AppError::INVALID_INPUT.message("Platform must be one of uplay, psn or xbl.".to_string()));
+    }
+    let format = CardFormat::from_extension(&extension)
+        .ok_or_else(|| AppError::INVALID_INPUT.message("Card format must be png or svg.".to_string()))?;
+
+    let since = (Utc::now() - Duration::seconds(renderer.max_age_secs())).naive_utc();
+    let mut current = snapshots.find_fresh_by_name(&platform, &name, since).await?;
+    if current.is_empty() {
+        let card = ubi_api.player_card(&platform, &name).await?;
+        current = tracker.record_card(&card).await?;
+    }
+
+    // the region the player is best in
+    let snapshot: PlayerSnapshot = current
+        .into_iter()
+        .max_by(|a, b| a.mmr.partial_cmp(&b.mmr).unwrap_or(std::cmp::Ordering::Equal))
+        .ok_or_else(|| AppError::NOT_FOUND.message(format!("No ranked stats for {:?}", name)))?;
+
+    let etag = format!("\"{}-{}\"", snapshot.id, format.extension());
+    // the route needs authentication, shared caches must not keep the card
+    let cache_control = format!("private, max-age={}", renderer.max_age_secs());
+    let if_none_match = req.headers().get(IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    let not_modified = matches!(if_none_match, Some(value) if value.split(',').any(|tag| tag.trim() == etag));
+    if not_modified {
+        return Ok(access
+            .quota
+            .ok()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(ETAG, etag)
+            .header(CACHE_CONTROL, cache_control)
+            .finish());
+    }
+
+    let trend = snapshots
+        .find_mmr_trend(&snapshot.profile_id, &snapshot.region, renderer.trend_points())
+        .await?;
+    let image = web::block(move || renderer.render(&snapshot, &trend, format))
+        .await
+        .map_err(|op| {
+            debug!("Error rendering player card. {:?}", op);
+            AppError::INTERNAL_ERROR.default()
+        })?;
+
+    Ok(access
+        .quota
+        .ok()
+        .header(CONTENT_TYPE, format.content_type())
+        .header(ETAG, etag)
+        .header(CACHE_CONTROL, cache_control)
+        .body(image.as_ref().clone()))
+}
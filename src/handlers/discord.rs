@@ -0,0 +1,314 @@
+use super::{AppResponse, AppResult};
+use crate::{
+    config::rate_limit::{Quota, RateLimiter},
+    db::{identity::IdentityRepository, profile_link::ProfileLinkRepository, user::UserRepository},
+    discord::{
+        commands::{self, CommandContext},
+        interaction::{Interaction, InteractionResponse, APPLICATION_COMMAND, PING},
+        DiscordBot,
+    },
+    errors::AppError,
+    tracker::Tracker,
+    ubi::ubi_api::UbiApi,
+};
+use actix_web::{
+    web::{Bytes, Data},
+    HttpRequest, HttpResponse,
+};
+use sqlx::PgPool;
+use tracing::{debug, error};
+
+const SIGNATURE_HEADER: &str = "X-Signature-Ed25519";
+const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+/// Discord interactions endpoint, set as "Interactions Endpoint URL" in the
+/// developer portal. Every request must carry a valid Ed25519 signature
+pub async fn interactions(
+    req: HttpRequest,
+    body: Bytes,
+    bot: Data<DiscordBot>,
+    ubi_api: Data<UbiApi>,
+    tracker: Data<Tracker>,
+    limiter: Data<RateLimiter>,
+    pool: Data<PgPool>,
+) -> AppResponse {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    bot.verify(header(SIGNATURE_HEADER), header(TIMESTAMP_HEADER), &body)?;
+
+    let interaction: Interaction = serde_json::from_slice(&body).map_err(|op| {
+        debug!("Error parsing Discord interaction. {:?}", op);
+        AppError::INVALID_INPUT.default()
+    })?;
+
+    match interaction.kind {
+        PING => Ok(HttpResponse::Ok().json(InteractionResponse::pong())),
+        APPLICATION_COMMAND => {
+            let pool = pool.into_inner();
+            let users = UserRepository::new(pool.clone());
+            let context = CommandContext {
+                ubi_api: ubi_api.get_ref().clone(),
+                links: ProfileLinkRepository::new(pool.clone()),
+                tracker: tracker.get_ref().clone(),
+                identities: IdentityRepository::new(pool),
+            };
+            let command = interaction
+                .data
+                .as_ref()
+                .map(|data| data.name.clone())
+                .unwrap_or_default();
+
+            if !commands::is_slow(&command) {
+                let content = commands::run(&context, &interaction).await;
+                let response = match command.as_str() {
+                    "leaderboard" => InteractionResponse::message(content),
+                    _ => InteractionResponse::ephemeral(content),
+                };
+                return Ok(HttpResponse::Ok().json(response));
+            }
+
+            // commands calling Ubisoft count against the same quotas as /ubi
+            if let Err(quota) = charge(&limiter, &context.identities, &users, &interaction).await? {
+                debug!("Discord /{} is rate limited", command);
+                return Ok(HttpResponse::Ok().json(InteractionResponse::ephemeral(format!(
+                    ":warning: Rate limit exceeded. Try again in {} seconds.",
+                    quota.retry_after_secs
+                ))));
+            }
+
+            // Discord wants an answer within 3 seconds, Ubisoft may take longer
+            let bot = bot.get_ref().clone();
+            actix_rt::spawn(async move {
+                let content = commands::run(&context, &interaction).await;
+                if let Err(e) = bot
+                    .edit_original(&interaction.application_id, &interaction.token, &content)
+                    .await
+                {
+                    error!("Error answering Discord /{}. {}", command, e);
+                }
+            });
+
+            Ok(HttpResponse::Ok().json(InteractionResponse::deferred()))
+        }
+        kind => Err(AppError::INVALID_INPUT.message(format!("Unsupported interaction type {}", kind))),
+    }
+}
+
+// takes a token from the bucket of the linked zbot user, or from one per
+// Discord user when the invoker never logged in to zbot with Discord
+async fn charge(
+    limiter: &RateLimiter,
+    identities: &IdentityRepository,
+    users: &UserRepository,
+    interaction: &Interaction,
+) -> AppResult<Result<Quota, Quota>> {
+    let discord_user = interaction
+        .invoker()
+        .ok_or_else(|| AppError::INVALID_INPUT.default())?;
+
+    let linked = match identities.find("discord", &discord_user.id).await? {
+        Some(identity) => users.find_by_id(identity.user_id).await?,
+        None => None,
+    };
+    Ok(match linked {
+        Some(user) => limiter.acquire(user.id, &user.role, None),
+        None => limiter.acquire_discord(&discord_user.id),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{DiscordConfig, RateLimitConfig, RoleLimit, TrackerConfig, UbiConfig},
+        db::ubi_user::UbiUserRepository,
+        handlers::app_config,
+        test_support,
+    };
+    use actix_http::Request;
+    use actix_web::{
+        dev::{Service, ServiceResponse},
+        http::StatusCode,
+        test, App,
+    };
+    use chrono::Utc;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    // fixed application key, the fixtures are signed like Discord signs them
+    const SEED: [u8; 32] = [7; 32];
+
+    fn key_pair(seed: &[u8; 32]) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(seed).unwrap()
+    }
+
+    fn ubi_config() -> UbiConfig {
+        UbiConfig {
+            appid: "appid".to_string(),
+            authorization_prefix: "ubi_v1 t=".to_string(),
+            spaces_id_pc: String::new(),
+            spaces_id_xbox: String::new(),
+            spaces_id_ps4: String::new(),
+            sandbox_pc: String::new(),
+            sandbox_xbox: String::new(),
+            sandbox_ps4: String::new(),
+            scheduler: Default::default(),
+            pool: Default::default(),
+        }
+    }
+
+    async fn app(pool: PgPool) -> impl Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error> {
+        let client = reqwest::Client::new();
+        let bot = DiscordBot::new(
+            &DiscordConfig {
+                public_key: Some(hex::encode(key_pair(&SEED).public_key().as_ref())),
+                // follow-ups of deferred commands go nowhere
+                api_base: "http://127.0.0.1:9".to_string(),
+            },
+            client.clone(),
+        )
+        .unwrap();
+        let ubi_api = UbiApi::new(client, ubi_config(), UbiUserRepository::new(Arc::new(pool.clone())));
+        let tracker = Tracker::new(TrackerConfig::default(), ubi_api.clone(), Arc::new(pool.clone()));
+        let limiter = RateLimiter::new(RateLimitConfig {
+            discord: RoleLimit {
+                capacity: 1,
+                refill_per_minute: 1,
+            },
+            ..RateLimitConfig::default()
+        });
+
+        test::init_service(
+            App::new()
+                .data(pool)
+                .data(bot)
+                .data(ubi_api)
+                .data(tracker)
+                .data(limiter)
+                .configure(app_config),
+        )
+        .await
+    }
+
+    fn command(name: &str, discord_user_id: &str) -> Value {
+        json!({
+            "type": APPLICATION_COMMAND,
+            "application_id": "app",
+            "token": "interaction-token",
+            "data": { "name": name, "options": [] },
+            "user": { "id": discord_user_id, "username": "tester" },
+        })
+    }
+
+    fn signature(body: &str, key_pair: &Ed25519KeyPair, timestamp: i64) -> String {
+        hex::encode(key_pair.sign(format!("{}{}", timestamp, body).as_bytes()).as_ref())
+    }
+
+    fn request(body: String, signature: String, timestamp: i64) -> Request {
+        test::TestRequest::post()
+            .uri("/discord/interactions")
+            .header(SIGNATURE_HEADER, signature)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header("content-type", "application/json")
+            .set_payload(body)
+            .to_request()
+    }
+
+    fn signed(body: &Value, key_pair: &Ed25519KeyPair, timestamp: i64) -> Request {
+        let body = body.to_string();
+        let signature = signature(&body, key_pair, timestamp);
+        request(body, signature, timestamp)
+    }
+
+    async fn call<S>(app: &mut S, request: Request) -> (StatusCode, Value)
+    where
+        S: Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    {
+        let response = test::call_service(app, request).await;
+        let status = response.status();
+        let body = test::read_body(response).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn ping_is_answered_with_pong() {
+        let pool = test_support::pool().await;
+        let mut app = app(pool).await;
+
+        let ping = json!({ "type": PING, "application_id": "app", "token": "interaction-token" });
+        let (status, body) = call(&mut app, signed(&ping, &key_pair(&SEED), Utc::now().timestamp())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "type": 1 }));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn valid_command_is_answered() {
+        let pool = test_support::pool().await;
+        let mut app = app(pool).await;
+
+        let leaderboard = command("leaderboard", &test_support::unique("discord"));
+        let (status, body) = call(&mut app, signed(&leaderboard, &key_pair(&SEED), Utc::now().timestamp())).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["type"], 4);
+        assert!(body["data"]["content"].is_string());
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn bad_signatures_are_rejected() {
+        let pool = test_support::pool().await;
+        let mut app = app(pool).await;
+        let ping = json!({ "type": PING, "application_id": "app", "token": "interaction-token" });
+
+        let (status, _) = call(&mut app, signed(&ping, &key_pair(&[8; 32]), Utc::now().timestamp())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // signature of another body
+        let now = Utc::now().timestamp();
+        let signature = signature(&ping.to_string(), &key_pair(&SEED), now);
+        let swapped = request(command("leaderboard", "someone").to_string(), signature, now);
+        let (status, _) = call(&mut app, swapped).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn stale_timestamps_are_rejected() {
+        let pool = test_support::pool().await;
+        let mut app = app(pool).await;
+        let ping = json!({ "type": PING, "application_id": "app", "token": "interaction-token" });
+
+        let an_hour_ago = Utc::now().timestamp() - 3600;
+        let (status, body) = call(&mut app, signed(&ping, &key_pair(&SEED), an_hour_ago)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "Request timestamp is too old.");
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn ubisoft_commands_are_charged_per_discord_user() {
+        let pool = test_support::pool().await;
+        let mut app = app(pool).await;
+        let discord_user_id = test_support::unique("discord");
+
+        let stats = command("stats", &discord_user_id);
+        let (_, body) = call(&mut app, signed(&stats, &key_pair(&SEED), Utc::now().timestamp())).await;
+        assert_eq!(body["type"], 5, "{}", body);
+
+        let (_, body) = call(&mut app, signed(&stats, &key_pair(&SEED), Utc::now().timestamp())).await;
+        assert_eq!(body["type"], 4);
+        assert!(body["data"]["content"].as_str().unwrap().contains("Rate limit exceeded"));
+
+        // other Discord users have their own bucket
+        let stats = command("stats", &test_support::unique("discord"));
+        let (_, body) = call(&mut app, signed(&stats, &key_pair(&SEED), Utc::now().timestamp())).await;
+        assert_eq!(body["type"], 5);
+    }
+}
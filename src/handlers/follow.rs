@@ -0,0 +1,128 @@
+//handlers follow
+use super::{auth::AuthenticatedUser, paging, AppResponse, AppResult};
+use crate::{
+    db::{follow::FollowRepository, user::UserRepository},
+    errors::AppError,
+    models::{
+        api_key::{PROFILE_READ, PROFILE_WRITE},
+        follow::{FeedQuery, FollowQuery, FollowStatus},
+        user::User,
+    },
+};
+use actix_web::{
+    web::{Path, Query},
+    HttpResponse,
+};
+use tracing::instrument;
+
+const DEFAULT_FEED_LIMIT: i64 = 20;
+const MAX_FEED_LIMIT: i64 = 100;
+
+/// POST /users/{username}/follow
+#[instrument(skip(user, repository, follows))]
+pub async fn follow(
+    user: AuthenticatedUser,
+    username: Path<String>,
+    repository: UserRepository,
+    follows: FollowRepository,
+) -> AppResponse {
+    user.require_scope(PROFILE_WRITE)?;
+
+    let followee = find_visible(&repository, &username).await?;
+    if followee.id == user.0 {
+        return Err(AppError::INVALID_INPUT.message("You can not follow yourself.".to_string()));
+    }
+
+    follows.follow(user.0, followee.id).await?;
+    Ok(HttpResponse::Ok().json(FollowStatus {
+        mutual: follows.is_following(followee.id, user.0).await?,
+        username: followee.username,
+        following: true,
+    }))
+}
+
+/// DELETE /users/{username}/follow
+#[instrument(skip(user, repository, follows))]
+pub async fn unfollow(
+    user: AuthenticatedUser,
+    username: Path<String>,
+    repository: UserRepository,
+    follows: FollowRepository,
+) -> AppResponse {
+    user.require_scope(PROFILE_WRITE)?;
+
+    // unfollowing works even once the other account is on its way out
+    let followee = repository
+        .find_by_username(&username)
+        .await?
+        .ok_or_else(|| not_found(&username))?;
+    if !follows.unfollow(user.0, followee.id).await? {
+        return Err(AppError::NOT_FOUND.message(format!("You do not follow {:?}.", followee.username)));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// GET /users/{username}/followers, public like the profile itself
+#[instrument(skip(repository, follows))]
+pub async fn followers(
+    username: Path<String>,
+    Query(query): Query<FollowQuery>,
+    repository: UserRepository,
+    follows: FollowRepository,
+) -> AppResponse {
+    let user = find_visible(&repository, &username).await?;
+    let (page, per_page) = paging(query.page, query.per_page);
+
+    let followers = follows.find_followers(user.id, per_page, (page - 1) * per_page).await?;
+    Ok(HttpResponse::Ok().json(followers))
+}
+
+/// GET /users/{username}/following
+#[instrument(skip(repository, follows))]
+pub async fn following(
+    username: Path<String>,
+    Query(query): Query<FollowQuery>,
+    repository: UserRepository,
+    follows: FollowRepository,
+) -> AppResponse {
+    let user = find_visible(&repository, &username).await?;
+    let (page, per_page) = paging(query.page, query.per_page);
+
+    let following = follows.find_following(user.id, per_page, (page - 1) * per_page).await?;
+    Ok(HttpResponse::Ok().json(following))
+}
+
+/// GET /me/friends, users the current user and who follow each other
+#[instrument(skip(user, follows))]
+pub async fn friends(user: AuthenticatedUser, Query(query): Query<FollowQuery>, follows: FollowRepository) -> AppResponse {
+    user.require_scope(PROFILE_READ)?;
+    let (page, per_page) = paging(query.page, query.per_page);
+
+    let friends = follows.find_friends(user.0, per_page, (page - 1) * per_page).await?;
+    Ok(HttpResponse::Ok().json(friends))
+}
+
+/// GET /me/feed, rank changes and level ups of followed users, newest first
+#[instrument(skip(user, follows))]
+pub async fn feed(user: AuthenticatedUser, Query(query): Query<FeedQuery>, follows: FollowRepository) -> AppResponse {
+    user.require_scope(PROFILE_READ)?;
+    let limit = query.limit.unwrap_or(DEFAULT_FEED_LIMIT).clamp(1, MAX_FEED_LIMIT);
+
+    let rows = follows.feed(user.0, query.before, limit).await?;
+    let items = rows.iter().flat_map(|row| row.to_items()).collect::<Vec<_>>();
+    Ok(HttpResponse::Ok().json(items))
+}
+
+// users pending deletion or suspended can not be found
+async fn find_visible(repository: &UserRepository, username: &str) -> AppResult<User> {
+    repository
+        .find_by_username(username)
+        .await?
+        .filter(|user| user.deletion_scheduled_for.is_none() && user.suspended_at.is_none())
+        .ok_or_else(|| not_found(username))
+}
+
+fn not_found(username: &str) -> AppError {
+    AppError::NOT_FOUND.message(format!("User {:?} not found.", username))
+}
@@ -1,43 +1,280 @@
 // mod handlers
+mod account;
+mod achievement;
+mod admin;
+mod api_key;
 mod auth;
+mod avatar;
+mod card;
+mod discord;
+mod follow;
+mod notification;
+mod oauth;
+mod quota;
 mod user;
 mod r6stats;
+mod realtime;
+mod two_factor;
+mod ubi_profile;
+mod webhook;
 
-use actix_web::{web, web::ServiceConfig, HttpResponse};
+use actix_web::{
+    http::header::USER_AGENT,
+    web,
+    web::{Data, ServiceConfig},
+    HttpRequest, HttpResponse,
+};
+use serde_json::json;
 
-use crate::errors::AppError;
-use user::{create_user, me, update_profile};
+pub use quota::quota_headers;
+
+use crate::{config::trusted_proxies::TrustedProxies, errors::AppError, models::audit::NewAuditEvent};
+use user::{
+    change_email, change_password, confirm_email, create_user, get_privacy, me, public_profile, set_privacy,
+    update_profile,
+};
 
 pub type AppResult<T> = Result<T, AppError>;
 pub type AppResponse = AppResult<HttpResponse>;
 
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
 pub fn app_config(config: &mut ServiceConfig) {
     let ping_resource = web::resource("/").route(web::get().to(ping));
 
     let auth = web::resource("/auth").route(web::post().to(auth::auth));
+    let auth_two_factor = web::resource("/auth/2fa").route(web::post().to(auth::auth_two_factor));
+    let auth_password_reset = web::resource("/auth/password_reset").route(web::post().to(auth::reset_password));
     let me = web::resource("/me")
         .route(web::get().to(me))
-        .route(web::post().to(update_profile));
+        .route(web::patch().to(update_profile))
+        .route(web::post().to(update_profile))
+        .route(web::delete().to(account::delete_account));
+    let me_restore = web::resource("/me/restore").route(web::post().to(account::restore_account));
+    let me_export = web::resource("/me/export").route(web::get().to(account::export_account));
+    let me_avatar = web::resource("/me/avatar")
+        .route(web::post().to(avatar::upload_avatar))
+        .route(web::delete().to(avatar::delete_avatar));
+    let uploads = web::resource("/uploads/{key:.*}").route(web::get().to(avatar::serve_upload));
+    let me_privacy = web::resource("/me/privacy")
+        .route(web::get().to(get_privacy))
+        .route(web::post().to(set_privacy));
+    let users_profile = web::resource("/users/{username}").route(web::get().to(public_profile));
+
+    //achievements
+    let achievements = web::resource("/achievements").route(web::get().to(achievement::list_achievements));
+    let me_badges = web::resource("/me/badges").route(web::get().to(achievement::my_badges));
+    let users_badges = web::resource("/users/{username}/badges").route(web::get().to(achievement::user_badges));
+
+    //follows
+    let users_follow = web::resource("/users/{username}/follow")
+        .route(web::post().to(follow::follow))
+        .route(web::delete().to(follow::unfollow));
+    let users_followers = web::resource("/users/{username}/followers").route(web::get().to(follow::followers));
+    let users_following = web::resource("/users/{username}/following").route(web::get().to(follow::following));
+    let me_friends = web::resource("/me/friends").route(web::get().to(follow::friends));
+    let me_feed = web::resource("/me/feed").route(web::get().to(follow::feed));
+    let me_password = web::resource("/me/password").route(web::post().to(change_password));
+    let me_email = web::resource("/me/email").route(web::post().to(change_email));
+    let me_email_confirm = web::resource("/me/email/confirm").route(web::get().to(confirm_email));
 
     let signup = web::resource("/signup").route(web::post().to(create_user));
 
+    //oauth
+    let oauth_authorize = web::resource("/oauth/{provider}/authorize").route(web::get().to(oauth::authorize));
+    let oauth_callback = web::resource("/oauth/{provider}/callback").route(web::get().to(oauth::callback));
+    let me_identities = web::resource("/me/identities").route(web::get().to(oauth::list_identities));
+    let me_identities_link = web::resource("/me/identities/{provider}").route(web::post().to(oauth::link));
+
+    //2fa
+    let me_two_factor = web::resource("/me/2fa")
+        .route(web::post().to(two_factor::enroll))
+        .route(web::delete().to(two_factor::disable));
+    let me_two_factor_verify = web::resource("/me/2fa/verify").route(web::post().to(two_factor::verify));
+
+    //api keys
+    let me_api_keys = web::resource("/me/api_keys")
+        .route(web::get().to(api_key::list_api_keys))
+        .route(web::post().to(api_key::create_api_key));
+    let me_api_key = web::resource("/me/api_keys/{id}").route(web::delete().to(api_key::revoke_api_key));
+
+    //linked ubi profiles
+    let me_ubi_profiles = web::resource("/me/ubi_profiles")
+        .route(web::get().to(ubi_profile::list_profiles))
+        .route(web::post().to(ubi_profile::link_profile));
+    let me_ubi_profile = web::resource("/me/ubi_profiles/{id}").route(web::delete().to(ubi_profile::unlink_profile));
+
+    //webhooks
+    let me_webhooks = web::resource("/me/webhooks")
+        .route(web::get().to(webhook::list_webhooks))
+        .route(web::post().to(webhook::create_webhook));
+    let me_webhook = web::resource("/me/webhooks/{id}").route(web::delete().to(webhook::delete_webhook));
+    let me_webhook_deliveries =
+        web::resource("/me/webhooks/{id}/deliveries").route(web::get().to(webhook::list_deliveries));
+    let me_webhook_replay = web::resource("/me/webhooks/{id}/deliveries/{delivery_id}/replay")
+        .route(web::post().to(webhook::replay_delivery));
+
+    //push notifications
+    let me_devices = web::resource("/me/devices")
+        .route(web::get().to(notification::list_devices))
+        .route(web::post().to(notification::register_device));
+    let me_device = web::resource("/me/devices/{id}").route(web::delete().to(notification::delete_device));
+    let me_notifications = web::resource("/me/notifications")
+        .route(web::get().to(notification::get_preferences))
+        .route(web::post().to(notification::set_preferences));
+    let recorded_notifications =
+        web::resource("/notifications/recorded").route(web::get().to(notification::recorded_notifications));
+
+    //realtime
+    let realtime_ws = web::resource("/realtime/ws").route(web::get().to(realtime::websocket));
+    let realtime_events = web::resource("/realtime/events").route(web::get().to(realtime::events));
+
+    //discord
+    let discord_interactions = web::resource("/discord/interactions").route(web::post().to(discord::interactions));
+
+    //admin
+    let admin_audit = web::resource("/admin/audit").route(web::get().to(admin::search_audit));
+    let admin_users = web::resource("/admin/users").route(web::get().to(admin::search_users));
+    let admin_user = web::resource("/admin/users/{id}").route(web::get().to(admin::get_user));
+    let admin_user_sessions = web::resource("/admin/users/{id}/sessions").route(web::get().to(admin::list_sessions));
+    let admin_user_ubi_profiles =
+        web::resource("/admin/users/{id}/ubi_profiles").route(web::get().to(admin::list_ubi_profiles));
+    let admin_user_audit = web::resource("/admin/users/{id}/audit").route(web::get().to(admin::user_audit));
+    let admin_user_suspend = web::resource("/admin/users/{id}/suspend").route(web::post().to(admin::suspend_user));
+    let admin_user_unsuspend =
+        web::resource("/admin/users/{id}/unsuspend").route(web::post().to(admin::unsuspend_user));
+    let admin_user_password_reset =
+        web::resource("/admin/users/{id}/password_reset").route(web::post().to(admin::force_password_reset));
+    let admin_user_impersonate =
+        web::resource("/admin/users/{id}/impersonate").route(web::post().to(admin::impersonate_user));
+
     //ubi
     let find_profile = web::resource("/ubi/find_profile").route(web::get().to(r6stats::find_profile));
     let find_stats = web::resource("/ubi/find_stats").route(web::get().to(r6stats::find_stats));
     let find_populations_statistics = web::resource("/ubi/find_populations_statistics").route(web::get().to(r6stats::find_populations_statistics));
     let find_player_xp_profiles = web::resource("/ubi/find_player_xp_profiles").route(web::get().to(r6stats::find_player_xp_profiles));
+    let player_card = web::resource("/ubi/player/{platform}/{name}").route(web::get().to(r6stats::player_card));
+    let player_card_image = web::resource("/ubi/player/{platform}/{name}/card.{format}")
+        .route(web::get().to(card::player_card_image));
+    let compare_players = web::resource("/ubi/compare").route(web::get().to(r6stats::compare_players));
+    let ubi_scheduler = web::resource("/ubi/scheduler").route(web::get().to(r6stats::scheduler_metrics));
+    let ubi_accounts = web::resource("/ubi/accounts")
+        .route(web::get().to(r6stats::list_accounts))
+        .route(web::post().to(r6stats::add_account));
+    let ubi_account_history = web::resource("/ubi/accounts/history").route(web::get().to(r6stats::account_history));
 
     config
         .service(ping_resource)
         .service(signup)
         .service(auth)
+        .service(auth_two_factor)
+        .service(auth_password_reset)
         .service(me)
+        .service(me_avatar)
+        .service(me_restore)
+        .service(me_export)
+        .service(uploads)
+        .service(me_privacy)
+        .service(users_profile)
+        .service(achievements)
+        .service(me_badges)
+        .service(users_badges)
+        .service(users_follow)
+        .service(users_followers)
+        .service(users_following)
+        .service(me_friends)
+        .service(me_feed)
+        .service(me_password)
+        .service(me_email)
+        .service(me_email_confirm)
+        .service(oauth_authorize)
+        .service(oauth_callback)
+        .service(me_identities)
+        .service(me_identities_link)
+        .service(me_two_factor)
+        .service(me_two_factor_verify)
+        .service(me_api_keys)
+        .service(me_api_key)
+        .service(me_ubi_profiles)
+        .service(me_ubi_profile)
+        .service(me_webhooks)
+        .service(me_webhook)
+        .service(me_webhook_deliveries)
+        .service(me_webhook_replay)
+        .service(me_devices)
+        .service(me_device)
+        .service(me_notifications)
+        .service(recorded_notifications)
+        .service(realtime_ws)
+        .service(realtime_events)
+        .service(discord_interactions)
+        .service(admin_audit)
+        .service(admin_users)
+        .service(admin_user)
+        .service(admin_user_sessions)
+        .service(admin_user_ubi_profiles)
+        .service(admin_user_audit)
+        .service(admin_user_suspend)
+        .service(admin_user_unsuspend)
+        .service(admin_user_password_reset)
+        .service(admin_user_impersonate)
         .service(find_stats)
         .service(find_populations_statistics)
         .service(find_player_xp_profiles)
-        .service(find_profile);
+        .service(find_profile)
+        .service(player_card)
+        .service(player_card_image)
+        .service(compare_players)
+        .service(ubi_scheduler)
+        .service(ubi_accounts)
+        .service(ubi_account_history);
 }
 
 pub async fn ping() -> HttpResponse {
     HttpResponse::Ok().json("ping")
 }
+
+/// client ip without the port. X-Forwarded-For is only honoured
+/// when the peer is one of the PROXY.TRUSTED proxies
+pub fn client_ip(req: &HttpRequest) -> String {
+    let forwarded_for = req
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+    let ip = match req.app_data::<Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client_ip(req.peer_addr(), forwarded_for),
+        None => req.peer_addr().map(|addr| addr.ip()),
+    };
+
+    ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string())
+}
+
+pub fn user_agent(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+/// audit event carrying the ip and user agent of the request
+pub fn audit_event(req: &HttpRequest, event_type: &'static str) -> NewAuditEvent {
+    let impersonated_by = req.extensions().get::<auth::Impersonator>().map(|admin| admin.0);
+    NewAuditEvent {
+        event_type,
+        actor_id: impersonated_by,
+        target_user_id: None,
+        ip_address: Some(client_ip(req)),
+        user_agent: user_agent(req),
+        details: json!({}),
+        impersonated_by,
+    }
+}
+
+/// (page, per_page) from optional query values, pages start at 1
+pub fn paging(page: Option<i64>, per_page: Option<i64>) -> (i64, i64) {
+    (
+        page.unwrap_or(1).max(1),
+        per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE),
+    )
+}
@@ -0,0 +1,135 @@
+//handlers notification
+use super::{
+    auth::{AdminUser, AuthenticatedUser},
+    AppResponse,
+};
+use crate::{
+    db::notification::NotificationRepository,
+    errors::AppError,
+    models::notification::{RegisterDevice, SetPreferences, PROVIDERS},
+    notifications::Notifier,
+};
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpResponse,
+};
+use chrono::NaiveTime;
+use tracing::{debug, instrument};
+use uuid::Uuid;
+use validator::Validate;
+
+#[instrument(skip(user, notifications))]
+pub async fn list_devices(user: AuthenticatedUser, notifications: NotificationRepository) -> AppResponse {
+    user.require_session()?;
+
+    let devices = notifications.find_devices(user.0).await?;
+    Ok(HttpResponse::Ok().json(devices))
+}
+
+/// called by the app on every start, keeps the token current
+#[instrument(skip(user, payload, notifications))]
+pub async fn register_device(
+    user: AuthenticatedUser,
+    payload: Json<RegisterDevice>,
+    notifications: NotificationRepository,
+) -> AppResponse {
+    user.require_session()?;
+
+    match payload.validate() {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let error_map = e.field_errors();
+
+            let message = if error_map.contains_key("token") {
+                "Invalid token.".to_string()
+            } else if error_map.contains_key("name") {
+                "Invalid name. Use 1 to 64 characters.".to_string()
+            } else {
+                "Invalid input.".to_string()
+            };
+
+            Err(AppError::INVALID_INPUT.message(message))
+        }
+    }?;
+    if !PROVIDERS.contains(&payload.provider.as_str()) {
+        return Err(AppError::INVALID_INPUT.message(format!("Provider must be one of {:?}", PROVIDERS)));
+    }
+
+    let payload = payload.into_inner();
+    let device = notifications
+        .register_device(user.0, &payload.provider, &payload.token, payload.name)
+        .await?;
+
+    Ok(HttpResponse::Created().json(device))
+}
+
+#[instrument(skip(user, notifications))]
+pub async fn delete_device(
+    user: AuthenticatedUser,
+    id: Path<Uuid>,
+    notifications: NotificationRepository,
+) -> AppResponse {
+    user.require_session()?;
+
+    if !notifications.delete_device(id.into_inner(), user.0).await? {
+        return Err(AppError::NOT_FOUND.message("Device not found.".to_string()));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[instrument(skip(user, notifications))]
+pub async fn get_preferences(user: AuthenticatedUser, notifications: NotificationRepository) -> AppResponse {
+    user.require_session()?;
+
+    let preferences = notifications.find_preferences(user.0).await?;
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, AppError> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| AppError::INVALID_INPUT.message(format!("Invalid time {:?}, use HH:MM.", value)))
+}
+
+#[instrument(skip(user, payload, notifications))]
+pub async fn set_preferences(
+    user: AuthenticatedUser,
+    payload: Json<SetPreferences>,
+    notifications: NotificationRepository,
+) -> AppResponse {
+    user.require_session()?;
+    payload
+        .validate()
+        .map_err(|_| AppError::INVALID_INPUT.message("Invalid utc_offset_minutes. Use -720 to 840.".to_string()))?;
+
+    let quiet_hours = match &payload.quiet_hours {
+        Some(quiet_hours) => {
+            let start = parse_time(&quiet_hours.start)?;
+            let end = parse_time(&quiet_hours.end)?;
+            if start == end {
+                return Err(AppError::INVALID_INPUT.message("Quiet hours must not start and end at the same time.".to_string()));
+            }
+            Some((start, end))
+        }
+        None => None,
+    };
+
+    let preferences = notifications
+        .set_preferences(
+            user.0,
+            payload.rank_up,
+            payload.rank_down,
+            payload.level_up,
+            quiet_hours,
+            payload.utc_offset_minutes,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+/// notifications kept by the recording provider, for local testing
+pub async fn recorded_notifications(admin: AdminUser, notifier: Data<Notifier>) -> AppResponse {
+    debug!("Admin {} reading recorded notifications", admin.0);
+
+    Ok(HttpResponse::Ok().json(notifier.recorded()))
+}
@@ -0,0 +1,716 @@
+//handlers oauth
+use super::{
+    audit_event,
+    auth::{ensure_can_login, issue_token, AuthenticatedUser},
+    AppResponse, AppResult,
+};
+use crate::{
+    config::crypto::{CryptoService, TwoFactorChallenge},
+    db::{audit::AuditRepository, identity::IdentityRepository, session::SessionRepository, user::UserRepository},
+    errors::AppError,
+    models::api_key,
+    models::audit::{EVENT_IDENTITY_LINK, EVENT_SIGNUP},
+    models::identity::NewIdentity,
+    models::user::{NewUser, User},
+    oauth::{providers::ExternalIdentity, OAuthService},
+};
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    http::header::LOCATION,
+    web::{Data, Path, Query},
+    HttpMessage, HttpRequest, HttpResponse,
+};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, instrument};
+
+// holds the nonce binding a flow to the browser that started it
+const NONCE_COOKIE: &str = "zbot_oauth_nonce";
+
+fn nonce_cookie(oauth: &OAuthService, nonce: String) -> Cookie<'static> {
+    Cookie::build(NONCE_COOKIE, nonce)
+        .path("/oauth")
+        .http_only(true)
+        .secure(oauth.secure_cookies())
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+#[derive(Deserialize)]
+pub struct Callback {
+    code: Option<String>,
+    state: String,
+    error: Option<String>, // access_denied etc.
+}
+
+/// redirects the browser to the provider login page
+#[instrument(skip(oauth, identities, crypto_service))]
+pub async fn authorize(
+    provider: Path<String>,
+    oauth: Data<OAuthService>,
+    identities: IdentityRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    let nonce = crypto_service.random_token();
+    let (url, state) = oauth.authorize(&provider, None, crypto_service.hash_token(&nonce))?;
+    identities.create_state(state).await?;
+
+    Ok(HttpResponse::Found()
+        .header(LOCATION, url)
+        .cookie(nonce_cookie(&oauth, nonce))
+        .finish())
+}
+
+/// starts linking another login to the current user,
+/// returns the url the client has to open. Only the browser that got the
+/// nonce cookie of this response can finish it
+#[instrument(skip(user, oauth, identities, crypto_service))]
+pub async fn link(
+    user: AuthenticatedUser,
+    provider: Path<String>,
+    oauth: Data<OAuthService>,
+    identities: IdentityRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    user.require_own_login()?;
+
+    let nonce = crypto_service.random_token();
+    let (url, state) = oauth.authorize(&provider, Some(user.0), crypto_service.hash_token(&nonce))?;
+    identities.create_state(state).await?;
+
+    Ok(HttpResponse::Ok()
+        .cookie(nonce_cookie(&oauth, nonce))
+        .json(json!({ "authorize_url": url })))
+}
+
+#[instrument(skip(user, identities))]
+pub async fn list_identities(user: AuthenticatedUser, identities: IdentityRepository) -> AppResponse {
+    user.require_scope(api_key::PROFILE_READ)?;
+
+    let identities = identities.find_by_user_id(user.0).await?;
+    Ok(HttpResponse::Ok().json(identities))
+}
+
+/// provider redirects back here with the authorization code.
+/// links the identity when the flow was started by `link`,
+/// otherwise logs in (creating the user on first login) and returns our jwt,
+/// or the 2fa challenge when the user has two factor authentication on
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(req, query, oauth, identities, repository, sessions, audit, crypto_service))]
+pub async fn callback(
+    req: HttpRequest,
+    provider: Path<String>,
+    Query(query): Query<Callback>,
+    oauth: Data<OAuthService>,
+    identities: IdentityRepository,
+    repository: UserRepository,
+    sessions: SessionRepository,
+    audit: AuditRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    let invalid_state = || AppError::INVALID_INPUT.message("Invalid or expired login state.".to_string());
+    let nonce = req.cookie(NONCE_COOKIE).ok_or_else(invalid_state)?;
+    let state = identities
+        .take_state(&provider, &query.state, &crypto_service.hash_token(nonce.value()))
+        .await?
+        .ok_or_else(invalid_state)?;
+
+    let code = match (query.code, query.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            debug!("{} login failed. {:?}", provider, error);
+            return Err(AppError::INVALID_CREDENTIALS.message("Login was cancelled or denied.".to_string()));
+        }
+    };
+
+    let external = oauth.exchange(&provider, &code, &state.code_verifier).await?;
+    let existing = identities.find(&provider, &external.subject).await?;
+
+    if let Some(user_id) = state.user_id {
+        return match existing {
+            Some(identity) if identity.user_id == user_id => Ok(HttpResponse::Ok().json(identity)),
+            Some(_) => Err(AppError::INVALID_INPUT.message(format!(
+                "This {} account is already linked to another user.",
+                provider
+            ))),
+            None => {
+                let identity = identities
+                    .create(new_identity(user_id, &provider, external))
+                    .await?;
+                audit
+                    .record(audit_event(&req, EVENT_IDENTITY_LINK).by(user_id).details(json!({
+                        "provider": *provider,
+                    })))
+                    .await;
+                Ok(HttpResponse::Ok().json(identity))
+            }
+        };
+    }
+
+    let user_id = match existing {
+        Some(identity) => {
+            let user = repository
+                .find_by_id(identity.user_id)
+                .await?
+                .ok_or(AppError::NOT_AUTHORIZED)?;
+            ensure_can_login(&user)?;
+            // same second step as a password login, finished through /auth/2fa
+            if user.totp_enabled {
+                let challenge_token = crypto_service.generate_challenge(user.id).await?;
+                return Ok(HttpResponse::Ok().json(TwoFactorChallenge {
+                    two_factor_required: true,
+                    challenge_token,
+                }));
+            }
+            user.id
+        }
+        None => {
+            let user = create_user_from(&external, &provider, &repository, &crypto_service).await?;
+            audit
+                .record(audit_event(&req, EVENT_SIGNUP).by(user.id).details(json!({ "provider": *provider })))
+                .await;
+            identities
+                .create(new_identity(user.id, &provider, external))
+                .await?;
+            user.id
+        }
+    };
+
+    let auth = issue_token(user_id, &provider, &req, &sessions, &audit, &crypto_service).await?;
+    Ok(HttpResponse::Ok().json(auth))
+}
+
+fn new_identity(user_id: uuid::Uuid, provider: &str, external: ExternalIdentity) -> NewIdentity {
+    NewIdentity {
+        user_id,
+        provider: provider.to_string(),
+        subject: external.subject,
+        email: external.email,
+        username: external.username,
+    }
+}
+
+// first login through a provider creates a user with an unusable random password.
+// an email that already belongs to a user is refused, the owner has to log in
+// and link the provider, otherwise the provider could take over the account
+async fn create_user_from(
+    external: &ExternalIdentity,
+    provider: &str,
+    repository: &UserRepository,
+    crypto_service: &CryptoService,
+) -> AppResult<User> {
+    let email = match (&external.email, external.email_verified) {
+        (Some(email), true) => email.clone(),
+        _ => {
+            return Err(AppError::INVALID_INPUT.message(format!(
+                "Your {} account has no verified email address.",
+                provider
+            )))
+        }
+    };
+
+    if repository.find_by_email(&email).await?.is_some() {
+        return Err(AppError::INVALID_INPUT.message(format!(
+            "An account with this email already exists, log in and link {} from your profile.",
+            provider
+        )));
+    }
+
+    let base = external
+        .username
+        .clone()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+    let username = available_username(&base, repository).await?;
+
+    let new_user = NewUser {
+        username,
+        email,
+        password: crypto_service.random_token(),
+    };
+    let user = repository.create(new_user, crypto_service).await?;
+    Ok(user)
+}
+
+// keeps [a-zA-Z0-9_], pads short names and adds a suffix while taken
+async fn available_username(base: &str, repository: &UserRepository) -> AppResult<String> {
+    let mut username: String = base
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .take(24)
+        .collect();
+    while username.len() < 4 {
+        username.push('_');
+    }
+
+    let mut candidate = username.clone();
+    for _ in 0..5 {
+        if repository.find_by_username(&candidate).await?.is_none() {
+            return Ok(candidate);
+        }
+        let suffix: u16 = rand::thread_rng().gen();
+        candidate = format!("{}_{:04x}", username, suffix);
+    }
+
+    Err(AppError::INTERNAL_ERROR.message("Could not pick a username.".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{login_guard::LoginGuard, totp, LoginConfig, OAuthConfig, OAuthProviderConfig},
+        db::identity::IdentityRepository,
+        handlers::app_config,
+        test_support,
+    };
+    use actix_http::Request;
+    use actix_web::{
+        dev::{Service, ServiceResponse},
+        http::StatusCode,
+        test, web, App,
+    };
+    use serde_json::Value;
+    use sha2::{Digest, Sha256};
+    use sqlx::PgPool;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    const PUBLIC_URL: &str = "http://zbot.test";
+
+    // an authorization code of the stub, bound to the PKCE challenge of its flow
+    struct Grant {
+        code_challenge: String,
+        claims: Value,
+    }
+
+    /// local identity provider, logs in whoever `claims` describes
+    #[derive(Clone, Default)]
+    struct StubIdp {
+        grants: Arc<Mutex<HashMap<String, Grant>>>,
+        tokens: Arc<Mutex<HashMap<String, Value>>>,
+        claims: Arc<Mutex<Value>>,
+    }
+
+    #[derive(Deserialize)]
+    struct StubAuthorize {
+        redirect_uri: String,
+        state: String,
+        code_challenge: String,
+        code_challenge_method: String,
+    }
+
+    async fn stub_authorize(stub: Data<StubIdp>, Query(query): Query<StubAuthorize>) -> HttpResponse {
+        assert_eq!(query.code_challenge_method, "S256");
+        let code = test_support::unique("code");
+        let claims = stub.claims.lock().unwrap().clone();
+        stub.grants.lock().unwrap().insert(
+            code.clone(),
+            Grant {
+                code_challenge: query.code_challenge,
+                claims,
+            },
+        );
+
+        let location = reqwest::Url::parse_with_params(
+            &query.redirect_uri,
+            &[("code", code.as_str()), ("state", query.state.as_str())],
+        )
+        .unwrap();
+        HttpResponse::Found().header(LOCATION, location.to_string()).finish()
+    }
+
+    #[derive(Deserialize)]
+    struct StubToken {
+        grant_type: String,
+        code: String,
+        code_verifier: String,
+    }
+
+    async fn stub_token(stub: Data<StubIdp>, form: web::Form<StubToken>) -> HttpResponse {
+        let grant = stub.grants.lock().unwrap().remove(&form.code);
+        let challenge = base64::encode_config(Sha256::digest(form.code_verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+
+        match grant {
+            Some(grant) if form.grant_type == "authorization_code" && grant.code_challenge == challenge => {
+                let token = test_support::unique("token");
+                stub.tokens.lock().unwrap().insert(token.clone(), grant.claims);
+                HttpResponse::Ok().json(json!({ "access_token": token, "token_type": "Bearer" }))
+            }
+            _ => HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" })),
+        }
+    }
+
+    async fn stub_userinfo(stub: Data<StubIdp>, req: HttpRequest) -> HttpResponse {
+        let token = req
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        match stub.tokens.lock().unwrap().get(token) {
+            Some(claims) => HttpResponse::Ok().json(claims),
+            None => HttpResponse::Unauthorized().finish(),
+        }
+    }
+
+    struct Harness {
+        stub: StubIdp,
+        _server: test::TestServer,
+        pool: PgPool,
+        oauth: OAuthService,
+    }
+
+    async fn harness() -> Harness {
+        let pool = test_support::pool().await;
+        let stub = StubIdp::default();
+        let server = {
+            let stub = stub.clone();
+            test::start(move || {
+                App::new()
+                    .data(stub.clone())
+                    .route("/authorize", web::get().to(stub_authorize))
+                    .route("/token", web::post().to(stub_token))
+                    .route("/userinfo", web::get().to(stub_userinfo))
+            })
+        };
+
+        // by address, localhost may resolve to ::1 the server does not listen on
+        let url = |path: &str| format!("http://{}{}", server.addr(), path);
+        let config = OAuthConfig {
+            oidc: Some(OAuthProviderConfig {
+                client_id: "zbot".to_string(),
+                client_secret: "stub-secret".to_string(),
+                authorize_url: Some(url("/authorize")),
+                token_url: Some(url("/token")),
+                userinfo_url: Some(url("/userinfo")),
+                scopes: None,
+            }),
+            ..OAuthConfig::default()
+        };
+        let oauth = OAuthService::new(&config, reqwest::Client::new(), PUBLIC_URL.to_string()).unwrap();
+
+        Harness {
+            stub,
+            _server: server,
+            pool,
+            oauth,
+        }
+    }
+
+    impl Harness {
+        async fn app(&self) -> impl Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error> {
+            test::init_service(
+                App::new()
+                    .data(self.pool.clone())
+                    .data(test_support::crypto_service())
+                    .data(self.oauth.clone())
+                    .data(LoginGuard::new(LoginConfig::default()))
+                    .configure(app_config),
+            )
+            .await
+        }
+
+        /// the next stub login is this new external account
+        fn next_login(&self) -> Value {
+            let subject = test_support::unique("sub");
+            let claims = json!({
+                "sub": subject,
+                "email": format!("{}@stub.test", subject),
+                "email_verified": true,
+                "preferred_username": "stub user",
+            });
+            *self.stub.claims.lock().unwrap() = claims.clone();
+            claims
+        }
+
+        async fn create_user(&self, email: &str) -> User {
+            UserRepository::new(Arc::new(self.pool.clone()))
+                .create(
+                    NewUser {
+                        username: test_support::unique("user"),
+                        email: email.to_string(),
+                        password: "password".to_string(),
+                    },
+                    &test_support::crypto_service(),
+                )
+                .await
+                .unwrap()
+        }
+
+        /// link flow for a logged in user, as /me/identities/{provider} starts
+        /// it. Returns the authorize url and the nonce of the browser
+        async fn start_link(&self, user_id: uuid::Uuid) -> (String, String) {
+            let crypto_service = test_support::crypto_service();
+            let nonce = crypto_service.random_token();
+            let (url, state) = self
+                .oauth
+                .authorize("oidc", Some(user_id), crypto_service.hash_token(&nonce))
+                .unwrap();
+            IdentityRepository::new(Arc::new(self.pool.clone()))
+                .create_state(state)
+                .await
+                .unwrap();
+            (url, nonce)
+        }
+
+        async fn find_identity(&self, subject: &str) -> Option<crate::models::identity::Identity> {
+            IdentityRepository::new(Arc::new(self.pool.clone()))
+                .find("oidc", subject)
+                .await
+                .unwrap()
+        }
+    }
+
+    /// the authorize url and the nonce cookie handed to the browser
+    async fn start_login<S>(app: &mut S) -> (String, String)
+    where
+        S: Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    {
+        let response = test::call_service(app, test::TestRequest::get().uri("/oauth/oidc/authorize").to_request()).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let nonce = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == NONCE_COOKIE)
+            .expect("nonce cookie");
+        assert!(nonce.http_only().unwrap_or(false));
+        let url = response.headers().get(LOCATION).unwrap().to_str().unwrap().to_string();
+        (url, nonce.value().to_string())
+    }
+
+    /// logs in at the stub, returns the callback path it redirects to
+    async fn login_at_stub(authorize_url: &str) -> String {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let response = client.get(authorize_url).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 302);
+
+        let location = response.headers()["location"].to_str().unwrap();
+        location.strip_prefix(PUBLIC_URL).unwrap().to_string()
+    }
+
+    async fn second_factor<S>(app: &mut S, challenge_token: &Value, code: &str) -> StatusCode
+    where
+        S: Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    {
+        let request = test::TestRequest::post()
+            .uri("/auth/2fa")
+            .set_json(&json!({ "challenge_token": challenge_token, "code": code }))
+            .to_request();
+        test::call_service(app, request).await.status()
+    }
+
+    async fn callback<S>(app: &mut S, path: &str, nonce: &str) -> (StatusCode, Value)
+    where
+        S: Service<Request = Request, Response = ServiceResponse, Error = actix_web::Error>,
+    {
+        let request = test::TestRequest::get()
+            .uri(path)
+            .cookie(Cookie::new(NONCE_COOKIE, nonce.to_string()))
+            .to_request();
+        let response = test::call_service(app, request).await;
+        let status = response.status();
+        let body = test::read_body(response).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn query_param(url: &str, name: &str) -> String {
+        let url = reqwest::Url::parse(&format!("{}{}", PUBLIC_URL, url.trim_start_matches(PUBLIC_URL))).unwrap();
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn first_login_creates_the_user_and_later_logins_reuse_it() {
+        let h = harness().await;
+        let mut app = h.app().await;
+        let claims = h.next_login();
+
+        let (authorize_url, nonce) = start_login(&mut app).await;
+        assert_eq!(query_param(&authorize_url, "code_challenge_method"), "S256");
+        let (status, body) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["token"].is_string());
+
+        let subject = claims["sub"].as_str().unwrap();
+        let identity = h.find_identity(subject).await.expect("identity linked");
+        let user = UserRepository::new(Arc::new(h.pool.clone()))
+            .find_by_email(claims["email"].as_str().unwrap())
+            .await
+            .unwrap()
+            .expect("user created");
+        assert_eq!(identity.user_id, user.id);
+
+        let (authorize_url, nonce) = start_login(&mut app).await;
+        let (status, body) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(h.find_identity(subject).await.unwrap().id, identity.id);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn code_from_another_flow_fails_the_pkce_check() {
+        let h = harness().await;
+        let mut app = h.app().await;
+        let claims = h.next_login();
+
+        let (first, _) = start_login(&mut app).await;
+        let (second, nonce) = start_login(&mut app).await;
+        let first_callback = login_at_stub(&first).await;
+
+        // the code of the first flow presented with the state (and verifier) of the second
+        let path = format!(
+            "/oauth/oidc/callback?code={}&state={}",
+            query_param(&first_callback, "code"),
+            query_param(&second, "state")
+        );
+        let (status, _) = callback(&mut app, &path, &nonce).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(h.find_identity(claims["sub"].as_str().unwrap()).await.is_none());
+
+        // the second state was used up by the failed attempt
+        let (status, _) = callback(&mut app, &path, &nonce).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn unknown_and_expired_states_are_rejected() {
+        let h = harness().await;
+        let mut app = h.app().await;
+        h.next_login();
+
+        let (status, _) = callback(&mut app, "/oauth/oidc/callback?code=nope&state=nope", "nope").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (authorize_url, nonce) = start_login(&mut app).await;
+        sqlx::query("update oauth_states set expires_at = now() - interval '1 minute' where state = $1")
+            .bind(query_param(&authorize_url, "state"))
+            .execute(&h.pool)
+            .await
+            .unwrap();
+        let (status, body) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "Invalid or expired login state.");
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn link_attaches_the_identity_to_the_logged_in_user() {
+        let h = harness().await;
+        let mut app = h.app().await;
+        let claims = h.next_login();
+        let subject = claims["sub"].as_str().unwrap();
+        let user = h.create_user(&format!("{}@zbot.test", test_support::unique("owner"))).await;
+
+        let (authorize_url, nonce) = h.start_link(user.id).await;
+        let (status, body) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["user_id"], json!(user.id));
+        assert_eq!(h.find_identity(subject).await.unwrap().user_id, user.id);
+
+        // logging in with the linked account is the user, no signup
+        let (authorize_url, nonce) = start_login(&mut app).await;
+        let (status, _) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::OK);
+        let signed_up = UserRepository::new(Arc::new(h.pool.clone()))
+            .find_by_email(claims["email"].as_str().unwrap())
+            .await
+            .unwrap();
+        assert!(signed_up.is_none());
+
+        // the same external account can not be linked to someone else
+        let other = h.create_user(&format!("{}@zbot.test", test_support::unique("other"))).await;
+        let (authorize_url, nonce) = h.start_link(other.id).await;
+        let (status, _) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(h.find_identity(subject).await.unwrap().user_id, user.id);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn only_the_browser_that_started_a_link_can_finish_it() {
+        let h = harness().await;
+        let mut app = h.app().await;
+        let claims = h.next_login();
+        let subject = claims["sub"].as_str().unwrap();
+        let user = h.create_user(&format!("{}@zbot.test", test_support::unique("linker"))).await;
+
+        // a link url sent to someone else, who logs in at the provider
+        let (authorize_url, nonce) = h.start_link(user.id).await;
+        let other_callback = login_at_stub(&authorize_url).await;
+
+        let request = test::TestRequest::get().uri(&other_callback).to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let (_, other_nonce) = start_login(&mut app).await;
+        let (status, body) = callback(&mut app, &other_callback, &other_nonce).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["message"], "Invalid or expired login state.");
+        assert!(h.find_identity(subject).await.is_none());
+
+        // the rejected attempts did not use the state up
+        let (status, _) = callback(&mut app, &other_callback, &nonce).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(h.find_identity(subject).await.unwrap().user_id, user.id);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn signup_with_the_email_of_an_existing_user_is_refused() {
+        let h = harness().await;
+        let mut app = h.app().await;
+        let claims = h.next_login();
+        h.create_user(claims["email"].as_str().unwrap()).await;
+
+        let (authorize_url, nonce) = start_login(&mut app).await;
+        let (status, body) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"].as_str().unwrap().contains("log in and link"));
+        assert!(h.find_identity(claims["sub"].as_str().unwrap()).await.is_none());
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn two_factor_users_get_the_challenge_and_codes_work_once() {
+        let h = harness().await;
+        let mut app = h.app().await;
+        h.next_login();
+        let user = h.create_user(&format!("{}@zbot.test", test_support::unique("totp"))).await;
+        let (authorize_url, nonce) = h.start_link(user.id).await;
+        let (status, _) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let secret = totp::generate_secret();
+        UserRepository::new(Arc::new(h.pool.clone()))
+            .update_totp(user.id, Some(secret.clone()), true)
+            .await
+            .unwrap();
+
+        let (authorize_url, nonce) = start_login(&mut app).await;
+        let (status, first) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        assert_eq!(status, StatusCode::OK, "{}", first);
+        assert_eq!(first["two_factor_required"], true);
+        assert!(first["token"].is_null());
+
+        let code = totp::code_at(&secret, chrono::Utc::now().timestamp());
+        let challenge = &first["challenge_token"];
+        assert_eq!(second_factor(&mut app, challenge, &code).await, StatusCode::OK);
+
+        // the challenge is used up, and so is the code with a fresh challenge
+        assert_eq!(second_factor(&mut app, challenge, &code).await, StatusCode::UNAUTHORIZED);
+        let (authorize_url, nonce) = start_login(&mut app).await;
+        let (_, second) = callback(&mut app, &login_at_stub(&authorize_url).await, &nonce).await;
+        let status = second_factor(&mut app, &second["challenge_token"], &code).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,168 @@
+//handlers quota
+// extractor guarding the /ubi routes: authentication, ubi:read scope and rate limit
+
+use super::auth::{AuthMethod, AuthenticatedUser};
+use crate::{
+    config::rate_limit::{Quota, RateLimiter},
+    db::user::UserRepository,
+    errors::AppError,
+    models::api_key,
+};
+use actix_web::{
+    dev::{HttpResponseBuilder, ServiceResponse},
+    error::InternalError,
+    http::{HeaderName, HeaderValue},
+    web::Data,
+    FromRequest, HttpRequest, HttpResponse,
+};
+use futures::future::{ready, LocalBoxFuture};
+use tracing::{debug, instrument};
+use uuid::Uuid;
+
+pub struct UbiAccess {
+    pub quota: Quota,
+    user_id: Uuid,
+    role: String,
+    api_key_id: Option<Uuid>,
+    limiter: Data<RateLimiter>,
+    req: HttpRequest,
+}
+
+impl UbiAccess {
+    /// takes `tokens` more for requests that fan out to several players,
+    /// the extractor already took the first one. Err is the 429 response
+    pub fn charge(&mut self, tokens: u32) -> Result<(), HttpResponse> {
+        if tokens == 0 {
+            return Ok(());
+        }
+
+        let acquired = self.limiter.acquire_many(self.user_id, &self.role, self.api_key_id, tokens);
+        let quota = match &acquired {
+            Ok(quota) | Err(quota) => quota.clone(),
+        };
+        self.req.extensions_mut().insert(quota.clone());
+        self.quota = quota;
+
+        match acquired {
+            Ok(_) => Ok(()),
+            Err(quota) => {
+                debug!("User {} is rate limited", self.user_id);
+                Err(quota.rate_limited().1)
+            }
+        }
+    }
+}
+
+impl Quota {
+    fn headers(&self) -> [(&'static str, String); 3] {
+        [
+            ("x-ratelimit-limit", self.limit.to_string()),
+            ("x-ratelimit-remaining", self.remaining.to_string()),
+            ("x-ratelimit-reset", self.reset_secs.to_string()),
+        ]
+    }
+
+    fn apply(&self, builder: &mut HttpResponseBuilder) {
+        for (name, value) in self.headers().iter() {
+            builder.header(*name, value.as_str());
+        }
+    }
+
+    /// 200 response carrying the X-RateLimit-* headers
+    pub fn ok(&self) -> HttpResponseBuilder {
+        let mut builder = HttpResponse::Ok();
+        self.apply(&mut builder);
+        builder
+    }
+
+    fn too_many_requests(&self) -> actix_web::Error {
+        let (error, response) = self.rate_limited();
+        InternalError::from_response(error, response).into()
+    }
+
+    fn rate_limited(&self) -> (AppError, HttpResponse) {
+        let error = AppError::RATE_LIMITED.message(format!(
+            "Rate limit exceeded. Try again in {} seconds.",
+            self.retry_after_secs
+        ));
+
+        let mut builder = HttpResponse::TooManyRequests();
+        self.apply(&mut builder);
+        builder.header("Retry-After", self.retry_after_secs.to_string());
+        let response = builder.json(&error);
+
+        (error, response)
+    }
+}
+
+/// response middleware, a request that was charged gets the X-RateLimit-*
+/// headers of its quota on every response, errors of the handler included
+pub fn quota_headers<B>(mut response: ServiceResponse<B>) -> ServiceResponse<B> {
+    let quota = response.request().extensions().get::<Quota>().cloned();
+    if let Some(quota) = quota {
+        for (name, value) in quota.headers().iter() {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                response.headers_mut().insert(HeaderName::from_static(name), value);
+            }
+        }
+    }
+
+    response
+}
+
+impl FromRequest for UbiAccess {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+    type Config = ();
+
+    #[instrument(skip(req, payload))]
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let user_future = AuthenticatedUser::from_request(req, payload);
+        let repository_result = UserRepository::from_request(req, payload).into_inner();
+        let limiter_result = Data::<RateLimiter>::from_request(req, payload).into_inner();
+
+        let req = req.clone();
+
+        match (repository_result, limiter_result) {
+            (Ok(repository), Ok(limiter)) => Box::pin(async move {
+                let user = user_future.await?;
+                user.require_scope(api_key::UBI_READ)?;
+
+                let role = repository
+                    .find_by_id(user.0)
+                    .await
+                    .map_err(AppError::from)?
+                    .ok_or_else(|| AppError::NOT_AUTHORIZED.default())?
+                    .role;
+                let api_key_id = match &user.1 {
+                    AuthMethod::ApiKey { id, .. } => Some(*id),
+                    AuthMethod::Session(_) | AuthMethod::Impersonation { .. } => None,
+                };
+
+                let acquired = limiter.acquire(user.0, &role, api_key_id);
+                let quota = match &acquired {
+                    Ok(quota) | Err(quota) => quota.clone(),
+                };
+                req.extensions_mut().insert(quota);
+                match acquired {
+                    Ok(quota) => Ok(UbiAccess {
+                        quota,
+                        user_id: user.0,
+                        role,
+                        api_key_id,
+                        limiter,
+                        req,
+                    }),
+                    Err(quota) => {
+                        debug!("User {} is rate limited", user.0);
+                        Err(quota.too_many_requests())
+                    }
+                }
+            }),
+            _ => Box::pin(ready(Err(AppError::NOT_AUTHORIZED.default().into()))),
+        }
+    }
+}
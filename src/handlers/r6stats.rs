@@ -1,13 +1,15 @@
 
 use crate::ubi;
+use crate::ubi::compare::{ComparePlayer, MAX_PLAYERS, MIN_PLAYERS};
+use crate::ubi::player_card::PLATFORMS;
 use crate::errors::AppError;
+use crate::models::ubi_user::AddUbiAccount;
 
-use super::{AppResponse};
+use super::{auth::AdminUser, quota::UbiAccess, AppResponse};
 use serde::{Deserialize};
-use actix_web::{
-    web::{Data, Query},
-    HttpResponse,
-};
+use tracing::{debug, info};
+use validator::Validate;
+use actix_web::{web::{Data, Json, Path, Query}, HttpResponse};
 
 
 #[derive(Deserialize)]
@@ -35,20 +37,20 @@ pub struct FindPopulationsStatistics {
     statistics: String,
 }
 
-pub async fn find_stats(Query(req): Query<FindStats>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
+pub async fn find_stats(access: UbiAccess, Query(req): Query<FindStats>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
     
     let player_stats = ubi_api.find_rank_stats(req.profile_id, req.region_id, req.platform_type.as_str()).await?;
     
-    Ok(HttpResponse::Ok().json(player_stats))
+    Ok(access.quota.ok().json(player_stats))
 }
 
 /// statistics <comma seperated strings>
 //	casualpvp_timeplayed,casualpvp_matchwon,casualpvp_matchlost,casualpvp_matchplayed,casualpvp_kills,casualpvp_death,rankedpvp_matchwon,rankedpvp_matchlost,rankedpvp_timeplayed,rankedpvp_matchplayed,rankedpvp_kills,rankedpvp_death
-pub async fn find_populations_statistics(Query(req): Query<FindPopulationsStatistics>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
+pub async fn find_populations_statistics(access: UbiAccess, Query(req): Query<FindPopulationsStatistics>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
     
     let statistics = ubi_api.find_populations_statistics(req.profile_id.as_str(), req.platform_type.as_str(), req.statistics.as_str()).await?;
     
-    Ok(HttpResponse::Ok().json(statistics))
+    Ok(access.quota.ok().json(statistics))
     //return response
     //{"results": {"80189261-91c0-4bf1-a5ad-81df3e64423e": 
     //{"casualpvp_matchwon:infinite": 54, 
@@ -67,21 +69,167 @@ pub async fn find_populations_statistics(Query(req): Query<FindPopulationsStatis
 //     }
 //   ]
 // }
-pub async fn find_player_xp_profiles(Query(req): Query<FindXpProfiles>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
+pub async fn find_player_xp_profiles(access: UbiAccess, Query(req): Query<FindXpProfiles>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
     
     let player_xp_profiles = ubi_api.find_player_xp_profiles(req.profile_id, req.platform_type.as_str()).await?;
     
-    Ok(HttpResponse::Ok().json(player_xp_profiles))
+    Ok(access.quota.ok().json(player_xp_profiles))
 }
 
-pub async fn find_profile(Query(req): Query<FindProfile>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
+pub async fn find_profile(access: UbiAccess, Query(req): Query<FindProfile>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
 
     let profiles = ubi_api.find_profile(req.name_on_platform.clone(), req.platform_type.clone()).await?;
     
-    if profiles.profiles.len() == 0 {
+    if profiles.profiles.is_empty() {
         return Err(AppError::NOT_FOUND.message(format!("Profile {:?} not found on platform {:?}", req.name_on_platform, req.platform_type)));
     }
     
-    Ok(HttpResponse::Ok().json(profiles))
+    Ok(access.quota.ok().json(profiles))
 }
 
+/// profile, rank in every region, level and summary stats in one document.
+/// Parts Ubisoft fails to deliver are listed in `missing`
+pub async fn player_card(access: UbiAccess, path: Path<(String, String)>, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
+    let (platform, name) = path.into_inner();
+    if !PLATFORMS.contains(&platform.as_str()) {
+        return Err(AppError::INVALID_INPUT.message("Platform must be one of uplay, psn or xbl.".to_string()));
+    }
+
+    let card = ubi_api.player_card(&platform, &name).await?;
+
+    Ok(access.quota.ok().json(card))
+}
+
+/// comma separated `platform:name` and `platform:profile_id` entries
+#[derive(Deserialize)]
+pub struct ComparePlayers {
+    players: Option<String>,
+    profile_ids: Option<String>,
+}
+
+fn split_platform(entry: &str) -> Result<(String, String), AppError> {
+    match entry.trim().split_once(':') {
+        Some((platform, value)) if PLATFORMS.contains(&platform) && !value.trim().is_empty() => {
+            Ok((platform.to_string(), value.trim().to_string()))
+        }
+        _ => Err(AppError::INVALID_INPUT.message(format!(
+            "Invalid player {:?}, expected platform:value with platform one of uplay, psn or xbl.",
+            entry
+        ))),
+    }
+}
+
+/// GET /ubi/compare?players=uplay:name,psn:name&profile_ids=uplay:id
+/// two to five players side by side, named players come first
+pub async fn compare_players(
+    mut access: UbiAccess,
+    Query(req): Query<ComparePlayers>,
+    ubi_api: Data<ubi::ubi_api::UbiApi>,
+) -> AppResponse {
+    let entries = |list: &Option<String>| {
+        list.iter()
+            .flat_map(|list| list.split(','))
+            .filter(|entry| !entry.trim().is_empty())
+            .map(split_platform)
+            .try_fold(Vec::new(), |mut entries: Vec<(String, String)>, entry| {
+                // names and profile ids are case insensitive, a repeated
+                // entry is fetched once
+                let (platform, value) = entry?;
+                let repeated = entries
+                    .iter()
+                    .any(|(other_platform, other)| *other_platform == platform && other.eq_ignore_ascii_case(&value));
+                if !repeated {
+                    entries.push((platform, value));
+                }
+                Ok::<_, AppError>(entries)
+            })
+    };
+    let (names, profile_ids) = (entries(&req.players)?, entries(&req.profile_ids)?);
+
+    let count = names.len() + profile_ids.len();
+    if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&count) {
+        return Err(AppError::INVALID_INPUT.message(format!(
+            "Compare {} to {} players, got {}.",
+            MIN_PLAYERS, MAX_PLAYERS, count
+        )));
+    }
+
+    let players = names
+        .into_iter()
+        .map(|(platform, name)| ComparePlayer::Name { platform, name })
+        .chain(
+            profile_ids
+                .into_iter()
+                .map(|(platform, profile_id)| ComparePlayer::ProfileId { platform, profile_id }),
+        )
+        .collect();
+    let profiles = ubi_api.resolve_players(players).await?;
+
+    // a name and a profile id can still resolve to the same player
+    let mut seen = Vec::new();
+    for profile in &profiles {
+        if seen.contains(&profile.profile_id) {
+            return Err(AppError::INVALID_INPUT.message(format!(
+                "{:?} is listed more than once.",
+                profile.name_on_platform
+            )));
+        }
+        seen.push(profile.profile_id.clone());
+    }
+
+    // one token per compared player, the extractor took the first
+    if let Err(response) = access.charge(count as u32 - 1) {
+        return Ok(response);
+    }
+
+    let comparison = ubi_api.compare(profiles).await?;
+
+    Ok(access.quota.ok().json(comparison))
+}
+
+/// outbound scheduler queue depths and remaining budgets, admins only
+pub async fn scheduler_metrics(admin: AdminUser, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
+    debug!("Scheduler metrics requested by {}", admin.0);
+    Ok(HttpResponse::Ok().json(ubi_api.scheduler.metrics()))
+}
+
+/// health and session expiry of every pooled service account, admins only
+pub async fn list_accounts(admin: AdminUser, ubi_api: Data<ubi::ubi_api::UbiApi>) -> AppResponse {
+    debug!("Ubi account health requested by {}", admin.0);
+    Ok(HttpResponse::Ok().json(ubi_api.accounts.health()))
+}
+
+/// logs a new service account in and adds it to the pool, admins only
+pub async fn add_account(
+    admin: AdminUser,
+    payload: Json<AddUbiAccount>,
+    ubi_api: Data<ubi::ubi_api::UbiApi>,
+) -> AppResponse {
+    payload
+        .validate()
+        .map_err(|_| AppError::INVALID_INPUT.message("Invalid email or password.".to_string()))?;
+
+    let account = ubi_api.add_account(&payload.email, &payload.password).await?;
+    info!("Ubi account {} added to the pool by {}", account.email, admin.0);
+
+    Ok(HttpResponse::Created().json(ubi_api.accounts.health()))
+}
+
+#[derive(Deserialize)]
+pub struct SessionHistoryQuery {
+    email: String,
+    limit: Option<i64>,
+}
+
+/// sessions issued for one service account, newest first, admins only
+pub async fn account_history(
+    admin: AdminUser,
+    Query(req): Query<SessionHistoryQuery>,
+    ubi_api: Data<ubi::ubi_api::UbiApi>,
+) -> AppResponse {
+    debug!("Ubi session history for {} requested by {}", req.email, admin.0);
+    let limit = req.limit.unwrap_or(50).clamp(1, 500);
+    let history = ubi_api.session_history(&req.email, limit).await?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
@@ -0,0 +1,64 @@
+//handlers realtime
+use super::{auth::AuthenticatedUser, AppResponse};
+use crate::{
+    errors::AppError,
+    models::api_key::UBI_READ,
+    realtime::{sse, ws, Realtime},
+};
+use actix_web::{
+    http::header::CACHE_CONTROL,
+    web::{Data, Payload, Query},
+    HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+#[derive(Debug, Deserialize)]
+pub struct SseQuery {
+    pub profile_ids: String, // comma separated
+}
+
+/// websocket upgrade, clients subscribe by sending
+/// `{"action": "subscribe", "profile_ids": [..]}`
+#[instrument(skip(user, req, payload, realtime))]
+pub async fn websocket(
+    user: AuthenticatedUser,
+    req: HttpRequest,
+    payload: Payload,
+    realtime: Data<Realtime>,
+) -> AppResponse {
+    user.require_scope(UBI_READ)?;
+
+    let mut response = actix_http::ws::handshake(req.head()).map_err(|op| {
+        debug!("Websocket handshake failed. {:?}", op);
+        AppError::INVALID_INPUT.message("Expected a websocket upgrade request.".to_string())
+    })?;
+    let connection = realtime.connect(user.0)?;
+
+    Ok(response.streaming(ws::serve(&realtime, connection, payload)))
+}
+
+/// server-sent events fallback, subscriptions come from the query
+#[instrument(skip(user, realtime))]
+pub async fn events(user: AuthenticatedUser, query: Query<SseQuery>, realtime: Data<Realtime>) -> AppResponse {
+    user.require_scope(UBI_READ)?;
+
+    let profile_ids: Vec<String> = query
+        .profile_ids
+        .split(',')
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect();
+    if profile_ids.is_empty() {
+        return Err(AppError::INVALID_INPUT.message("Pass at least one profile id in profile_ids.".to_string()));
+    }
+
+    let mut connection = realtime.connect(user.0)?;
+    connection.subscribe(profile_ids)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .header("X-Accel-Buffering", "no")
+        .streaming(sse::serve(&realtime, connection)))
+}
@@ -0,0 +1,125 @@
+//handlers two_factor
+use super::{
+    auth::{normalize_recovery_code, verify_second_factor, AuthenticatedUser},
+    AppResponse,
+};
+use crate::{
+    config::{crypto::CryptoService, totp},
+    db::{recovery_code::RecoveryCodeRepository, user::UserRepository},
+    errors::AppError,
+    models::two_factor::TwoFactorCode,
+};
+use actix_web::{
+    web::{Data, Json},
+    HttpResponse,
+};
+use chrono::Utc;
+use serde_json::json;
+use tracing::instrument;
+
+const RECOVERY_CODES: usize = 10;
+const ISSUER: &str = "zbot";
+
+/// starts enrolment, returns the secret and the otpauth:// uri for the QR code.
+/// 2fa stays off until a first code is verified
+#[instrument(skip(user, repository))]
+pub async fn enroll(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
+    user.require_own_login()?;
+
+    let user = repository
+        .find_by_id(user.0)
+        .await?
+        .ok_or(AppError::INTERNAL_ERROR)?;
+    if user.totp_enabled {
+        return Err(AppError::INVALID_INPUT.message("Two factor authentication is already enabled.".to_string()));
+    }
+
+    let secret = totp::generate_secret();
+    repository.update_totp(user.id, Some(secret.clone()), false).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "secret": secret,
+        "provisioning_uri": totp::provisioning_uri(&secret, &user.username, ISSUER),
+    })))
+}
+
+/// confirms enrolment with a first code, turns 2fa on and
+/// returns the recovery codes, they are only shown here
+#[instrument(skip(user, payload, repository, recovery_codes, crypto_service))]
+pub async fn verify(
+    user: AuthenticatedUser,
+    payload: Json<TwoFactorCode>,
+    repository: UserRepository,
+    recovery_codes: RecoveryCodeRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    user.require_own_login()?;
+
+    let user = repository
+        .find_by_id(user.0)
+        .await?
+        .ok_or(AppError::INTERNAL_ERROR)?;
+    let secret = match (&user.totp_secret, user.totp_enabled) {
+        (Some(secret), false) => secret.clone(),
+        (_, true) => {
+            return Err(AppError::INVALID_INPUT.message("Two factor authentication is already enabled.".to_string()))
+        }
+        (None, false) => {
+            return Err(AppError::INVALID_INPUT.message("Start two factor enrolment first.".to_string()))
+        }
+    };
+
+    let step = totp::verify(&secret, &payload.code, Utc::now().timestamp())
+        .ok_or_else(|| AppError::INVALID_INPUT.message("Invalid two factor code.".to_string()))?;
+    if !repository.use_totp_step(user.id, step).await? {
+        return Err(AppError::INVALID_INPUT.message("Two factor code already used, wait for the next one.".to_string()));
+    }
+
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            let token = crypto_service.random_token();
+            format!("{}-{}-{}-{}", &token[0..4], &token[4..8], &token[8..12], &token[12..16])
+        })
+        .collect();
+    let code_hashes = codes
+        .iter()
+        .map(|code| crypto_service.hash_token(&normalize_recovery_code(code)))
+        .collect();
+
+    recovery_codes.replace_all(user.id, code_hashes).await?;
+    repository.update_totp(user.id, Some(secret), true).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "enabled": true,
+        "recovery_codes": codes,
+    })))
+}
+
+/// turns 2fa off, needs a current TOTP or recovery code
+#[instrument(skip(user, payload, repository, recovery_codes, crypto_service))]
+pub async fn disable(
+    user: AuthenticatedUser,
+    payload: Json<TwoFactorCode>,
+    repository: UserRepository,
+    recovery_codes: RecoveryCodeRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    user.require_own_login()?;
+
+    let user = repository
+        .find_by_id(user.0)
+        .await?
+        .ok_or(AppError::INTERNAL_ERROR)?;
+    if !user.totp_enabled {
+        return Err(AppError::INVALID_INPUT.message("Two factor authentication is not enabled.".to_string()));
+    }
+
+    if !verify_second_factor(&user, &payload.code, &repository, &recovery_codes, &crypto_service).await? {
+        return Err(AppError::INVALID_CREDENTIALS.message("Invalid two factor code.".to_string()));
+    }
+
+    recovery_codes.delete_all(user.id).await?;
+    repository.update_totp(user.id, None, false).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "enabled": false })))
+}
@@ -0,0 +1,95 @@
+use super::{audit_event, auth::AuthenticatedUser, AppResponse};
+use crate::{
+    db::{audit::AuditRepository, profile_link::ProfileLinkRepository},
+    errors::AppError,
+    models::{
+        api_key::{PROFILE_READ, PROFILE_WRITE},
+        audit::{EVENT_UBI_LINK, EVENT_UBI_UNLINK},
+        profile_link::{LinkProfile, NewProfileLink},
+    },
+    ubi::{player_card::PLATFORMS, ubi_api::UbiApi},
+};
+use actix_web::{
+    web::{Data, Json, Path},
+    HttpRequest, HttpResponse,
+};
+use serde_json::json;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Ubisoft profiles linked to the current user
+pub async fn list_profiles(user: AuthenticatedUser, links: ProfileLinkRepository) -> AppResponse {
+    user.require_scope(PROFILE_READ)?;
+
+    let profiles = links.find_by_user_id(user.0).await?;
+    Ok(HttpResponse::Ok().json(profiles))
+}
+
+/// resolves the profile on Ubisoft and links it to the current user
+pub async fn link_profile(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    payload: Json<LinkProfile>,
+    links: ProfileLinkRepository,
+    audit: AuditRepository,
+    ubi_api: Data<UbiApi>,
+) -> AppResponse {
+    user.require_scope(PROFILE_WRITE)?;
+    payload
+        .validate()
+        .map_err(|_| AppError::INVALID_INPUT.message("Invalid name_on_platform.".to_string()))?;
+    if !PLATFORMS.contains(&payload.platform_type.as_str()) {
+        return Err(AppError::INVALID_INPUT.message("Platform must be one of uplay, psn or xbl.".to_string()));
+    }
+
+    let profile = ubi_api
+        .find_profile(payload.name_on_platform.clone(), payload.platform_type.clone())
+        .await?
+        .profiles
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            AppError::NOT_FOUND.message(format!(
+                "Profile {:?} not found on platform {:?}",
+                payload.name_on_platform, payload.platform_type
+            ))
+        })?;
+
+    let link = links
+        .link_user(NewProfileLink {
+            user_id: Some(user.0),
+            discord_user_id: None,
+            profile_id: profile.profile_id,
+            platform: profile.platform_type,
+            name_on_platform: profile.name_on_platform,
+        })
+        .await?;
+    audit
+        .record(audit_event(&req, EVENT_UBI_LINK).by(user.0).details(json!({
+            "profile_id": link.profile_id,
+            "platform": link.platform,
+            "name_on_platform": link.name_on_platform,
+        })))
+        .await;
+
+    Ok(HttpResponse::Created().json(link))
+}
+
+pub async fn unlink_profile(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    id: Path<Uuid>,
+    links: ProfileLinkRepository,
+    audit: AuditRepository,
+) -> AppResponse {
+    user.require_scope(PROFILE_WRITE)?;
+
+    let id = id.into_inner();
+    if !links.unlink_user(user.0, id).await? {
+        return Err(AppError::NOT_FOUND.message("Linked profile not found.".to_string()));
+    }
+    audit
+        .record(audit_event(&req, EVENT_UBI_UNLINK).by(user.0).details(json!({ "link_id": id })))
+        .await;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,28 +1,46 @@
 //handlers user
 
-use super::{auth::AuthenticatedUser, AppResponse};
+use super::{
+    audit_event,
+    auth::{verify_current_password, AuthenticatedUser},
+    AppResponse,
+};
 use crate::{
-    config::crypto::CryptoService,
+    config::{crypto::CryptoService, login_guard::LoginGuard, mailer::Mailer},
     db,
-    db::user::UserRepository,
+    db::{
+        audit::AuditRepository, email_change::EmailChangeRepository, follow::FollowRepository, profile_link::ProfileLinkRepository,
+        session::SessionRepository, user::UserRepository,
+    },
     errors::AppError,
-    models::user::{NewUser, UpdateProfile, User},
+    models::api_key,
+    models::audit::{EVENT_EMAIL_CHANGE, EVENT_PASSWORD_CHANGE, EVENT_PROFILE_UPDATE, EVENT_SIGNUP},
+    models::email_change::NewEmailChange,
+    models::location,
+    models::user::{
+        ChangeEmail, ChangePassword, ConfirmEmail, NewUser, ProfilePrivacy, PublicProfile, UpdateProfile, User, ROLE_ADMIN,
+    },
 };
 
 use actix_web::{
-    web::{Data, Json},
-    HttpResponse,
+    http::header::{ETAG, IF_MATCH},
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse,
 };
+use chrono::{Duration, Utc};
+use serde_json::json;
 
 use color_eyre::Result;
 use sqlx::{error::DatabaseError, postgres::PgError};
 use tracing::{debug, instrument};
 use validator::Validate;
 
-#[instrument(skip(user, repository, crypto_service))]
+#[instrument(skip(req, user, repository, audit, crypto_service))]
 pub async fn create_user(
+    req: HttpRequest,
     user: Json<NewUser>,
     repository: UserRepository,
+    audit: AuditRepository,
     crypto_service: Data<CryptoService>,
 ) -> AppResponse {
     match user.validate() {
@@ -30,7 +48,9 @@ pub async fn create_user(
         Err(e) => {
             let error_map = e.field_errors();
 
-            let message = if error_map.contains_key("username") {
+            let message = if error_map.contains_key("username") && user.username.contains('@') {
+                "Invalid username. It can not contain \"@\".".to_string()
+            } else if error_map.contains_key("username") {
                 format!("Invalid username. \"{}\" is too short.", user.username)
             } else if error_map.contains_key("email") {
                 format!("Invalid email address \"{}\"", user.email)
@@ -47,7 +67,10 @@ pub async fn create_user(
     let result: Result<User> = repository.create(user.0, crypto_service.as_ref()).await;
 
     match result {
-        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+        Ok(user) => {
+            audit.record(audit_event(&req, EVENT_SIGNUP).by(user.id)).await;
+            Ok(HttpResponse::Ok().json(user))
+        }
         Err(e) => {
             let pg_error: &PgError = e.root_cause().downcast_ref::<PgError>().ok_or_else(|| {
                 debug!("Error creating user. {:?}", e);
@@ -76,20 +99,31 @@ pub async fn create_user(
 
 #[instrument[skip(user, repository)]]
 pub async fn me(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
+    user.require_scope(api_key::PROFILE_READ)?;
+
     let user = repository
         .find_by_id(user.0)
         .await?
         .ok_or(AppError::INTERNAL_ERROR)?;
+    let locations = repository.find_locations(user.id).await?;
 
-    Ok(HttpResponse::Ok().json(user))
+    Ok(HttpResponse::Ok()
+        .header(ETAG, user.etag())
+        .json(user.with_locations(locations.iter().map(|l| l.to_location()).collect())))
 }
 
-#[instrument[skip(user, repository)]]
+/// PATCH /me (POST kept for older clients), only the given fields change.
+/// With If-Match the update applies only to the version the client last saw
+#[instrument[skip(user, repository, audit, req)]]
 pub async fn update_profile(
     user: AuthenticatedUser,
     repository: UserRepository,
-    profile: Json<UpdateProfile>,
+    audit: AuditRepository,
+    mut profile: Json<UpdateProfile>,
+    req: HttpRequest,
 ) -> AppResponse {
+    user.require_scope(api_key::PROFILE_WRITE)?;
+
     // valid update_profile has all required fields
     match profile.validate() {
         Ok(_) => Ok(()),
@@ -99,8 +133,10 @@ pub async fn update_profile(
             let message = if error_map.contains_key("image") {
                 format!(
                     "Invalid image. \"{}\" is not a valid url.",
-                    profile.image.as_deref().unwrap()
+                    profile.image.clone().flatten().unwrap_or_default()
                 )
+            } else if error_map.contains_key("full_name") {
+                "Invalid full name. Too short".to_string()
             } else {
                 "Invalid input.".to_string()
             };
@@ -108,24 +144,263 @@ pub async fn update_profile(
             Err(AppError::INVALID_INPUT.message(message))
         }
     }?;
+    if let Some(Some(locations)) = profile.locations.as_mut() {
+        location::normalize(locations).map_err(|message| AppError::INVALID_INPUT.message(message))?;
+    }
+
+    let if_match = match req.headers().get(IF_MATCH) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| AppError::INVALID_INPUT.message("Invalid If-Match header.".to_string()))?,
+        ),
+        None => None,
+    };
+
+    //update to DB
+    let profile = profile.into_inner();
+    let fields = profile.fields();
+    let updated_user = repository
+        .update_profile(user.0, profile, if_match)
+        .await?
+        .ok_or_else(|| AppError::PRECONDITION_FAILED.default())?;
+    if !fields.is_empty() {
+        audit
+            .record(audit_event(&req, EVENT_PROFILE_UPDATE).by(user.0).details(json!({ "fields": fields })))
+            .await;
+    }
+    let locations = repository.find_locations(updated_user.id).await?;
+
+    Ok(HttpResponse::Ok()
+        .header(ETAG, updated_user.etag())
+        .json(updated_user.with_locations(locations.iter().map(|l| l.to_location()).collect())))
+}
+
+/// GET /users/{username}, works without a login as well
+#[instrument(skip(viewer, repository, links, follows))]
+pub async fn public_profile(
+    username: Path<String>,
+    viewer: Option<AuthenticatedUser>,
+    repository: UserRepository,
+    links: ProfileLinkRepository,
+    follows: FollowRepository,
+) -> AppResponse {
+    // accounts pending deletion are gone for everyone else already
+    let user = repository
+        .find_by_username(&username)
+        .await?
+        .filter(|user| user.deletion_scheduled_for.is_none())
+        .ok_or_else(|| AppError::NOT_FOUND.message(format!("User {:?} not found.", username.as_str())))?;
+
+    // api keys need the profile scope to be treated as the owner
+    let full_view = match viewer.filter(|viewer| viewer.require_scope(api_key::PROFILE_READ).is_ok()) {
+        Some(viewer) if viewer.0 == user.id => true,
+        Some(viewer) if viewer.require_session().is_ok() => {
+            matches!(repository.find_by_id(viewer.0).await?, Some(found) if found.role == ROLE_ADMIN)
+        }
+        _ => false,
+    };
+    let privacy = repository.find_privacy(user.id).await?;
+    let shown = |visible: bool| full_view || visible;
+
+    let locations = if shown(privacy.show_locations) {
+        let locations = repository.find_locations(user.id).await?;
+        Some(locations.iter().map(|l| l.to_location()).collect())
+    } else {
+        None
+    };
+    let ubi_profiles = if shown(privacy.show_ubi_profiles) {
+        let profiles = links.find_by_user_id(user.id).await?;
+        Some(profiles.iter().map(|link| link.to_linked()).collect())
+    } else {
+        None
+    };
+    // badges name the linked profiles, hidden profiles hide them too
+    let badges = if shown(privacy.show_badges && privacy.show_ubi_profiles) {
+        Some(repository.find_badges(user.id).await?)
+    } else {
+        None
+    };
+    let (followers, following) = follows.count(user.id).await?;
+
+    Ok(HttpResponse::Ok().json(PublicProfile {
+        username: user.username,
+        email: Some(user.email).filter(|_| full_view),
+        full_name: user.full_name.filter(|_| shown(privacy.show_full_name)),
+        bio: user.bio.filter(|_| shown(privacy.show_bio)),
+        image: user.image.filter(|_| shown(privacy.show_image)),
+        locations,
+        ubi_profiles,
+        badges,
+        followers,
+        following,
+        created_at: user.created_at,
+    }))
+}
+
+#[instrument(skip(user, repository))]
+pub async fn get_privacy(user: AuthenticatedUser, repository: UserRepository) -> AppResponse {
+    user.require_scope(api_key::PROFILE_READ)?;
+
+    let privacy = repository.find_privacy(user.0).await?;
+    Ok(HttpResponse::Ok().json(privacy))
+}
+
+#[instrument(skip(user, payload, repository))]
+pub async fn set_privacy(
+    user: AuthenticatedUser,
+    payload: Json<ProfilePrivacy>,
+    repository: UserRepository,
+) -> AppResponse {
+    user.require_scope(api_key::PROFILE_WRITE)?;
+
+    let privacy = repository.set_privacy(user.0, payload.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(privacy))
+}
+
+/// change password, re-verifies the old one
+/// every session except the calling one is revoked
+#[allow(clippy::too_many_arguments)]
+#[instrument[skip(req, user, payload, repository, sessions, audit, crypto_service, login_guard)]]
+pub async fn change_password(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    payload: Json<ChangePassword>,
+    repository: UserRepository,
+    sessions: SessionRepository,
+    audit: AuditRepository,
+    crypto_service: Data<CryptoService>,
+    login_guard: Data<LoginGuard>,
+) -> AppResponse {
+    user.require_own_login()?;
+    payload.validate().map_err(|_| {
+        AppError::INVALID_INPUT.message("Invalid password. Too short".to_string())
+    })?;
 
-    // find user from Auth token
+    let current_session = user.session_id();
     let user = repository
         .find_by_id(user.0)
         .await?
         .ok_or(AppError::INTERNAL_ERROR)?;
 
-    //update to DB
-    let updated_user = repository.update_profile(user.id, profile.0).await?;
+    verify_current_password(&req, &user, &payload.old_password, &crypto_service, &login_guard).await?;
+
+    let payload = payload.into_inner();
+    repository
+        .update_password(user.id, payload.new_password, &crypto_service)
+        .await?;
+    let revoked = sessions.revoke_all(user.id, current_session).await?;
+    audit
+        .record(audit_event(&req, EVENT_PASSWORD_CHANGE).by(user.id).details(json!({
+            "revoked_sessions": revoked,
+        })))
+        .await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Password changed.",
+        "revoked_sessions": revoked,
+    })))
+}
+
+/// starts an email change, the new address has to be confirmed
+/// through the emailed link before it replaces the current one
+#[allow(clippy::too_many_arguments)]
+#[instrument[skip(req, user, payload, repository, email_changes, crypto_service, mailer, login_guard)]]
+pub async fn change_email(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    payload: Json<ChangeEmail>,
+    repository: UserRepository,
+    email_changes: EmailChangeRepository,
+    crypto_service: Data<CryptoService>,
+    mailer: Data<Mailer>,
+    login_guard: Data<LoginGuard>,
+) -> AppResponse {
+    user.require_own_login()?;
+    payload.validate().map_err(|_| {
+        AppError::INVALID_INPUT.message(format!("Invalid email address \"{}\"", payload.new_email))
+    })?;
+
+    let user = repository
+        .find_by_id(user.0)
+        .await?
+        .ok_or(AppError::INTERNAL_ERROR)?;
+
+    verify_current_password(&req, &user, &payload.password, &crypto_service, &login_guard).await?;
+
+    if repository.find_by_email(&payload.new_email).await?.is_some() {
+        return Err(AppError::INVALID_INPUT.message("Email address already exists.".to_string()));
+    }
 
-    match repository.find_location_by_user_id(user.id).await {
-        Ok(user_location) => match user_location {
-            Some(location) => {
-                let user_with_locations = updated_user.with_locations(location.to_array());
-                Ok(HttpResponse::Ok().json(user_with_locations))
+    let token = crypto_service.random_token();
+    let email_change = email_changes
+        .create(NewEmailChange {
+            user_id: user.id,
+            new_email: payload.new_email.clone(),
+            token_hash: crypto_service.hash_token(&token),
+            expires_at: (Utc::now() + Duration::hours(24)).naive_utc(),
+        })
+        .await?;
+
+    let link = mailer.link(&format!("/me/email/confirm?token={}", token));
+    mailer
+        .send(
+            &email_change.new_email,
+            "Confirm your new zbot email address",
+            &format!(
+                "Hi {},\n\nopen this link within 24 hours to confirm your new email address:\n{}\n",
+                user.username, link
+            ),
+        )
+        .await;
+
+    Ok(HttpResponse::Accepted().json(json!({
+        "message": format!("Confirmation sent to {}", email_change.new_email),
+        "expires_at": email_change.expires_at,
+    })))
+}
+
+/// confirms a pending email change, the token is the proof
+/// so no login is needed to follow the emailed link
+#[instrument[skip(req, query, email_changes, audit, crypto_service)]]
+pub async fn confirm_email(
+    req: HttpRequest,
+    Query(query): Query<ConfirmEmail>,
+    email_changes: EmailChangeRepository,
+    audit: AuditRepository,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    let invalid_token = || AppError::INVALID_INPUT.message("Invalid or expired confirmation token.".to_string());
+    let email_change = email_changes
+        .find_pending(&crypto_service.hash_token(&query.token))
+        .await?
+        .ok_or_else(invalid_token)?;
+
+    match email_changes.confirm(email_change.id).await {
+        Ok(None) => Err(invalid_token()),
+        Ok(Some(user)) => {
+            audit
+                .record(audit_event(&req, EVENT_EMAIL_CHANGE).by(user.id).details(json!({
+                    "new_email": email_change.new_email,
+                })))
+                .await;
+            Ok(HttpResponse::Ok().json(user))
+        }
+        Err(e) => {
+            let pg_error: &PgError = e.root_cause().downcast_ref::<PgError>().ok_or_else(|| {
+                debug!("Error confirming email. {:?}", e);
+                AppError::INTERNAL_ERROR
+            })?;
+
+            match pg_error.code() {
+                Some(db::UNIQUE_VIOLATION_CODE) => {
+                    Err(AppError::INVALID_INPUT.message("Email address already exists.".to_string()))
+                }
+                _ => {
+                    debug!("Error confirming email. {:?}", pg_error);
+                    Err(AppError::INTERNAL_ERROR.into())
+                }
             }
-            None => Ok(HttpResponse::Ok().json(user)),
-        },
-        Err(_) => Ok(HttpResponse::Ok().json(user)),
+        }
     }
 }
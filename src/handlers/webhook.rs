@@ -0,0 +1,162 @@
+//handlers webhook
+use super::{auth::AuthenticatedUser, AppResponse};
+use crate::{
+    config::crypto::CryptoService,
+    db::{profile_link::ProfileLinkRepository, webhook::WebhookRepository},
+    errors::AppError,
+    models::webhook::{CreateWebhook, CreatedWebhook, NewWebhook, MMR_THRESHOLD, WEBHOOK_EVENTS},
+    webhooks::WebhookDispatcher,
+};
+use actix_web::{
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+    pub limit: Option<i64>,
+}
+
+#[instrument(skip(user, webhooks))]
+pub async fn list_webhooks(user: AuthenticatedUser, webhooks: WebhookRepository) -> AppResponse {
+    user.require_session()?;
+
+    let webhooks = webhooks.find_by_user_id(user.0).await?;
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+/// the signing secret is only part of this response
+#[instrument(skip(user, payload, webhooks, links, dispatcher, crypto_service))]
+pub async fn create_webhook(
+    user: AuthenticatedUser,
+    payload: Json<CreateWebhook>,
+    webhooks: WebhookRepository,
+    links: ProfileLinkRepository,
+    dispatcher: Data<WebhookDispatcher>,
+    crypto_service: Data<CryptoService>,
+) -> AppResponse {
+    user.require_session()?;
+
+    match payload.validate() {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let error_map = e.field_errors();
+
+            let message = if error_map.contains_key("url") {
+                "Invalid url.".to_string()
+            } else if error_map.contains_key("mmr_threshold") {
+                "Invalid mmr_threshold. Use 0 to 20000.".to_string()
+            } else {
+                "Invalid input.".to_string()
+            };
+
+            Err(AppError::INVALID_INPUT.message(message))
+        }
+    }?;
+
+    if payload.events.is_empty() {
+        return Err(AppError::INVALID_INPUT.message(format!("Pick at least one event of {:?}", WEBHOOK_EVENTS)));
+    }
+    if let Some(unknown) = payload.events.iter().find(|event| !WEBHOOK_EVENTS.contains(&event.as_str())) {
+        return Err(AppError::INVALID_INPUT.message(format!(
+            "Unknown event {:?}, expected one of {:?}",
+            unknown, WEBHOOK_EVENTS
+        )));
+    }
+    if payload.events.iter().any(|event| event == MMR_THRESHOLD) && payload.mmr_threshold.is_none() {
+        return Err(AppError::INVALID_INPUT.message("The mmr_threshold event needs an mmr_threshold.".to_string()));
+    }
+    if let Some(profile_id) = &payload.profile_id {
+        let linked = links.find_by_user_id(user.0).await?;
+        if !linked.iter().any(|link| &link.profile_id == profile_id) {
+            return Err(AppError::INVALID_INPUT.message("Link the profile before adding a webhook for it.".to_string()));
+        }
+    }
+    let url = dispatcher.validate_url(&payload.url).await?;
+
+    if webhooks.count_by_user_id(user.0).await? >= dispatcher.max_per_user() {
+        return Err(AppError::INVALID_INPUT.message(format!(
+            "A user can have at most {} webhooks.",
+            dispatcher.max_per_user()
+        )));
+    }
+
+    let payload = payload.into_inner();
+    let mut events = payload.events;
+    events.sort();
+    events.dedup();
+
+    let secret = crypto_service.random_token();
+    let webhook = webhooks
+        .create(NewWebhook {
+            user_id: user.0,
+            url: url.to_string(),
+            secret: secret.clone(),
+            events: events.join(" "),
+            profile_id: payload.profile_id,
+            mmr_threshold: payload.mmr_threshold,
+        })
+        .await?;
+
+    Ok(HttpResponse::Created().json(CreatedWebhook { secret, webhook }))
+}
+
+#[instrument(skip(user, webhooks))]
+pub async fn delete_webhook(user: AuthenticatedUser, id: Path<Uuid>, webhooks: WebhookRepository) -> AppResponse {
+    user.require_session()?;
+
+    if !webhooks.delete(id.into_inner(), user.0).await? {
+        return Err(AppError::NOT_FOUND.message("Webhook not found.".to_string()));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// latest deliveries first, replays included
+#[instrument(skip(user, webhooks))]
+pub async fn list_deliveries(
+    user: AuthenticatedUser,
+    id: Path<Uuid>,
+    query: Query<DeliveriesQuery>,
+    webhooks: WebhookRepository,
+) -> AppResponse {
+    user.require_session()?;
+
+    let webhook = webhooks
+        .find_by_id(id.into_inner(), user.0)
+        .await?
+        .ok_or_else(|| AppError::NOT_FOUND.message("Webhook not found.".to_string()))?;
+
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let deliveries = webhooks.find_deliveries(webhook.id, limit).await?;
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+/// queues the same payload again as a new delivery, sent by the next poll
+#[instrument(skip(user, webhooks))]
+pub async fn replay_delivery(
+    user: AuthenticatedUser,
+    path: Path<(Uuid, Uuid)>,
+    webhooks: WebhookRepository,
+) -> AppResponse {
+    user.require_session()?;
+    let (id, delivery_id) = path.into_inner();
+
+    let webhook = webhooks
+        .find_by_id(id, user.0)
+        .await?
+        .ok_or_else(|| AppError::NOT_FOUND.message("Webhook not found.".to_string()))?;
+    let original = webhooks
+        .find_delivery(delivery_id, webhook.id)
+        .await?
+        .ok_or_else(|| AppError::NOT_FOUND.message("Delivery not found.".to_string()))?;
+
+    let delivery = webhooks
+        .create_delivery(webhook.id, &original.event, &original.payload, Some(original.id))
+        .await?;
+
+    Ok(HttpResponse::Accepted().json(delivery))
+}
@@ -1,58 +1,134 @@
 #[macro_use]
 extern crate validator_derive;
 
+mod accounts;
+mod achievements;
 mod config;
 mod db;
+mod discord;
 mod errors;
 mod handlers;
 mod models;
+mod notifications;
+mod oauth;
+mod realtime;
+mod storage;
+#[cfg(test)]
+mod test_support;
+mod tracker;
 mod ubi;
+mod webhooks;
 
 //local modules
 use crate::config::Config;
-use crate::db::ubi_user::UbiUserRepository;
-use crate::handlers::app_config;
+use crate::db::{rate_limit::RateLimitRepository, ubi_user::UbiUserRepository};
+use crate::handlers::{app_config, quota_headers};
 
 use std::sync::Arc;
 
 //external packages
-use actix_web::{middleware::Logger, App, HttpServer};
+use actix_web::{dev::Service, middleware::Logger, App, HttpServer};
+use futures::future::FutureExt;
 use color_eyre::Result;
-use tracing::info;
+use tracing::{error, info};
 
 #[actix_rt::main]
 async fn main() -> Result<()> {
     let config = Config::from_env().expect("Failed to load env configuration");
     let db_pool = config.db_pool().await.expect("Database connection failed!");
-    let crypto_service = config.crypto_service().clone();
+    let crypto_service = config
+        .crypto_service()
+        .expect("Failed to initialise crypto service");
+    let login_guard = config.login_guard();
+    let trusted_proxies = config
+        .trusted_proxies()
+        .expect("Failed to parse PROXY.TRUSTED");
+    let mailer = config.mailer();
+    let rate_limiter = config.rate_limiter();
+    let card_renderer = config.card_renderer();
+
+    if rate_limiter.persist() {
+        let repository = RateLimitRepository::new(Arc::new(db_pool.clone()));
+        let snapshot = repository
+            .find_all()
+            .await
+            .expect("Loading rate limit buckets failed!");
+        rate_limiter.restore(snapshot);
+
+        let limiter = rate_limiter.clone();
+        actix_rt::spawn(async move {
+            let mut interval = actix_rt::time::interval(std::time::Duration::from_secs(
+                limiter.persist_interval_secs(),
+            ));
+            loop {
+                interval.tick().await;
+                if let Err(e) = repository.replace_all(limiter.snapshot()).await {
+                    error!("Error persisting rate limit buckets. {:?}", e);
+                }
+            }
+        });
+    }
 
     info!("STARTING at http://{}:{}", config.host, config.port);
 
     let req_client = reqwest::Client::new();
+    let oauth_service = config
+        .oauth_service(req_client.clone())
+        .expect("Failed to configure OAuth providers");
+    let discord_bot = config
+        .discord_bot(req_client.clone())
+        .expect("Failed to configure Discord bot");
+    let avatars = config
+        .avatars(req_client.clone())
+        .expect("Failed to configure storage");
+    let accounts = config.accounts(avatars.clone(), Arc::new(db_pool.clone()));
+    actix_rt::spawn(accounts.clone().run());
     let ubi_user_db = UbiUserRepository::new(Arc::new(db_pool.clone()));
-    let mut ubi_api = ubi::ubi_api::UbiApi::new(
-        config.auth.email.as_str(),
-        config.auth.password.as_str(),
-        req_client,
-        config.ubi,
-    );
-
+    let ubi_api = ubi::ubi_api::UbiApi::new(req_client.clone(), config.ubi.clone(), ubi_user_db);
 
     ubi_api
-        .login(ubi_user_db)
+        .login(config.auth.email.as_str(), config.auth.password.as_str())
         .await
         .expect("UBI authentication failed!");
 
-    // TODO: (next time)
-    // now that we are logged int, ubi_users table in DB has our ubi user token
-    // use that, dont login again
+    let tracker = config.tracker(ubi_api.clone(), Arc::new(db_pool.clone()));
+    let webhook_dispatcher = config
+        .webhook_dispatcher(Arc::new(db_pool.clone()))
+        .expect("Failed to configure webhooks");
+    let notifier = config
+        .notifier(req_client, Arc::new(db_pool.clone()))
+        .expect("Failed to configure push notifications");
+    let achievements = config
+        .achievements(Arc::new(db_pool.clone()))
+        .expect("Failed to load achievements");
+    actix_rt::spawn(achievements.clone().listen(tracker.subscribe()));
+    actix_rt::spawn(notifier.clone().listen(tracker.subscribe()));
+    actix_rt::spawn(webhook_dispatcher.clone().listen(tracker.subscribe()));
+    actix_rt::spawn(webhook_dispatcher.clone().run());
+    actix_rt::spawn(tracker.clone().run());
+    let realtime = config.realtime(tracker.clone());
 
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
+            .wrap_fn(|req, srv| srv.call(req).map(|response| response.map(quota_headers)))
             .data(db_pool.clone())
             .data(crypto_service.clone())
+            .data(login_guard.clone())
+            .data(trusted_proxies.clone())
+            .data(mailer.clone())
+            .data(oauth_service.clone())
+            .data(discord_bot.clone())
+            .data(rate_limiter.clone())
+            .data(card_renderer.clone())
             .data(ubi_api.clone())
+            .data(tracker.clone())
+            .data(webhook_dispatcher.clone())
+            .data(realtime.clone())
+            .data(notifier.clone())
+            .data(avatars.clone())
+            .data(accounts.clone())
+            .data(achievements.clone())
             .configure(app_config)
     })
     .bind(format!("{}:{}", config.host, config.port))?
@@ -0,0 +1,49 @@
+use super::{
+    api_key::ApiKey,
+    audit::AuditEvent,
+    email_change::EmailChange,
+    follow::FollowUser,
+    identity::Identity,
+    notification::{DeviceToken, NotificationPreferences},
+    player_snapshot::PlayerSnapshot,
+    profile_link::ProfileLink,
+    session::UserSession,
+    user::{Badge, ProfilePrivacy, UserWithLocation},
+    webhook::Webhook,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// DELETE /me, the password confirms it is really the owner
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccount {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledDeletion {
+    pub deletion_scheduled_for: NaiveDateTime,
+}
+
+/// GET /me/export, everything stored about the user. Secrets (password,
+/// totp, token and key hashes) are left out
+#[derive(Debug, Serialize)]
+pub struct AccountExport {
+    pub exported_at: NaiveDateTime,
+    pub user: UserWithLocation,
+    pub privacy: ProfilePrivacy,
+    pub badges: Vec<Badge>,
+    pub sessions: Vec<UserSession>,
+    pub identities: Vec<Identity>,
+    pub api_keys: Vec<ApiKey>,
+    pub email_changes: Vec<EmailChange>,
+    pub audit_events: Vec<AuditEvent>,
+    pub ubi_profiles: Vec<ProfileLink>,
+    pub following: Vec<FollowUser>,
+    pub followers: Vec<FollowUser>,
+    /// stats recorded for the linked profiles
+    pub snapshots: Vec<PlayerSnapshot>,
+    pub webhooks: Vec<Webhook>,
+    pub devices: Vec<DeviceToken>,
+    pub notification_preferences: NotificationPreferences,
+}
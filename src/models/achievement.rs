@@ -0,0 +1,49 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// what a criterion looks at, filled from the player card and snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    MaxRank, // best season rank over every region, 23 is Champion
+    MaxMmr,
+    Level,
+    RankedKills, // career, from the populations statistics
+    RankedWins,
+    RankedKd,
+    CasualKills,
+    CasualWins,
+    HoursPlayed, // ranked and casual
+    WinStreak, // ranked wins in a row, from consecutive snapshots
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Criterion {
+    pub metric: Metric,
+    pub min: f64,
+}
+
+/// one entry of the definitions file, every criterion has to be met
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Achievement {
+    pub id: String, // stored as profile_badges.badge
+    pub name: String,
+    pub description: String,
+    pub criteria: Vec<Criterion>,
+}
+
+/// GET /me/badges, a badge with its definition and the linked profile that
+/// earned it. name and description are missing once the definition was
+/// removed from the file
+#[derive(Debug, Serialize)]
+pub struct EarnedBadge {
+    pub badge: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub awarded_at: NaiveDateTime,
+}
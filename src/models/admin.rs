@@ -0,0 +1,33 @@
+use super::user::User;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub const STATUS_ACTIVE: &str = "active";
+pub const STATUS_SUSPENDED: &str = "suspended";
+pub const STATUS_PENDING_DELETION: &str = "pending_deletion";
+pub const STATUSES: [&str; 3] = [STATUS_ACTIVE, STATUS_SUSPENDED, STATUS_PENDING_DELETION];
+
+/// GET /admin/users?q=&role=&status=&page=&per_page=
+#[derive(Debug, Deserialize)]
+pub struct UserSearch {
+    pub q: Option<String>, // part of the username, email or full name
+    pub role: Option<String>,
+    pub status: Option<String>, // active | suspended | pending_deletion
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+/// suspensions and impersonations need a reason for the audit trail
+#[derive(Debug, Deserialize, Validate)]
+pub struct AdminReason {
+    #[validate(length(min = 3, max = 500))]
+    pub reason: String,
+}
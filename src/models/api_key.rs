@@ -0,0 +1,58 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// scopes an api key can be granted, sessions have all of them
+pub const PROFILE_READ: &str = "profile:read";
+pub const PROFILE_WRITE: &str = "profile:write";
+pub const UBI_READ: &str = "ubi:read";
+pub const SCOPES: [&str; 3] = [PROFILE_READ, PROFILE_WRITE, UBI_READ];
+
+//retrive from DB
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: String,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl ApiKey {
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes.split_whitespace().map(|s| s.to_string()).collect()
+    }
+}
+
+/// returned once on creation, the key itself is never stored
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewApiKey {
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: String,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateApiKey {
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    pub scopes: Vec<String>,
+    #[validate(range(min = 1, max = 365))]
+    pub expires_in_days: Option<i64>,
+}
@@ -0,0 +1,89 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+pub const EVENT_SIGNUP: &str = "signup";
+pub const EVENT_LOGIN: &str = "login";
+pub const EVENT_LOGIN_FAILED: &str = "login_failed";
+pub const EVENT_PASSWORD_CHANGE: &str = "password_change";
+pub const EVENT_PASSWORD_RESET: &str = "password_reset";
+pub const EVENT_EMAIL_CHANGE: &str = "email_change";
+pub const EVENT_IDENTITY_LINK: &str = "identity_link";
+pub const EVENT_PROFILE_UPDATE: &str = "profile_update";
+pub const EVENT_UBI_LINK: &str = "ubi_profile_link";
+pub const EVENT_UBI_UNLINK: &str = "ubi_profile_unlink";
+pub const EVENT_ADMIN_SUSPEND: &str = "admin_suspend";
+pub const EVENT_ADMIN_UNSUSPEND: &str = "admin_unsuspend";
+pub const EVENT_ADMIN_FORCE_PASSWORD_RESET: &str = "admin_force_password_reset";
+pub const EVENT_ADMIN_IMPERSONATE: &str = "admin_impersonate";
+
+//retrive from DB
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub event_type: String,
+    pub actor_id: Option<Uuid>,
+    pub target_user_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: Value,
+    pub created_at: NaiveDateTime,
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewAuditEvent {
+    pub event_type: &'static str,
+    pub actor_id: Option<Uuid>,
+    pub target_user_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: Value,
+    pub impersonated_by: Option<Uuid>, // the admin acting through an impersonation session
+}
+
+impl NewAuditEvent {
+    /// the user acting on its own account, or the admin impersonating them
+    pub fn by(mut self, user_id: Uuid) -> Self {
+        self.actor_id = Some(self.impersonated_by.unwrap_or(user_id));
+        self.target_user_id = Some(user_id);
+        self
+    }
+
+    pub fn actor(mut self, actor_id: Uuid) -> Self {
+        self.actor_id = Some(actor_id);
+        self
+    }
+
+    pub fn target(mut self, target_user_id: Option<Uuid>) -> Self {
+        self.target_user_id = target_user_id;
+        self
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// GET /admin/audit?event_type=&actor_id=&target_user_id=&ip_address=&from=&to=&page=&per_page=
+#[derive(Debug, Deserialize)]
+pub struct AuditSearch {
+    pub event_type: Option<String>,
+    pub actor_id: Option<Uuid>,
+    pub target_user_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
@@ -0,0 +1,114 @@
+use crate::tracker::events::{leveled_up, region_events, RankChange, SnapshotSummary, LEVEL_UP, MMR_CHANGE};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// a user in a follower or following list, with the fields its privacy
+/// settings show
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct FollowUser {
+    pub username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    pub followed_at: NaiveDateTime,
+    pub mutual: bool, // both follow each other
+}
+
+/// POST /users/{username}/follow
+#[derive(Debug, Serialize)]
+pub struct FollowStatus {
+    pub username: String,
+    pub following: bool,
+    pub mutual: bool,
+}
+
+/// ?page=&per_page=
+#[derive(Debug, Deserialize)]
+pub struct FollowQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+/// GET /me/feed?before=&limit=, `before` is the occurred_at of the last
+/// item already shown
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    pub before: Option<NaiveDateTime>,
+    pub limit: Option<i64>,
+}
+
+//retrive from DB, a snapshot of a followed user's profile and the one before it
+#[derive(Debug, sqlx::FromRow)]
+pub struct FeedRow {
+    pub username: String,
+    pub snapshot_id: Uuid,
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub region: String,
+    pub season: i32,
+    pub rank: i32,
+    pub mmr: f32,
+    pub level: Option<i32>,
+    pub wins: i32,
+    pub losses: i32,
+    pub created_at: NaiveDateTime,
+    pub previous_season: i32,
+    pub previous_rank: i32,
+    pub previous_mmr: f32,
+    pub previous_level: Option<i32>,
+    pub previous_wins: i32,
+    pub previous_losses: i32,
+    pub first_at_level: bool, // no earlier snapshot of the profile, in any region, has this level
+}
+
+/// a rank change or level up of a followed user, shaped like the tracker
+/// events sent to webhooks and realtime clients
+#[derive(Debug, Serialize)]
+pub struct FeedItem {
+    pub username: String,
+    #[serde(flatten)]
+    pub change: RankChange,
+}
+
+impl FeedRow {
+    /// the tracker events of the snapshot, without mmr changes
+    pub fn to_items(&self) -> Vec<FeedItem> {
+        let previous = SnapshotSummary::new(
+            self.previous_rank,
+            self.previous_mmr,
+            self.previous_level,
+            self.previous_wins,
+            self.previous_losses,
+        );
+        let current = SnapshotSummary::new(self.rank, self.mmr, self.level, self.wins, self.losses);
+
+        let mut events: Vec<_> = region_events(&previous, &current, self.season == self.previous_season)
+            .into_iter()
+            .filter(|event| *event != MMR_CHANGE)
+            .collect();
+        if self.first_at_level && leveled_up(&previous, &current) {
+            events.push(LEVEL_UP);
+        }
+
+        events
+            .into_iter()
+            .map(|event| FeedItem {
+                username: self.username.clone(),
+                change: RankChange {
+                    event,
+                    profile_id: self.profile_id.clone(),
+                    platform: self.platform.clone(),
+                    name_on_platform: self.name_on_platform.clone(),
+                    region: self.region.clone(),
+                    snapshot_id: self.snapshot_id,
+                    previous: previous.clone(),
+                    current: current.clone(),
+                    occurred_at: DateTime::from_utc(self.created_at, Utc),
+                },
+            })
+            .collect()
+    }
+}
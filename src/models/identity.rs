@@ -0,0 +1,43 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use uuid::Uuid;
+
+//retrive from DB
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Identity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub username: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewIdentity {
+    pub user_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub username: Option<String>,
+}
+
+//retrive from DB, only what the callback needs
+#[derive(Debug, sqlx::FromRow)]
+pub struct OAuthState {
+    pub code_verifier: String,
+    pub user_id: Option<Uuid>,
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewOAuthState {
+    pub state: String,
+    pub provider: String,
+    pub code_verifier: String,
+    pub user_id: Option<Uuid>,
+    pub browser_hash: String, // hash of the nonce cookie of the browser that started the flow
+    pub expires_at: NaiveDateTime,
+}
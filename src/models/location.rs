@@ -0,0 +1,191 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+pub const MAX_LOCATIONS: usize = 10;
+
+/// ISO 3166-1 alpha-2 codes, sorted
+pub const COUNTRY_CODES: [&str; 249] = [
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS", "BT", "BV", "BW", "BY", "BZ",
+    "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN", "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ",
+    "DE", "DJ", "DK", "DM", "DO", "DZ",
+    "EC", "EE", "EG", "EH", "ER", "ES", "ET",
+    "FI", "FJ", "FK", "FM", "FO", "FR",
+    "GA", "GB", "GD", "GE", "GF", "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY",
+    "HK", "HM", "HN", "HR", "HT", "HU",
+    "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT",
+    "JE", "JM", "JO", "JP",
+    "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ",
+    "LA", "LB", "LC", "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY",
+    "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK", "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ",
+    "NA", "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ",
+    "OM",
+    "PA", "PE", "PF", "PG", "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY",
+    "QA",
+    "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS", "ST", "SV", "SX", "SY", "SZ",
+    "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO", "TR", "TT", "TV", "TW", "TZ",
+    "UA", "UG", "UM", "US", "UY", "UZ",
+    "VA", "VC", "VE", "VG", "VI", "VN", "VU",
+    "WF", "WS",
+    "YE", "YT",
+    "ZA", "ZM", "ZW",
+];
+
+pub fn is_country_code(code: &str) -> bool {
+    COUNTRY_CODES.binary_search(&code).is_ok()
+}
+
+//retrive from DB
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UserLocation {
+    pub id: Uuid,
+    pub label: String,
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl UserLocation {
+    pub fn to_location(&self) -> Location {
+        Location {
+            id: self.id,
+            label: self.label.clone(),
+            address: Address {
+                street: self.street.clone(),
+                city: self.city.clone(),
+                state: self.state.clone(),
+                postal_code: self.postal_code.clone(),
+                country: self.country.clone().unwrap_or_default(),
+            },
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// the shape of a location in every response
+#[derive(Debug, Clone, Serialize)]
+pub struct Location {
+    pub id: Uuid,
+    pub label: String,
+    pub address: Address,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct Address {
+    #[validate(length(min = 1, max = 128))]
+    pub street: Option<String>,
+    #[validate(length(min = 1, max = 64))]
+    pub city: Option<String>,
+    #[validate(length(min = 1, max = 64))]
+    pub state: Option<String>,
+    #[validate(length(min = 1, max = 16))]
+    pub postal_code: Option<String>,
+    pub country: String, // ISO 3166-1 alpha-2, e.g. "IN"
+}
+
+//add it to DB
+#[derive(Debug, Deserialize, Validate)]
+pub struct LocationInput {
+    #[validate(length(min = 1, max = 32))]
+    pub label: String,
+    pub address: Address,
+}
+
+/// validates a full set of locations, uppercasing the country codes
+/// returns the message for the first invalid entry
+pub fn normalize(locations: &mut [LocationInput]) -> Result<(), String> {
+    if locations.len() > MAX_LOCATIONS {
+        return Err(format!("Too many locations. Use at most {}.", MAX_LOCATIONS));
+    }
+
+    let mut labels: Vec<String> = Vec::new();
+    for location in locations.iter_mut() {
+        location.label = location.label.trim().to_string();
+        location.address.country = location.address.country.trim().to_uppercase();
+
+        if location.validate().is_err() {
+            return Err("Invalid location label. Use 1 to 32 characters.".to_string());
+        }
+        if let Err(e) = location.address.validate() {
+            let field = e.field_errors().keys().next().copied().unwrap_or("address");
+            return Err(format!("Invalid {} in location \"{}\".", field, location.label));
+        }
+        if !is_country_code(&location.address.country) {
+            return Err(format!(
+                "Invalid country \"{}\" in location \"{}\". Use an ISO 3166-1 alpha-2 code.",
+                location.address.country, location.label
+            ));
+        }
+        if labels.contains(&location.label) {
+            return Err(format!("Duplicate location label \"{}\".", location.label));
+        }
+        labels.push(location.label.clone());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn location(label: &str, country: &str) -> LocationInput {
+        serde_json::from_value(json!({
+            "label": label,
+            "address": { "city": "Pune", "country": country },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn country_codes_are_sorted_for_the_binary_search() {
+        assert!(COUNTRY_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(is_country_code("IN"));
+        assert!(!is_country_code("in"));
+        assert!(!is_country_code("XX"));
+    }
+
+    #[test]
+    fn labels_are_trimmed_and_countries_uppercased() {
+        let mut locations = vec![location(" Home ", " in"), location("Work", "De")];
+        normalize(&mut locations).unwrap();
+        assert_eq!(locations[0].label, "Home");
+        assert_eq!(locations[0].address.country, "IN");
+        assert_eq!(locations[1].address.country, "DE");
+
+        assert_eq!(normalize(&mut []), Ok(()));
+    }
+
+    #[test]
+    fn the_first_invalid_location_is_reported() {
+        let error = |mut locations: Vec<LocationInput>| normalize(&mut locations).unwrap_err();
+
+        assert_eq!(
+            error(vec![location("Home", "IN"), location("Work", "XX")]),
+            "Invalid country \"XX\" in location \"Work\". Use an ISO 3166-1 alpha-2 code."
+        );
+        assert_eq!(
+            error(vec![location("   ", "IN")]),
+            "Invalid location label. Use 1 to 32 characters."
+        );
+        assert_eq!(
+            error(vec![location("Home", "IN"), location(" Home", "DE")]),
+            "Duplicate location label \"Home\"."
+        );
+
+        let mut empty_city = location("Home", "IN");
+        empty_city.address.city = Some(String::new());
+        assert_eq!(error(vec![empty_city]), "Invalid city in location \"Home\".");
+
+        let too_many = (0..=MAX_LOCATIONS).map(|i| location(&format!("Place {}", i), "IN")).collect();
+        assert_eq!(error(too_many), "Too many locations. Use at most 10.");
+    }
+}
@@ -1,3 +1,20 @@
 // models
+pub mod account;
+pub mod achievement;
+pub mod admin;
+pub mod api_key;
+pub mod audit;
+pub mod email_change;
+pub mod follow;
+pub mod identity;
+pub mod location;
+pub mod notification;
+pub mod password_reset;
+pub mod player_snapshot;
+pub mod profile_link;
+pub mod rate_limit;
+pub mod session;
+pub mod two_factor;
 pub mod user;
 pub mod ubi_user;
+pub mod webhook;
@@ -0,0 +1,88 @@
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+pub const PROVIDER_FCM: &str = "fcm";
+pub const PROVIDER_APNS: &str = "apns";
+pub const PROVIDERS: [&str; 2] = [PROVIDER_FCM, PROVIDER_APNS];
+
+//retrive from DB
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DeviceToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub provider: String,
+    #[serde(skip_serializing)]
+    pub token: String,
+    pub name: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+}
+
+/// POST /me/devices, registering a known token again moves it to this user
+#[derive(Debug, Deserialize, Validate)]
+pub struct RegisterDevice {
+    pub provider: String, // fcm | apns
+    #[validate(length(min = 1, max = 4096))]
+    pub token: String,
+    #[validate(length(min = 1, max = 64))]
+    pub name: Option<String>,
+}
+
+//retrive from DB, defaults for users without a row
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct NotificationPreferences {
+    pub user_id: Uuid,
+    pub rank_up: bool,
+    pub rank_down: bool,
+    pub level_up: bool,
+    pub quiet_hours_start: Option<NaiveTime>,
+    pub quiet_hours_end: Option<NaiveTime>,
+    pub utc_offset_minutes: i32,
+}
+
+impl NotificationPreferences {
+    pub fn wants(&self, event: &str) -> bool {
+        match event {
+            "rank_up" => self.rank_up,
+            "rank_down" => self.rank_down,
+            "level_up" => self.level_up,
+            _ => false,
+        }
+    }
+
+    /// quiet hours may wrap midnight, e.g. 22:00 - 07:00
+    pub fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        match (self.quiet_hours_start, self.quiet_hours_end) {
+            (Some(start), Some(end)) => {
+                let local = (now.naive_utc() + Duration::minutes(self.utc_offset_minutes as i64)).time();
+                if start <= end {
+                    start <= local && local < end
+                } else {
+                    local >= start || local < end
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// POST /me/notifications, replaces every preference
+#[derive(Debug, Deserialize, Validate)]
+pub struct SetPreferences {
+    pub rank_up: bool,
+    pub rank_down: bool,
+    pub level_up: bool,
+    pub quiet_hours: Option<QuietHours>,
+    #[validate(range(min = -720, max = 840))]
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+/// local times as "HH:MM"
+#[derive(Debug, Deserialize)]
+pub struct QuietHours {
+    pub start: String,
+    pub end: String,
+}
@@ -0,0 +1,27 @@
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+//retrive from DB
+#[derive(Debug, sqlx::FromRow)]
+pub struct PasswordReset {
+    pub user_id: Uuid,
+    pub expires_at: NaiveDateTime,
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewPasswordReset {
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+/// POST /auth/password_reset, the token comes from the emailed link
+#[derive(Debug, Deserialize, Validate)]
+pub struct ResetPassword {
+    pub token: String,
+    #[validate(length(min = 5))]
+    pub new_password: String,
+}
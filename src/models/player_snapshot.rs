@@ -0,0 +1,88 @@
+use crate::ubi::ubi_api::{PlayerStats, Profile};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use uuid::Uuid;
+
+//retrive from DB
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct PlayerSnapshot {
+    pub id: Uuid,
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub region: String,
+    pub season: i32,
+    pub rank: i32,
+    pub max_rank: i32,
+    pub mmr: f32,
+    pub max_mmr: f32,
+    pub kills: i32,
+    pub deaths: i32,
+    pub wins: i32,
+    pub losses: i32,
+    pub abandons: i32,
+    pub level: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub checked_at: NaiveDateTime,
+}
+
+/// outcome of recording a snapshot. `previous` is the row it replaced as
+/// latest, only set when something changed
+#[derive(Debug, Clone)]
+pub struct RecordedSnapshot {
+    pub current: PlayerSnapshot,
+    pub previous: Option<PlayerSnapshot>,
+    pub created: bool, // false when only checked_at was bumped
+}
+
+//add it to DB
+#[derive(Debug, Clone)]
+pub struct NewPlayerSnapshot {
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub region: String,
+    pub season: i32,
+    pub rank: i32,
+    pub max_rank: i32,
+    pub mmr: f32,
+    pub max_mmr: f32,
+    pub kills: i32,
+    pub deaths: i32,
+    pub wins: i32,
+    pub losses: i32,
+    pub abandons: i32,
+    pub level: Option<i32>,
+}
+
+impl NewPlayerSnapshot {
+    pub fn from_stats(profile: &Profile, stats: &PlayerStats, level: Option<i32>) -> Self {
+        NewPlayerSnapshot {
+            profile_id: profile.profile_id.clone(),
+            platform: profile.platform_type.clone(),
+            name_on_platform: profile.name_on_platform.clone(),
+            region: stats.region.clone(),
+            season: stats.season,
+            rank: stats.rank,
+            max_rank: stats.max_rank,
+            mmr: stats.mmr,
+            max_mmr: stats.max_mmr,
+            kills: stats.kills,
+            deaths: stats.deaths,
+            wins: stats.wins,
+            losses: stats.losses,
+            abandons: stats.abandons,
+            level,
+        }
+    }
+
+    /// nothing worth a new row since `snapshot`
+    pub fn unchanged_since(&self, snapshot: &PlayerSnapshot) -> bool {
+        self.season == snapshot.season
+            && self.wins == snapshot.wins
+            && self.losses == snapshot.losses
+            && self.abandons == snapshot.abandons
+            && (self.mmr - snapshot.mmr).abs() < f32::EPSILON
+            && (self.level.is_none() || self.level == snapshot.level)
+    }
+}
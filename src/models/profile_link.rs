@@ -0,0 +1,71 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+//retrive from DB
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ProfileLink {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub discord_user_id: Option<String>,
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl ProfileLink {
+    pub fn to_linked(&self) -> LinkedProfile {
+        LinkedProfile {
+            profile_id: self.profile_id.clone(),
+            platform: self.platform.clone(),
+            name_on_platform: self.name_on_platform.clone(),
+        }
+    }
+}
+
+/// a linked profile as other users see it, without the discord id
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkedProfile {
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewProfileLink {
+    pub user_id: Option<Uuid>,
+    pub discord_user_id: Option<String>,
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+}
+
+/// POST /me/ubi_profiles
+#[derive(Debug, Deserialize, Validate)]
+pub struct LinkProfile {
+    #[validate(length(min = 1, max = 64))]
+    pub name_on_platform: String,
+    pub platform_type: String, // uplay | psn | xbl
+}
+
+//retrive from DB, best region of each linked profile
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct LeaderboardEntry {
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub region: String,
+    pub rank: i32,
+    pub mmr: f32,
+}
+
+//retrive from DB, one row per linked profile
+#[derive(Debug, sqlx::FromRow)]
+pub struct TrackedProfile {
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+}
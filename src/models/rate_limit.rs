@@ -0,0 +1,11 @@
+use chrono::NaiveDateTime;
+
+//retrive from DB
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct RateLimitBucket {
+    pub bucket_key: String,
+    pub tokens: f64,
+    pub updated_at: NaiveDateTime,
+    pub capacity: i32,
+    pub refill_per_minute: i32,
+}
@@ -0,0 +1,26 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use uuid::Uuid;
+
+//retrive from DB
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct UserSession {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub impersonated_by: Option<Uuid>, // the admin, for support sessions
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewUserSession {
+    pub user_id: Uuid,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub expires_at: NaiveDateTime,
+    pub impersonated_by: Option<Uuid>,
+}
@@ -0,0 +1,13 @@
+use serde::Deserialize;
+
+/// a 6 digit TOTP code or one of the recovery codes
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLogin {
+    pub challenge_token: String,
+    pub code: String,
+}
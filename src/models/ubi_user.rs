@@ -1,33 +1,51 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
+pub const SESSION_LOGIN: &str = "login";
+pub const SESSION_RENEW: &str = "renew";
+
 //retrive from DB
 #[derive(Debug, Clone, Serialize, sqlx::FromRow)]
 pub struct UbiUser {
     pub id: Uuid,
     pub email: String,
+    #[serde(skip_serializing)]
     pub password: String,
+    #[serde(skip_serializing)]
     pub token: String,
-    pub expiration: String,
+    pub expiration: DateTime<Utc>,
+    pub pool_member: bool, // joins the account pool at startup
 
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
 
-//add it to DB
-#[derive(Debug, Deserialize, Validate)]
-pub struct NewUbiUser {
-    #[validate(email)]
+//add it to DB, or refresh the stored session of a known email
+#[derive(Debug)]
+pub struct UbiUserSession {
     pub email: String,
     pub password: String,
     pub token: String,
-    pub expiration: String,
+    pub expiration: DateTime<Utc>,
+    pub kind: &'static str, // SESSION_LOGIN | SESSION_RENEW
+}
+
+//retrive from DB
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct UbiSessionHistory {
+    pub id: Uuid,
+    pub kind: String,
+    pub expiration: DateTime<Utc>,
+    pub created_at: NaiveDateTime,
 }
 
+/// admin request adding a service account to the pool
 #[derive(Debug, Deserialize, Validate)]
-pub struct UpdateUbiUser {
-    pub token: String,
-    pub expiration: String,
+pub struct AddUbiAccount {
+    #[validate(email)]
+    pub email: String,
+    #[validate(length(min = 1))]
+    pub password: String,
 }
@@ -1,7 +1,12 @@
+use super::{
+    location::{Location, LocationInput},
+    profile_link::LinkedProfile,
+};
 use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use serde::{Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 //retrive from DB
 #[derive(Debug, Serialize, sqlx::FromRow)]
@@ -16,8 +21,18 @@ pub struct User {
     pub image: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub role: String,
+    pub deletion_scheduled_for: Option<NaiveDateTime>,
+    pub suspended_at: Option<NaiveDateTime>,
+    pub suspended_reason: Option<String>,
+    pub password_reset_required: bool,
 }
 
+pub const ROLE_ADMIN: &str = "admin";
+
 #[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct UserWithLocation {
     pub id: Uuid,
@@ -28,11 +43,27 @@ pub struct UserWithLocation {
     pub image: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
-    pub locations: Vec<String>,
+    pub deletion_scheduled_for: Option<NaiveDateTime>,
+    pub locations: Vec<Location>,
 }
 
 impl User {
-    pub fn with_locations(&self, locations: Vec<String>) -> UserWithLocation {
+    /// changes with every write to the row
+    pub fn etag(&self) -> String {
+        format!(
+            "\"{}.{:06}\"",
+            self.updated_at.timestamp(),
+            self.updated_at.timestamp_subsec_micros()
+        )
+    }
+
+    /// If-Match compares strongly, "*" matches any version
+    pub fn matches(&self, if_match: &str) -> bool {
+        let etag = self.etag();
+        if_match.split(',').map(str::trim).any(|tag| tag == "*" || tag == etag)
+    }
+
+    pub fn with_locations(&self, locations: Vec<Location>) -> UserWithLocation {
         UserWithLocation {
             id: self.id,
             username: self.username.clone(),
@@ -42,46 +73,76 @@ impl User {
             image: self.image.clone(),
             created_at: self.created_at,
             updated_at: self.updated_at,
+            deletion_scheduled_for: self.deletion_scheduled_for,
             locations,
         }
     }
 }
 
+/// which fields /users/{username} shows to others, POST /me/privacy replaces all
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ProfilePrivacy {
+    pub show_full_name: bool,
+    pub show_bio: bool,
+    pub show_image: bool,
+    pub show_locations: bool,
+    pub show_ubi_profiles: bool,
+    pub show_badges: bool,
+}
+
+//retrive from DB, a badge of one of the profiles the user links
 #[derive(Debug, Serialize, sqlx::FromRow)]
-pub struct UserLocation {
-    pub id: Uuid,
-    pub user_id: Uuid,
-    pub street: Option<String>,
-    pub city: Option<String>,
-    pub state: Option<String>,
-    pub country: Option<String>,
+pub struct Badge {
+    pub badge: String,
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub awarded_at: NaiveDateTime,
 }
 
-impl UserLocation {
-    pub fn to_array(&self) -> Vec<String> {
-        let mut locations: Vec<String> = Vec::new();
+/// GET /users/{username}, hidden fields are left out. The owner and admins
+/// see every field and the email
+#[derive(Debug, Serialize)]
+pub struct PublicProfile {
+    pub username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub full_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locations: Option<Vec<Location>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ubi_profiles: Option<Vec<LinkedProfile>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub badges: Option<Vec<Badge>>,
+    pub followers: i64,
+    pub following: i64,
+    pub created_at: NaiveDateTime,
+}
 
-        if let Some(street) = self.street.clone() {
-            locations.push(street);
-        }
-        if let Some(city) = self.city.clone() {
-            locations.push(city);
-        }
-        if let Some(state) = self.state.clone() {
-            locations.push(state);
-        }
-        if let Some(country) = self.country.clone() {
-            locations.push(country);
-        }
+/// POST /me/avatar, `image` is the largest thumbnail
+#[derive(Debug, Serialize)]
+pub struct Avatar {
+    pub image: String,
+    pub thumbnails: BTreeMap<u32, String>,
+}
 
-        locations
+// '@' is kept for email addresses, logins containing it are looked up by email only
+fn validate_username(username: &str) -> Result<(), ValidationError> {
+    if username.contains('@') {
+        return Err(ValidationError::new("username_at"));
     }
+    Ok(())
 }
 
 //add it to DB
 #[derive(Debug, Deserialize, Validate)]
 pub struct NewUser {
-    #[validate(length(min = 4))]
+    #[validate(length(min = 4), custom = "validate_username")]
     pub username: String,
     #[validate(email)]
     pub email: String,
@@ -89,52 +150,171 @@ pub struct NewUser {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangePassword {
+    pub old_password: String,
+    #[validate(length(min = 5))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ChangeEmail {
+    #[validate(email)]
+    pub new_email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEmail {
+    pub token: String,
+}
+
+// a field that is present, even as null, is Some
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// PATCH /me, absent fields are left alone and null clears them
 #[derive(Debug, Deserialize, Validate)]
 pub struct UpdateProfile {
     #[validate(length(min = 4))]
-    pub full_name: Option<String>,
-    pub bio: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub full_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub bio: Option<Option<String>>,
     #[validate(url)]
-    pub image: Option<String>,
-    pub locations: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub image: Option<Option<String>>,
+    /// replaces every location of the user, labels are unique. null or [] removes them
+    #[serde(default, deserialize_with = "present")]
+    pub locations: Option<Option<Vec<LocationInput>>>,
 }
 
-#[derive(Debug, Deserialize, Validate)]
-pub struct UpdateUserLocation {
-    pub street: Option<String>,
-    pub city: Option<String>,
-    pub state: Option<String>,
-    pub country: Option<String>,
-}
-
-impl UpdateUserLocation {
-    pub fn create_from_vec(locations: &mut Vec<String>) -> UpdateUserLocation {
-        let country: Option<String> = locations.pop();
-        let state: Option<String> = locations.pop();
-        let city: Option<String> = locations.pop();
-        let street: Option<String> = locations.pop();
-
-        // if locations.len() == 4 {
-        //     street = Some(locations[0].to_string());
-        //     city = Some(locations[1].to_string());
-        //     state = Some(locations[2].to_string());
-        //     country = Some(locations[3].to_string());
-        // } else if locations.len() == 3 {
-        //     city = Some(locations[0].to_string());
-        //     state = Some(locations[1].to_string());
-        //     country = Some(locations[2].to_string());
-        // } else if locations.len() == 2 {
-        //     state = Some(locations[0].to_string());
-        //     country = Some(locations[1].to_string());
-        // } else if locations.len() == 1 {
-        //     country = Some(locations[0].to_string());
-        // }
-
-        UpdateUserLocation {
-            street,
-            city,
-            state,
-            country,
+impl UpdateProfile {
+    pub fn is_empty(&self) -> bool {
+        self.full_name.is_none() && self.bio.is_none() && self.image.is_none() && self.locations.is_none()
+    }
+
+    /// names of the given fields, for the audit log
+    pub fn fields(&self) -> Vec<&'static str> {
+        let given = [
+            ("full_name", self.full_name.is_some()),
+            ("bio", self.bio.is_some()),
+            ("image", self.image.is_some()),
+            ("locations", self.locations.is_some()),
+        ];
+        given.iter().filter(|(_, is_given)| *is_given).map(|(name, _)| *name).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::user::UserRepository, test_support};
+    use chrono::NaiveDate;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn user(updated_at: NaiveDateTime) -> User {
+        User {
+            id: Uuid::nil(),
+            username: "player".to_string(),
+            email: "player@zbot.test".to_string(),
+            password_hash: String::new(),
+            full_name: None,
+            bio: None,
+            image: None,
+            created_at: updated_at,
+            updated_at,
+            totp_secret: None,
+            totp_enabled: false,
+            role: "user".to_string(),
+            deletion_scheduled_for: None,
+            suspended_at: None,
+            suspended_reason: None,
+            password_reset_required: false,
         }
     }
+
+    fn update(body: serde_json::Value) -> UpdateProfile {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn absent_fields_are_kept_and_null_clears_them() {
+        let profile = update(json!({ "full_name": "Some Player", "bio": null }));
+        assert_eq!(profile.full_name, Some(Some("Some Player".to_string())));
+        assert_eq!(profile.bio, Some(None));
+        assert_eq!(profile.image, None);
+        assert!(profile.locations.is_none());
+        assert_eq!(profile.fields(), vec!["full_name", "bio"]);
+
+        let profile = update(json!({ "locations": null }));
+        assert!(matches!(profile.locations, Some(None)));
+        assert!(!profile.is_empty());
+
+        let profile = update(json!({}));
+        assert!(profile.is_empty());
+        assert!(profile.fields().is_empty());
+    }
+
+    #[test]
+    fn if_match_needs_the_current_etag() {
+        let updated_at = NaiveDate::from_ymd(2026, 10, 19).and_hms_micro(12, 0, 0, 42);
+        let user = user(updated_at);
+        assert_eq!(user.etag(), "\"1792411200.000042\"");
+
+        assert!(user.matches("\"1792411200.000042\""));
+        assert!(user.matches("\"1.000000\", \"1792411200.000042\""));
+        assert!(user.matches("*"));
+
+        // an older version, a weak tag and the bare value
+        assert!(!user.matches("\"1792411200.000041\""));
+        assert!(!user.matches("W/\"1792411200.000042\""));
+        assert!(!user.matches("1792411200.000042"));
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn stale_if_match_leaves_the_profile_alone() {
+        let repository = UserRepository::new(Arc::new(test_support::pool().await));
+        let user = repository
+            .create(
+                NewUser {
+                    username: test_support::unique("user"),
+                    email: format!("{}@zbot.test", test_support::unique("profile")),
+                    password: "password".to_string(),
+                },
+                &test_support::crypto_service(),
+            )
+            .await
+            .unwrap();
+        let etag = user.etag();
+
+        let updated = repository
+            .update_profile(user.id, update(json!({ "bio": "first" })), Some(&etag))
+            .await
+            .unwrap()
+            .expect("the current version");
+        assert_eq!(updated.bio.as_deref(), Some("first"));
+        assert_ne!(updated.etag(), etag);
+
+        // a second client still holding the first version
+        let stale = repository
+            .update_profile(user.id, update(json!({ "bio": "second" })), Some(&etag))
+            .await
+            .unwrap();
+        assert!(stale.is_none());
+
+        let unchanged = repository
+            .update_profile(user.id, update(json!({})), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(unchanged.bio.as_deref(), Some("first"));
+    }
 }
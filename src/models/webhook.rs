@@ -0,0 +1,103 @@
+use crate::tracker::events::{LEVEL_UP, RANK_DOWN, RANK_UP};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// fired when the mmr crosses the webhook's `mmr_threshold`, either way
+pub const MMR_THRESHOLD: &str = "mmr_threshold";
+pub const WEBHOOK_EVENTS: [&str; 4] = [RANK_UP, RANK_DOWN, LEVEL_UP, MMR_THRESHOLD];
+
+pub const DELIVERY_PENDING: &str = "pending";
+pub const DELIVERY_DELIVERED: &str = "delivered";
+pub const DELIVERY_FAILED: &str = "failed";
+
+//retrive from DB
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub url: String,
+    pub events: String,
+    pub profile_id: Option<String>,
+    pub mmr_threshold: Option<i32>,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl Webhook {
+    pub fn event_list(&self) -> Vec<&str> {
+        self.events.split_whitespace().collect()
+    }
+
+    pub fn wants(&self, event: &str) -> bool {
+        self.event_list().contains(&event)
+    }
+}
+
+/// returned once on creation, the secret is never listed again
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    pub secret: String,
+    #[serde(flatten)]
+    pub webhook: Webhook,
+}
+
+//add it to DB
+#[derive(Debug)]
+pub struct NewWebhook {
+    pub user_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub events: String,
+    pub profile_id: Option<String>,
+    pub mmr_threshold: Option<i32>,
+}
+
+/// POST /me/webhooks
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateWebhook {
+    #[validate(length(min = 1, max = 2048))]
+    pub url: String,
+    pub events: Vec<String>,
+    pub profile_id: Option<String>, // one of the user's linked profiles, all of them when absent
+    #[validate(range(min = 0, max = 20000))]
+    pub mmr_threshold: Option<i32>,
+}
+
+//retrive from DB
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: String,
+    pub status: String, // pending | delivered | failed
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub replay_of: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+//retrive from DB, a claimed delivery with what is needed to send it
+#[derive(Debug, sqlx::FromRow)]
+pub struct DueDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+/// body posted to the endpoint, `data` is the tracker change
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a, T: Serialize> {
+    pub event: &'a str,
+    pub webhook_id: Uuid,
+    pub data: &'a T,
+}
@@ -0,0 +1,360 @@
+//module notifications
+// native push notifications on tracker changes. Every linked user gets one
+// per registered device, unless the event is turned off or it is within
+// their quiet hours
+pub mod providers;
+
+use crate::{
+    config::NotificationsConfig,
+    db::notification::NotificationRepository,
+    models::notification::{PROVIDER_APNS, PROVIDER_FCM},
+    tracker::events::{RankChange, TrackerEvent, LEVEL_UP, RANK_DOWN, RANK_UP},
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use color_eyre::Result;
+use providers::{Apns, Fcm, RecordedNotification, Recording};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast::{self, RecvError};
+use tracing::{debug, error, info};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    pub data: HashMap<String, String>, // handed to the app as is
+}
+
+#[derive(Debug)]
+pub enum PushError {
+    /// the device is gone, its token gets removed
+    InvalidToken,
+    Failed(String),
+}
+
+#[async_trait]
+pub trait PushProvider: Send + Sync {
+    fn name(&self) -> &str;
+    async fn send(&self, token: &str, notification: &Notification) -> Result<(), PushError>;
+}
+
+/// text of the push for a change, other events are not pushed
+fn notification_for(change: &RankChange) -> Option<Notification> {
+    let (title, body) = match change.event {
+        RANK_UP => (
+            "Rank up!".to_string(),
+            format!(
+                "{} reached {} in {} ({:.0} MMR)",
+                change.name_on_platform,
+                change.current.rank_name,
+                change.region.to_uppercase(),
+                change.current.mmr
+            ),
+        ),
+        RANK_DOWN => (
+            "Rank down".to_string(),
+            format!(
+                "{} dropped to {} in {} ({:.0} MMR)",
+                change.name_on_platform,
+                change.current.rank_name,
+                change.region.to_uppercase(),
+                change.current.mmr
+            ),
+        ),
+        LEVEL_UP => (
+            "Level up!".to_string(),
+            format!(
+                "{} reached level {}",
+                change.name_on_platform,
+                change.current.level.unwrap_or_default()
+            ),
+        ),
+        _ => return None,
+    };
+
+    let data = [
+        ("event", change.event.to_string()),
+        ("profile_id", change.profile_id.clone()),
+        ("platform", change.platform.clone()),
+        ("region", change.region.clone()),
+    ]
+    .iter()
+    .map(|(key, value)| (key.to_string(), value.clone()))
+    .collect();
+
+    Some(Notification { title, body, data })
+}
+
+#[derive(Clone)]
+pub struct Notifier {
+    providers: Arc<HashMap<String, Box<dyn PushProvider>>>,
+    recorded: Arc<Mutex<VecDeque<RecordedNotification>>>,
+    pool: Arc<PgPool>,
+}
+
+impl Notifier {
+    /// providers without credentials are skipped, or recorded when
+    /// NOTIFICATIONS.RECORD opts in
+    pub fn new(config: &NotificationsConfig, client: reqwest::Client, pool: Arc<PgPool>) -> Result<Self> {
+        let recorded = Arc::new(Mutex::new(VecDeque::new()));
+        let mut providers: Vec<Box<dyn PushProvider>> = Vec::new();
+
+        match &config.fcm {
+            Some(fcm) => providers.push(Box::new(Fcm::new(fcm, client)?)),
+            None if config.record => providers.push(Box::new(Recording::new(PROVIDER_FCM, recorded.clone()))),
+            None => info!("NOTIFICATIONS.FCM is not configured, no pushes to Android devices"),
+        }
+        match &config.apns {
+            Some(apns) => providers.push(Box::new(Apns::new(apns)?)),
+            None if config.record => providers.push(Box::new(Recording::new(PROVIDER_APNS, recorded.clone()))),
+            None => info!("NOTIFICATIONS.APNS is not configured, no pushes to Apple devices"),
+        }
+
+        Ok(Notifier::with_providers(providers, recorded, pool))
+    }
+
+    fn with_providers(
+        providers: Vec<Box<dyn PushProvider>>,
+        recorded: Arc<Mutex<VecDeque<RecordedNotification>>>,
+        pool: Arc<PgPool>,
+    ) -> Self {
+        let providers: HashMap<String, Box<dyn PushProvider>> = providers
+            .into_iter()
+            .map(|provider| (provider.name().to_string(), provider))
+            .collect();
+
+        Notifier {
+            providers: Arc::new(providers),
+            recorded,
+            pool,
+        }
+    }
+
+    /// what the recording providers kept, latest last
+    pub fn recorded(&self) -> Vec<RecordedNotification> {
+        let recorded = self.recorded.lock().expect("recorded notifications lock poisoned");
+        recorded.iter().cloned().collect()
+    }
+
+    /// pushes `change` to the devices of every user that linked the profile
+    pub async fn notify(&self, change: &RankChange) -> Result<usize> {
+        let notification = match notification_for(change) {
+            Some(notification) => notification,
+            None => return Ok(0),
+        };
+
+        let repository = NotificationRepository::new(self.pool.clone());
+        let now = Utc::now();
+        let mut sent = 0;
+        for recipient in repository.find_recipients(&change.profile_id).await? {
+            if !recipient.wants(change.event) {
+                continue;
+            }
+            if recipient.is_quiet(now) {
+                debug!("Quiet hours, no push for {}", recipient.user_id);
+                continue;
+            }
+
+            for device in repository.find_devices(recipient.user_id).await? {
+                let provider = match self.providers.get(&device.provider) {
+                    Some(provider) => provider,
+                    None => continue,
+                };
+                match provider.send(&device.token, &notification).await {
+                    Ok(()) => sent += 1,
+                    Err(PushError::InvalidToken) => {
+                        info!("Removing stale {} device {}", device.provider, device.id);
+                        repository.delete_token(device.id).await?;
+                    }
+                    Err(PushError::Failed(reason)) => {
+                        error!("Push to device {} failed. {}", device.id, reason)
+                    }
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// turns tracker changes into pushes
+    pub async fn listen(self, mut events: broadcast::Receiver<TrackerEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(TrackerEvent::Change(change)) => match self.notify(&change).await {
+                    Ok(0) => {}
+                    Ok(sent) => debug!("Sent {} pushes for {}", sent, change.event),
+                    Err(e) => error!("Error sending push notifications. {:?}", e),
+                },
+                Ok(TrackerEvent::Snapshot(_)) | Ok(TrackerEvent::Card(_)) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    error!("Notifier fell behind, {} tracker events dropped.", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::{profile_link::ProfileLinkRepository, user::UserRepository},
+        models::{profile_link::NewProfileLink, user::NewUser},
+        test_support,
+        tracker::events::{SnapshotSummary, MMR_CHANGE},
+    };
+    use chrono::{Duration, NaiveTime};
+    use uuid::Uuid;
+
+    // APNs stand in that no longer knows any token
+    struct Rejecting;
+
+    #[async_trait]
+    impl PushProvider for Rejecting {
+        fn name(&self) -> &str {
+            PROVIDER_APNS
+        }
+
+        async fn send(&self, _token: &str, _notification: &Notification) -> Result<(), PushError> {
+            Err(PushError::InvalidToken)
+        }
+    }
+
+    fn notifier(pool: Arc<PgPool>) -> Notifier {
+        let recorded = Arc::new(Mutex::new(VecDeque::new()));
+        let providers: Vec<Box<dyn PushProvider>> =
+            vec![Box::new(Recording::new(PROVIDER_FCM, recorded.clone())), Box::new(Rejecting)];
+        Notifier::with_providers(providers, recorded, pool)
+    }
+
+    fn change(event: &'static str, profile_id: &str) -> RankChange {
+        RankChange {
+            event,
+            profile_id: profile_id.to_string(),
+            platform: "uplay".to_string(),
+            name_on_platform: "Player".to_string(),
+            region: "emea".to_string(),
+            snapshot_id: Uuid::nil(),
+            previous: SnapshotSummary::new(15, 2500.0, Some(100), 10, 10),
+            current: SnapshotSummary::new(16, 2600.0, Some(101), 11, 10),
+            occurred_at: Utc::now(),
+        }
+    }
+
+    /// a user that linked `profile_id`, with an FCM device
+    async fn recipient(pool: &Arc<PgPool>, profile_id: &str) -> (Uuid, String) {
+        let user = UserRepository::new(pool.clone())
+            .create(
+                NewUser {
+                    username: test_support::unique("user"),
+                    email: format!("{}@zbot.test", test_support::unique("push")),
+                    password: "password".to_string(),
+                },
+                &test_support::crypto_service(),
+            )
+            .await
+            .unwrap();
+        ProfileLinkRepository::new(pool.clone())
+            .link_user(NewProfileLink {
+                user_id: Some(user.id),
+                discord_user_id: None,
+                profile_id: profile_id.to_string(),
+                platform: "uplay".to_string(),
+                name_on_platform: "Player".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let token = test_support::unique("fcm");
+        NotificationRepository::new(pool.clone())
+            .register_device(user.id, PROVIDER_FCM, &token, None)
+            .await
+            .unwrap();
+        (user.id, token)
+    }
+
+    fn masked(token: &str) -> String {
+        format!("…{}", &token[token.len() - 4..])
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn pushes_follow_the_preferences_of_each_user() {
+        let pool = Arc::new(test_support::pool().await);
+        let notifier = notifier(pool.clone());
+        let profile_id = test_support::unique("profile");
+        let (_, token) = recipient(&pool, &profile_id).await;
+        let (no_rank_ups, other_token) = recipient(&pool, &profile_id).await;
+        NotificationRepository::new(pool.clone())
+            .set_preferences(no_rank_ups, false, true, true, None, 0)
+            .await
+            .unwrap();
+
+        assert_eq!(notifier.notify(&change(RANK_UP, &profile_id)).await.unwrap(), 1);
+        let recorded = notifier.recorded();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].token, masked(&token));
+        assert_eq!(recorded[0].notification.title, "Rank up!");
+
+        assert_eq!(notifier.notify(&change(RANK_DOWN, &profile_id)).await.unwrap(), 2);
+        let tokens: Vec<String> = notifier.recorded().into_iter().skip(1).map(|r| r.token).collect();
+        assert!(tokens.contains(&masked(&token)) && tokens.contains(&masked(&other_token)));
+
+        // mmr changes are not pushed
+        assert_eq!(notifier.notify(&change(MMR_CHANGE, &profile_id)).await.unwrap(), 0);
+        assert_eq!(notifier.recorded().len(), 3);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn nothing_is_pushed_in_quiet_hours() {
+        let pool = Arc::new(test_support::pool().await);
+        let notifier = notifier(pool.clone());
+        let profile_id = test_support::unique("profile");
+        let (user_id, _) = recipient(&pool, &profile_id).await;
+
+        // an hour either side of now in the user's time zone, two hours ahead of UTC
+        let local = (Utc::now() + Duration::hours(2)).time();
+        let quiet_hours: (NaiveTime, NaiveTime) = (local - Duration::hours(1), local + Duration::hours(1));
+        let repository = NotificationRepository::new(pool.clone());
+        repository
+            .set_preferences(user_id, true, true, true, Some(quiet_hours), 120)
+            .await
+            .unwrap();
+        assert_eq!(notifier.notify(&change(RANK_UP, &profile_id)).await.unwrap(), 0);
+        assert!(notifier.recorded().is_empty());
+
+        // the same hours in UTC are over
+        let quiet_hours = (quiet_hours.0 - Duration::hours(4), quiet_hours.1 - Duration::hours(4));
+        repository
+            .set_preferences(user_id, true, true, true, Some(quiet_hours), 120)
+            .await
+            .unwrap();
+        assert_eq!(notifier.notify(&change(RANK_UP, &profile_id)).await.unwrap(), 1);
+    }
+
+    #[actix_rt::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn tokens_the_provider_rejects_are_removed() {
+        let pool = Arc::new(test_support::pool().await);
+        let notifier = notifier(pool.clone());
+        let profile_id = test_support::unique("profile");
+        let (user_id, _) = recipient(&pool, &profile_id).await;
+        let repository = NotificationRepository::new(pool.clone());
+        repository
+            .register_device(user_id, PROVIDER_APNS, &test_support::unique("apns"), None)
+            .await
+            .unwrap();
+
+        assert_eq!(notifier.notify(&change(LEVEL_UP, &profile_id)).await.unwrap(), 1);
+        let devices = repository.find_devices(user_id).await.unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].provider, PROVIDER_FCM);
+    }
+}
@@ -0,0 +1,302 @@
+//notification providers
+// FCM (HTTP v1 with a service account) and APNs (token based auth). Both
+// mint short lived credentials from a private key and cache them
+
+use super::{Notification, PushError, PushProvider};
+use crate::config::{ApnsConfig, FcmConfig};
+use async_trait::async_trait;
+use chrono::Utc;
+use color_eyre::Result;
+use eyre::eyre;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+// APNs rejects provider tokens older than an hour
+const APNS_TOKEN_TTL_SECS: u64 = 50 * 60;
+const RECORDED_MAX: usize = 100;
+
+// credential reused until shortly before it expires
+struct CachedToken {
+    value: String,
+    valid_until: Instant,
+}
+
+fn cached(cache: &Mutex<Option<CachedToken>>) -> Option<String> {
+    let cache = cache.lock().expect("push token cache lock poisoned");
+    cache
+        .as_ref()
+        .filter(|token| token.valid_until > Instant::now())
+        .map(|token| token.value.clone())
+}
+
+fn store(cache: &Mutex<Option<CachedToken>>, value: String, ttl_secs: u64) {
+    let mut cache = cache.lock().expect("push token cache lock poisoned");
+    *cache = Some(CachedToken {
+        value,
+        valid_until: Instant::now() + Duration::from_secs(ttl_secs),
+    });
+}
+
+#[derive(Serialize)]
+struct ServiceAccountClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: u64,
+}
+
+/// Firebase Cloud Messaging, https://firebase.google.com/docs/cloud-messaging/send-message
+pub struct Fcm {
+    config: FcmConfig,
+    key: EncodingKey,
+    client: reqwest::Client,
+    access_token: Mutex<Option<CachedToken>>,
+}
+
+impl Fcm {
+    pub fn new(config: &FcmConfig, client: reqwest::Client) -> Result<Self> {
+        let key = EncodingKey::from_rsa_pem(config.private_key.replace("\\n", "\n").as_bytes())
+            .map_err(|e| eyre!("NOTIFICATIONS.FCM.PRIVATE_KEY is not an RSA pem key: {}", e))?;
+
+        Ok(Fcm {
+            config: config.clone(),
+            key,
+            client,
+            access_token: Mutex::new(None),
+        })
+    }
+
+    // service account jwt exchanged for an oauth access token
+    async fn access_token(&self) -> Result<String, PushError> {
+        if let Some(token) = cached(&self.access_token) {
+            return Ok(token);
+        }
+
+        let now = Utc::now().timestamp();
+        let claims = ServiceAccountClaims {
+            iss: &self.config.client_email,
+            scope: FCM_SCOPE,
+            aud: &self.config.token_uri,
+            iat: now,
+            exp: now + 3600,
+        };
+        let assertion = encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+            .map_err(|e| PushError::Failed(format!("Signing FCM assertion: {}", e)))?;
+
+        let response = self
+            .client
+            .post(&self.config.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(|e| PushError::Failed(format!("FCM token request: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(PushError::Failed(format!("FCM token request answered {}", response.status())));
+        }
+        let token: AccessToken = response
+            .json()
+            .await
+            .map_err(|e| PushError::Failed(format!("FCM token response: {}", e)))?;
+
+        store(&self.access_token, token.access_token.clone(), token.expires_in.saturating_sub(60));
+        Ok(token.access_token)
+    }
+}
+
+#[async_trait]
+impl PushProvider for Fcm {
+    fn name(&self) -> &str {
+        "fcm"
+    }
+
+    async fn send(&self, token: &str, notification: &Notification) -> Result<(), PushError> {
+        let url = format!(
+            "https://fcm.googleapis.com/v1/projects/{}/messages:send",
+            self.config.project_id
+        );
+        let response = self
+            .client
+            .post(&url)
+            .bearer_auth(self.access_token().await?)
+            .json(&json!({
+                "message": {
+                    "token": token,
+                    "notification": { "title": notification.title, "body": notification.body },
+                    "data": notification.data,
+                }
+            }))
+            .send()
+            .await
+            .map_err(|e| PushError::Failed(format!("FCM send: {}", e)))?;
+
+        match response.status().as_u16() {
+            200..=299 => Ok(()),
+            // UNREGISTERED or INVALID_ARGUMENT for a stale token
+            400 | 404 => Err(PushError::InvalidToken),
+            status => Err(PushError::Failed(format!("FCM answered {}", status))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ApnsClaims<'a> {
+    iss: &'a str,
+    iat: i64,
+}
+
+/// Apple Push Notification service, token based connection
+pub struct Apns {
+    config: ApnsConfig,
+    key: EncodingKey,
+    client: reqwest::Client,
+    provider_token: Mutex<Option<CachedToken>>,
+}
+
+impl Apns {
+    /// APNs only speaks HTTP/2, so this gets its own client
+    pub fn new(config: &ApnsConfig) -> Result<Self> {
+        let key = EncodingKey::from_ec_pem(config.private_key.replace("\\n", "\n").as_bytes())
+            .map_err(|e| eyre!("NOTIFICATIONS.APNS.PRIVATE_KEY is not an EC pem key: {}", e))?;
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .map_err(|e| eyre!("Building the APNs client: {}", e))?;
+
+        Ok(Apns {
+            config: config.clone(),
+            key,
+            client,
+            provider_token: Mutex::new(None),
+        })
+    }
+
+    fn provider_token(&self) -> Result<String, PushError> {
+        if let Some(token) = cached(&self.provider_token) {
+            return Ok(token);
+        }
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.config.key_id.clone());
+        let claims = ApnsClaims {
+            iss: &self.config.team_id,
+            iat: Utc::now().timestamp(),
+        };
+        let token = encode(&header, &claims, &self.key)
+            .map_err(|e| PushError::Failed(format!("Signing APNs token: {}", e)))?;
+
+        store(&self.provider_token, token.clone(), APNS_TOKEN_TTL_SECS);
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl PushProvider for Apns {
+    fn name(&self) -> &str {
+        "apns"
+    }
+
+    async fn send(&self, token: &str, notification: &Notification) -> Result<(), PushError> {
+        let host = if self.config.sandbox {
+            "https://api.sandbox.push.apple.com"
+        } else {
+            "https://api.push.apple.com"
+        };
+        let mut body = json!({
+            "aps": { "alert": { "title": notification.title, "body": notification.body }, "sound": "default" }
+        });
+        for (key, value) in &notification.data {
+            body[key] = json!(value);
+        }
+
+        let response = self
+            .client
+            .post(&format!("{}/3/device/{}", host, token))
+            .bearer_auth(self.provider_token()?)
+            .header("apns-topic", self.config.topic.as_str())
+            .header("apns-push-type", "alert")
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| PushError::Failed(format!("APNs send: {}", e)))?;
+
+        match response.status().as_u16() {
+            200..=299 => Ok(()),
+            // BadDeviceToken, Unregistered
+            400 | 410 => Err(PushError::InvalidToken),
+            status => Err(PushError::Failed(format!("APNs answered {}", status))),
+        }
+    }
+}
+
+/// a notification the recording provider kept
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordedNotification {
+    pub provider: String,
+    pub token: String, // masked, only the last characters
+    pub notification: Notification,
+}
+
+// device tokens are credentials for pushing to a device
+fn mask_token(token: &str) -> String {
+    let visible: String = token.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+    format!("…{}", visible)
+}
+
+/// stands in for providers without credentials, logs and keeps the last
+/// notifications in memory so they can be inspected
+#[derive(Clone)]
+pub struct Recording {
+    name: String,
+    recorded: Arc<Mutex<VecDeque<RecordedNotification>>>,
+}
+
+impl Recording {
+    pub fn new(name: &str, recorded: Arc<Mutex<VecDeque<RecordedNotification>>>) -> Self {
+        Recording {
+            name: name.to_string(),
+            recorded,
+        }
+    }
+}
+
+#[async_trait]
+impl PushProvider for Recording {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send(&self, token: &str, notification: &Notification) -> Result<(), PushError> {
+        info!("PUSH via {} (recorded) {}: {}", self.name, notification.title, notification.body);
+        let token = mask_token(token);
+        debug!("Recorded push for token {}", token);
+
+        let mut recorded = self.recorded.lock().expect("recorded notifications lock poisoned");
+        recorded.push_back(RecordedNotification {
+            provider: self.name.clone(),
+            token,
+            notification: notification.clone(),
+        });
+        while recorded.len() > RECORDED_MAX {
+            recorded.pop_front();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+//module oauth
+// authorization code + PKCE logins against external identity providers
+pub mod providers;
+
+use crate::{
+    config::OAuthConfig, errors::AppError, handlers::AppResult, models::identity::NewOAuthState,
+};
+use chrono::{Duration, Utc};
+use color_eyre::Result;
+use providers::{Discord, ExternalIdentity, OAuthProvider, Oidc};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Arc};
+use tracing::{debug, info, instrument};
+use uuid::Uuid;
+
+/// how long the user has to finish the login at the provider
+const STATE_TTL_MINUTES: i64 = 10;
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Clone)]
+pub struct OAuthService {
+    providers: Arc<HashMap<String, Box<dyn OAuthProvider>>>,
+    client: reqwest::Client,
+    public_url: String,
+}
+
+fn random_urlsafe(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::thread_rng().gen()).collect();
+    base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+}
+
+// PKCE S256 code_challenge for a code_verifier
+fn code_challenge(code_verifier: &str) -> String {
+    base64::encode_config(
+        Sha256::digest(code_verifier.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+impl OAuthService {
+    pub fn new(config: &OAuthConfig, client: reqwest::Client, public_url: String) -> Result<Self> {
+        let mut configured: Vec<Box<dyn OAuthProvider>> = Vec::new();
+
+        if let Some(discord) = &config.discord {
+            configured.push(Box::new(Discord::new(discord)));
+        }
+        if let Some(google) = &config.google {
+            configured.push(Box::new(Oidc::google(google)));
+        }
+        if let Some(oidc) = &config.oidc {
+            configured.push(Box::new(Oidc::generic(oidc)?));
+        }
+
+        let providers: HashMap<String, Box<dyn OAuthProvider>> = configured
+            .into_iter()
+            .map(|provider| (provider.name().to_string(), provider))
+            .collect();
+        info!("OAuth providers: {:?}", providers.keys().collect::<Vec<_>>());
+
+        Ok(OAuthService {
+            providers: Arc::new(providers),
+            client,
+            public_url,
+        })
+    }
+
+    pub fn provider(&self, name: &str) -> AppResult<&dyn OAuthProvider> {
+        self.providers
+            .get(name)
+            .map(|provider| provider.as_ref())
+            .ok_or_else(|| AppError::NOT_FOUND.message(format!("Unknown login provider {:?}", name)))
+    }
+
+    /// the nonce cookie is only sent over https when we are served over https
+    pub fn secure_cookies(&self) -> bool {
+        self.public_url.starts_with("https://")
+    }
+
+    fn redirect_uri(&self, provider: &str) -> String {
+        format!(
+            "{}/oauth/{}/callback",
+            self.public_url.trim_end_matches('/'),
+            provider
+        )
+    }
+
+    /// provider url to send the browser to, and the state row to remember.
+    /// user_id is set when a logged in user links another login, browser_hash
+    /// is the hash of the nonce cookie handed to the browser starting the flow
+    pub fn authorize(
+        &self,
+        provider: &str,
+        user_id: Option<Uuid>,
+        browser_hash: String,
+    ) -> AppResult<(String, NewOAuthState)> {
+        let endpoints = self.provider(provider)?.endpoints();
+        let state = random_urlsafe(24);
+        let code_verifier = random_urlsafe(32);
+
+        let url = reqwest::Url::parse_with_params(
+            &endpoints.authorize_url,
+            &[
+                ("response_type", "code"),
+                ("client_id", endpoints.client_id.as_str()),
+                ("redirect_uri", self.redirect_uri(provider).as_str()),
+                ("scope", endpoints.scopes.as_str()),
+                ("state", state.as_str()),
+                ("code_challenge", code_challenge(&code_verifier).as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|op| {
+            debug!("Error parsing authorize URL {:?}", op);
+            AppError::INTERNAL_ERROR.default()
+        })?;
+
+        let new_state = NewOAuthState {
+            state,
+            provider: provider.to_string(),
+            code_verifier,
+            user_id,
+            browser_hash,
+            expires_at: (Utc::now() + Duration::minutes(STATE_TTL_MINUTES)).naive_utc(),
+        };
+
+        Ok((url.to_string(), new_state))
+    }
+
+    /// trades the authorization code for an access token and reads the userinfo
+    #[instrument(skip(self, code, code_verifier))]
+    pub async fn exchange(&self, provider: &str, code: &str, code_verifier: &str) -> AppResult<ExternalIdentity> {
+        let oauth_provider = self.provider(provider)?;
+        let endpoints = oauth_provider.endpoints();
+        let redirect_uri = self.redirect_uri(provider);
+
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri.as_str()),
+            ("client_id", endpoints.client_id.as_str()),
+            ("client_secret", endpoints.client_secret.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+
+        let response = self
+            .client
+            .post(&endpoints.token_url)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|op| {
+                debug!("Error exchanging {} authorization code. {:?}", provider, op);
+                AppError::INTERNAL_ERROR.default()
+            })?;
+
+        if !response.status().is_success() {
+            debug!("{} token endpoint returned {}", provider, response.status());
+            return Err(AppError::INVALID_CREDENTIALS.message("Login was not accepted by the provider.".to_string()));
+        }
+
+        let token = response.json::<TokenResponse>().await.map_err(|op| {
+            debug!("Error parsing {} token response. {:?}", provider, op);
+            AppError::INTERNAL_ERROR.default()
+        })?;
+
+        let userinfo = self
+            .client
+            .get(&endpoints.userinfo_url)
+            .bearer_auth(token.access_token)
+            .send()
+            .await
+            .map_err(|op| {
+                debug!("Error fetching {} userinfo. {:?}", provider, op);
+                AppError::INTERNAL_ERROR.default()
+            })?
+            .json::<Value>()
+            .await
+            .map_err(|op| {
+                debug!("Error parsing {} userinfo. {:?}", provider, op);
+                AppError::INTERNAL_ERROR.default()
+            })?;
+
+        let identity = oauth_provider.identity(&userinfo)?;
+        Ok(identity)
+    }
+}
@@ -0,0 +1,181 @@
+//oauth providers
+// every provider knows its endpoints and how to read its userinfo document
+
+use crate::config::OAuthProviderConfig;
+use color_eyre::Result;
+use eyre::eyre;
+use serde_json::Value;
+
+/// what we learn about the user from the provider
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    pub scopes: String, // space separated
+}
+
+pub trait OAuthProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn endpoints(&self) -> &Endpoints;
+    fn identity(&self, userinfo: &Value) -> Result<ExternalIdentity>;
+}
+
+fn string_claim(userinfo: &Value, claim: &str) -> Option<String> {
+    userinfo
+        .get(claim)
+        .and_then(Value::as_str)
+        .map(|value| value.to_string())
+}
+
+/// Discord, https://discord.com/developers/docs/topics/oauth2
+pub struct Discord {
+    endpoints: Endpoints,
+}
+
+impl Discord {
+    pub fn new(config: &OAuthProviderConfig) -> Self {
+        Discord {
+            endpoints: Endpoints {
+                client_id: config.client_id.clone(),
+                client_secret: config.client_secret.clone(),
+                authorize_url: config
+                    .authorize_url
+                    .clone()
+                    .unwrap_or_else(|| "https://discord.com/api/oauth2/authorize".to_string()),
+                token_url: config
+                    .token_url
+                    .clone()
+                    .unwrap_or_else(|| "https://discord.com/api/oauth2/token".to_string()),
+                userinfo_url: config
+                    .userinfo_url
+                    .clone()
+                    .unwrap_or_else(|| "https://discord.com/api/users/@me".to_string()),
+                scopes: config
+                    .scopes
+                    .clone()
+                    .unwrap_or_else(|| "identify email".to_string()),
+            },
+        }
+    }
+}
+
+impl OAuthProvider for Discord {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    // {"id": "80351110224678912", "username": "Nelly", "email": "nelly@discord.com", "verified": true, ..}
+    fn identity(&self, userinfo: &Value) -> Result<ExternalIdentity> {
+        let subject = string_claim(userinfo, "id")
+            .ok_or_else(|| eyre!("Discord userinfo without id"))?;
+
+        Ok(ExternalIdentity {
+            subject,
+            email: string_claim(userinfo, "email"),
+            email_verified: userinfo
+                .get("verified")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            username: string_claim(userinfo, "username"),
+        })
+    }
+}
+
+/// OpenID Connect, standard claims from the userinfo endpoint.
+/// Google is an OIDC provider with well known endpoints.
+pub struct Oidc {
+    name: String,
+    endpoints: Endpoints,
+}
+
+impl Oidc {
+    pub fn google(config: &OAuthProviderConfig) -> Self {
+        Oidc {
+            name: "google".to_string(),
+            endpoints: Endpoints {
+                client_id: config.client_id.clone(),
+                client_secret: config.client_secret.clone(),
+                authorize_url: config
+                    .authorize_url
+                    .clone()
+                    .unwrap_or_else(|| "https://accounts.google.com/o/oauth2/v2/auth".to_string()),
+                token_url: config
+                    .token_url
+                    .clone()
+                    .unwrap_or_else(|| "https://oauth2.googleapis.com/token".to_string()),
+                userinfo_url: config.userinfo_url.clone().unwrap_or_else(|| {
+                    "https://openidconnect.googleapis.com/v1/userinfo".to_string()
+                }),
+                scopes: config
+                    .scopes
+                    .clone()
+                    .unwrap_or_else(|| "openid email profile".to_string()),
+            },
+        }
+    }
+
+    /// any other provider, all endpoints must be configured
+    pub fn generic(config: &OAuthProviderConfig) -> Result<Self> {
+        let required = |value: &Option<String>, key: &str| {
+            value
+                .clone()
+                .ok_or_else(|| eyre!("OAUTH.OIDC.{} is required", key.to_uppercase()))
+        };
+
+        Ok(Oidc {
+            name: "oidc".to_string(),
+            endpoints: Endpoints {
+                client_id: config.client_id.clone(),
+                client_secret: config.client_secret.clone(),
+                authorize_url: required(&config.authorize_url, "authorize_url")?,
+                token_url: required(&config.token_url, "token_url")?,
+                userinfo_url: required(&config.userinfo_url, "userinfo_url")?,
+                scopes: config
+                    .scopes
+                    .clone()
+                    .unwrap_or_else(|| "openid email profile".to_string()),
+            },
+        })
+    }
+}
+
+impl OAuthProvider for Oidc {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    fn identity(&self, userinfo: &Value) -> Result<ExternalIdentity> {
+        let subject = string_claim(userinfo, "sub")
+            .ok_or_else(|| eyre!("{} userinfo without sub", self.name))?;
+
+        Ok(ExternalIdentity {
+            subject,
+            email: string_claim(userinfo, "email"),
+            email_verified: userinfo
+                .get("email_verified")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            username: string_claim(userinfo, "preferred_username")
+                .or_else(|| string_claim(userinfo, "name")),
+        })
+    }
+}
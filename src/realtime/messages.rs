@@ -0,0 +1,38 @@
+//realtime messages
+// json exchanged with websocket clients, sse sends the server side only
+use crate::models::player_snapshot::PlayerSnapshot;
+use crate::tracker::events::RankChange;
+use serde::{Deserialize, Serialize};
+
+/// sent by websocket clients as text frames
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe { profile_ids: Vec<String> },
+    Unsubscribe { profile_ids: Vec<String> },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// current subscriptions, after every change
+    Subscribed { profile_ids: Vec<String> },
+    Snapshot { snapshot: PlayerSnapshot },
+    RankChange { change: RankChange },
+    /// pushes dropped because the client did not keep up
+    Lagged { dropped: u64 },
+    Error { message: String },
+}
+
+impl ServerMessage {
+    /// sse event name
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ServerMessage::Subscribed { .. } => "subscribed",
+            ServerMessage::Snapshot { .. } => "snapshot",
+            ServerMessage::RankChange { .. } => "rank_change",
+            ServerMessage::Lagged { .. } => "lagged",
+            ServerMessage::Error { .. } => "error",
+        }
+    }
+}
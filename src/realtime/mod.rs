@@ -0,0 +1,202 @@
+//module realtime
+// pushes tracker events to connected clients, over websocket or sse as a
+// fallback. Each connection has its own bounded queue, pushes a slow client
+// can not take are dropped and reported instead of piling up
+pub mod messages;
+pub mod sse;
+pub mod ws;
+
+use crate::{
+    config::RealtimeConfig,
+    errors::AppError,
+    handlers::AppResult,
+    tracker::{events::TrackerEvent, Tracker},
+};
+use bytes::Bytes;
+use futures::channel::mpsc;
+use messages::ServerMessage;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const MAX_PROFILE_ID_LEN: usize = 64;
+
+/// Realtime is shared by all workers, clones share the connection counts
+#[derive(Clone)]
+pub struct Realtime {
+    config: RealtimeConfig,
+    tracker: Tracker,
+    connections: Arc<Mutex<HashMap<Uuid, usize>>>, // open connections per user
+}
+
+impl Realtime {
+    pub fn new(config: RealtimeConfig, tracker: Tracker) -> Self {
+        Realtime {
+            config,
+            tracker,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn config(&self) -> &RealtimeConfig {
+        &self.config
+    }
+
+    pub fn events(&self) -> broadcast::Receiver<TrackerEvent> {
+        self.tracker.subscribe()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, usize>> {
+        self.connections.lock().expect("realtime connections lock poisoned")
+    }
+
+    /// opens a connection for `user_id` unless it already has the maximum
+    pub fn connect(&self, user_id: Uuid) -> AppResult<Connection> {
+        let mut connections = self.lock();
+        let open = connections.entry(user_id).or_insert(0);
+        if *open >= self.config.max_connections_per_user {
+            return Err(AppError::RATE_LIMITED.message(format!(
+                "At most {} realtime connections per user.",
+                self.config.max_connections_per_user
+            )));
+        }
+        *open += 1;
+
+        Ok(Connection {
+            realtime: self.clone(),
+            user_id,
+            subscriptions: BTreeSet::new(),
+        })
+    }
+}
+
+/// one client, released on drop
+pub struct Connection {
+    realtime: Realtime,
+    user_id: Uuid,
+    subscriptions: BTreeSet<String>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let mut connections = self.realtime.lock();
+        if let Some(open) = connections.get_mut(&self.user_id) {
+            *open -= 1;
+            if *open == 0 {
+                connections.remove(&self.user_id);
+            }
+        }
+    }
+}
+
+impl Connection {
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.subscriptions.iter().cloned().collect()
+    }
+
+    /// adds profile ids, all or nothing when the limit would be exceeded
+    pub fn subscribe(&mut self, profile_ids: Vec<String>) -> AppResult<()> {
+        if let Some(invalid) = profile_ids
+            .iter()
+            .find(|id| id.is_empty() || id.len() > MAX_PROFILE_ID_LEN)
+        {
+            return Err(AppError::INVALID_INPUT.message(format!("Invalid profile id {:?}", invalid)));
+        }
+
+        let new = profile_ids
+            .iter()
+            .filter(|id| !self.subscriptions.contains(*id))
+            .collect::<BTreeSet<_>>()
+            .len();
+        let max = self.realtime.config.max_subscriptions;
+        if self.subscriptions.len() + new > max {
+            return Err(AppError::INVALID_INPUT.message(format!("At most {} profiles per connection.", max)));
+        }
+
+        self.subscriptions.extend(profile_ids);
+        Ok(())
+    }
+
+    pub fn unsubscribe(&mut self, profile_ids: Vec<String>) {
+        for id in profile_ids {
+            self.subscriptions.remove(&id);
+        }
+    }
+
+    /// what to push for a tracker event, nothing when not subscribed
+    pub fn message_for(&self, event: &TrackerEvent) -> Option<ServerMessage> {
+        match event {
+            TrackerEvent::Snapshot(snapshot) if self.subscriptions.contains(&snapshot.profile_id) => {
+                Some(ServerMessage::Snapshot {
+                    snapshot: snapshot.clone(),
+                })
+            }
+            TrackerEvent::Change(change) if self.subscriptions.contains(&change.profile_id) => {
+                Some(ServerMessage::RankChange { change: change.clone() })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// queued for a client, each transport encodes it its own way
+#[derive(Debug)]
+pub enum Outgoing {
+    Message(ServerMessage),
+    Heartbeat,
+    Pong(Bytes),
+    Close,
+}
+
+/// sending half of a connection's queue
+pub struct Outbox {
+    sender: mpsc::Sender<Outgoing>,
+    dropped: u64,
+}
+
+impl Outbox {
+    pub fn channel(buffer: usize) -> (Outbox, mpsc::Receiver<Outgoing>) {
+        let (sender, receiver) = mpsc::channel(buffer);
+        (Outbox { sender, dropped: 0 }, receiver)
+    }
+
+    /// queues a push, dropping it when the queue is full. A `Lagged` notice
+    /// goes out before the next push that fits. False once the client is gone
+    pub fn push(&mut self, message: ServerMessage) -> bool {
+        if self.dropped > 0 {
+            match self.sender.try_send(Outgoing::Message(ServerMessage::Lagged { dropped: self.dropped })) {
+                Ok(()) => self.dropped = 0,
+                Err(e) if e.is_full() => {
+                    self.dropped += 1;
+                    return true;
+                }
+                Err(_) => return false,
+            }
+        }
+
+        match self.sender.try_send(Outgoing::Message(message)) {
+            Ok(()) => true,
+            Err(e) if e.is_full() => {
+                self.dropped += 1;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// pushes lost before they reached this connection
+    pub fn lagged(&mut self, dropped: u64) {
+        self.dropped += dropped;
+    }
+
+    /// control frames are skipped, not counted, when the queue is full
+    pub fn control(&mut self, outgoing: Outgoing) -> bool {
+        match self.sender.try_send(outgoing) {
+            Ok(()) => true,
+            Err(e) => e.is_full(),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//realtime sse
+// server-sent events for clients that can not open a websocket. The
+// subscriptions are fixed when connecting, heartbeats are comment lines
+use super::{messages::ServerMessage, Connection, Outbox, Outgoing, Realtime};
+use crate::tracker::events::TrackerEvent;
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use std::time::Duration;
+use tokio::sync::broadcast::RecvError;
+
+enum Input {
+    Event(Box<Result<TrackerEvent, RecvError>>),
+    Tick,
+}
+
+/// `text/event-stream` body, starting with the subscriptions
+pub fn serve(realtime: &Realtime, connection: Connection) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let (mut outbox, outgoing) = Outbox::channel(realtime.config().buffer);
+    outbox.push(ServerMessage::Subscribed {
+        profile_ids: connection.subscriptions(),
+    });
+    actix_rt::spawn(session(realtime.clone(), connection, outbox));
+
+    outgoing.map(|outgoing| {
+        let event = match outgoing {
+            Outgoing::Message(message) => {
+                format!("event: {}\ndata: {}\n\n", message.kind(), serde_json::to_string(&message)?)
+            }
+            _ => ": ping\n\n".to_string(),
+        };
+        Ok(Bytes::from(event))
+    })
+}
+
+async fn session(realtime: Realtime, connection: Connection, mut outbox: Outbox) {
+    let heartbeat = Duration::from_secs(realtime.config().heartbeat_secs);
+    let events = realtime.events().map(|event| Input::Event(Box::new(event)));
+    let ticks = actix_rt::time::interval(heartbeat).map(|_| Input::Tick);
+    let mut inputs = stream::select(events, ticks);
+
+    // a closed response shows up as a failed push, at the latest on the next heartbeat
+    while let Some(input) = inputs.next().await {
+        let open = match input {
+            Input::Event(event) => match *event {
+                Ok(event) => match connection.message_for(&event) {
+                    Some(message) => outbox.push(message),
+                    None => true,
+                },
+                Err(RecvError::Lagged(dropped)) => {
+                    outbox.lagged(dropped);
+                    true
+                }
+                Err(RecvError::Closed) => false,
+            },
+            Input::Tick => outbox.control(Outgoing::Heartbeat),
+        };
+
+        if !open {
+            break;
+        }
+    }
+}
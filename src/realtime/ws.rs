@@ -0,0 +1,139 @@
+//realtime websocket
+// handshake and framing come from actix-http, the session itself runs as a
+// spawned task that feeds the streamed response
+use super::{
+    messages::{ClientMessage, ServerMessage},
+    Connection, Outbox, Outgoing, Realtime,
+};
+use crate::tracker::events::TrackerEvent;
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{CloseCode, Codec, Frame, Message};
+use actix_web::{error::PayloadError, web::Payload};
+use bytes::{Bytes, BytesMut};
+use futures::{future::ready, stream, Stream, StreamExt};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::RecvError;
+use tracing::debug;
+
+enum Input {
+    Chunk(Result<Bytes, PayloadError>),
+    Closed,
+    Event(Result<TrackerEvent, RecvError>),
+    Tick,
+}
+
+/// response body of an upgraded connection
+pub fn serve(
+    realtime: &Realtime,
+    connection: Connection,
+    payload: Payload,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let (outbox, outgoing) = Outbox::channel(realtime.config().buffer);
+    actix_rt::spawn(session(realtime.clone(), connection, payload, outbox));
+
+    let mut codec = Codec::new();
+    outgoing.map(move |outgoing| {
+        let message = match outgoing {
+            Outgoing::Message(message) => Message::Text(serde_json::to_string(&message)?),
+            Outgoing::Heartbeat => Message::Ping(Bytes::new()),
+            Outgoing::Pong(bytes) => Message::Pong(bytes),
+            Outgoing::Close => Message::Close(Some(CloseCode::Normal.into())),
+        };
+        let mut buffer = BytesMut::new();
+        codec.encode(message, &mut buffer)?;
+        Ok(buffer.freeze())
+    })
+}
+
+async fn session(realtime: Realtime, mut connection: Connection, payload: Payload, mut outbox: Outbox) {
+    let heartbeat = Duration::from_secs(realtime.config().heartbeat_secs);
+    let timeout = Duration::from_secs(realtime.config().client_timeout_secs);
+
+    let frames = payload.map(Input::Chunk).chain(stream::once(ready(Input::Closed)));
+    let events = realtime.events().map(Input::Event);
+    let ticks = actix_rt::time::interval(heartbeat).map(|_| Input::Tick);
+    let mut inputs = stream::select(frames, stream::select(events, ticks));
+
+    let mut codec = Codec::new();
+    let mut buffer = BytesMut::new();
+    let mut last_seen = Instant::now();
+
+    while let Some(input) = inputs.next().await {
+        let open = match input {
+            Input::Chunk(Ok(chunk)) => {
+                last_seen = Instant::now();
+                buffer.extend_from_slice(&chunk);
+                let mut open = true;
+                while open {
+                    match codec.decode(&mut buffer) {
+                        Ok(Some(frame)) => open = handle_frame(frame, &mut connection, &mut outbox),
+                        Ok(None) => break,
+                        Err(e) => {
+                            debug!("Invalid websocket frame. {:?}", e);
+                            outbox.control(Outgoing::Close);
+                            open = false;
+                        }
+                    }
+                }
+                open
+            }
+            Input::Chunk(Err(_)) | Input::Closed => false,
+            Input::Event(Ok(event)) => match connection.message_for(&event) {
+                Some(message) => outbox.push(message),
+                None => true,
+            },
+            Input::Event(Err(RecvError::Lagged(dropped))) => {
+                outbox.lagged(dropped);
+                true
+            }
+            Input::Event(Err(RecvError::Closed)) => false,
+            Input::Tick if last_seen.elapsed() > timeout => {
+                debug!("Websocket client timed out.");
+                outbox.control(Outgoing::Close);
+                false
+            }
+            Input::Tick => outbox.control(Outgoing::Heartbeat),
+        };
+
+        if !open {
+            break;
+        }
+    }
+}
+
+// false once the session should end
+fn handle_frame(frame: Frame, connection: &mut Connection, outbox: &mut Outbox) -> bool {
+    match frame {
+        Frame::Text(text) => {
+            let reply = match serde_json::from_slice::<ClientMessage>(&text) {
+                Ok(ClientMessage::Subscribe { profile_ids }) => match connection.subscribe(profile_ids) {
+                    Ok(()) => ServerMessage::Subscribed {
+                        profile_ids: connection.subscriptions(),
+                    },
+                    Err(e) => ServerMessage::Error {
+                        message: e.message().to_string(),
+                    },
+                },
+                Ok(ClientMessage::Unsubscribe { profile_ids }) => {
+                    connection.unsubscribe(profile_ids);
+                    ServerMessage::Subscribed {
+                        profile_ids: connection.subscriptions(),
+                    }
+                }
+                Err(_) => ServerMessage::Error {
+                    message: r#"Expected {"action": "subscribe" | "unsubscribe", "profile_ids": [..]}"#.to_string(),
+                },
+            };
+            outbox.push(reply)
+        }
+        Frame::Binary(_) | Frame::Continuation(_) => outbox.push(ServerMessage::Error {
+            message: "Only text frames are supported.".to_string(),
+        }),
+        Frame::Ping(bytes) => outbox.control(Outgoing::Pong(bytes)),
+        Frame::Pong(_) => true,
+        Frame::Close(_) => {
+            outbox.control(Outgoing::Close);
+            false
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//avatars
+// uploads are decoded and re-encoded as square png thumbnails, nothing
+// of the original file is stored
+use super::Storage;
+use crate::config::StorageConfig;
+use color_eyre::Result;
+use image::{imageops::FilterType, io::Reader, ImageFormat, ImageOutputFormat};
+use rand::Rng;
+use std::{collections::BTreeMap, io::Cursor, sync::Arc};
+use uuid::Uuid;
+
+/// thumbnail edges in pixels, the largest one becomes users.image
+pub const AVATAR_SIZES: [u32; 3] = [256, 128, 64];
+pub const ACCEPTED_TYPES: [&str; 2] = ["image/png", "image/jpeg"];
+
+/// a fresh prefix per upload, so cached urls never show a stale avatar
+fn new_prefix(user_id: Uuid) -> String {
+    let version: [u8; 8] = rand::thread_rng().gen();
+    format!("avatars/{}/{}", user_id, hex::encode(version))
+}
+
+fn key(prefix: &str, size: u32) -> String {
+    format!("{}/{}.png", prefix, size)
+}
+
+/// thumbnails for every size, the error is the message for the client.
+/// `content_type` has to agree with the bytes
+pub fn thumbnails(body: &[u8], content_type: &str, max_dimension: u32) -> std::result::Result<Vec<(u32, Vec<u8>)>, String> {
+    let expected = match content_type {
+        "image/png" => ImageFormat::Png,
+        "image/jpeg" => ImageFormat::Jpeg,
+        _ => return Err(format!("Unsupported image type {}. Use one of {:?}", content_type, ACCEPTED_TYPES)),
+    };
+
+    let reader = Reader::new(Cursor::new(body))
+        .with_guessed_format()
+        .map_err(|_| "Unreadable image.".to_string())?;
+    if reader.format() != Some(expected) {
+        return Err(format!("The file is not a valid {} image.", content_type));
+    }
+
+    // checked before decoding, a small file can still claim huge dimensions
+    let (width, height) = Reader::with_format(Cursor::new(body), expected)
+        .into_dimensions()
+        .map_err(|_| "Unreadable image.".to_string())?;
+    if width > max_dimension || height > max_dimension {
+        return Err(format!(
+            "Image is {}x{}, at most {}x{} is allowed.",
+            width, height, max_dimension, max_dimension
+        ));
+    }
+
+    let image = reader.decode().map_err(|_| "Unreadable image.".to_string())?;
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let mut png = Vec::new();
+            image
+                .resize_to_fill(size, size, FilterType::Lanczos3)
+                .write_to(&mut png, ImageOutputFormat::Png)
+                .map_err(|_| "Error resizing the image.".to_string())?;
+            Ok((size, png))
+        })
+        .collect()
+}
+
+/// avatar thumbnails in the configured storage
+#[derive(Clone)]
+pub struct Avatars {
+    storage: Arc<dyn Storage>,
+    max_upload_bytes: usize,
+    max_dimension: u32,
+}
+
+impl Avatars {
+    pub fn new(config: &StorageConfig, storage: Arc<dyn Storage>) -> Self {
+        Avatars {
+            storage,
+            max_upload_bytes: config.max_upload_bytes,
+            max_dimension: config.max_dimension,
+        }
+    }
+
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    pub fn max_upload_bytes(&self) -> usize {
+        self.max_upload_bytes
+    }
+
+    pub fn max_dimension(&self) -> u32 {
+        self.max_dimension
+    }
+
+    /// stores the thumbnails under a new prefix, which is returned
+    pub async fn store(&self, user_id: Uuid, thumbnails: Vec<(u32, Vec<u8>)>) -> Result<String> {
+        let prefix = new_prefix(user_id);
+        for (size, png) in thumbnails {
+            self.storage.put(&key(&prefix, size), png, "image/png").await?;
+        }
+        Ok(prefix)
+    }
+
+    pub async fn remove(&self, prefix: &str) -> Result<()> {
+        for &size in AVATAR_SIZES.iter() {
+            self.storage.delete(&key(prefix, size)).await?;
+        }
+        Ok(())
+    }
+
+    /// the url of every thumbnail by size
+    pub fn urls(&self, prefix: &str) -> BTreeMap<u32, String> {
+        AVATAR_SIZES
+            .iter()
+            .map(|&size| (size, self.storage.url(&key(prefix, size))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+
+    fn encoded(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let image = ImageBuffer::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 0]));
+        let mut body = Vec::new();
+        DynamicImage::ImageRgb8(image).write_to(&mut body, format).unwrap();
+        body
+    }
+
+    #[test]
+    fn thumbnails_are_square_pngs_of_every_size() {
+        let body = encoded(300, 200, ImageOutputFormat::Jpeg(90));
+        let thumbnails = thumbnails(&body, "image/jpeg", 4096).unwrap();
+
+        let sizes: Vec<u32> = thumbnails.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, AVATAR_SIZES.to_vec());
+        for (size, png) in thumbnails {
+            let image = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+            assert_eq!(image.dimensions(), (size, size));
+        }
+    }
+
+    #[test]
+    fn the_type_has_to_match_the_content() {
+        let png = encoded(16, 16, ImageOutputFormat::Png);
+        assert!(thumbnails(&png, "image/png", 4096).is_ok());
+        assert_eq!(
+            thumbnails(&png, "image/jpeg", 4096).unwrap_err(),
+            "The file is not a valid image/jpeg image."
+        );
+        assert!(thumbnails(&png, "image/gif", 4096)
+            .unwrap_err()
+            .starts_with("Unsupported image type image/gif."));
+        assert_eq!(
+            thumbnails(b"<svg></svg>", "image/png", 4096).unwrap_err(),
+            "The file is not a valid image/png image."
+        );
+    }
+
+    #[test]
+    fn oversized_images_are_rejected() {
+        let png = encoded(40, 20, ImageOutputFormat::Png);
+        assert!(thumbnails(&png, "image/png", 40).is_ok());
+        assert_eq!(
+            thumbnails(&png, "image/png", 32).unwrap_err(),
+            "Image is 40x20, at most 32x32 is allowed."
+        );
+
+        let wide = encoded(5000, 1, ImageOutputFormat::Png);
+        assert_eq!(
+            thumbnails(&wide, "image/png", 4096).unwrap_err(),
+            "Image is 5000x1, at most 4096x4096 is allowed."
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//local storage
+use super::Storage;
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::WrapErr;
+use std::{io::ErrorKind, path::PathBuf};
+use tokio::fs;
+
+/// files below a directory, served by the api at `base_url`
+pub struct LocalStorage {
+    root: PathBuf,
+    base_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str, base_url: String) -> Self {
+        LocalStorage {
+            root: PathBuf::from(root),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, body: Vec<u8>, _content_type: &str) -> Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Creating {:?}", parent))?;
+        }
+        fs::write(&path, body)
+            .await
+            .with_context(|| format!("Writing {:?}", path))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(key)).await {
+            Ok(body) => Ok(Some(body)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Reading {}", key)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.root.join(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e).with_context(|| format!("Deleting {}", key)),
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[actix_rt::test]
+    async fn objects_are_stored_read_and_deleted() {
+        let root = std::env::temp_dir().join(test_support::unique("storage"));
+        let storage = LocalStorage::new(root.to_str().unwrap(), "http://zbot.test/uploads/".to_string());
+        let key = "avatars/user/version/64.png";
+
+        assert_eq!(storage.get(key).await.unwrap(), None);
+        storage.put(key, b"png".to_vec(), "image/png").await.unwrap();
+        assert_eq!(storage.get(key).await.unwrap(), Some(b"png".to_vec()));
+        assert!(root.join("avatars/user/version/64.png").is_file());
+        assert_eq!(storage.url(key), "http://zbot.test/uploads/avatars/user/version/64.png");
+
+        storage.delete(key).await.unwrap();
+        assert_eq!(storage.get(key).await.unwrap(), None);
+        // already gone
+        storage.delete(key).await.unwrap();
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+//module storage
+// where uploaded files live. The local backend serves them itself under
+// /uploads, the s3 backend hands out urls of the bucket
+pub mod avatar;
+pub mod local;
+pub mod s3;
+
+use async_trait::async_trait;
+use color_eyre::Result;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<()>;
+    /// None for a missing object
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// deleting a missing object is not an error
+    async fn delete(&self, key: &str) -> Result<()>;
+    /// where clients fetch the object from
+    fn url(&self, key: &str) -> String;
+}
+
+/// keys are generated by the server, this guards the ones read back from requests
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('/')
+        && key.split('/').all(|part| !part.is_empty() && part != "." && part != "..")
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '-' | '_' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_stay_below_the_root() {
+        assert!(is_valid_key("avatars/3f1c/0a9b/256.png"));
+        assert!(is_valid_key("a.b_c-d"));
+
+        for key in [
+            "",
+            "/etc/passwd",
+            "../secret",
+            "avatars/../../secret",
+            "avatars/./256.png",
+            "avatars//256.png",
+            "avatars/",
+            "avatars\\..\\secret",
+            "avatars/%2e%2e/secret",
+            "avatars/256.png?x=1",
+        ]
+        .iter()
+        {
+            assert!(!is_valid_key(key), "{}", key);
+        }
+    }
+}
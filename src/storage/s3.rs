@@ -0,0 +1,268 @@
+//s3 storage
+// any S3 compatible object store (AWS, MinIO, R2 ..), requests are signed
+// with AWS signature version 4
+// https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-header-based-auth.html
+use super::Storage;
+use crate::config::S3Config;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use eyre::{eyre, WrapErr};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("hmac accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub struct S3Storage {
+    config: S3Config,
+    client: reqwest::Client,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config, client: reqwest::Client) -> Result<Self> {
+        Url::parse(&config.endpoint).map_err(|e| eyre!("STORAGE.S3.ENDPOINT is not a url: {}", e))?;
+
+        Ok(S3Storage {
+            config: config.clone(),
+            client,
+        })
+    }
+
+    // path style (endpoint/bucket/key) unless virtual hosted (bucket.endpoint/key)
+    fn object_url(&self, key: &str) -> Result<Url> {
+        let endpoint = self.config.endpoint.trim_end_matches('/');
+        let url = if self.config.path_style {
+            format!("{}/{}/{}", endpoint, self.config.bucket, key)
+        } else {
+            let (scheme, host) = endpoint.split_at(endpoint.find("://").map(|i| i + 3).unwrap_or(0));
+            format!("{}{}.{}/{}", scheme, self.config.bucket, host, key)
+        };
+        Url::parse(&url).wrap_err("Building the object url")
+    }
+
+    // keys only hold unreserved characters (see storage::is_valid_key), so
+    // the url path already is the canonical uri
+    fn authorization(&self, method: &Method, url: &Url, payload_hash: &str, now: DateTime<Utc>) -> Result<String> {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            _ => return Err(eyre!("Object url without host")),
+        };
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            SIGNED_HEADERS,
+            payload_hash
+        );
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            ALGORITHM,
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = hmac(format!("AWS4{}", self.config.secret_key).as_bytes(), &date);
+        let key = hmac(&key, &self.config.region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+
+        Ok(format!(
+            "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM, self.config.access_key, scope, SIGNED_HEADERS, signature
+        ))
+    }
+
+    async fn send(&self, method: Method, key: &str, body: Vec<u8>, content_type: Option<&str>) -> Result<reqwest::Response> {
+        let url = self.object_url(key)?;
+        let now = Utc::now();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let authorization = self.authorization(&method, &url, &payload_hash, now)?;
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string())
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization);
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+
+        request
+            .body(body)
+            .send()
+            .await
+            .with_context(|| format!("Requesting object {}", key))
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<()> {
+        let response = self.send(Method::PUT, key, body, Some(content_type)).await?;
+        if !response.status().is_success() {
+            return Err(eyre!("Storing {} answered {}", key, response.status()));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let response = self.send(Method::GET, key, Vec::new(), None).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => Err(eyre!("Reading {} answered {}", key, status)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let response = self.send(Method::DELETE, key, Vec::new(), None).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(()),
+            status if status.is_success() => Ok(()),
+            status => Err(eyre!("Deleting {} answered {}", key, status)),
+        }
+    }
+
+    /// the bucket has to allow anonymous reads, or a CDN in front of it
+    /// is configured as STORAGE.S3.PUBLIC_URL
+    fn url(&self, key: &str) -> String {
+        match &self.config.public_url {
+            Some(public_url) => format!("{}/{}", public_url.trim_end_matches('/'), key),
+            None => self
+                .object_url(key)
+                .map(|url| url.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpRequest, HttpResponse};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    fn config(endpoint: String) -> S3Config {
+        S3Config {
+            endpoint,
+            bucket: "avatars".to_string(),
+            access_key: "access".to_string(),
+            secret_key: "secret".to_string(),
+            region: "eu-west-1".to_string(),
+            path_style: true,
+            public_url: None,
+        }
+    }
+
+    /// bucket in memory, answers like S3 and only takes signed requests
+    #[derive(Clone, Default)]
+    struct StubBucket {
+        objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    }
+
+    fn header<'a>(req: &'a HttpRequest, name: &str) -> &'a str {
+        req.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default()
+    }
+
+    async fn stub_object(stub: web::Data<StubBucket>, req: HttpRequest, body: web::Bytes) -> HttpResponse {
+        let authorization = header(&req, "authorization");
+        let signed = authorization.starts_with("AWS4-HMAC-SHA256 Credential=access/")
+            && authorization.contains("/eu-west-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, ")
+            && header(&req, "x-amz-content-sha256") == hex::encode(Sha256::digest(&body))
+            && !header(&req, "x-amz-date").is_empty();
+        if !signed {
+            return HttpResponse::Forbidden().finish();
+        }
+
+        let key = req.match_info().query("key").to_string();
+        let mut objects = stub.objects.lock().unwrap();
+        match *req.method() {
+            Method::PUT => {
+                objects.insert(key, body.to_vec());
+                HttpResponse::Ok().finish()
+            }
+            Method::GET => match objects.get(&key) {
+                Some(object) => HttpResponse::Ok().body(object.clone()),
+                None => HttpResponse::NotFound().finish(),
+            },
+            Method::DELETE => {
+                objects.remove(&key);
+                HttpResponse::NoContent().finish()
+            }
+            _ => HttpResponse::MethodNotAllowed().finish(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn objects_round_trip_through_the_bucket() {
+        let stub = StubBucket::default();
+        let server = {
+            let stub = stub.clone();
+            test::start(move || {
+                App::new()
+                    .data(stub.clone())
+                    .route("/avatars/{key:.*}", web::to(stub_object))
+            })
+        };
+        let storage = S3Storage::new(&config(server.url("")), reqwest::Client::new()).unwrap();
+        let key = "avatars/user/version/64.png";
+
+        assert_eq!(storage.get(key).await.unwrap(), None);
+        storage.put(key, b"png".to_vec(), "image/png").await.unwrap();
+        assert_eq!(stub.objects.lock().unwrap().get(key), Some(&b"png".to_vec()));
+        assert_eq!(storage.get(key).await.unwrap(), Some(b"png".to_vec()));
+
+        storage.delete(key).await.unwrap();
+        assert!(stub.objects.lock().unwrap().is_empty());
+        storage.delete(key).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn rejected_requests_are_errors() {
+        let server = test::start(|| App::new().route("/avatars/{key:.*}", web::to(HttpResponse::Forbidden)));
+        let storage = S3Storage::new(&config(server.url("")), reqwest::Client::new()).unwrap();
+
+        assert!(storage.put("a.png", b"png".to_vec(), "image/png").await.is_err());
+        assert!(storage.get("a.png").await.is_err());
+        assert!(storage.delete("a.png").await.is_err());
+    }
+
+    #[test]
+    fn urls_of_path_style_virtual_hosted_and_public_buckets() {
+        let mut config = config("https://s3.example.com/".to_string());
+        let path_style = S3Storage::new(&config, reqwest::Client::new()).unwrap();
+        assert_eq!(path_style.url("a/64.png"), "https://s3.example.com/avatars/a/64.png");
+
+        config.path_style = false;
+        let virtual_hosted = S3Storage::new(&config, reqwest::Client::new()).unwrap();
+        assert_eq!(virtual_hosted.url("a/64.png"), "https://avatars.s3.example.com/a/64.png");
+
+        config.public_url = Some("https://cdn.example.com/".to_string());
+        let public = S3Storage::new(&config, reqwest::Client::new()).unwrap();
+        assert_eq!(public.url("a/64.png"), "https://cdn.example.com/a/64.png");
+
+        assert!(S3Storage::new(&self::config("not a url".to_string()), reqwest::Client::new()).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+//test support
+// helpers for handler tests. They run against a migrated postgres named by
+// TEST_DATABASE_URL, are #[ignore]d by default and fail when it is not set
+
+use crate::config::crypto::CryptoService;
+use rand::Rng;
+use sqlx::PgPool;
+
+pub async fn pool() -> PgPool {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set for database tests");
+
+    PgPool::builder()
+        .max_size(4)
+        .build(&url)
+        .await
+        .expect("Test database connection failed!")
+}
+
+pub fn crypto_service() -> CryptoService {
+    CryptoService::new("test-secret-key".to_string(), "test-jwt-secret".to_string())
+        .expect("Failed to initialise crypto service")
+}
+
+/// `prefix` with a random suffix, tests share one database
+pub fn unique(prefix: &str) -> String {
+    let suffix: [u8; 6] = rand::thread_rng().gen();
+    format!("{}_{}", prefix, hex::encode(suffix))
+}
@@ -0,0 +1,114 @@
+//tracker events
+// what changed between two snapshots of the same profile and region
+use crate::models::player_snapshot::PlayerSnapshot;
+use crate::ubi::player_card::{rank_name, PlayerCard};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub const RANK_UP: &str = "rank_up";
+pub const RANK_DOWN: &str = "rank_down";
+pub const LEVEL_UP: &str = "level_up";
+pub const MMR_CHANGE: &str = "mmr_change";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotSummary {
+    pub rank: i32,
+    pub rank_name: &'static str,
+    pub mmr: f32,
+    pub level: Option<i32>,
+    pub wins: i32,
+    pub losses: i32,
+}
+
+impl SnapshotSummary {
+    pub fn new(rank: i32, mmr: f32, level: Option<i32>, wins: i32, losses: i32) -> Self {
+        SnapshotSummary {
+            rank,
+            rank_name: rank_name(rank),
+            mmr,
+            level,
+            wins,
+            losses,
+        }
+    }
+}
+
+impl From<&PlayerSnapshot> for SnapshotSummary {
+    fn from(snapshot: &PlayerSnapshot) -> Self {
+        SnapshotSummary::new(snapshot.rank, snapshot.mmr, snapshot.level, snapshot.wins, snapshot.losses)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RankChange {
+    pub event: &'static str, // RANK_UP | RANK_DOWN | LEVEL_UP | MMR_CHANGE
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub region: String,
+    pub snapshot_id: Uuid,
+    pub previous: SnapshotSummary,
+    pub current: SnapshotSummary,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// published to every subscriber of the tracker
+#[derive(Debug, Clone)]
+pub enum TrackerEvent {
+    Snapshot(PlayerSnapshot), // a new snapshot was stored
+    Change(RankChange),
+    Card(Arc<PlayerCard>), // the card behind new snapshots, after their own events
+}
+
+/// rank and mmr events between two consecutive snapshots of one region.
+/// Rank changes across a season reset are not reported
+pub fn region_events(previous: &SnapshotSummary, current: &SnapshotSummary, same_season: bool) -> Vec<&'static str> {
+    let mut events = Vec::new();
+    if same_season {
+        if current.rank > previous.rank {
+            events.push(RANK_UP);
+        } else if current.rank < previous.rank {
+            events.push(RANK_DOWN);
+        }
+    }
+    if (current.mmr - previous.mmr).abs() >= 1.0 {
+        events.push(MMR_CHANGE);
+    }
+
+    events
+}
+
+/// the level belongs to the card and is copied to the snapshot of every
+/// region, so a level up is reported once per card, not per region
+pub fn leveled_up(previous: &SnapshotSummary, current: &SnapshotSummary) -> bool {
+    match (previous.level, current.level) {
+        (Some(old), Some(new)) => new > old,
+        _ => false,
+    }
+}
+
+impl RankChange {
+    pub fn between(event: &'static str, previous: &PlayerSnapshot, current: &PlayerSnapshot) -> Self {
+        RankChange {
+            event,
+            profile_id: current.profile_id.clone(),
+            platform: current.platform.clone(),
+            name_on_platform: current.name_on_platform.clone(),
+            region: current.region.clone(),
+            snapshot_id: current.id,
+            previous: previous.into(),
+            current: current.into(),
+            occurred_at: Utc::now(),
+        }
+    }
+}
+
+/// rank and mmr changes between two consecutive snapshots of one region
+pub fn detect(previous: &PlayerSnapshot, current: &PlayerSnapshot) -> Vec<RankChange> {
+    region_events(&previous.into(), &current.into(), previous.season == current.season)
+        .into_iter()
+        .map(|event| RankChange::between(event, previous, current))
+        .collect()
+}
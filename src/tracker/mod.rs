@@ -0,0 +1,124 @@
+//module tracker
+// keeps snapshots of linked profiles current and publishes what changed.
+// Every snapshot write goes through here so subscribers (webhooks, realtime
+// pushes, notifications, achievements) see changes from handlers and the background loop alike
+pub mod events;
+
+use crate::{
+    config::TrackerConfig,
+    db::{player_snapshot::PlayerSnapshotRepository, profile_link::ProfileLinkRepository},
+    handlers::AppResult,
+    models::player_snapshot::PlayerSnapshot,
+    ubi::{
+        player_card::PlayerCard,
+        scheduler::Priority,
+        ubi_api::{Profile, UbiApi},
+    },
+};
+use events::{detect, leveled_up, RankChange, TrackerEvent, LEVEL_UP};
+use sqlx::PgPool;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+use tracing::{debug, error, info};
+
+#[derive(Clone)]
+pub struct Tracker {
+    config: TrackerConfig,
+    ubi_api: UbiApi,
+    pool: Arc<PgPool>,
+    events: broadcast::Sender<TrackerEvent>,
+}
+
+impl Tracker {
+    pub fn new(config: TrackerConfig, ubi_api: UbiApi, pool: Arc<PgPool>) -> Self {
+        let (events, _) = broadcast::channel(config.event_buffer.max(1));
+        Tracker {
+            config,
+            ubi_api: ubi_api.with_priority(Priority::Background),
+            pool,
+            events,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TrackerEvent> {
+        self.events.subscribe()
+    }
+
+    /// stores the snapshots of a fetched card and publishes the changes.
+    /// Returns the current snapshot of every region
+    pub async fn record_card(&self, card: &PlayerCard) -> AppResult<Vec<PlayerSnapshot>> {
+        let recorded = PlayerSnapshotRepository::new(self.pool.clone())
+            .record_card(card)
+            .await?;
+
+        let created = recorded.iter().any(|snapshot| snapshot.created);
+        let mut leveled = false;
+        let mut current = Vec::with_capacity(recorded.len());
+        for snapshot in recorded {
+            if snapshot.created {
+                self.publish(TrackerEvent::Snapshot(snapshot.current.clone()));
+            }
+            if let Some(previous) = &snapshot.previous {
+                let mut changes = detect(previous, &snapshot.current);
+                if !leveled && leveled_up(&previous.into(), &(&snapshot.current).into()) {
+                    leveled = true;
+                    changes.push(RankChange::between(LEVEL_UP, previous, &snapshot.current));
+                }
+                for change in changes {
+                    debug!("{} {} in {}", change.event, change.name_on_platform, change.region);
+                    self.publish(TrackerEvent::Change(change));
+                }
+            }
+            current.push(snapshot.current);
+        }
+        if created {
+            self.publish(TrackerEvent::Card(Arc::new(card.clone())));
+        }
+
+        Ok(current)
+    }
+
+    fn publish(&self, event: TrackerEvent) {
+        // no subscribers is fine
+        let _ = self.events.send(event);
+    }
+
+    /// background loop refreshing every linked profile on the background lane
+    pub async fn run(self) {
+        if !self.config.enabled {
+            info!("Tracker disabled.");
+            return;
+        }
+
+        let mut interval = actix_rt::time::interval(Duration::from_secs(self.config.interval_secs));
+        loop {
+            interval.tick().await;
+
+            let tracked = match ProfileLinkRepository::new(self.pool.clone()).find_tracked().await {
+                Ok(tracked) => tracked,
+                Err(e) => {
+                    error!("Error loading tracked profiles. {:?}", e);
+                    continue;
+                }
+            };
+
+            debug!("Tracking {} profiles", tracked.len());
+            for link in tracked {
+                let profile = Profile {
+                    profile_id: link.profile_id,
+                    user_id: String::new(),
+                    platform_type: link.platform,
+                    id_on_platform: String::new(),
+                    name_on_platform: link.name_on_platform,
+                };
+                let result = match self.ubi_api.player_card_for(profile).await {
+                    Ok(card) => self.record_card(&card).await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    debug!("Tracking a profile failed. {}", e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//ubi player comparison
+// two to five players side by side. Cards and operator statistics of every
+// player are fetched concurrently, each metric names its winners and how
+// far every player is from the best value in percent
+
+use super::player_card::{rank_name, PlayerCard, QueueSummary};
+use super::ubi_api::{PopulationsStatistics, Profile, UbiApi};
+use crate::handlers::AppResult;
+use futures::future::try_join_all;
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::debug;
+
+pub const MIN_PLAYERS: usize = 2;
+pub const MAX_PLAYERS: usize = 5;
+
+// operators shown per player, most played first
+const OPERATOR_HIGHLIGHTS: usize = 3;
+
+// answered per operator as operatorpvp_kills:<operator index>:infinite
+const OPERATOR_STATISTICS: &str = "operatorpvp_kills,operatorpvp_death,operatorpvp_roundwon,\
+operatorpvp_roundlost,operatorpvp_timeplayed";
+
+/// a player to compare, looked up by name or by profile id
+#[derive(Debug)]
+pub enum ComparePlayer {
+    Name { platform: String, name: String },
+    ProfileId { platform: String, profile_id: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperatorHighlight {
+    pub operator: String, // Ubisoft operator index, e.g. "2:1"
+    #[serde(flatten)]
+    pub stats: QueueSummary, // ranked and casual, wins and losses are rounds
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComparedPlayer {
+    pub profile_id: String,
+    pub platform: String,
+    pub name_on_platform: String,
+    pub region: Option<String>, // the region with the highest mmr
+    pub rank: Option<i32>,
+    pub rank_name: Option<&'static str>,
+    pub mmr: Option<f32>,
+    pub kd: Option<f32>,       // ranked, all seasons
+    pub win_rate: Option<f32>, // ranked, all seasons, percent
+    pub level: Option<i32>,
+    pub operators: Vec<OperatorHighlight>,
+    pub missing: Vec<String>, // parts that could not be fetched
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetricComparison {
+    pub metric: &'static str,
+    pub winners: Vec<String>, // profile ids sharing the best value
+    pub differences: Vec<Option<f32>>, // percent from the best value, in player order
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub players: Vec<ComparedPlayer>,
+    pub metrics: Vec<MetricComparison>,
+}
+
+impl ComparedPlayer {
+    fn new(card: PlayerCard, operators: Option<Vec<OperatorHighlight>>) -> Self {
+        let best = card
+            .ranks
+            .values()
+            .max_by(|a, b| a.mmr.partial_cmp(&b.mmr).unwrap_or(std::cmp::Ordering::Equal));
+        let ranked = card.summary.as_ref().map(|summary| &summary.ranked);
+
+        let mut missing = card.missing.clone();
+        if operators.is_none() {
+            missing.push("operators".to_string());
+        }
+
+        ComparedPlayer {
+            region: best.map(|stats| stats.region.clone()),
+            rank: best.map(|stats| stats.rank),
+            rank_name: best.map(|stats| rank_name(stats.rank)),
+            mmr: best.map(|stats| stats.mmr),
+            kd: ranked.map(|ranked| ranked.kd),
+            win_rate: ranked.map(|ranked| ranked.win_rate),
+            level: card.level,
+            operators: operators.unwrap_or_default(),
+            missing,
+            profile_id: card.profile.profile_id,
+            platform: card.profile.platform_type,
+            name_on_platform: card.profile.name_on_platform,
+        }
+    }
+}
+
+fn operator_highlights(statistics: &PopulationsStatistics, profile_id: &str) -> Option<Vec<OperatorHighlight>> {
+    let stats = statistics.results.get(profile_id)?;
+
+    let mut by_operator: HashMap<&str, HashMap<&str, i32>> = HashMap::new();
+    for (key, value) in stats {
+        let stat = key
+            .strip_prefix("operatorpvp_")
+            .and_then(|key| key.strip_suffix(":infinite"))
+            .and_then(|key| key.split_once(':'));
+        if let Some((name, operator)) = stat {
+            by_operator.entry(operator).or_default().insert(name, *value);
+        }
+    }
+
+    let mut operators: Vec<OperatorHighlight> = by_operator
+        .into_iter()
+        .map(|(operator, stats)| {
+            let stat = |name: &str| stats.get(name).copied().unwrap_or(0);
+            OperatorHighlight {
+                operator: operator.to_string(),
+                stats: QueueSummary::new(
+                    stat("kills"),
+                    stat("death"),
+                    stat("roundwon"),
+                    stat("roundlost"),
+                    stat("timeplayed"),
+                ),
+            }
+        })
+        .collect();
+    operators.sort_by(|a, b| {
+        b.stats
+            .time_played_secs
+            .cmp(&a.stats.time_played_secs)
+            .then_with(|| a.operator.cmp(&b.operator))
+    });
+    operators.truncate(OPERATOR_HIGHLIGHTS);
+
+    Some(operators)
+}
+
+// higher is better for every metric, players without a value are left out
+fn compare_metric(
+    metric: &'static str,
+    players: &[ComparedPlayer],
+    value: impl Fn(&ComparedPlayer) -> Option<f32>,
+) -> MetricComparison {
+    let values: Vec<Option<f32>> = players.iter().map(value).collect();
+    let best = values.iter().flatten().copied().fold(None, |best: Option<f32>, value| {
+        Some(best.map_or(value, |best| best.max(value)))
+    });
+
+    let winners = match best {
+        Some(best) => players
+            .iter()
+            .zip(&values)
+            .filter(|(_, value)| **value == Some(best))
+            .map(|(player, _)| player.profile_id.clone())
+            .collect(),
+        None => Vec::new(),
+    };
+    let differences = values
+        .iter()
+        .map(|value| match (*value, best) {
+            (Some(value), Some(best)) if value == best => Some(0.0),
+            (Some(value), Some(best)) if best != 0.0 => Some(100.0 * (value - best) / best.abs()),
+            _ => None,
+        })
+        .collect();
+
+    MetricComparison {
+        metric,
+        winners,
+        differences,
+    }
+}
+
+impl Comparison {
+    pub fn new(players: Vec<ComparedPlayer>) -> Self {
+        let metrics = vec![
+            compare_metric("rank", &players, |player| player.rank.map(|rank| rank as f32)),
+            compare_metric("mmr", &players, |player| player.mmr),
+            compare_metric("kd", &players, |player| player.kd),
+            compare_metric("win_rate", &players, |player| player.win_rate),
+            compare_metric("level", &players, |player| player.level.map(|level| level as f32)),
+        ];
+
+        Comparison { players, metrics }
+    }
+}
+
+impl UbiApi {
+    /// profiles of `players` in the given order, fails if any of them can not be found
+    pub async fn resolve_players(&self, players: Vec<ComparePlayer>) -> AppResult<Vec<Profile>> {
+        try_join_all(players.into_iter().map(|player| async move {
+            match player {
+                ComparePlayer::Name { platform, name } => self.resolve_profile(&platform, &name).await,
+                ComparePlayer::ProfileId { platform, profile_id } => {
+                    self.resolve_profile_id(&platform, &profile_id).await
+                }
+            }
+        }))
+        .await
+    }
+
+    /// compares resolved profiles in the given order
+    pub async fn compare(&self, profiles: Vec<Profile>) -> AppResult<Comparison> {
+        let compared = try_join_all(profiles.into_iter().map(|profile| self.compared_player(profile))).await?;
+
+        Ok(Comparison::new(compared))
+    }
+
+    async fn compared_player(&self, profile: Profile) -> AppResult<ComparedPlayer> {
+        let card = self.player_card_for(profile.clone());
+        let statistics =
+            self.find_populations_statistics(&profile.profile_id, &profile.platform_type, OPERATOR_STATISTICS);
+        let (card, statistics) = futures::join!(card, statistics);
+
+        let operators = match statistics {
+            Ok(statistics) => operator_highlights(&statistics, &profile.profile_id),
+            Err(e) => {
+                debug!("Comparison operator statistics failed. {}", e);
+                None
+            }
+        };
+
+        Ok(ComparedPlayer::new(card?, operators))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(profile_id: &str, rank: Option<i32>, mmr: Option<f32>, kd: Option<f32>, level: Option<i32>) -> ComparedPlayer {
+        ComparedPlayer {
+            profile_id: profile_id.to_string(),
+            platform: "uplay".to_string(),
+            name_on_platform: profile_id.to_string(),
+            region: rank.map(|_| "emea".to_string()),
+            rank,
+            rank_name: rank.map(rank_name),
+            mmr,
+            kd,
+            win_rate: None,
+            level,
+            operators: Vec::new(),
+            missing: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn the_best_value_wins_and_the_others_are_measured_against_it() {
+        let players = vec![
+            player("a", Some(20), Some(2400.0), Some(1.5), Some(150)),
+            player("b", Some(23), Some(3000.0), Some(0.75), Some(150)),
+            player("c", Some(15), Some(3300.0), None, Some(100)),
+        ];
+
+        let mmr = compare_metric("mmr", &players, |player| player.mmr);
+        assert_eq!(mmr.winners, vec!["c"]);
+        assert_eq!(mmr.differences[2], Some(0.0));
+        assert!((mmr.differences[0].unwrap() + 27.27).abs() < 0.01);
+        assert!((mmr.differences[1].unwrap() + 9.09).abs() < 0.01);
+
+        // players without a value are neither winners nor compared
+        let kd = compare_metric("kd", &players, |player| player.kd);
+        assert_eq!(kd.winners, vec!["a"]);
+        assert_eq!(kd.differences, vec![Some(0.0), Some(-50.0), None]);
+
+        // ties share the win
+        let level = compare_metric("level", &players, |player| player.level.map(|level| level as f32));
+        assert_eq!(level.winners, vec!["a", "b"]);
+        assert_eq!(level.differences, vec![Some(0.0), Some(0.0), Some(-100.0 / 3.0)]);
+    }
+
+    #[test]
+    fn metrics_nobody_has_have_no_winner() {
+        let players = vec![player("a", None, None, Some(0.0), None), player("b", None, None, None, None)];
+
+        let mmr = compare_metric("mmr", &players, |player| player.mmr);
+        assert!(mmr.winners.is_empty());
+        assert_eq!(mmr.differences, vec![None, None]);
+
+        // a best value of 0 is a win, but no base for percentages
+        let kd = compare_metric("kd", &players, |player| player.kd);
+        assert_eq!(kd.winners, vec!["a"]);
+        assert_eq!(kd.differences, vec![Some(0.0), None]);
+    }
+
+    #[test]
+    fn comparisons_cover_every_metric_in_player_order() {
+        let comparison = Comparison::new(vec![
+            player("a", Some(23), Some(3000.0), Some(1.0), Some(200)),
+            player("b", Some(22), Some(3100.0), Some(2.0), Some(100)),
+        ]);
+
+        let names: Vec<&str> = comparison.metrics.iter().map(|metric| metric.metric).collect();
+        assert_eq!(names, vec!["rank", "mmr", "kd", "win_rate", "level"]);
+        let winners: Vec<Vec<&str>> = comparison
+            .metrics
+            .iter()
+            .map(|metric| metric.winners.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(winners, vec![vec!["a"], vec!["b"], vec!["b"], vec![], vec!["a"]]);
+        assert_eq!(comparison.metrics[4].differences, vec![Some(0.0), Some(-50.0)]);
+        assert_eq!(comparison.players[1].profile_id, "b");
+    }
+}
@@ -1 +1,5 @@
+pub mod compare;
+pub mod player_card;
+pub mod pool;
+pub mod scheduler;
 pub mod ubi_api;
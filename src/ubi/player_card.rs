@@ -0,0 +1,199 @@
+//ubi player card
+// everything the app shows for one player in a single document. Rank per
+// region, level and summary stats are fetched concurrently, a part that
+// fails is left out and listed in `missing` instead of failing the card
+
+use super::ubi_api::{PlayerStats, PopulationsStatistics, Profile, UbiApi};
+use crate::errors::AppError;
+use crate::handlers::AppResult;
+use futures::future::join_all;
+use serde::Serialize;
+use std::collections::HashMap;
+use tracing::debug;
+
+pub const PLATFORMS: [&str; 3] = ["uplay", "psn", "xbl"];
+pub const REGIONS: [&str; 3] = ["apac", "emea", "ncsa"];
+
+// rank ids since season 15, index is `PlayerStats.rank`
+const RANK_NAMES: [&str; 24] = [
+    "Unranked",
+    "Copper V", "Copper IV", "Copper III", "Copper II", "Copper I",
+    "Bronze V", "Bronze IV", "Bronze III", "Bronze II", "Bronze I",
+    "Silver V", "Silver IV", "Silver III", "Silver II", "Silver I",
+    "Gold III", "Gold II", "Gold I",
+    "Platinum III", "Platinum II", "Platinum I",
+    "Diamond",
+    "Champion",
+];
+
+pub fn rank_name(rank: i32) -> &'static str {
+    RANK_NAMES.get(rank as usize).copied().unwrap_or("Unranked")
+}
+
+// statistics requested for the summary
+const SUMMARY_STATISTICS: &str = "casualpvp_timeplayed,casualpvp_matchwon,casualpvp_matchlost,\
+casualpvp_kills,casualpvp_death,rankedpvp_timeplayed,rankedpvp_matchwon,rankedpvp_matchlost,\
+rankedpvp_kills,rankedpvp_death";
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueSummary {
+    pub kills: i32,
+    pub deaths: i32,
+    pub kd: f32,
+    pub wins: i32,
+    pub losses: i32,
+    pub win_rate: f32, // percent
+    pub time_played_secs: i32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SummaryStats {
+    pub ranked: QueueSummary,
+    pub casual: QueueSummary,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerCard {
+    pub profile: Profile,
+    pub level: Option<i32>,
+    pub xp: Option<i32>,
+    pub ranks: HashMap<String, PlayerStats>, // by region
+    pub summary: Option<SummaryStats>,
+    pub missing: Vec<String>, // parts that could not be fetched
+}
+
+pub fn ratio(numerator: i32, denominator: i32) -> f32 {
+    if denominator == 0 {
+        numerator as f32
+    } else {
+        numerator as f32 / denominator as f32
+    }
+}
+
+impl QueueSummary {
+    pub fn new(kills: i32, deaths: i32, wins: i32, losses: i32, time_played_secs: i32) -> Self {
+        QueueSummary {
+            kills,
+            deaths,
+            kd: ratio(kills, deaths),
+            wins,
+            losses,
+            win_rate: 100.0 * ratio(wins, wins + losses).min(1.0),
+            time_played_secs,
+        }
+    }
+}
+
+fn queue_summary(stats: &HashMap<String, i32>, queue: &str) -> QueueSummary {
+    let stat = |name: &str| {
+        stats
+            .get(&format!("{}_{}:infinite", queue, name))
+            .copied()
+            .unwrap_or(0)
+    };
+
+    QueueSummary::new(stat("kills"), stat("death"), stat("matchwon"), stat("matchlost"), stat("timeplayed"))
+}
+
+impl SummaryStats {
+    pub fn from_statistics(statistics: &PopulationsStatistics, profile_id: &str) -> Option<Self> {
+        let stats = statistics.results.get(profile_id)?;
+        Some(SummaryStats {
+            ranked: queue_summary(stats, "rankedpvp"),
+            casual: queue_summary(stats, "casualpvp"),
+        })
+    }
+}
+
+impl UbiApi {
+    /// resolves `name` on `platform` and builds its card
+    pub async fn player_card(&self, platform: &str, name: &str) -> AppResult<PlayerCard> {
+        let profile = self.resolve_profile(platform, name).await?;
+
+        self.player_card_for(profile).await
+    }
+
+    /// first profile named `name` on `platform`
+    pub async fn resolve_profile(&self, platform: &str, name: &str) -> AppResult<Profile> {
+        self.find_profile(name.to_string(), platform.to_string())
+            .await?
+            .profiles
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                AppError::NOT_FOUND.message(format!("Profile {:?} not found on platform {:?}", name, platform))
+            })
+    }
+
+    /// the profile `profile_id` has on `platform`
+    pub async fn resolve_profile_id(&self, platform: &str, profile_id: &str) -> AppResult<Profile> {
+        self.find_profiles_by_id(profile_id)
+            .await?
+            .profiles
+            .into_iter()
+            .find(|profile| profile.platform_type == platform && profile.profile_id.eq_ignore_ascii_case(profile_id))
+            .ok_or_else(|| {
+                AppError::NOT_FOUND.message(format!("Profile {:?} not found on platform {:?}", profile_id, platform))
+            })
+    }
+
+    /// card for an already resolved profile
+    pub async fn player_card_for(&self, profile: Profile) -> AppResult<PlayerCard> {
+        let platform = profile.platform_type.as_str();
+        let profile_id = profile.profile_id.as_str();
+
+        let ranks = join_all(
+            REGIONS
+                .iter()
+                .map(|region| self.find_rank_stats(profile_id.to_string(), region.to_string(), platform)),
+        );
+        let xp = self.find_player_xp_profiles(profile_id.to_string(), platform);
+        let statistics = self.find_populations_statistics(profile_id, platform, SUMMARY_STATISTICS);
+        let (ranks, xp, statistics) = futures::join!(ranks, xp, statistics);
+
+        let mut missing = Vec::new();
+        let mut card_ranks = HashMap::new();
+        for (region, rank) in REGIONS.iter().zip(ranks) {
+            match rank {
+                Ok(stats) => {
+                    card_ranks.insert(region.to_string(), stats);
+                }
+                Err(e) => {
+                    debug!("Player card rank {} failed. {}", region, e);
+                    missing.push(format!("rank_{}", region));
+                }
+            }
+        }
+
+        let player_xp = match xp {
+            Ok(profiles) => profiles.player_profiles.into_iter().next(),
+            Err(e) => {
+                debug!("Player card xp failed. {}", e);
+                None
+            }
+        };
+        if player_xp.is_none() {
+            missing.push("level".to_string());
+        }
+
+        let summary = match statistics {
+            Ok(statistics) => SummaryStats::from_statistics(&statistics, profile_id),
+            Err(e) => {
+                debug!("Player card statistics failed. {}", e);
+                None
+            }
+        };
+        if summary.is_none() {
+            missing.push("summary".to_string());
+        }
+
+        Ok(PlayerCard {
+            level: player_xp.as_ref().map(|xp| xp.level),
+            xp: player_xp.as_ref().map(|xp| xp.xp),
+            ranks: card_ranks,
+            summary,
+            missing,
+            profile,
+        })
+    }
+}
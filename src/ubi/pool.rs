@@ -0,0 +1,430 @@
+//ubi account pool
+// several Ubisoft service accounts share the outbound load. Each one keeps
+// its own session, accounts answering 401/429 are quarantined for a while
+
+use crate::config::UbiPoolConfig;
+use crate::errors::AppError;
+use crate::handlers::AppResult;
+use chrono::{DateTime, Utc};
+use futures::channel::oneshot;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    RoundRobin,
+    LeastLoaded,
+}
+
+impl Strategy {
+    fn from_config(strategy: &str) -> Self {
+        match strategy {
+            "least_loaded" => Strategy::LeastLoaded,
+            _ => Strategy::RoundRobin,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ServiceAccount {
+    email: String,
+    password: String,
+    authorization: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+    refreshing: bool,
+    in_flight: usize,
+    requests: u64,
+    failures: u64,
+    consecutive_quarantines: u32,
+    quarantined_until: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl ServiceAccount {
+    fn quarantined(&self, now: Instant) -> bool {
+        matches!(self.quarantined_until, Some(until) if until > now)
+    }
+
+    fn session_valid(&self) -> bool {
+        self.authorization.is_some() && matches!(self.expires_at, Some(expires_at) if expires_at > Utc::now())
+    }
+}
+
+/// per account view for the admin endpoint, never includes credentials
+#[derive(Debug, Serialize)]
+pub struct AccountHealth {
+    pub email: String,
+    pub status: &'static str, // healthy | expiring | no_session | quarantined
+    pub expires_at: Option<DateTime<Utc>>,
+    pub quarantined_for_secs: Option<u64>,
+    pub in_flight: usize,
+    pub requests: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+}
+
+/// credentials handed out when a lease has no usable session yet
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug)]
+struct State {
+    accounts: Vec<ServiceAccount>,
+    next: usize,
+    refresh_waiters: Vec<oneshot::Sender<()>>, // woken whenever a refresh ends
+}
+
+impl State {
+    fn refresh_done(&mut self) {
+        for waiter in self.refresh_waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+}
+
+enum Checkout {
+    Lease(Lease),
+    Wait(oneshot::Receiver<()>),
+}
+
+/// AccountPool is shared by every UbiApi clone
+#[derive(Debug, Clone)]
+pub struct AccountPool {
+    config: UbiPoolConfig,
+    strategy: Strategy,
+    state: Arc<Mutex<State>>,
+}
+
+/// one in-flight use of an account, released on drop
+pub struct Lease {
+    pool: AccountPool,
+    index: usize,
+    pub email: String,
+    /// `None` means the caller has to open a session first
+    pub authorization: Option<String>,
+    refreshing: bool,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut state = self.pool.lock();
+        if let Some(account) = state.accounts.get_mut(self.index) {
+            account.in_flight -= 1;
+            // an abandoned refresh must not block the account forever
+            if self.refreshing {
+                account.refreshing = false;
+                state.refresh_done();
+            }
+        }
+    }
+}
+
+impl Lease {
+    /// credentials to (re)open this account's session, marks it as refreshing
+    pub fn begin_refresh(&mut self) -> Credentials {
+        self.refreshing = true;
+        let mut state = self.pool.lock();
+        let account = &mut state.accounts[self.index];
+        account.refreshing = true;
+
+        Credentials {
+            email: account.email.clone(),
+            password: account.password.clone(),
+        }
+    }
+
+    pub fn session_opened(&mut self, authorization: String, expires_at: Option<DateTime<Utc>>) {
+        self.pool.set_session(self.index, authorization.clone(), expires_at);
+        self.authorization = Some(authorization);
+        self.refreshing = false;
+    }
+
+    pub fn session_failed(&mut self, reason: String) {
+        self.pool.quarantine(self.index, reason, true);
+        self.refreshing = false;
+    }
+
+    /// feeds the upstream status code back into the account health
+    pub fn report(&self, status: u16) {
+        let mut state = self.pool.lock();
+        let account = &mut state.accounts[self.index];
+        account.requests += 1;
+        drop(state);
+
+        match status {
+            401 => self
+                .pool
+                .quarantine(self.index, "Session rejected (401)".to_string(), true),
+            429 => self
+                .pool
+                .quarantine(self.index, "Throttled by Ubisoft (429)".to_string(), false),
+            _ => {
+                let mut state = self.pool.lock();
+                state.accounts[self.index].consecutive_quarantines = 0;
+            }
+        }
+    }
+}
+
+impl AccountPool {
+    pub fn new(config: UbiPoolConfig) -> Self {
+        AccountPool {
+            strategy: Strategy::from_config(&config.strategy),
+            config,
+            state: Arc::new(Mutex::new(State {
+                accounts: Vec::new(),
+                next: 0,
+                refresh_waiters: Vec::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("ubi account pool lock poisoned")
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().accounts.len()
+    }
+
+    pub fn emails(&self) -> Vec<String> {
+        self.lock().accounts.iter().map(|account| account.email.clone()).collect()
+    }
+
+    /// adds an account, a known email only gets its password updated
+    pub fn add(&self, email: &str, password: &str) {
+        let mut state = self.lock();
+        match state.accounts.iter_mut().find(|account| account.email == email) {
+            Some(account) => account.password = password.to_string(),
+            None => state.accounts.push(ServiceAccount {
+                email: email.to_string(),
+                password: password.to_string(),
+                authorization: None,
+                expires_at: None,
+                refreshing: false,
+                in_flight: 0,
+                requests: 0,
+                failures: 0,
+                consecutive_quarantines: 0,
+                quarantined_until: None,
+                last_error: None,
+            }),
+        }
+    }
+
+    /// lease on a specific account, used at startup to open every session
+    pub fn lease_by_email(&self, email: &str) -> Option<Lease> {
+        let mut state = self.lock();
+        let index = state.accounts.iter().position(|account| account.email == email)?;
+        Some(self.lease(&mut state, index))
+    }
+
+    /// picks an account that is not quarantined. A session close to expiry
+    /// comes back without authorization, the caller renews it. Meanwhile the
+    /// account is leased with its old session while that is still valid,
+    /// otherwise checkouts wait for the refresh to end
+    pub async fn checkout(&self) -> AppResult<Lease> {
+        loop {
+            match self.try_checkout()? {
+                Checkout::Lease(lease) => return Ok(lease),
+                Checkout::Wait(refreshed) => {
+                    debug!("Waiting for a Ubisoft session refresh.");
+                    // a dropped sender ends the wait as well
+                    let _ = refreshed.await;
+                }
+            }
+        }
+    }
+
+    fn try_checkout(&self) -> AppResult<Checkout> {
+        let now = Instant::now();
+        let refresh_before = chrono::Duration::seconds(self.config.refresh_before_secs);
+        let mut state = self.lock();
+
+        let available: Vec<usize> = state
+            .accounts
+            .iter()
+            .enumerate()
+            .filter(|(_, account)| !account.quarantined(now) && (!account.refreshing || account.session_valid()))
+            .map(|(index, _)| index)
+            .collect();
+
+        let index = match self.strategy {
+            Strategy::RoundRobin => {
+                let start = state.next;
+                let count = state.accounts.len().max(1);
+                let index = available
+                    .iter()
+                    .copied()
+                    .min_by_key(|index| (index + count - start % count) % count);
+                if let Some(index) = index {
+                    state.next = index + 1;
+                }
+                index
+            }
+            Strategy::LeastLoaded => available.iter().copied().min_by_key(|index| {
+                let account = &state.accounts[*index];
+                (account.in_flight, account.requests)
+            }),
+        };
+        let index = match index {
+            Some(index) => index,
+            None if state.accounts.iter().any(|account| account.refreshing && !account.quarantined(now)) => {
+                let (sender, receiver) = oneshot::channel();
+                state.refresh_waiters.push(sender);
+                return Ok(Checkout::Wait(receiver));
+            }
+            None => {
+                debug!("No Ubisoft service account available.");
+                return Err(AppError::UPSTREAM_BUSY.default());
+            }
+        };
+
+        let mut lease = self.lease(&mut state, index);
+        let account = &mut state.accounts[index];
+        let fresh = matches!(account.expires_at, Some(expires_at) if expires_at - refresh_before > Utc::now());
+        if !fresh && !account.refreshing {
+            // marked under the same lock, so only this lease renews it
+            account.refreshing = true;
+            lease.refreshing = true;
+            lease.authorization = None;
+        }
+        Ok(Checkout::Lease(lease))
+    }
+
+    fn lease(&self, state: &mut State, index: usize) -> Lease {
+        let account = &mut state.accounts[index];
+        account.in_flight += 1;
+        Lease {
+            pool: self.clone(),
+            index,
+            email: account.email.clone(),
+            authorization: account.authorization.clone(),
+            refreshing: false,
+        }
+    }
+
+    fn set_session(&self, index: usize, authorization: String, expires_at: Option<DateTime<Utc>>) {
+        let mut state = self.lock();
+        let account = &mut state.accounts[index];
+        account.authorization = Some(authorization);
+        account.expires_at = expires_at;
+        account.refreshing = false;
+        account.quarantined_until = None;
+        account.last_error = None;
+        state.refresh_done();
+    }
+
+    // quarantine doubles for every consecutive incident, capped
+    fn quarantine(&self, index: usize, reason: String, drop_session: bool) {
+        let mut state = self.lock();
+        let account = &mut state.accounts[index];
+
+        let factor = 1u64 << account.consecutive_quarantines.min(16);
+        let secs = self
+            .config
+            .quarantine_secs
+            .saturating_mul(factor)
+            .min(self.config.max_quarantine_secs);
+
+        info!("Quarantining Ubisoft account {} for {}s: {}", account.email, secs, reason);
+        account.consecutive_quarantines += 1;
+        account.failures += 1;
+        account.refreshing = false;
+        account.quarantined_until = Some(Instant::now() + Duration::from_secs(secs));
+        account.last_error = Some(reason);
+        if drop_session {
+            account.authorization = None;
+            account.expires_at = None;
+        }
+        state.refresh_done();
+    }
+
+    pub fn health(&self) -> Vec<AccountHealth> {
+        let now = Instant::now();
+        let refresh_before = chrono::Duration::seconds(self.config.refresh_before_secs);
+        let state = self.lock();
+
+        state
+            .accounts
+            .iter()
+            .map(|account| {
+                let status = match (account.quarantined(now), account.expires_at) {
+                    (true, _) => "quarantined",
+                    (false, Some(expires_at)) if expires_at - refresh_before > Utc::now() => "healthy",
+                    (false, Some(expires_at)) if expires_at > Utc::now() => "expiring",
+                    _ => "no_session",
+                };
+                AccountHealth {
+                    email: account.email.clone(),
+                    status,
+                    expires_at: account.expires_at,
+                    quarantined_for_secs: account
+                        .quarantined_until
+                        .filter(|until| *until > now)
+                        .map(|until| (until - now).as_secs()),
+                    in_flight: account.in_flight,
+                    requests: account.requests,
+                    failures: account.failures,
+                    last_error: account.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    fn pool_with_account() -> AccountPool {
+        let pool = AccountPool::new(UbiPoolConfig::default());
+        pool.add("service@example.com", "password");
+        pool
+    }
+
+    #[actix_rt::test]
+    async fn checkouts_wait_for_the_running_refresh() {
+        let pool = pool_with_account();
+        let mut refresher = pool.checkout().await.unwrap();
+        assert!(refresher.authorization.is_none());
+
+        let mut waiting = Box::pin(pool.checkout());
+        assert!((&mut waiting).now_or_never().is_none());
+
+        refresher.begin_refresh();
+        refresher.session_opened("token".to_string(), Some(Utc::now() + chrono::Duration::hours(3)));
+        let lease = waiting.await.unwrap();
+        assert_eq!(lease.authorization.as_deref(), Some("token"));
+    }
+
+    #[actix_rt::test]
+    async fn a_failed_refresh_ends_the_wait() {
+        let pool = pool_with_account();
+        let mut refresher = pool.checkout().await.unwrap();
+        let mut waiting = Box::pin(pool.checkout());
+        assert!((&mut waiting).now_or_never().is_none());
+
+        refresher.begin_refresh();
+        refresher.session_failed("Login failed".to_string());
+        assert!(waiting.await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn an_abandoned_refresh_is_taken_over() {
+        let pool = pool_with_account();
+        let refresher = pool.checkout().await.unwrap();
+        let mut waiting = Box::pin(pool.checkout());
+        assert!((&mut waiting).now_or_never().is_none());
+
+        drop(refresher);
+        let lease = waiting.await.unwrap();
+        assert!(lease.authorization.is_none());
+    }
+}
@@ -0,0 +1,414 @@
+//ubi scheduler
+// every request to Ubisoft passes through here: a global rate, a budget per
+// endpoint and two priority lanes with bounded queues and timeouts. Waiters
+// are granted in arrival order, interactive ones before any background one
+
+use crate::config::UbiSchedulerConfig;
+use crate::errors::AppError;
+use crate::handlers::AppResult;
+use serde::Serialize;
+use futures::channel::oneshot;
+use futures::future::{select, Either};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Interactive, // a user is waiting for the answer
+    Background,  // tracking
+}
+
+// lanes in the order they are served
+const LANES: [Priority; 2] = [Priority::Interactive, Priority::Background];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endpoint {
+    Sessions,
+    Profiles,
+    RankStats,
+    PlayerXp,
+    Statistics,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, per_second: f64) -> Self {
+        TokenBucket {
+            capacity: capacity.max(1.0),
+            per_second: per_second.max(0.001),
+            tokens: capacity.max(1.0),
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    // time until one token is available
+    fn wait(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.per_second)
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LaneMetrics {
+    pub queue_depth: usize,
+    pub max_queue_depth: usize,
+    pub granted: u64,
+    pub rejected_queue_full: u64,
+    pub timed_out: u64,
+    pub average_wait_ms: u64,
+    #[serde(skip)]
+    total_wait_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SchedulerMetrics {
+    pub requests_per_second: f64,
+    pub lanes: HashMap<Priority, LaneMetrics>,
+    pub endpoint_tokens: HashMap<Endpoint, f64>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    endpoint: Endpoint,
+    queued_at: Instant,
+    grant: oneshot::Sender<()>,
+}
+
+#[derive(Debug)]
+struct State {
+    global: TokenBucket,
+    endpoints: HashMap<Endpoint, TokenBucket>,
+    lanes: HashMap<Priority, LaneMetrics>,
+    queues: HashMap<Priority, VecDeque<Waiter>>,
+    next_waiter: u64,
+}
+
+impl State {
+    /// hands out the available tokens to the queued waiters in order, a waiter
+    /// whose endpoint budget is spent does not hold up later ones on other
+    /// endpoints. Background waiters only run once no interactive one waits.
+    /// Returns how long until the next waiter could be granted
+    fn dispatch(&mut self, now: Instant) -> Duration {
+        self.global.refill(now);
+        for bucket in self.endpoints.values_mut() {
+            bucket.refill(now);
+        }
+
+        for priority in LANES.iter() {
+            let queue = self.queues.entry(*priority).or_default();
+            let mut index = 0;
+            while index < queue.len() && self.global.tokens >= 1.0 {
+                let endpoint = self
+                    .endpoints
+                    .get_mut(&queue[index].endpoint)
+                    .expect("all endpoints have a budget");
+                if endpoint.tokens < 1.0 {
+                    index += 1;
+                    continue;
+                }
+
+                let waiter = queue.remove(index).expect("index is in the queue");
+                // the receiver is gone when the request was dropped meanwhile
+                if waiter.grant.send(()).is_ok() {
+                    endpoint.tokens -= 1.0;
+                    self.global.tokens -= 1.0;
+
+                    let lane = self.lanes.entry(*priority).or_default();
+                    lane.granted += 1;
+                    lane.total_wait_ms += now.duration_since(waiter.queued_at).as_millis() as u64;
+                    lane.average_wait_ms = lane.total_wait_ms / lane.granted;
+                }
+                self.lanes.entry(*priority).or_default().queue_depth = queue.len();
+            }
+
+            if !queue.is_empty() {
+                break;
+            }
+        }
+
+        let global_wait = self.global.wait();
+        let endpoints = &self.endpoints;
+        self.queues
+            .values()
+            .flatten()
+            .map(|waiter| endpoints[&waiter.endpoint].wait().max(global_wait))
+            .min()
+            .unwrap_or(global_wait)
+    }
+
+    fn leave(&mut self, priority: Priority, id: u64) {
+        let queue = self.queues.entry(priority).or_default();
+        queue.retain(|waiter| waiter.id != id);
+        self.lanes.entry(priority).or_default().queue_depth = queue.len();
+    }
+}
+
+/// UbiScheduler is shared by every UbiApi clone
+#[derive(Debug, Clone)]
+pub struct UbiScheduler {
+    config: UbiSchedulerConfig,
+    state: Arc<Mutex<State>>,
+}
+
+// leaves the queue when the waiting request is rejected or dropped
+struct QueueSlot<'a> {
+    scheduler: &'a UbiScheduler,
+    priority: Priority,
+    id: u64,
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.scheduler.lock().leave(self.priority, self.id);
+    }
+}
+
+impl UbiScheduler {
+    pub fn new(config: UbiSchedulerConfig) -> Self {
+        let per_minute = |budget: u32| TokenBucket::new(budget as f64, budget as f64 / 60.0);
+        let budgets = &config.endpoint_budgets;
+
+        let mut endpoints = HashMap::new();
+        endpoints.insert(Endpoint::Sessions, per_minute(budgets.sessions));
+        endpoints.insert(Endpoint::Profiles, per_minute(budgets.profiles));
+        endpoints.insert(Endpoint::RankStats, per_minute(budgets.rank_stats));
+        endpoints.insert(Endpoint::PlayerXp, per_minute(budgets.player_xp));
+        endpoints.insert(Endpoint::Statistics, per_minute(budgets.statistics));
+
+        let lanes = LANES.iter().map(|priority| (*priority, LaneMetrics::default())).collect();
+        let queues = LANES.iter().map(|priority| (*priority, VecDeque::new())).collect();
+
+        let state = State {
+            global: TokenBucket::new(config.burst as f64, config.requests_per_second),
+            endpoints,
+            lanes,
+            queues,
+            next_waiter: 0,
+        };
+
+        UbiScheduler {
+            config,
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("ubi scheduler lock poisoned")
+    }
+
+    fn lane_limits(&self, priority: Priority) -> (usize, Duration) {
+        match priority {
+            Priority::Interactive => (
+                self.config.interactive_queue,
+                Duration::from_millis(self.config.interactive_timeout_ms),
+            ),
+            Priority::Background => (
+                self.config.background_queue,
+                Duration::from_millis(self.config.background_timeout_ms),
+            ),
+        }
+    }
+
+    /// waits for a slot to call `endpoint`. Fails fast when the lane queue is
+    /// full and gives up after the lane timeout
+    pub async fn acquire(&self, endpoint: Endpoint, priority: Priority) -> AppResult<()> {
+        let (max_queue, timeout) = self.lane_limits(priority);
+        let started = Instant::now();
+        let (grant, mut granted) = oneshot::channel();
+
+        let id = {
+            let mut state = self.lock();
+            let queue = state.queues.entry(priority).or_default();
+            if queue.len() >= max_queue {
+                state.lanes.entry(priority).or_default().rejected_queue_full += 1;
+                debug!("Ubi {:?} queue full, rejecting {:?}", priority, endpoint);
+                return Err(AppError::UPSTREAM_BUSY.default());
+            }
+
+            let id = state.next_waiter;
+            state.next_waiter += 1;
+            let queue = state.queues.entry(priority).or_default();
+            queue.push_back(Waiter {
+                id,
+                endpoint,
+                queued_at: started,
+                grant,
+            });
+            let depth = queue.len();
+            let lane = state.lanes.entry(priority).or_default();
+            lane.queue_depth = depth;
+            lane.max_queue_depth = lane.max_queue_depth.max(depth);
+            id
+        };
+        let _slot = QueueSlot {
+            scheduler: self,
+            priority,
+            id,
+        };
+
+        loop {
+            // every waiter wakes when the next token is due and dispatches for
+            // the whole queue, the ones granted are woken through their channel
+            let wait = self.lock().dispatch(Instant::now());
+            if let Ok(Some(())) = granted.try_recv() {
+                return Ok(());
+            }
+
+            let remaining = timeout.checked_sub(started.elapsed()).unwrap_or_default();
+            if remaining == Duration::from_secs(0) {
+                let mut state = self.lock();
+                state.lanes.entry(priority).or_default().timed_out += 1;
+                debug!("Ubi {:?} request to {:?} timed out in queue", priority, endpoint);
+                return Err(AppError::UPSTREAM_BUSY.default());
+            }
+
+            let delay = actix_rt::time::delay_for(wait.max(Duration::from_millis(1)).min(remaining));
+            if let Either::Left((Ok(()), _)) = select(&mut granted, delay).await {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> SchedulerMetrics {
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        let now = Instant::now();
+
+        let endpoint_tokens = state
+            .endpoints
+            .iter_mut()
+            .map(|(endpoint, bucket)| {
+                bucket.refill(now);
+                (*endpoint, bucket.tokens.floor())
+            })
+            .collect();
+
+        SchedulerMetrics {
+            requests_per_second: self.config.requests_per_second,
+            lanes: state.lanes.clone(),
+            endpoint_tokens,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EndpointBudgets;
+    use actix_web::{http::StatusCode, ResponseError};
+
+    // `burst` requests right away, next to no refill afterwards
+    fn scheduler(burst: u32) -> UbiScheduler {
+        UbiScheduler::new(UbiSchedulerConfig {
+            requests_per_second: 0.001,
+            burst,
+            endpoint_budgets: EndpointBudgets {
+                sessions: 1,
+                ..EndpointBudgets::default()
+            },
+            interactive_queue: 1,
+            interactive_timeout_ms: 50,
+            ..UbiSchedulerConfig::default()
+        })
+    }
+
+    fn enqueue(state: &mut State, priority: Priority, endpoint: Endpoint) -> oneshot::Receiver<()> {
+        let (grant, granted) = oneshot::channel();
+        let id = state.next_waiter;
+        state.next_waiter += 1;
+        state.queues.entry(priority).or_default().push_back(Waiter {
+            id,
+            endpoint,
+            queued_at: Instant::now(),
+            grant,
+        });
+        granted
+    }
+
+    fn is_granted(granted: &mut oneshot::Receiver<()>) -> bool {
+        matches!(granted.try_recv(), Ok(Some(())))
+    }
+
+    #[test]
+    fn waiters_are_granted_in_arrival_order() {
+        let scheduler = scheduler(3);
+        let mut state = scheduler.lock();
+        let mut first = enqueue(&mut state, Priority::Interactive, Endpoint::Sessions);
+        let mut second = enqueue(&mut state, Priority::Interactive, Endpoint::Sessions);
+        let mut third = enqueue(&mut state, Priority::Interactive, Endpoint::Profiles);
+        let mut fourth = enqueue(&mut state, Priority::Interactive, Endpoint::Profiles);
+        let mut fifth = enqueue(&mut state, Priority::Interactive, Endpoint::Profiles);
+
+        state.dispatch(Instant::now());
+        // the sessions budget is spent after the first, the later profiles go ahead
+        assert!(is_granted(&mut first));
+        assert!(!is_granted(&mut second));
+        assert!(is_granted(&mut third));
+        assert!(is_granted(&mut fourth));
+        assert!(!is_granted(&mut fifth));
+        assert_eq!(state.lanes[&Priority::Interactive].granted, 3);
+        assert_eq!(state.queues[&Priority::Interactive].len(), 2);
+    }
+
+    #[test]
+    fn interactive_waiters_go_before_background_ones() {
+        let scheduler = scheduler(1);
+        let mut state = scheduler.lock();
+        let mut background = enqueue(&mut state, Priority::Background, Endpoint::Profiles);
+        let mut interactive = enqueue(&mut state, Priority::Interactive, Endpoint::Profiles);
+
+        state.dispatch(Instant::now());
+        assert!(is_granted(&mut interactive));
+        assert!(!is_granted(&mut background));
+        assert_eq!(state.queues[&Priority::Background].len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn full_queues_reject_and_waiters_time_out() {
+        let scheduler = scheduler(1);
+        scheduler.acquire(Endpoint::Profiles, Priority::Interactive).await.unwrap();
+
+        // the second waits for a token that does not come in time, the third
+        // finds the queue of one taken
+        let (second, third) = futures::join!(
+            scheduler.acquire(Endpoint::Profiles, Priority::Interactive),
+            scheduler.acquire(Endpoint::Profiles, Priority::Interactive),
+        );
+        for result in [second, third].iter() {
+            let error = result.as_ref().unwrap_err();
+            assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let lane = &scheduler.metrics().lanes[&Priority::Interactive];
+        assert_eq!(lane.granted, 1);
+        assert_eq!(lane.rejected_queue_full, 1);
+        assert_eq!(lane.timed_out, 1);
+        assert_eq!(lane.queue_depth, 0);
+        assert_eq!(lane.max_queue_depth, 1);
+
+        // background requests have their own queue
+        let background = scheduler.acquire(Endpoint::Profiles, Priority::Background);
+        assert!(futures::FutureExt::now_or_never(background).is_none());
+    }
+}
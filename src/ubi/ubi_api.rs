@@ -1,13 +1,14 @@
-use crate::{config::UbiConfig, errors::AppError, handlers::AppResponse, handlers::AppResult};
-use crate::models::ubi_user::{NewUbiUser, UbiUser, UpdateUbiUser};
+use crate::{config::UbiConfig, errors::AppError, handlers::AppResult};
+use crate::models::ubi_user::{UbiSessionHistory, UbiUser, UbiUserSession, SESSION_LOGIN, SESSION_RENEW};
 use crate::UbiUserRepository;
-use crate::db;
-use actix_web::HttpResponse;
+use super::pool::{AccountPool, Credentials, Lease};
+use super::scheduler::{Endpoint, Priority, UbiScheduler};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 // use serde_json::json;
 use std::collections::HashMap;
-use sqlx::{error::DatabaseError, postgres::PgError};
-use tracing::{debug, info};
+use std::sync::Arc;
+use tracing::{debug, error, info};
 use reqwest::header::{
     HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONNECTION, CONTENT_LENGTH, CONTENT_TYPE,
     USER_AGENT, REFERER,
@@ -22,7 +23,7 @@ pub struct Session {
     pub profile_id: String,
     pub user_id: String,
     pub name_on_platform: String,
-    pub expiration: String, //2020-08-26T16:46:59.4772040Z
+    pub expiration: DateTime<Utc>, //2020-08-26T16:46:59.4772040Z
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -52,10 +53,10 @@ pub struct PopulationsStatistics {
 }
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PlayerXp {
-    xp: i32,
-    profile_id: String,
-    lootbox_probability: i32,
-    level: i32,
+    pub xp: i32,
+    pub profile_id: String,
+    pub lootbox_probability: i32,
+    pub level: i32,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -91,143 +92,161 @@ pub struct PlayerStats {
 
 #[derive(Clone)]
 pub struct UbiApi {
-    email: String,
-    password: String,
     pub ubi_config: UbiConfig,
-    pub expiration: String,
-    pub authorization: String,
     client: reqwest::Client,
+    repository: Arc<UbiUserRepository>,
+    pub scheduler: UbiScheduler,
+    pub accounts: AccountPool,
+    priority: Priority,
 }
 
 impl UbiApi {
     pub fn new(
-        email: &str,
-        password: &str,
         reqwest_client: reqwest::Client,
         ubi_config: UbiConfig,
+        repository: UbiUserRepository,
     ) -> Self {
+        let scheduler = UbiScheduler::new(ubi_config.scheduler.clone());
+        let accounts = AccountPool::new(ubi_config.pool.clone());
         UbiApi {
-            email: email.to_string(),
-            password: password.to_string(),
             ubi_config,
             client: reqwest_client,
-            authorization: String::from(""),
-            expiration: String::from(""),
+            repository: Arc::new(repository),
+            scheduler,
+            accounts,
+            priority: Priority::Interactive,
+        }
+    }
+
+    /// clone sending its requests on another scheduler lane
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        UbiApi {
+            priority,
+            ..self.clone()
         }
     }
 
-    pub fn prefix_authorization(&mut self, token: String, expiration: String) {
-        self.authorization = format!(
+    fn prefix_authorization(&self, token: String) -> String {
+        format!(
             "{}{}",
             self.ubi_config.authorization_prefix.clone(),
             token
-        );
-        self.expiration = expiration;
+        )
     }
 
-    pub async fn login(&mut self, repository: UbiUserRepository) -> AppResponse {
-
-        let user_result = repository
-            .find_by_email(self.email.as_str())
-            .await?
-            .ok_or_else(|| {
-                debug!("User doesn't exist.");
-                AppError::INVALID_CREDENTIALS
-            });
-
-        if let Ok(user) = user_result {
-            info!("Found existing UBI login");
-
-            //check expiry
-            let expiry = chrono::DateTime::parse_from_rfc3339(user.expiration.as_str()).unwrap();
-            if expiry > chrono::Utc::now() {
-                //update login if not expired
-                info!("Token not expired, updating login :)");
-                self.update_login(user.clone(), repository).await?;
-                return Ok(HttpResponse::Ok().json(user));
+    /// builds the pool from `AUTH.*` and the ubi_users rows marked as pool
+    /// members, then opens all sessions. Fails only when none of them works
+    pub async fn login(&self, email: &str, password: &str) -> AppResult<()> {
+        self.accounts.add(email, password);
+        for stored in self.repository.find_pool_members().await? {
+            self.accounts.add(&stored.email, &stored.password);
+        }
+
+        let mut opened = 0;
+        for email in self.accounts.emails() {
+            if let Some(mut lease) = self.accounts.lease_by_email(&email) {
+                match self.open_session(&mut lease).await {
+                    Ok(_) => opened += 1,
+                    Err(e) => error!("UBI login failed for {}. {}", email, e),
+                }
+            }
+        }
+
+        info!("UBI sessions open for {} of {} accounts", opened, self.accounts.len());
+        if opened == 0 {
+            return Err(AppError::INVALID_CREDENTIALS.message("No UBI account could log in.".to_string()));
+        }
+        Ok(())
+    }
+
+    /// admin: logs a new service account in and adds it to the pool
+    pub async fn add_account(&self, email: &str, password: &str) -> AppResult<UbiUser> {
+        let credentials = Credentials {
+            email: email.to_string(),
+            password: password.to_string(),
+        };
+        let user = self.start_session(&credentials).await?;
+        self.repository.set_pool_member(email, true).await?;
+
+        self.accounts.add(email, password);
+        if let Some(mut lease) = self.accounts.lease_by_email(email) {
+            lease.session_opened(user.token.clone(), Some(user.expiration));
+        }
+        Ok(user)
+    }
+
+    /// admin: latest sessions issued for one pooled account
+    pub async fn session_history(&self, email: &str, limit: i64) -> AppResult<Vec<UbiSessionHistory>> {
+        Ok(self.repository.find_history(email, limit).await?)
+    }
+
+    // (re)opens the session of a leased account, quarantining it on failure
+    async fn open_session(&self, lease: &mut Lease) -> AppResult<()> {
+        let credentials = lease.begin_refresh();
+
+        match self.start_session(&credentials).await {
+            Ok(user) => {
+                lease.session_opened(user.token, Some(user.expiration));
+                Ok(())
+            }
+            Err(e) => {
+                lease.session_failed(format!("Login failed: {}", e));
+                Err(e)
+            }
+        }
+    }
+
+    // renews the stored session while it is valid, otherwise logs in again.
+    // Always on the interactive lane, every queued request may be waiting for it
+    async fn start_session(&self, credentials: &Credentials) -> AppResult<UbiUser> {
+        let stored = self.repository.find_by_email(credentials.email.as_str()).await?;
+
+        if let Some(user) = stored {
+            if user.expiration > Utc::now() {
+                info!("Renewing stored UBI session for {}", user.email);
+                match self.ping_me(&user, credentials).await {
+                    Ok(user) => return Ok(user),
+                    Err(e) => debug!("Renewing UBI session failed, logging in again. {}", e),
+                }
             }
-        
-            info!("Token expired, lets login again!");
-            //you reached here, means token expired
-            //let's login now below..
-            //but before that lets delete DB entry for existing user
-            repository.delete_by_id(user.id).await?;
         }
-        
-        // return Ok(HttpResponse::Ok().json(UbiUser {
-        //     id: uuid::Uuid::nil(),
-        //     email: "ajinkya@test.com".to_string(),
-        //     password: "12345".to_string(),
-        //     token: "sadad".to_string(),
-        //     expiration: "soon".to_string(),
-        //     created_at: chrono::NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
-        //     updated_at: chrono::NaiveDate::from_ymd(2016, 7, 8).and_hms(9, 10, 11),
-        // }));
 
         let mut post_payload = HashMap::new();
         post_payload.insert("rememberMe".to_string(), true);
 
         let request_url = "https://public-ubiservices.ubi.com/v3/profiles/sessions";
+        self.scheduler.acquire(Endpoint::Sessions, Priority::Interactive).await?;
         let response = self
             .client
             .post(request_url)
             .json(&post_payload)
-            .headers(self.construct_headers(true))
-            .basic_auth(self.email.clone(), Some(self.password.clone()))
+            .headers(self.construct_headers(None))
+            .basic_auth(credentials.email.clone(), Some(credentials.password.clone()))
             .send()
             .await
             .map_err(|op| {
                 debug!("Error login in to UBI session. {:?}", op);
                 AppError::INTERNAL_ERROR.default()
             })?;
+        Self::check_session_status(response.status().as_u16())?;
 
-        //let status_code = response.status().clone();
-        //println!("login status: {}", status_code);
-        // println!("BODY: {:#?}", response.text().await?);
         let body = response.json::<Session>().await.map_err(|op| {
             debug!("Error reading UBI session response. {:?}", op);
             AppError::INTERNAL_ERROR.default()
         })?;
 
-        self.prefix_authorization(body.ticket, body.expiration);
-
-        let ubi_user = NewUbiUser {
-            email: self.email.clone(),
-            password: self.password.clone(),
-            token: self.authorization.clone(),
-            expiration: self.expiration.clone(),
-        };
-        let result = repository.create(ubi_user).await;
+        self.store_session(credentials, body, SESSION_LOGIN).await
+    }
 
-        match result {
-            Ok(user) => Ok(HttpResponse::Ok().json(user)),
-            Err(e) => {
-                let pg_error: &PgError = e.root_cause().downcast_ref::<PgError>().ok_or_else(|| {
-                    debug!("Error creating user. {:?}", e);
-                    AppError::INTERNAL_ERROR
-                })?;
-    
-                let error = match (pg_error.code(), pg_error.column_name()) {
-                    (Some(db::UNIQUE_VIOLATION_CODE), Some("email")) => {
-                        AppError::INVALID_INPUT.message("Email address already exists.".to_string())
-                    }
-                    (Some(db::UNIQUE_VIOLATION_CODE), Some("username")) => {
-                        AppError::INVALID_INPUT.message("Username already exists.".to_string())
-                    }
-                    (Some(db::UNIQUE_VIOLATION_CODE), None) => {
-                        AppError::INVALID_INPUT.message("Username or email already exists.".to_string())
-                    }
-                    _ => {
-                        debug!("Error creating user. {:?}", pg_error);
-                        AppError::INTERNAL_ERROR.into()
-                    }
-                };
-                Err(error)
-            }
+    fn check_session_status(status: u16) -> AppResult<()> {
+        match status {
+            401 => Err(AppError::INVALID_CREDENTIALS.message("UBI rejected the account credentials.".to_string())),
+            429 => Err(AppError::UPSTREAM_BUSY.default()),
+            _ => Ok(()),
         }
     }
 
-    fn construct_headers(&self, login: bool) -> HeaderMap {
+    fn construct_headers(&self, authorization: Option<&str>) -> HeaderMap {
         let mut headers = HeaderMap::new();
         headers.insert(
             "ubi-appid",
@@ -245,39 +264,31 @@ impl UbiApi {
         headers.insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
         headers.insert(REFERER, HeaderValue::from_static("https://connect.ubisoft.com"));
 
-        if !login {
-            info!("HeaderValue token: {:?}", self.authorization.clone());
+        if let Some(authorization) = authorization {
             headers.insert(
                 AUTHORIZATION,
-                HeaderValue::from_str(self.authorization.as_str()).unwrap(),
+                HeaderValue::from_str(authorization).unwrap(),
             );
         }
 
         headers
     }
 
-    pub async fn update_login(&mut self, ubi_user: UbiUser, repository: UbiUserRepository) -> AppResponse {
-        self.authorization = ubi_user.token;
-        self.expiration = ubi_user.expiration;
-
-        self.ping_me(ubi_user.id, repository).await
-    }
-
-    pub async fn ping_me(&mut self, user_id: uuid::Uuid, repository: UbiUserRepository) -> AppResponse {
+    async fn ping_me(&self, ubi_user: &UbiUser, credentials: &Credentials) -> AppResult<UbiUser> {
 
         let url = "https://public-ubiservices.ubi.com/v3/profiles/sessions";
+        self.scheduler.acquire(Endpoint::Sessions, Priority::Interactive).await?;
         let response = self
             .client
             .post(url)
-            .headers(self.construct_headers(false))
+            .headers(self.construct_headers(Some(ubi_user.token.as_str())))
             .send()
             .await
             .map_err(|op| {
                 debug!("Error login in to UBI session. {:?}", op);
                 AppError::INTERNAL_ERROR.default()
             })?;
-
-        // println!("BODY: {:#?}", response.text().await);
+        Self::check_session_status(response.status().as_u16())?;
 
         let session = response.json::<Session>()
             .await
@@ -285,41 +296,62 @@ impl UbiApi {
                 debug!("Error pinging me. {:?}", op);
                 AppError::INTERNAL_ERROR.default()
             })?;
-        
-        //Ok(HttpResponse::Ok().json(body))
-
-        //add ticket authorization_prefix
-        self.prefix_authorization(session.ticket, session.expiration);
 
-        // push prefixed token to database
-        let update_user = UpdateUbiUser {
-            token: self.authorization.clone(),
-            expiration: self.expiration.clone(),
-        };
+        self.store_session(credentials, session, SESSION_RENEW).await
+    }
 
-        self.update_token_to_db(user_id, update_user, repository).await
+    // upserts the prefixed token, one ubi_users row per account
+    async fn store_session(&self, credentials: &Credentials, session: Session, kind: &'static str) -> AppResult<UbiUser> {
+        let user = self
+            .repository
+            .upsert_session(UbiUserSession {
+                email: credentials.email.clone(),
+                password: credentials.password.clone(),
+                token: self.prefix_authorization(session.ticket),
+                expiration: session.expiration,
+                kind,
+            })
+            .await?;
+
+        Ok(user)
     }
 
-    pub async fn update_token_to_db(&mut self, user_id: uuid::Uuid, update_user: UpdateUbiUser, repository: UbiUserRepository) -> AppResponse {
-        let result = repository.update_ubi_user(user_id, update_user).await;
+    /// GET through the scheduler with a pooled account. An account answering
+    /// 401/429 is quarantined and the request retried once on another one
+    async fn get(&self, endpoint: Endpoint, url: reqwest::Url) -> AppResult<reqwest::Response> {
+        let attempts = self.accounts.len().clamp(1, 2);
 
-        match result {
-            Ok(user) => Ok(HttpResponse::Ok().json(user)),
-            Err(e) => {
-                let pg_error: &PgError = e.root_cause().downcast_ref::<PgError>().ok_or_else(|| {
-                    debug!("Error updating ubi user. {:?}", e);
-                    AppError::INTERNAL_ERROR
+        for attempt in 1..=attempts {
+            // no scheduler token is spent while every account is quarantined
+            let mut lease = self.accounts.checkout().await?;
+            if lease.authorization.is_none() {
+                self.open_session(&mut lease).await?;
+            }
+            self.scheduler.acquire(endpoint, self.priority).await?;
+
+            let response = self
+                .client
+                .get(url.clone())
+                .headers(self.construct_headers(lease.authorization.as_deref()))
+                .send()
+                .await
+                .map_err(|op| {
+                    debug!("Error calling ubi_api {:?}. {:?}", endpoint, op);
+                    AppError::INTERNAL_ERROR.default()
                 })?;
-    
-                let error = match (pg_error.code(), pg_error.column_name()) {
-                    _ => {
-                        debug!("Error updating ubi user. {:?}", pg_error);
-                        AppError::INTERNAL_ERROR.into()
-                    }
-                };
-                Err(error)
+
+            let status = response.status().as_u16();
+            lease.report(status);
+            match status {
+                401 | 429 if attempt < attempts => {
+                    debug!("UBI account {} got {}, retrying on another account", lease.email, status);
+                }
+                401 | 429 => return Err(AppError::UPSTREAM_BUSY.default()),
+                _ => return Ok(response),
             }
         }
+
+        Err(AppError::UPSTREAM_BUSY.default())
     }
 
     pub async fn find_profile(&self, username: String, platform_type: String) -> AppResult<Profiles> {
@@ -336,24 +368,40 @@ impl UbiApi {
         //     AppError::INTERNAL_ERROR.default()
         // })?;
 
-        let response = self
-            .client
-            .get(url)
-            .headers(self.construct_headers(false))
-            .send()
-            .await
+        let response = self.get(Endpoint::Profiles, url).await?;
+
+        if response.status() == 404 {
+            debug!("Error profile not found");
+            return Err(AppError::NOT_FOUND.default());
+        }
+
+        // println!("BODY: {:#?}", response.text().await);
+
+        let profiles = response.json::<Profiles>().await
             .map_err(|op| {
-                debug!("Error ubi_api could not find username. {:?}", op);
+                debug!("Error parsing Profiles {:?}", op);
                 AppError::INTERNAL_ERROR.default()
             })?;
 
+        Ok(profiles)
+    }
+
+    /// profiles of every platform sharing `profile_id`
+    pub async fn find_profiles_by_id(&self, profile_id: &str) -> AppResult<Profiles> {
+        let url = reqwest::Url::parse_with_params("https://public-ubiservices.ubi.com/v3/profiles",
+            &[("profileIds", profile_id)])
+            .map_err(|op| {
+                debug!("Error parsing URL {:?}", op);
+                AppError::INTERNAL_ERROR.default()
+            })?;
+
+        let response = self.get(Endpoint::Profiles, url).await?;
+
         if response.status() == 404 {
             debug!("Error profile not found");
             return Err(AppError::NOT_FOUND.default());
         }
 
-        // println!("BODY: {:#?}", response.text().await);
-
         let profiles = response.json::<Profiles>().await
             .map_err(|op| {
                 debug!("Error parsing Profiles {:?}", op);
@@ -364,16 +412,14 @@ impl UbiApi {
     }
 
     fn get_ubi_spaces_url(&self, platform_type: &str) -> &str {
-        let base_url = match platform_type {
+        match platform_type {
 			"xbl" =>
 			"https://public-ubiservices.ubi.com/v1/spaces/98a601e5-ca91-4440-b1c5-753f601a2c90/sandboxes/OSBOR_XBOXONE_LNCH_A",
 			"psn" =>
             "https://public-ubiservices.ubi.com/v1/spaces/05bfb3f7-6c21-4c42-be1f-97a33fb5cf66/sandboxes/OSBOR_PS4_LNCH_A",
             _ =>
 			"https://public-ubiservices.ubi.com/v1/spaces/5172a557-50b5-4665-b7db-e3f2e8c5041d/sandboxes/OSBOR_PC_LNCH_A",
-        };
-
-        base_url
+        }
     }
 
     // extra stats
@@ -395,16 +441,7 @@ impl UbiApi {
             }
         )?;
 
-        let response = self
-            .client
-            .get(url)
-            .headers(self.construct_headers(false))
-            .send()
-            .await
-            .map_err(|op| {
-                debug!("Error ubi_api could not find populations statistics. {:?}", op);
-                AppError::INTERNAL_ERROR.default()
-            })?;
+        let response = self.get(Endpoint::Statistics, url).await?;
 
         // let res = response.text().await.map_err(|op| {
         //     debug!("Error parsing find_populations_statistics {:?}", op);
@@ -434,16 +471,7 @@ impl UbiApi {
             }
         )?;
 
-        let response = self
-            .client
-            .get(url)
-            .headers(self.construct_headers(false))
-            .send()
-            .await
-            .map_err(|op| {
-                debug!("Error ubi_api could not find stats. {:?}", op);
-                AppError::INTERNAL_ERROR.default()
-            })?;
+        let response = self.get(Endpoint::PlayerXp, url).await?;
 
         let body = response.json::<PlayerXpProfiles>().await
             .map_err(|op| {
@@ -466,16 +494,7 @@ impl UbiApi {
             }
         )?;
 
-        let response = self
-            .client
-            .get(url)
-            .headers(self.construct_headers(false))
-            .send()
-            .await
-            .map_err(|op| {
-                debug!("Error ubi_api could not find stats. {:?}", op);
-                AppError::INTERNAL_ERROR.default()
-            })?;
+        let response = self.get(Endpoint::RankStats, url).await?;
 
         let body = response.json::<RankStats>().await
             .map_err(|op| {
@@ -483,8 +502,10 @@ impl UbiApi {
                 AppError::INTERNAL_ERROR.default()
             })?;
         
-        let stats = body.players.get(&profile_id).expect("Profile id not found");
-        Ok(stats.to_owned())
+        body.players.get(&profile_id).cloned().ok_or_else(|| {
+            debug!("Rank stats missing profile {}", profile_id);
+            AppError::NOT_FOUND.message(format!("No rank stats for profile {:?}", profile_id))
+        })
     }
 
     //find_level
@@ -0,0 +1,159 @@
+//webhook addresses
+// webhook urls are user input, the dispatcher must never be pointed at the
+// host itself, the internal network or cloud metadata endpoints
+
+use crate::{errors::AppError, handlers::AppResult};
+use actix_web::web::block;
+use reqwest::Url;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use tracing::debug;
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local() // 169.254/16, cloud metadata
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0 // 0.0.0.0/8
+        || (a == 100 && (64..128).contains(&b)) // 100.64/10, carrier grade nat
+        || (a == 198 && (b == 18 || b == 19)) // 198.18/15, benchmarking
+        || a >= 240) // reserved
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // ::ffff:a.b.c.d mapped and 64:ff9b::a.b.c.d NAT64 reach IPv4 hosts
+    let embedded_v4 = match segments {
+        [0, 0, 0, 0, 0, 0xffff, ..] | [0x64, 0xff9b, 0, 0, 0, 0, ..] => {
+            Some(Ipv4Addr::new(
+                (segments[6] >> 8) as u8,
+                segments[6] as u8,
+                (segments[7] >> 8) as u8,
+                segments[7] as u8,
+            ))
+        }
+        _ => None,
+    };
+    if let Some(ip) = embedded_v4 {
+        return is_public_v4(&ip);
+    }
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || segments[0] & 0xfe00 == 0xfc00 // fc00::/7, unique local
+        || segments[0] & 0xffc0 == 0xfe80 // fe80::/10, link local
+        || segments[0] & 0xffc0 == 0xfec0 // fec0::/10, old site local
+        || segments[0] == 0x2001 && segments[1] == 0x0db8 // documentation
+        || segments[..6] == [0, 0, 0, 0, 0, 0]) // IPv4 compatible, deprecated
+}
+
+/// false for loopback, private, link local and other non routable addresses
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+/// resolves the host of `url` and fails unless every address is public.
+/// Checked again before each delivery, a name may resolve elsewhere later
+pub async fn ensure_public(url: &Url) -> AppResult<()> {
+    let blocked = || AppError::INVALID_INPUT.message("Webhook urls must point to a public host.".to_string());
+    let host = url
+        .host_str()
+        .ok_or_else(blocked)?
+        .trim_matches(|c| c == '[' || c == ']')
+        .to_string();
+    if host == "localhost" || host.ends_with(".localhost") {
+        return Err(blocked());
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addresses: Vec<IpAddr> = block(move || (host.as_str(), port).to_socket_addrs())
+        .await
+        .map_err(|e| {
+            debug!("Cannot resolve webhook host. {:?}", e);
+            AppError::INVALID_INPUT.message("Webhook host does not resolve.".to_string())
+        })?
+        .map(|address| address.ip())
+        .collect();
+
+    if addresses.is_empty() || !addresses.iter().all(is_public) {
+        return Err(blocked());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public(ip: &str) -> bool {
+        is_public(&ip.parse().unwrap())
+    }
+
+    #[test]
+    fn internal_v4_addresses_are_not_public() {
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "198.18.0.1",
+            "192.0.2.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+        ]
+        .iter()
+        {
+            assert!(!public(ip), "{}", ip);
+        }
+        for ip in ["1.1.1.1", "8.8.8.8", "100.128.0.1", "198.20.0.1"].iter() {
+            assert!(public(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn internal_v6_addresses_are_not_public() {
+        for ip in [
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12::1",
+            "fe80::1",
+            "fec0::1",
+            "ff02::1",
+            "2001:db8::1",
+            "::7f00:1", // IPv4 compatible
+        ]
+        .iter()
+        {
+            assert!(!public(ip), "{}", ip);
+        }
+        assert!(public("2606:4700:4700::1111"));
+    }
+
+    #[test]
+    fn v4_addresses_inside_v6_are_checked_as_v4() {
+        // mapped
+        assert!(!public("::ffff:127.0.0.1"));
+        assert!(!public("::ffff:169.254.169.254"));
+        assert!(!public("::ffff:10.0.0.1"));
+        assert!(public("::ffff:1.1.1.1"));
+
+        // NAT64
+        assert!(!public("64:ff9b::127.0.0.1"));
+        assert!(!public("64:ff9b::192.168.0.1"));
+        assert!(!public("64:ff9b::a9fe:a9fe"));
+        assert!(public("64:ff9b::8.8.8.8"));
+    }
+}
@@ -0,0 +1,309 @@
+//module webhooks
+// user registered endpoints called on tracker changes. Deliveries are stored
+// first and sent by a background loop, failed ones are retried with
+// exponential backoff until `max_attempts`
+pub mod address;
+
+use crate::{
+    config::WebhookConfig,
+    db::webhook::WebhookRepository,
+    errors::AppError,
+    handlers::AppResult,
+    models::webhook::{DueDelivery, Webhook, WebhookPayload, MMR_THRESHOLD},
+    tracker::events::{RankChange, TrackerEvent, LEVEL_UP, MMR_CHANGE, RANK_DOWN, RANK_UP},
+};
+use chrono::Utc;
+use color_eyre::Result;
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::Url;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, RecvError};
+use tracing::{debug, error, info};
+
+pub const SIGNATURE_HEADER: &str = "X-Zbot-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Zbot-Timestamp";
+pub const EVENT_HEADER: &str = "X-Zbot-Event";
+pub const DELIVERY_HEADER: &str = "X-Zbot-Delivery";
+
+// a claimed delivery is skipped by other workers for this long
+const CLAIM_LEASE_SECS: i64 = 120;
+
+/// hex HMAC-SHA256 of "timestamp.body", sent as `sha256=<hex>`.
+/// Receivers recompute it with their secret and reject old timestamps
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// 30s, 60s, 120s .. capped
+fn backoff(config: &WebhookConfig, attempts: i32) -> chrono::Duration {
+    let factor = 1i64 << attempts.clamp(0, 16);
+    chrono::Duration::seconds(config.base_backoff_secs.saturating_mul(factor).min(config.max_backoff_secs))
+}
+
+/// the webhook event a tracker change maps to, if the webhook wants it
+fn webhook_event(webhook: &Webhook, change: &RankChange) -> Option<&'static str> {
+    match change.event {
+        RANK_UP | RANK_DOWN | LEVEL_UP if webhook.wants(change.event) => Some(change.event),
+        MMR_CHANGE if webhook.wants(MMR_THRESHOLD) => {
+            let threshold = webhook.mmr_threshold? as f32;
+            let crossed = (change.previous.mmr < threshold) != (change.current.mmr < threshold);
+            if crossed {
+                Some(MMR_THRESHOLD)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    config: WebhookConfig,
+    client: reqwest::Client,
+    pool: Arc<PgPool>,
+}
+
+impl WebhookDispatcher {
+    /// own client, a redirect would lead the request past the address checks
+    pub fn new(config: WebhookConfig, pool: Arc<PgPool>) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        Ok(WebhookDispatcher { config, client, pool })
+    }
+
+    pub fn max_per_user(&self) -> i64 {
+        self.config.max_per_user
+    }
+
+    /// only https to a public host, plain http when WEBHOOKS.ALLOW_HTTP is set.
+    /// Private hosts are refused unless WEBHOOKS.ALLOW_PRIVATE_HOSTS is set
+    pub async fn validate_url(&self, url: &str) -> AppResult<Url> {
+        let invalid = |message: &str| AppError::INVALID_INPUT.message(message.to_string());
+        let url = Url::parse(url).map_err(|_| invalid("Invalid url."))?;
+
+        match url.scheme() {
+            "https" => {}
+            "http" if self.config.allow_http => {}
+            _ => return Err(invalid("Webhook urls must use https.")),
+        }
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(invalid("Webhook urls must not contain credentials."));
+        }
+        if url.host_str().is_none() {
+            return Err(invalid("Webhook url has no host."));
+        }
+        if !self.config.allow_private_hosts {
+            address::ensure_public(&url).await?;
+        }
+
+        Ok(url)
+    }
+
+    /// stores a delivery for every webhook interested in `change`
+    pub async fn enqueue(&self, change: &RankChange) -> Result<usize> {
+        let repository = WebhookRepository::new(self.pool.clone());
+        let webhooks = repository.find_for_profile(&change.profile_id).await?;
+
+        let mut queued = 0;
+        for webhook in webhooks {
+            if let Some(event) = webhook_event(&webhook, change) {
+                let payload = serde_json::to_string(&WebhookPayload {
+                    event,
+                    webhook_id: webhook.id,
+                    data: change,
+                })?;
+                repository.create_delivery(webhook.id, event, &payload, None).await?;
+                queued += 1;
+            }
+        }
+
+        Ok(queued)
+    }
+
+    /// turns tracker changes into deliveries
+    pub async fn listen(self, mut events: broadcast::Receiver<TrackerEvent>) {
+        loop {
+            match events.recv().await {
+                Ok(TrackerEvent::Change(change)) => match self.enqueue(&change).await {
+                    Ok(0) => {}
+                    Ok(queued) => debug!("Queued {} webhook deliveries for {}", queued, change.event),
+                    Err(e) => error!("Error queueing webhook deliveries. {:?}", e),
+                },
+                Ok(TrackerEvent::Snapshot(_)) | Ok(TrackerEvent::Card(_)) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    error!("Webhook listener fell behind, {} tracker events dropped.", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// sends due deliveries every WEBHOOKS.POLL_INTERVAL_SECS
+    pub async fn run(self) {
+        let repository = WebhookRepository::new(self.pool.clone());
+        let mut interval = actix_rt::time::interval(Duration::from_secs(self.config.poll_interval_secs));
+        loop {
+            interval.tick().await;
+
+            let lease_until = (Utc::now() + chrono::Duration::seconds(CLAIM_LEASE_SECS)).naive_utc();
+            let due = match repository.claim_due(self.config.batch_size, lease_until).await {
+                Ok(due) => due,
+                Err(e) => {
+                    error!("Error claiming webhook deliveries. {:?}", e);
+                    continue;
+                }
+            };
+
+            let sends = due.into_iter().map(|delivery| self.deliver(&repository, delivery));
+            futures::future::join_all(sends).await;
+        }
+    }
+
+    // the url is validated again, its host may resolve to a private address by now
+    async fn send(&self, delivery: &DueDelivery) -> std::result::Result<reqwest::Response, String> {
+        let url = self
+            .validate_url(&delivery.url)
+            .await
+            .map_err(|e| format!("Blocked: {}", e.message()))?;
+
+        let timestamp = Utc::now().timestamp();
+        self.client
+            .post(url)
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, sign(&delivery.secret, timestamp, &delivery.payload))
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(EVENT_HEADER, delivery.event.as_str())
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| format!("Request failed: {}", e))
+    }
+
+    async fn deliver(&self, repository: &WebhookRepository, delivery: DueDelivery) {
+        let (status_code, failure) = match self.send(&delivery).await {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16() as i32), None),
+            Ok(response) => (
+                Some(response.status().as_u16() as i32),
+                Some(format!("Endpoint answered {}", response.status())),
+            ),
+            Err(error) => (None, Some(error)),
+        };
+
+        let result = match failure {
+            None => {
+                debug!("Delivered {} of webhook {} ({})", delivery.id, delivery.webhook_id, delivery.event);
+                repository.mark_delivered(delivery.id, status_code.unwrap_or_default()).await
+            }
+            Some(error) => {
+                let attempts = delivery.attempts + 1;
+                let retry_at = if attempts < self.config.max_attempts {
+                    Some((Utc::now() + backoff(&self.config, delivery.attempts)).naive_utc())
+                } else {
+                    info!("Giving up webhook delivery {} after {} attempts.", delivery.id, attempts);
+                    None
+                };
+                repository
+                    .mark_attempt_failed(delivery.id, status_code, &error, retry_at)
+                    .await
+            }
+        };
+
+        if let Err(e) = result {
+            error!("Error updating webhook delivery {}. {:?}", delivery.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::events::SnapshotSummary;
+    use uuid::Uuid;
+
+    fn webhook(events: &str, mmr_threshold: Option<i32>) -> Webhook {
+        Webhook {
+            id: Uuid::nil(),
+            user_id: Uuid::nil(),
+            url: "https://hooks.example.com".to_string(),
+            events: events.to_string(),
+            profile_id: None,
+            mmr_threshold,
+            active: true,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    fn change(event: &'static str, previous_mmr: f32, current_mmr: f32) -> RankChange {
+        RankChange {
+            event,
+            profile_id: "profile".to_string(),
+            platform: "uplay".to_string(),
+            name_on_platform: "Player".to_string(),
+            region: "emea".to_string(),
+            snapshot_id: Uuid::nil(),
+            previous: SnapshotSummary::new(15, previous_mmr, Some(100), 10, 10),
+            current: SnapshotSummary::new(15, current_mmr, Some(100), 11, 10),
+            occurred_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn signatures_cover_timestamp_and_body() {
+        let body = r#"{"event":"rank_up"}"#;
+        assert_eq!(
+            sign("whsec_test", 1_700_000_000, body),
+            "sha256=d780880efbb6e57321c1e9bd7e31c5e0eadff139967e8a63a0ac5bbf6f2eb9ab"
+        );
+        assert_ne!(sign("whsec_test", 1_700_000_001, body), sign("whsec_test", 1_700_000_000, body));
+        assert_ne!(sign("whsec_other", 1_700_000_000, body), sign("whsec_test", 1_700_000_000, body));
+    }
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_the_maximum() {
+        let config = WebhookConfig {
+            base_backoff_secs: 30,
+            max_backoff_secs: 3600,
+            ..WebhookConfig::default()
+        };
+        let secs: Vec<i64> = (0..9).map(|attempts| backoff(&config, attempts).num_seconds()).collect();
+        assert_eq!(secs, vec![30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(backoff(&config, -1).num_seconds(), 30);
+        assert_eq!(backoff(&config, i32::MAX).num_seconds(), 3600);
+    }
+
+    #[test]
+    fn subscribed_events_are_sent() {
+        let webhook = webhook("rank_up level_up", None);
+        assert_eq!(webhook_event(&webhook, &change(RANK_UP, 2500.0, 2600.0)), Some(RANK_UP));
+        assert_eq!(webhook_event(&webhook, &change(LEVEL_UP, 2500.0, 2500.0)), Some(LEVEL_UP));
+        assert_eq!(webhook_event(&webhook, &change(RANK_DOWN, 2600.0, 2500.0)), None);
+        assert_eq!(webhook_event(&webhook, &change(MMR_CHANGE, 2400.0, 2600.0)), None);
+    }
+
+    #[test]
+    fn mmr_changes_are_sent_when_they_cross_the_threshold() {
+        let webhook = webhook("mmr_threshold", Some(2500));
+        // upwards, downwards and landing exactly on it
+        assert_eq!(webhook_event(&webhook, &change(MMR_CHANGE, 2450.0, 2550.0)), Some(MMR_THRESHOLD));
+        assert_eq!(webhook_event(&webhook, &change(MMR_CHANGE, 2550.0, 2450.0)), Some(MMR_THRESHOLD));
+        assert_eq!(webhook_event(&webhook, &change(MMR_CHANGE, 2499.0, 2500.0)), Some(MMR_THRESHOLD));
+
+        // staying on one side
+        assert_eq!(webhook_event(&webhook, &change(MMR_CHANGE, 2500.0, 2600.0)), None);
+        assert_eq!(webhook_event(&webhook, &change(MMR_CHANGE, 2300.0, 2400.0)), None);
+
+        let without_threshold = self::webhook("mmr_threshold", None);
+        assert_eq!(webhook_event(&without_threshold, &change(MMR_CHANGE, 2450.0, 2550.0)), None);
+    }
+}